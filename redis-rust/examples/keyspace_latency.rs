@@ -0,0 +1,93 @@
+//! Latency of single writes while a keyspace grows to millions of keys.
+//!
+//! Inserts the same keys into a `Dict`, which rehashes a bucket per write,
+//! into a `Database`, which keeps its keys in one, and into a
+//! `std::collections::HashMap`, which moves every entry at once each time it
+//! doubles, timing each insert on its own:
+//!
+//! ```text
+//! cargo run --release --example keyspace_latency -- [keys]
+//! ```
+
+#![allow(clippy::needless_return)]
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use redis_starter_rust::dict::Dict;
+use redis_starter_rust::Database;
+
+const DEFAULT_KEYS: usize = 4_000_000;
+
+fn main() {
+    let keys: usize = match std::env::args().nth(1) {
+        Some(n) => n.parse().expect("the number of keys"),
+        None => DEFAULT_KEYS,
+    };
+    println!("inserting {} keys, one timed write at a time\n", keys);
+    println!(
+        "{:<10} {:>8} {:>8} {:>8} {:>10} {:>10} {:>10}",
+        "keyspace", "p50", "p99", "p99.9", "p99.99", "max", "total"
+    );
+
+    let mut dict: Dict<Vec<u8>, Vec<u8>> = Dict::new();
+    report(
+        "Dict",
+        measure(keys, |key| {
+            dict.insert(key, b"value".to_vec());
+        }),
+    );
+
+    let mut db: Database = Database::new();
+    report(
+        "Database",
+        measure(keys, |key| db.set(&key, b"value".to_vec(), None)),
+    );
+
+    let mut map: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+    report(
+        "HashMap",
+        measure(keys, |key| {
+            map.insert(key, b"value".to_vec());
+        }),
+    );
+}
+
+/// The time each of `keys` inserts took, in nanoseconds.
+fn measure<F: FnMut(Vec<u8>)>(keys: usize, mut insert: F) -> Vec<u64> {
+    let mut samples: Vec<u64> = Vec::with_capacity(keys);
+    for i in 0..keys {
+        let key: Vec<u8> = format!("key:{}", i).into_bytes();
+        let started: Instant = Instant::now();
+        insert(key);
+        samples.push(started.elapsed().as_nanos() as u64);
+    }
+    return samples;
+}
+
+fn report(name: &str, mut samples: Vec<u64>) {
+    let total: Duration = Duration::from_nanos(samples.iter().sum());
+    samples.sort_unstable();
+    let percentile = |p: f64| -> String {
+        let index: usize = ((samples.len() as f64 * p) as usize).min(samples.len() - 1);
+        return format_ns(samples[index]);
+    };
+    println!(
+        "{:<10} {:>8} {:>8} {:>8} {:>10} {:>10} {:>10}",
+        name,
+        percentile(0.5),
+        percentile(0.99),
+        percentile(0.999),
+        percentile(0.9999),
+        format_ns(*samples.last().unwrap()),
+        format!("{:.2?}", total),
+    );
+}
+
+fn format_ns(ns: u64) -> String {
+    return match ns {
+        0..1_000 => format!("{}ns", ns),
+        1_000..1_000_000 => format!("{:.1}us", ns as f64 / 1e3),
+        _ => format!("{:.2}ms", ns as f64 / 1e6),
+    };
+}
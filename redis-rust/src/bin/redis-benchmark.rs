@@ -0,0 +1,453 @@
+#![allow(clippy::needless_return)]
+
+//! A load generator speaking the same options and printing the same report as
+//! `redis-benchmark`, so numbers can be compared with a real Redis:
+//!
+//! ```text
+//! redis-benchmark -t set,get -n 100000 -c 1,10,50 -P 16 -r 100000 -q
+//! ```
+//!
+//! `-c` also takes a comma separated list of client counts; every test then
+//! runs once per count and a table shows how throughput scales with them.
+
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Error};
+use redis_starter_rust::random::random_below;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The tests run by default, in order, as `redis-benchmark` names them.
+const TESTS: &[&str] = &[
+    "PING_INLINE",
+    "PING_MBULK",
+    "SET",
+    "GET",
+    "LPUSH",
+    "RPUSH",
+    "SADD",
+    "HSET",
+    "ZADD",
+    "LRANGE_100",
+];
+
+/// Elements pushed to `mylist` before the LRANGE tests.
+const LRANGE_LIST_LEN: usize = 100;
+
+struct Options {
+    host: String,
+    port: u16,
+    clients: Vec<usize>,
+    requests: usize,
+    data_size: usize,
+    pipeline: usize,
+    tests: Vec<String>,
+    /// Range of the random numbers replacing `__rand_int__`; `None` keeps the placeholder.
+    keyspace: Option<usize>,
+    quiet: bool,
+    csv: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, Error> {
+        let mut options: Options = Options {
+            host: String::from("127.0.0.1"),
+            port: 6379,
+            clients: vec![50],
+            requests: 100_000,
+            data_size: 3,
+            pipeline: 1,
+            tests: TESTS.iter().map(|t| t.to_string()).collect(),
+            keyspace: None,
+            quiet: false,
+            csv: false,
+        };
+
+        let mut i: usize = 0;
+        while i < args.len() {
+            let flag: &str = args[i].as_str();
+            match flag {
+                "-q" => options.quiet = true,
+                "--csv" => options.csv = true,
+                _ => {
+                    let value: &str = match args.get(i + 1) {
+                        Some(v) => v.as_str(),
+                        None => bail!("option {} needs a value", flag),
+                    };
+                    match flag {
+                        "-h" => options.host = value.to_string(),
+                        "-p" => options.port = value.parse()?,
+                        "-c" => {
+                            options.clients = value
+                                .split(',')
+                                .map(|c| c.trim().parse::<usize>())
+                                .collect::<Result<Vec<usize>, _>>()?;
+                        }
+                        "-n" => options.requests = value.parse()?,
+                        "-d" => options.data_size = value.parse()?,
+                        "-P" => options.pipeline = value.parse()?,
+                        "-r" => options.keyspace = Some(value.parse()?),
+                        "-t" => {
+                            options.tests =
+                                value.split(',').map(|t| t.trim().to_uppercase()).collect();
+                        }
+                        _ => bail!("unknown option {}", flag),
+                    }
+                    i += 1;
+                }
+            }
+            i += 1;
+        }
+
+        if options.clients.contains(&0) || options.pipeline == 0 || options.keyspace == Some(0) {
+            bail!("-c, -P and -r must be positive");
+        }
+        for test in options.tests.iter() {
+            if !TESTS.contains(&test.as_str()) {
+                bail!("unknown test {}, expected one of {}", test, TESTS.join(","));
+            }
+        }
+        return Ok(options);
+    }
+}
+
+/// Encodes a command as a RESP array of bulk strings.
+fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut out: Vec<u8> = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+    return out;
+}
+
+/// Replaces `__rand_int__` with a random 12 digit number below `keyspace`.
+fn randomize(template: &str, keyspace: Option<usize>) -> Vec<u8> {
+    return match keyspace {
+        Some(range) => template
+            .replace("__rand_int__", &format!("{:012}", random_below(range)))
+            .into_bytes(),
+        None => template.as_bytes().to_vec(),
+    };
+}
+
+/// The bytes of one request of `test`.
+fn request(test: &str, data: &[u8], keyspace: Option<usize>) -> Vec<u8> {
+    let key: Vec<u8> = randomize("key:__rand_int__", keyspace);
+    let element: Vec<u8> = randomize("element:__rand_int__", keyspace);
+    return match test {
+        "PING_INLINE" => b"PING\r\n".to_vec(),
+        "PING_MBULK" => encode_command(&[b"PING"]),
+        "SET" => encode_command(&[b"SET", &key, data]),
+        "GET" => encode_command(&[b"GET", &key]),
+        "LPUSH" => encode_command(&[b"LPUSH", b"mylist", data]),
+        "RPUSH" => encode_command(&[b"RPUSH", b"mylist", data]),
+        "SADD" => encode_command(&[b"SADD", b"myset", &element]),
+        "HSET" => encode_command(&[b"HSET", b"myhash", &element, data]),
+        "ZADD" => encode_command(&[b"ZADD", b"myzset", b"0", &element]),
+        "LRANGE_100" => encode_command(&[b"LRANGE", b"mylist", b"0", b"99"]),
+        _ => unreachable!("tests are checked when the options are parsed"),
+    };
+}
+
+/// Length of the complete reply at the start of `buf`, or `None` while more
+/// bytes are needed.
+fn reply_len(buf: &[u8]) -> Option<usize> {
+    let line_end: usize = buf.windows(2).position(|w| w == b"\r\n")?;
+    let header: &str = std::str::from_utf8(&buf[1..line_end]).unwrap_or("");
+    let after_header: usize = line_end + 2;
+
+    return match buf[0] {
+        b'$' => match header.parse::<i64>() {
+            Ok(len) if len >= 0 => {
+                let total: usize = after_header + len as usize + 2;
+                (buf.len() >= total).then_some(total)
+            }
+            _ => Some(after_header),
+        },
+        b'*' => match header.parse::<i64>() {
+            Ok(count) if count >= 0 => {
+                let mut pos: usize = after_header;
+                for _ in 0..count {
+                    pos += reply_len(&buf[pos..])?;
+                }
+                Some(pos)
+            }
+            _ => Some(after_header),
+        },
+        // simple strings, errors and integers are a single line
+        _ => Some(after_header),
+    };
+}
+
+/// Sends requests until the shared budget is spent, returning the latency of
+/// every request in microseconds. A pipelined request waits for the replies of
+/// its whole batch, as in `redis-benchmark`.
+async fn run_client(
+    addr: String,
+    remaining: Arc<AtomicUsize>,
+    test: String,
+    data: Arc<Vec<u8>>,
+    options: Arc<Options>,
+) -> Result<Vec<u64>, Error> {
+    let mut stream: TcpStream = TcpStream::connect(&addr).await?;
+    stream.set_nodelay(true)?;
+
+    let mut latencies: Vec<u64> = Vec::new();
+    let mut buf: Vec<u8> = Vec::with_capacity(16 * 1024);
+    let mut chunk: Vec<u8> = vec![0; 16 * 1024];
+    let mut reported_error: bool = false;
+    loop {
+        let claimed: usize =
+            match remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                return n.checked_sub(n.min(options.pipeline));
+            }) {
+                Ok(before) => before.min(options.pipeline),
+                Err(_) => 0,
+            };
+        if claimed == 0 {
+            return Ok(latencies);
+        }
+
+        let mut batch: Vec<u8> = Vec::new();
+        for _ in 0..claimed {
+            batch.extend(request(&test, &data, options.keyspace));
+        }
+
+        let started: Instant = Instant::now();
+        stream.write_all(&batch).await?;
+        let mut pending: usize = claimed;
+        while pending > 0 {
+            if let Some(len) = reply_len(&buf) {
+                if buf[0] == b'-' && !reported_error {
+                    eprintln!(
+                        "Error from server: {}",
+                        String::from_utf8_lossy(&buf[1..len - 2])
+                    );
+                    reported_error = true;
+                }
+                buf.drain(..len);
+                pending -= 1;
+                continue;
+            }
+            let read: usize = stream.read(&mut chunk).await?;
+            if read == 0 {
+                bail!("server closed the connection");
+            }
+            buf.extend_from_slice(&chunk[..read]);
+        }
+        let elapsed: u64 = started.elapsed().as_micros() as u64;
+        latencies.extend(std::iter::repeat_n(elapsed, claimed));
+    }
+}
+
+/// Throughput and latency distribution of one test run.
+struct Report {
+    elapsed: Duration,
+    rps: f64,
+    /// avg, min, p50, p95, p99 and max, in milliseconds.
+    latency: [f64; 6],
+}
+
+/// The value under which `quantile` of the sorted `values` fall.
+fn percentile(sorted: &[u64], quantile: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank: usize = (quantile * sorted.len() as f64).ceil() as usize;
+    return sorted[rank.clamp(1, sorted.len()) - 1];
+}
+
+async fn run_test(test: &str, clients: usize, options: &Arc<Options>) -> Result<Report, Error> {
+    let addr: String = format!("{}:{}", options.host, options.port);
+    let data: Arc<Vec<u8>> = Arc::new(vec![b'x'; options.data_size]);
+    let remaining: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(options.requests));
+
+    let started: Instant = Instant::now();
+    let mut tasks = Vec::with_capacity(clients);
+    for _ in 0..clients {
+        tasks.push(tokio::spawn(run_client(
+            addr.clone(),
+            Arc::clone(&remaining),
+            test.to_string(),
+            Arc::clone(&data),
+            Arc::clone(options),
+        )));
+    }
+    let mut latencies: Vec<u64> = Vec::with_capacity(options.requests);
+    for task in tasks {
+        latencies.extend(task.await??);
+    }
+    let elapsed: Duration = started.elapsed();
+
+    latencies.sort_unstable();
+    let ms = |us: u64| us as f64 / 1000.0;
+    let avg: f64 = match latencies.len() {
+        0 => 0.0,
+        n => latencies.iter().sum::<u64>() as f64 / n as f64 / 1000.0,
+    };
+    return Ok(Report {
+        elapsed,
+        rps: latencies.len() as f64 / elapsed.as_secs_f64(),
+        latency: [
+            avg,
+            ms(latencies.first().copied().unwrap_or(0)),
+            ms(percentile(&latencies, 0.50)),
+            ms(percentile(&latencies, 0.95)),
+            ms(percentile(&latencies, 0.99)),
+            ms(latencies.last().copied().unwrap_or(0)),
+        ],
+    });
+}
+
+fn print_report(name: &str, clients: usize, options: &Options, report: &Report) {
+    let [avg, min, p50, p95, p99, max] = report.latency;
+    if options.csv {
+        println!(
+            "\"{}\",\"{:.2}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\"",
+            name, report.rps, avg, min, p50, p95, p99, max
+        );
+        return;
+    }
+    if options.quiet {
+        println!(
+            "{}: {:.2} requests per second, p50={:.3} msec",
+            name, report.rps, p50
+        );
+        return;
+    }
+
+    println!("====== {} ======", name);
+    println!(
+        "  {} requests completed in {:.2} seconds",
+        options.requests,
+        report.elapsed.as_secs_f64()
+    );
+    println!("  {} parallel clients", clients);
+    println!("  {} bytes payload", options.data_size);
+    println!("  keep alive: 1");
+    println!();
+    println!("Summary:");
+    println!(
+        "  throughput summary: {:.2} requests per second",
+        report.rps
+    );
+    println!("  latency summary (msec):");
+    println!(
+        "  {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "avg", "min", "p50", "p95", "p99", "max"
+    );
+    println!(
+        "  {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+        avg, min, p50, p95, p99, max
+    );
+    println!();
+}
+
+/// Fills `mylist` so LRANGE_100 has a hundred elements to return.
+async fn prepare_lrange(options: &Options) -> Result<(), Error> {
+    let mut stream: TcpStream =
+        TcpStream::connect(format!("{}:{}", options.host, options.port)).await?;
+    let data: Vec<u8> = vec![b'x'; options.data_size];
+    let mut batch: Vec<u8> = Vec::new();
+    for _ in 0..LRANGE_LIST_LEN {
+        batch.extend(encode_command(&[b"LPUSH", b"mylist", &data]));
+    }
+    stream.write_all(&batch).await?;
+
+    let mut buf: Vec<u8> = Vec::new();
+    let mut chunk: Vec<u8> = vec![0; 4096];
+    let mut replies: usize = 0;
+    while replies < LRANGE_LIST_LEN {
+        if let Some(len) = reply_len(&buf) {
+            buf.drain(..len);
+            replies += 1;
+            continue;
+        }
+        let read: usize = stream.read(&mut chunk).await?;
+        if read == 0 {
+            bail!("server closed the connection");
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+    return Ok(());
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args: Vec<String> = env::args().collect();
+    let options: Arc<Options> = Arc::new(Options::parse(&args[1..])?);
+    let sweep: bool = options.clients.len() > 1;
+
+    if options.csv {
+        println!("\"test\",\"rps\",\"avg_latency_ms\",\"min_latency_ms\",\"p50_latency_ms\",\"p95_latency_ms\",\"p99_latency_ms\",\"max_latency_ms\"");
+    }
+
+    // requests per second of every test, for each client count
+    let mut scaling: Vec<(String, Vec<f64>)> = Vec::new();
+    for test in options.tests.iter() {
+        if test == "LRANGE_100" {
+            prepare_lrange(&options)
+                .await
+                .map_err(|e| anyhow!("could not prepare LRANGE_100: {}", e))?;
+        }
+
+        let mut rates: Vec<f64> = Vec::new();
+        for &clients in options.clients.iter() {
+            let report: Report = run_test(test, clients, &options).await?;
+            let name: String = match sweep {
+                true => format!("{} ({} clients)", test, clients),
+                false => test.clone(),
+            };
+            print_report(&name, clients, &options, &report);
+            rates.push(report.rps);
+        }
+        scaling.push((test.clone(), rates));
+    }
+
+    if sweep && !options.csv {
+        println!();
+        println!("====== requests per second by number of clients ======");
+        let mut header: String = format!("{:<12}", "test");
+        for clients in options.clients.iter() {
+            header.push_str(&format!("{:>12}", clients));
+        }
+        println!("{}", header);
+        for (test, rates) in scaling {
+            let mut line: String = format!("{:<12}", test);
+            for rps in rates {
+                line.push_str(&format!("{:>12.2}", rps));
+            }
+            println!("{}", line);
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_len_test() {
+        assert_eq!(reply_len(b"+OK\r\n"), Some(5));
+        assert_eq!(reply_len(b"$3\r\nbar\r\n:1\r\n"), Some(9));
+        assert_eq!(reply_len(b"$-1\r\n"), Some(5));
+        assert_eq!(reply_len(b"*2\r\n$1\r\na\r\n:12\r\n"), Some(16));
+        assert_eq!(reply_len(b"$3\r\nba"), None);
+        assert_eq!(reply_len(b"*2\r\n$1\r\na\r\n"), None);
+    }
+
+    #[test]
+    fn percentile_test() {
+        let sorted: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&sorted, 0.50), 50);
+        assert_eq!(percentile(&sorted, 0.99), 99);
+        assert_eq!(percentile(&sorted, 1.0), 100);
+        assert_eq!(percentile(&[], 0.5), 0);
+    }
+}
@@ -0,0 +1,557 @@
+#![allow(clippy::needless_return)]
+
+//! A command line client taking the same options and printing replies the
+//! same way as `redis-cli`, so it can be pointed at any server:
+//!
+//! ```text
+//! redis-cli -p 6380 -n 2 HGETALL user:1
+//! redis-cli --csv LRANGE mylist 0 -1
+//! cat commands.resp | redis-cli --pipe
+//! redis-cli --scan --pattern 'user:*'
+//! ```
+//!
+//! Without a command it starts a prompt with line editing and a history kept
+//! in `~/.rediscli_history` (or `$REDISCLI_HISTFILE`). Replies are printed in
+//! the human readable form when stdout is a terminal and raw otherwise, as
+//! `redis-cli` does; `--raw`, `--no-raw` and `--csv` pick one explicitly.
+
+use std::env;
+use std::io::{IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::{anyhow, bail, Error};
+use bytes::{Buf, BytesMut};
+use redis_starter_rust::random::random_hex;
+use redis_starter_rust::redis_client::{ClientOptions, Cmd, RedisClient};
+use redis_starter_rust::redis_parser::{parse_reply, split_args};
+use redis_starter_rust::RedisType;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Output {
+    /// Quoted strings and numbered, indented aggregates, for people.
+    Tty,
+    /// Values as they are, one per line, for scripts.
+    Raw,
+    Csv,
+}
+
+struct Options {
+    host: String,
+    port: u16,
+    db: usize,
+    output: Output,
+    pipe: bool,
+    scan: bool,
+    pattern: Option<String>,
+    count: Option<usize>,
+    /// The command to run once; empty for the prompt.
+    command: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, Error> {
+        let mut options: Options = Options {
+            host: String::from("127.0.0.1"),
+            port: 6379,
+            db: 0,
+            output: match std::io::stdout().is_terminal() {
+                true => Output::Tty,
+                false => Output::Raw,
+            },
+            pipe: false,
+            scan: false,
+            pattern: None,
+            count: None,
+            command: Vec::new(),
+        };
+
+        let mut i: usize = 0;
+        while i < args.len() {
+            let flag: &str = args[i].as_str();
+            match flag {
+                "--raw" => options.output = Output::Raw,
+                "--no-raw" => options.output = Output::Tty,
+                "--csv" => options.output = Output::Csv,
+                "--pipe" => options.pipe = true,
+                "--scan" => options.scan = true,
+                "-h" | "-p" | "-n" | "--pattern" | "--count" => {
+                    let value: &str = match args.get(i + 1) {
+                        Some(v) => v.as_str(),
+                        None => bail!("option {} needs a value", flag),
+                    };
+                    match flag {
+                        "-h" => options.host = value.to_string(),
+                        "-p" => options.port = value.parse()?,
+                        "-n" => options.db = value.parse()?,
+                        "--pattern" => options.pattern = Some(value.to_string()),
+                        _ => options.count = Some(value.parse()?),
+                    }
+                    i += 1;
+                }
+                _ if flag.starts_with('-') && options.command.is_empty() => {
+                    bail!("unknown option {}", flag)
+                }
+                _ => {
+                    // everything from the first argument on is the command
+                    options.command = args[i..].to_vec();
+                    break;
+                }
+            }
+            i += 1;
+        }
+        return Ok(options);
+    }
+
+    fn addr(&self) -> String {
+        return format!("{}:{}", self.host, self.port);
+    }
+}
+
+/// A string quoted and escaped the way `redis-cli` shows it.
+fn quote(bytes: &[u8]) -> String {
+    let mut out: String = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => out.push(b as char),
+            b => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+/// The bytes of a string reply; `None` for any other kind.
+fn string_bytes(reply: &RedisType) -> Option<Vec<u8>> {
+    return match reply {
+        RedisType::BulkBytes(b) => Some(b.clone()),
+        RedisType::BulkString(s) => Some(s.as_bytes().to_vec()),
+        _ => None,
+    };
+}
+
+/// Formats `reply` for a terminal; `indent` is the column nested lines start at.
+fn format_tty(reply: &RedisType, indent: usize) -> Vec<u8> {
+    let line: String = match reply {
+        RedisType::SimpleString(s) => s.to_string(),
+        RedisType::Status(s) => s.clone(),
+        RedisType::Error(e) => format!("(error) {}", e),
+        RedisType::Integer(n) => format!("(integer) {}", n),
+        RedisType::BulkString(_) | RedisType::BulkBytes(_) => quote(&string_bytes(reply).unwrap()),
+        RedisType::Null | RedisType::NullBulk | RedisType::NullArray => String::from("(nil)"),
+        RedisType::Boolean(b) => format!("({})", b),
+        RedisType::Array(elements) | RedisType::Push(elements) => {
+            return format_elements(elements, indent);
+        }
+        RedisType::Sequence(elements) => return format_elements(elements, indent),
+        RedisType::Map(pairs) => {
+            if pairs.is_empty() {
+                return b"(empty hash)\n".to_vec();
+            }
+            let width: usize = pairs.len().to_string().len();
+            let mut out: Vec<u8> = Vec::new();
+            for (i, (key, value)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.extend(std::iter::repeat_n(b' ', indent));
+                }
+                let label: String = format!("{:>width$}# ", i + 1, width = width);
+                out.extend_from_slice(label.as_bytes());
+                let mut key: Vec<u8> = format_tty(key, indent + label.len());
+                key.pop();
+                key.extend_from_slice(b" => ");
+                let nested: usize = indent + label.len() + key.len();
+                out.extend(key);
+                out.extend(format_tty(value, nested));
+            }
+            return out;
+        }
+    };
+    let mut out: Vec<u8> = line.into_bytes();
+    out.push(b'\n');
+    return out;
+}
+
+/// Numbers the elements of an aggregate, lining nested ones up under their parent.
+fn format_elements(elements: &[RedisType], indent: usize) -> Vec<u8> {
+    if elements.is_empty() {
+        return b"(empty array)\n".to_vec();
+    }
+    let width: usize = elements.len().to_string().len();
+    let mut out: Vec<u8> = Vec::new();
+    for (i, element) in elements.iter().enumerate() {
+        if i > 0 {
+            out.extend(std::iter::repeat_n(b' ', indent));
+        }
+        let label: String = format!("{:>width$}) ", i + 1, width = width);
+        out.extend_from_slice(label.as_bytes());
+        out.extend(format_tty(element, indent + label.len()));
+    }
+    return out;
+}
+
+/// Formats `reply` with values as they are, aggregates one element per line.
+fn format_raw(reply: &RedisType) -> Vec<u8> {
+    return match reply {
+        RedisType::SimpleString(s) => s.as_bytes().to_vec(),
+        RedisType::Status(s) | RedisType::Error(s) | RedisType::Integer(s) => s.as_bytes().to_vec(),
+        RedisType::BulkString(_) | RedisType::BulkBytes(_) => string_bytes(reply).unwrap(),
+        RedisType::Null | RedisType::NullBulk | RedisType::NullArray => Vec::new(),
+        RedisType::Boolean(b) => format!("({})", b).into_bytes(),
+        RedisType::Array(elements) | RedisType::Push(elements) => {
+            join(elements.iter(), b"\n", format_raw)
+        }
+        RedisType::Sequence(elements) => join(elements.iter(), b"\n", format_raw),
+        RedisType::Map(pairs) => join(
+            pairs.iter().flat_map(|(key, value)| [key, value]),
+            b"\n",
+            format_raw,
+        ),
+    };
+}
+
+/// Formats `reply` as one line of comma separated values.
+fn format_csv(reply: &RedisType) -> Vec<u8> {
+    return match reply {
+        RedisType::SimpleString(s) => quote(s.as_bytes()).into_bytes(),
+        RedisType::Status(s) => quote(s.as_bytes()).into_bytes(),
+        RedisType::Error(e) => format!("ERROR,{}", quote(e.as_bytes())).into_bytes(),
+        RedisType::Integer(n) => n.as_bytes().to_vec(),
+        RedisType::BulkString(_) | RedisType::BulkBytes(_) => {
+            quote(&string_bytes(reply).unwrap()).into_bytes()
+        }
+        RedisType::Null | RedisType::NullBulk | RedisType::NullArray => b"NULL".to_vec(),
+        RedisType::Boolean(b) => b.to_string().into_bytes(),
+        RedisType::Array(elements) | RedisType::Push(elements) => {
+            join(elements.iter(), b",", format_csv)
+        }
+        RedisType::Sequence(elements) => join(elements.iter(), b",", format_csv),
+        RedisType::Map(pairs) => join(
+            pairs.iter().flat_map(|(key, value)| [key, value]),
+            b",",
+            format_csv,
+        ),
+    };
+}
+
+fn join<'a, 'r: 'a>(
+    elements: impl Iterator<Item = &'a RedisType<'r>>,
+    separator: &[u8],
+    format: fn(&RedisType) -> Vec<u8>,
+) -> Vec<u8> {
+    let parts: Vec<Vec<u8>> = elements.map(format).collect();
+    return parts.join(separator);
+}
+
+/// The text printed for `reply`, newline included.
+fn format_reply(reply: &RedisType, output: Output) -> Vec<u8> {
+    let mut out: Vec<u8> = match output {
+        Output::Tty => return format_tty(reply, 0),
+        Output::Raw => format_raw(reply),
+        Output::Csv => format_csv(reply),
+    };
+    out.push(b'\n');
+    return out;
+}
+
+/// Sends one command and prints its reply; `false` for an error reply.
+async fn run_command(
+    client: &mut RedisClient,
+    args: &[Vec<u8>],
+    output: Output,
+) -> Result<bool, Error> {
+    let name: String = String::from_utf8_lossy(&args[0]).to_string();
+    let reply: RedisType = client.query(&Cmd::new(&name).args(&args[1..])).await?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&format_reply(&reply, output))?;
+    stdout.flush()?;
+    return Ok(!matches!(reply, RedisType::Error(_)));
+}
+
+fn history_file() -> Option<PathBuf> {
+    if let Ok(path) = env::var("REDISCLI_HISTFILE") {
+        return (!path.is_empty()).then(|| PathBuf::from(path));
+    }
+    return env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".rediscli_history"));
+}
+
+async fn interactive(options: &Options) -> Result<(), Error> {
+    let mut client: RedisClient = connect(options).await?;
+    let mut editor: DefaultEditor = DefaultEditor::new()?;
+    let history: Option<PathBuf> = history_file();
+    if let Some(path) = history.as_ref() {
+        // there is no history yet on the first run
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let prompt: String = match client.options().db {
+            0 => format!("{}> ", options.addr()),
+            db => format!("{}[{}]> ", options.addr(), db),
+        };
+        let line: String = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let args: Vec<Vec<u8>> = match split_args(&line) {
+            Ok(args) => args,
+            Err(e) => {
+                println!("{}", e.message);
+                continue;
+            }
+        };
+        if args.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        if let Some(path) = history.as_ref() {
+            let _ = editor.save_history(path);
+        }
+
+        let name: String = String::from_utf8_lossy(&args[0]).to_lowercase();
+        if name == "quit" || name == "exit" {
+            return Ok(());
+        }
+        if let Err(e) = run_command(&mut client, &args, options.output).await {
+            println!("Could not connect to Redis at {}: {}", options.addr(), e);
+        }
+    }
+}
+
+async fn connect(options: &Options) -> Result<RedisClient, Error> {
+    let mut client_options: ClientOptions = ClientOptions::new(&options.addr());
+    client_options.db = options.db;
+    return RedisClient::with_options(client_options)
+        .await
+        .map_err(|e| {
+            anyhow!(
+                "Could not connect to Redis at {}: {}",
+                options.addr(),
+                e.message
+            )
+        });
+}
+
+/// Prints every key matching `--pattern`, walking the keyspace with SCAN.
+async fn scan(options: &Options) -> Result<(), Error> {
+    let mut client: RedisClient = connect(options).await?;
+    let mut stdout = std::io::stdout().lock();
+    let mut cursor: Vec<u8> = b"0".to_vec();
+    loop {
+        let mut cmd: Cmd = Cmd::new("SCAN").arg(&cursor);
+        if let Some(pattern) = options.pattern.as_ref() {
+            cmd = cmd.arg("MATCH").arg(pattern);
+        }
+        if let Some(count) = options.count {
+            cmd = cmd.arg("COUNT").arg(&count);
+        }
+        let reply: RedisType = client.call(&cmd).await?;
+        let (next, keys): (Vec<u8>, Vec<RedisType>) = match reply {
+            RedisType::Array(parts) if parts.len() == 2 => {
+                let mut parts: Vec<RedisType> = *parts;
+                let keys: RedisType = parts.pop().unwrap();
+                match (string_bytes(&parts[0]), keys) {
+                    (Some(next), RedisType::Array(keys)) => (next, *keys),
+                    _ => bail!("unexpected SCAN reply"),
+                }
+            }
+            _ => bail!("unexpected SCAN reply"),
+        };
+        for key in keys.iter() {
+            stdout.write_all(&format_raw(key))?;
+            stdout.write_all(b"\n")?;
+        }
+        if next == b"0" {
+            return Ok(());
+        }
+        cursor = next;
+    }
+}
+
+/// Sends the RESP commands read from stdin as fast as possible and counts the
+/// replies. An ECHO of a random marker follows them; its reply is the last.
+async fn pipe(options: &Options) -> Result<bool, Error> {
+    let mut input: Vec<u8> = Vec::new();
+    if options.db != 0 {
+        Cmd::new("SELECT").arg(&options.db).encode(&mut input);
+    }
+    std::io::stdin().read_to_end(&mut input)?;
+    let marker: String = random_hex(20);
+    Cmd::new("ECHO").arg(&marker).encode(&mut input);
+
+    let socket: TcpStream = TcpStream::connect(options.addr())
+        .await
+        .map_err(|e| anyhow!("Could not connect to Redis at {}: {}", options.addr(), e))?;
+    let (mut reader, mut writer) = socket.into_split();
+    let writing = tokio::spawn(async move {
+        writer.write_all(&input).await?;
+        eprintln!("All data transferred. Waiting for the last reply...");
+        return Ok::<_, std::io::Error>(writer);
+    });
+
+    let mut buffer: BytesMut = BytesMut::with_capacity(16 * 1024);
+    let mut replies: usize = 0;
+    let mut errors: usize = 0;
+    let mut skip: usize = (options.db != 0) as usize;
+    loop {
+        let (reply, used) = match parse_reply(&buffer)? {
+            Some(parsed) => parsed,
+            None => {
+                if reader.read_buf(&mut buffer).await? == 0 {
+                    bail!("Connection closed by the server before the last reply");
+                }
+                continue;
+            }
+        };
+        buffer.advance(used);
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        if string_bytes(&reply).as_deref() == Some(marker.as_bytes()) {
+            break;
+        }
+        if let RedisType::Error(e) = &reply {
+            eprintln!("{}", e);
+            errors += 1;
+        }
+        replies += 1;
+    }
+    drop(writing.await??);
+
+    eprintln!("Last reply received from server.");
+    println!("errors: {}, replies: {}", errors, replies);
+    return Ok(errors == 0);
+}
+
+async fn run(options: &Options) -> Result<bool, Error> {
+    if options.pipe {
+        return pipe(options).await;
+    }
+    if options.scan {
+        scan(options).await?;
+        return Ok(true);
+    }
+    if options.command.is_empty() {
+        interactive(options).await?;
+        return Ok(true);
+    }
+
+    let mut client: RedisClient = connect(options).await?;
+    let args: Vec<Vec<u8>> = options
+        .command
+        .iter()
+        .map(|a| a.as_bytes().to_vec())
+        .collect();
+    return run_command(&mut client, &args, options.output).await;
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let result: Result<bool, Error> = match Options::parse(&args[1..]) {
+        Ok(options) => run(&options).await,
+        Err(e) => Err(e),
+    };
+    return match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bulk(s: &str) -> RedisType<'static> {
+        return RedisType::BulkBytes(s.as_bytes().to_vec());
+    }
+
+    #[test]
+    fn format_tty_test() {
+        assert_eq!(
+            format_tty(&bulk("a \"b\"\n\x01"), 0),
+            b"\"a \\\"b\\\"\\n\\x01\"\n"
+        );
+        assert_eq!(
+            format_tty(&RedisType::Integer(String::from("7")), 0),
+            b"(integer) 7\n"
+        );
+        assert_eq!(format_tty(&RedisType::NullBulk, 0), b"(nil)\n");
+        assert_eq!(
+            format_tty(&RedisType::Array(Box::new(Vec::new())), 0),
+            b"(empty array)\n"
+        );
+
+        let nested: RedisType = RedisType::Array(Box::new(vec![
+            RedisType::Array(Box::new(vec![bulk("a"), bulk("b")])),
+            bulk("c"),
+        ]));
+        assert_eq!(
+            String::from_utf8(format_tty(&nested, 0)).unwrap(),
+            "1) 1) \"a\"\n   2) \"b\"\n2) \"c\"\n"
+        );
+
+        let ten: RedisType = RedisType::Array(Box::new((0..10).map(|_| bulk("x")).collect()));
+        let text: String = String::from_utf8(format_tty(&ten, 0)).unwrap();
+        assert!(text.starts_with(" 1) \"x\"\n 2) \"x\"\n"));
+        assert!(text.ends_with("10) \"x\"\n"));
+
+        let map: RedisType = RedisType::Map(Box::new(vec![
+            (
+                bulk("k"),
+                RedisType::Array(Box::new(vec![bulk("a"), bulk("b")])),
+            ),
+            (bulk("n"), RedisType::Integer(String::from("1"))),
+        ]));
+        assert_eq!(
+            String::from_utf8(format_tty(&map, 0)).unwrap(),
+            "1# \"k\" => 1) \"a\"\n          2) \"b\"\n2# \"n\" => (integer) 1\n"
+        );
+    }
+
+    #[test]
+    fn format_raw_and_csv_test() {
+        let reply: RedisType = RedisType::Array(Box::new(vec![
+            bulk("a,b"),
+            RedisType::Integer(String::from("2")),
+            RedisType::NullBulk,
+        ]));
+        assert_eq!(format_reply(&reply, Output::Raw), b"a,b\n2\n\n");
+        assert_eq!(format_reply(&reply, Output::Csv), b"\"a,b\",2,NULL\n");
+        let error: RedisType = RedisType::Error(String::from("ERR no"));
+        assert_eq!(format_reply(&error, Output::Raw), b"ERR no\n");
+        assert_eq!(format_reply(&error, Output::Csv), b"ERROR,\"ERR no\"\n");
+    }
+
+    #[test]
+    fn options_test() {
+        let args: Vec<String> = ["-p", "7000", "-n", "2", "--csv", "SET", "-x", "--raw"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let options: Options = Options::parse(&args).unwrap();
+        assert_eq!(options.port, 7000);
+        assert_eq!(options.db, 2);
+        assert_eq!(options.output, Output::Csv);
+        assert_eq!(options.command, vec!["SET", "-x", "--raw"]);
+        assert!(Options::parse(&[String::from("--bogus")]).is_err());
+    }
+}
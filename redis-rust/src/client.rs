@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+
+use crate::redis_parser::{RedisType, RESP2};
+use crate::tracking::TrackingOptions;
+
+/// A connected client as seen by CLIENT LIST and the other connections.
+///
+/// The connection task owns the socket; everything another connection may need
+/// to read or change lives here behind the registry.
+pub struct Client {
+    pub id: u64,
+    pub addr: String,
+    pub laddr: String,
+    pub fd: i32,
+    created: Instant,
+    meta: Mutex<ClientMeta>,
+    /// Protocol version chosen with HELLO, read for every reply.
+    protocol: AtomicU8,
+    killed: AtomicBool,
+    kill_notify: Notify,
+    /// Out-of-band messages (Pub/Sub deliveries) for the connection task to write.
+    outbox: UnboundedSender<RedisType<'static>>,
+    inbox: Mutex<Option<UnboundedReceiver<RedisType<'static>>>>,
+    /// Messages queued with `send` that the connection task has not written yet.
+    pending: AtomicUsize,
+}
+
+/// The mutable part of a client, updated as it runs commands.
+#[derive(Clone)]
+pub struct ClientMeta {
+    pub name: String,
+    pub db: usize,
+    /// Name of the last command run, lowercase.
+    pub last_cmd: String,
+    pub last_interaction: Instant,
+    pub no_evict: bool,
+    /// Set once the client issued MONITOR.
+    pub monitor: bool,
+    /// Set between MULTI and EXEC or DISCARD.
+    pub multi: bool,
+    /// Set once the client became a replica with PSYNC.
+    pub replica: bool,
+    /// Port the replica listens on, announced with REPLCONF listening-port.
+    pub listening_port: u16,
+    /// Pub/Sub channels and patterns this client is subscribed to.
+    pub channels: BTreeSet<Vec<u8>>,
+    pub patterns: BTreeSet<Vec<u8>>,
+    pub tracking: TrackingOptions,
+}
+
+impl ClientMeta {
+    /// Number of subscriptions, as reported in SUBSCRIBE replies.
+    pub fn subscriptions(&self) -> usize {
+        return self.channels.len() + self.patterns.len();
+    }
+}
+
+impl Client {
+    pub fn new(id: u64, addr: &str, laddr: &str, fd: i32) -> Self {
+        let now: Instant = Instant::now();
+        let (outbox, inbox) = unbounded_channel();
+        return Client {
+            id,
+            addr: addr.to_string(),
+            laddr: laddr.to_string(),
+            fd,
+            created: now,
+            meta: Mutex::new(ClientMeta {
+                name: String::new(),
+                db: 0,
+                last_cmd: String::from("NULL"),
+                last_interaction: now,
+                no_evict: false,
+                monitor: false,
+                multi: false,
+                replica: false,
+                listening_port: 0,
+                channels: BTreeSet::new(),
+                patterns: BTreeSet::new(),
+                tracking: TrackingOptions::default(),
+            }),
+            protocol: AtomicU8::new(RESP2),
+            killed: AtomicBool::new(false),
+            kill_notify: Notify::new(),
+            outbox,
+            inbox: Mutex::new(Some(inbox)),
+            pending: AtomicUsize::new(0),
+        };
+    }
+
+    /// A client that is not registered anywhere, for sessions without a connection.
+    pub fn detached() -> Self {
+        return Client::new(0, "", "", -1);
+    }
+
+    pub fn meta(&self) -> ClientMeta {
+        return self.meta.lock().unwrap().clone();
+    }
+
+    pub fn update<F: FnOnce(&mut ClientMeta)>(&self, f: F) {
+        f(&mut self.meta.lock().unwrap());
+    }
+
+    pub fn protocol(&self) -> u8 {
+        return self.protocol.load(Ordering::Relaxed);
+    }
+
+    pub fn set_protocol(&self, protocol: u8) {
+        self.protocol.store(protocol, Ordering::Relaxed);
+    }
+
+    /// Queues a message to be written to this client outside of any command reply.
+    pub fn send(&self, message: RedisType<'static>) {
+        // counted first, so the connection task never writes a message not counted yet
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        // the receiver is gone once the connection closed, the message is then dropped
+        if self.outbox.send(message).is_err() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Called by the connection task once it wrote a message from the inbox.
+    pub fn written(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Number of messages queued with `send` and not written yet.
+    pub fn pending_messages(&self) -> usize {
+        return self.pending.load(Ordering::SeqCst);
+    }
+
+    /// Hands the receiving end of `send` to the connection task; `None` after the first call.
+    pub fn take_inbox(&self) -> Option<UnboundedReceiver<RedisType<'static>>> {
+        return self.inbox.lock().unwrap().take();
+    }
+
+    /// Asks the connection task to close this client after its current replies.
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::SeqCst);
+        // notify_one keeps a permit, so a kill is not lost if nobody is waiting yet
+        self.kill_notify.notify_one();
+    }
+
+    pub fn is_killed(&self) -> bool {
+        return self.killed.load(Ordering::SeqCst);
+    }
+
+    /// Completes once `kill` has been called.
+    pub async fn killed(&self) {
+        while !self.is_killed() {
+            self.kill_notify.notified().await;
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        return self.created.elapsed();
+    }
+
+    /// The line describing this client in CLIENT LIST and CLIENT INFO.
+    pub fn describe(&self) -> String {
+        let meta: ClientMeta = self.meta();
+        let mut flags: String = String::new();
+        if meta.monitor {
+            flags.push('O');
+        }
+        if meta.subscriptions() > 0 {
+            flags.push('P');
+        }
+        if meta.replica {
+            flags.push('S');
+        }
+        if meta.multi {
+            flags.push('x');
+        }
+        if meta.no_evict {
+            flags.push('e');
+        }
+        if meta.tracking.enabled {
+            flags.push('t');
+        }
+        if meta.tracking.broken_redirect {
+            flags.push('R');
+        }
+        if meta.tracking.bcast {
+            flags.push('B');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+
+        let mut out: String = String::new();
+        let _ = write!(
+            out,
+            "id={} addr={} laddr={} fd={} name={} age={} idle={} flags={} db={} sub={} psub={} cmd={} user=default redir={} resp={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            self.fd,
+            meta.name,
+            self.age().as_secs(),
+            meta.last_interaction.elapsed().as_secs(),
+            flags,
+            meta.db,
+            meta.channels.len(),
+            meta.patterns.len(),
+            meta.last_cmd,
+            match meta.tracking.enabled {
+                true => meta.tracking.redirect as i64,
+                false => -1,
+            },
+            self.protocol()
+        );
+        return out;
+    }
+}
+
+/// An active CLIENT PAUSE.
+#[derive(Clone, Copy)]
+struct Pause {
+    until: Instant,
+    /// Pause every command rather than only writes.
+    all: bool,
+}
+
+/// Every connected client, by id, plus the CLIENT PAUSE state.
+pub struct ClientRegistry {
+    clients: Mutex<BTreeMap<u64, Arc<Client>>>,
+    next_id: AtomicU64,
+    pause: Mutex<Option<Pause>>,
+    unpaused: Notify,
+    /// Clients that issued MONITOR, with their count for a lock-free check.
+    monitors: Mutex<BTreeMap<u64, Arc<Client>>>,
+    monitor_count: AtomicUsize,
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        return ClientRegistry::new();
+    }
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        return ClientRegistry {
+            clients: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            pause: Mutex::new(None),
+            unpaused: Notify::new(),
+            monitors: Mutex::new(BTreeMap::new()),
+            monitor_count: AtomicUsize::new(0),
+        };
+    }
+
+    /// Assigns the next client id and adds the client to the registry.
+    pub fn register(&self, addr: &str, laddr: &str, fd: i32) -> Arc<Client> {
+        let id: u64 = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let client: Arc<Client> = Arc::new(Client::new(id, addr, laddr, fd));
+        self.clients.lock().unwrap().insert(id, Arc::clone(&client));
+        return client;
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.clients.lock().unwrap().remove(&id);
+        let mut monitors = self.monitors.lock().unwrap();
+        if monitors.remove(&id).is_some() {
+            self.monitor_count.store(monitors.len(), Ordering::Relaxed);
+        }
+    }
+
+    /// Makes `client` receive every command processed from now on.
+    pub fn add_monitor(&self, client: &Arc<Client>) {
+        client.update(|meta| meta.monitor = true);
+        let mut monitors = self.monitors.lock().unwrap();
+        monitors.insert(client.id, Arc::clone(client));
+        self.monitor_count.store(monitors.len(), Ordering::Relaxed);
+    }
+
+    pub fn has_monitors(&self) -> bool {
+        return self.monitor_count.load(Ordering::Relaxed) > 0;
+    }
+
+    pub fn monitors(&self) -> Vec<Arc<Client>> {
+        return self.monitors.lock().unwrap().values().cloned().collect();
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Client>> {
+        return self.clients.lock().unwrap().get(&id).cloned();
+    }
+
+    /// Every registered client, in id order.
+    pub fn list(&self) -> Vec<Arc<Client>> {
+        return self.clients.lock().unwrap().values().cloned().collect();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.clients.lock().unwrap().len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Kills clients that sent nothing for longer than `timeout`; used by the cron.
+    /// Subscribers and replicas are expected to sit idle and are left alone.
+    pub fn close_idle(&self, timeout: Duration) -> usize {
+        let mut closed: usize = 0;
+        for client in self.list() {
+            let meta: ClientMeta = client.meta();
+            if meta.last_interaction.elapsed() > timeout
+                && meta.subscriptions() == 0
+                && !meta.replica
+                && !client.is_killed()
+            {
+                client.kill();
+                closed += 1;
+            }
+        }
+        return closed;
+    }
+
+    /// Pauses writes, or every command when `all` is set, for `duration`. An
+    /// existing pause is extended rather than shortened.
+    pub fn pause(&self, duration: Duration, all: bool) {
+        let until: Instant = Instant::now() + duration;
+        let mut pause = self.pause.lock().unwrap();
+        *pause = match *pause {
+            Some(p) if p.until > Instant::now() => Some(Pause {
+                until: p.until.max(until),
+                all: p.all || all,
+            }),
+            _ => Some(Pause { until, all }),
+        };
+    }
+
+    pub fn unpause(&self) {
+        *self.pause.lock().unwrap() = None;
+        self.unpaused.notify_waiters();
+    }
+
+    /// When a pause covering this kind of command is active, the instant it ends.
+    fn paused_until(&self, is_write: bool) -> Option<Instant> {
+        return match *self.pause.lock().unwrap() {
+            Some(p) if p.until > Instant::now() && (p.all || is_write) => Some(p.until),
+            _ => None,
+        };
+    }
+
+    /// Whether writes are currently paused; the cron holds back expiry meanwhile.
+    pub fn is_paused(&self) -> bool {
+        return self.paused_until(true).is_some();
+    }
+
+    /// Waits until no pause applies to a command of the given kind.
+    pub async fn wait_if_paused(&self, is_write: bool) {
+        loop {
+            // register for the wakeup before checking so an UNPAUSE in between is seen
+            let unpaused = self.unpaused.notified();
+            let until: Instant = match self.paused_until(is_write) {
+                Some(u) => u,
+                None => return,
+            };
+            tokio::select! {
+                _ = tokio::time::sleep_until(until.into()) => {}
+                _ = unpaused => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_test() {
+        let registry: ClientRegistry = ClientRegistry::new();
+        let first: Arc<Client> = registry.register("127.0.0.1:5000", "127.0.0.1:6379", 7);
+        let second: Arc<Client> = registry.register("127.0.0.1:5001", "127.0.0.1:6379", 8);
+        assert_eq!((first.id, second.id), (1, 2));
+
+        second.update(|m| {
+            m.name = String::from("worker");
+            m.db = 3;
+            m.last_cmd = String::from("get");
+        });
+        assert_eq!(
+            second.describe(),
+            "id=2 addr=127.0.0.1:5001 laddr=127.0.0.1:6379 fd=8 name=worker age=0 idle=0 flags=N db=3 sub=0 psub=0 cmd=get user=default redir=-1 resp=2"
+        );
+
+        assert!(!registry.has_monitors());
+        registry.add_monitor(&first);
+        assert!(registry.has_monitors());
+        assert!(first.describe().contains(" flags=O "));
+
+        registry.unregister(1);
+        assert!(!registry.has_monitors());
+        assert_eq!(registry.list().len(), 1);
+        assert_eq!(registry.close_idle(Duration::from_secs(60)), 0);
+        assert_eq!(registry.close_idle(Duration::ZERO), 1);
+        assert!(second.is_killed());
+    }
+
+    #[tokio::test]
+    async fn pause_test() {
+        let registry: Arc<ClientRegistry> = Arc::new(ClientRegistry::new());
+        registry.pause(Duration::from_secs(60), false);
+        assert!(registry.is_paused());
+
+        // reads go through a write pause
+        registry.wait_if_paused(false).await;
+
+        let waiter = {
+            let registry: Arc<ClientRegistry> = Arc::clone(&registry);
+            tokio::spawn(async move { registry.wait_if_paused(true).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        registry.unpause();
+        waiter.await.unwrap();
+        assert!(!registry.is_paused());
+    }
+}
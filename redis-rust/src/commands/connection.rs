@@ -0,0 +1,493 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::commands::{
+    parse_db_index, parse_i64, syntax_error, unknown_subcommand, wrong_arity, Context,
+};
+use crate::redis_parser::{RedisType, RESP2, RESP3};
+use crate::tracking::TrackingOptions;
+use crate::Error;
+
+pub fn ping(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    // in subscribed mode the reply has the shape of a Pub/Sub message
+    if ctx.session.client.meta().subscriptions() > 0 && args.len() <= 2 {
+        let message: Vec<u8> = args.get(1).cloned().unwrap_or_default();
+        return Ok(RedisType::Array(Box::new(vec![
+            RedisType::BulkString(String::from("pong")),
+            RedisType::BulkBytes(message),
+        ])));
+    }
+
+    return match args.len() {
+        1 => Ok(RedisType::SimpleString("PONG")),
+        2 => Ok(RedisType::BulkBytes(args[1].clone())),
+        _ => Err(wrong_arity("ping")),
+    };
+}
+
+pub fn echo(_ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return Ok(RedisType::BulkBytes(args[1].clone()));
+}
+
+pub fn select(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    ctx.session.db = parse_db_index(ctx, &args[1])?;
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn client(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+
+    match subcommand.as_str() {
+        "ID" if args.len() == 2 => {
+            return Ok(RedisType::Integer(ctx.session.client.id.to_string()));
+        }
+        "INFO" if args.len() == 2 => {
+            return Ok(RedisType::BulkString(format!(
+                "{}\n",
+                ctx.session.client.describe()
+            )));
+        }
+        "LIST" => return client_list(ctx, args),
+        "SETNAME" if args.len() == 3 => {
+            // names are shown space separated in CLIENT LIST, so keep them to one token
+            let name: &[u8] = &args[2];
+            if name.iter().any(|&c| !(b'!'..=b'~').contains(&c)) {
+                return Err(Error::new(
+                    "ERR Client names cannot contain spaces, newlines or special characters.",
+                ));
+            }
+            let name: String = String::from_utf8_lossy(name).to_string();
+            ctx.session.client.update(|meta| meta.name = name);
+            return Ok(RedisType::SimpleString("OK"));
+        }
+        "GETNAME" if args.len() == 2 => {
+            let name: String = ctx.session.client.meta().name;
+            return match name.is_empty() {
+                true => Ok(RedisType::NullBulk),
+                false => Ok(RedisType::BulkString(name)),
+            };
+        }
+        "KILL" if args.len() >= 3 => return client_kill(ctx, args),
+        "TRACKING" if args.len() >= 3 => return client_tracking(ctx, args),
+        "CACHING" if args.len() == 3 => return client_caching(ctx, args),
+        "GETREDIR" if args.len() == 2 => {
+            let tracking: TrackingOptions = ctx.session.client.meta().tracking;
+            let redirect: i64 = match tracking.enabled {
+                true => tracking.redirect as i64,
+                false => -1,
+            };
+            return Ok(RedisType::Integer(redirect.to_string()));
+        }
+        "TRACKINGINFO" if args.len() == 2 => return client_trackinginfo(ctx),
+        "PAUSE" if args.len() == 3 || args.len() == 4 => {
+            let timeout: i64 = parse_i64(&args[2])
+                .ok()
+                .filter(|t| *t >= 0)
+                .ok_or_else(|| Error::new("ERR timeout is not an integer or out of range"))?;
+            let all: bool = match args.get(3) {
+                None => true,
+                Some(mode) if mode.eq_ignore_ascii_case(b"ALL") => true,
+                Some(mode) if mode.eq_ignore_ascii_case(b"WRITE") => false,
+                Some(_) => return Err(syntax_error()),
+            };
+            ctx.state
+                .clients
+                .pause(Duration::from_millis(timeout as u64), all);
+            return Ok(RedisType::SimpleString("OK"));
+        }
+        "UNPAUSE" if args.len() == 2 => {
+            ctx.state.clients.unpause();
+            return Ok(RedisType::SimpleString("OK"));
+        }
+        "NO-EVICT" if args.len() == 3 => {
+            let no_evict: bool = match String::from_utf8_lossy(&args[2]).to_uppercase().as_str() {
+                "ON" => true,
+                "OFF" => false,
+                _ => return Err(syntax_error()),
+            };
+            ctx.session.client.update(|meta| meta.no_evict = no_evict);
+            return Ok(RedisType::SimpleString("OK"));
+        }
+        "ID" | "INFO" | "SETNAME" | "GETNAME" | "KILL" | "PAUSE" | "UNPAUSE" | "NO-EVICT"
+        | "TRACKING" | "CACHING" | "GETREDIR" | "TRACKINGINFO" => {
+            return Err(wrong_arity(&format!(
+                "client|{}",
+                subcommand.to_lowercase()
+            )));
+        }
+        _ => return Err(unknown_subcommand(args)),
+    }
+}
+
+/// CLIENT LIST [TYPE normal|pubsub] [ID id [id ...]]
+fn client_list(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut clients: Vec<Arc<Client>> = ctx.state.clients.list();
+
+    let mut i: usize = 2;
+    while i < args.len() {
+        let option: String = String::from_utf8_lossy(&args[i]).to_uppercase();
+        match option.as_str() {
+            "TYPE" if i + 1 < args.len() => {
+                let kind: String = String::from_utf8_lossy(&args[i + 1]).to_lowercase();
+                match kind.as_str() {
+                    "normal" => clients.retain(|c| c.meta().subscriptions() == 0),
+                    "pubsub" => clients.retain(|c| c.meta().subscriptions() > 0),
+                    "master" | "replica" | "slave" => clients.clear(),
+                    _ => {
+                        return Err(Error {
+                            message: format!("ERR Unknown client type '{}'", kind),
+                        })
+                    }
+                }
+                i += 2;
+            }
+            "ID" if i + 1 < args.len() => {
+                let mut ids: Vec<u64> = Vec::new();
+                for arg in &args[i + 1..] {
+                    match parse_i64(arg) {
+                        Ok(id) if id > 0 => ids.push(id as u64),
+                        _ => return Err(Error::new("ERR Invalid client ID")),
+                    }
+                }
+                clients.retain(|c| ids.contains(&c.id));
+                i = args.len();
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let mut out: String = String::new();
+    for client in clients {
+        out.push_str(&client.describe());
+        out.push('\n');
+    }
+    return Ok(RedisType::BulkString(out));
+}
+
+/// CLIENT KILL addr:port, or CLIENT KILL followed by filter pairs.
+fn client_kill(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let clients: Vec<Arc<Client>> = ctx.state.clients.list();
+
+    // the old form names a single client and replies OK or an error
+    if args.len() == 3 {
+        let addr: &[u8] = &args[2];
+        return match clients.iter().find(|c| c.addr.as_bytes() == addr) {
+            Some(client) => {
+                client.kill();
+                Ok(RedisType::SimpleString("OK"))
+            }
+            None => Err(Error::new("ERR No such client")),
+        };
+    }
+
+    if !args.len().is_multiple_of(2) {
+        return Err(syntax_error());
+    }
+
+    let mut id: Option<u64> = None;
+    let mut addr: Option<&[u8]> = None;
+    let mut laddr: Option<&[u8]> = None;
+    let mut max_age: Option<u64> = None;
+    let mut skip_me: bool = true;
+    for pair in args[2..].chunks(2) {
+        let (option, value) = (&pair[0], &pair[1]);
+        match String::from_utf8_lossy(option).to_uppercase().as_str() {
+            "ID" => match parse_i64(value) {
+                Ok(n) if n > 0 => id = Some(n as u64),
+                _ => return Err(Error::new("ERR client-id should be greater than 0")),
+            },
+            "ADDR" => addr = Some(value),
+            "LADDR" => laddr = Some(value),
+            // there are no ACL users yet, every connection is the default user
+            "USER" => {
+                if value.as_slice() != b"default" {
+                    return Err(Error {
+                        message: format!("ERR No such user '{}'", String::from_utf8_lossy(value)),
+                    });
+                }
+            }
+            "MAXAGE" => match parse_i64(value) {
+                Ok(n) if n >= 0 => max_age = Some(n as u64),
+                _ => return Err(syntax_error()),
+            },
+            "SKIPME" => {
+                skip_me = match String::from_utf8_lossy(value).to_lowercase().as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(syntax_error()),
+                }
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let mut killed: usize = 0;
+    for client in clients {
+        let matches: bool = id.is_none_or(|id| client.id == id)
+            && addr.is_none_or(|a| client.addr.as_bytes() == a)
+            && laddr.is_none_or(|a| client.laddr.as_bytes() == a)
+            && max_age.is_none_or(|age| client.age().as_secs() >= age)
+            && !(skip_me && client.id == ctx.session.client.id);
+        if matches {
+            client.kill();
+            killed += 1;
+        }
+    }
+    return Ok(RedisType::Integer(killed.to_string()));
+}
+
+/// CLIENT TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST] [OPTIN] [OPTOUT] [NOLOOP]
+fn client_tracking(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let on: bool = match String::from_utf8_lossy(&args[2]).to_uppercase().as_str() {
+        "ON" => true,
+        "OFF" => false,
+        _ => return Err(syntax_error()),
+    };
+
+    let mut options: TrackingOptions = TrackingOptions {
+        enabled: true,
+        ..TrackingOptions::default()
+    };
+    let mut i: usize = 3;
+    while i < args.len() {
+        let option: String = String::from_utf8_lossy(&args[i]).to_uppercase();
+        match option.as_str() {
+            "REDIRECT" if i + 1 < args.len() => {
+                if options.redirect != 0 {
+                    return Err(Error::new(
+                        "ERR A client can only redirect to a single other client",
+                    ));
+                }
+                options.redirect = match parse_i64(&args[i + 1]) {
+                    Ok(id) if id > 0 && ctx.state.clients.get(id as u64).is_some() => id as u64,
+                    Ok(_) => {
+                        return Err(Error::new(
+                            "ERR The client ID you want redirect to does not exist",
+                        ))
+                    }
+                    Err(e) => return Err(e),
+                };
+                i += 1;
+            }
+            "PREFIX" if i + 1 < args.len() => {
+                options.prefixes.push(args[i + 1].clone());
+                i += 1;
+            }
+            "BCAST" => options.bcast = true,
+            "OPTIN" => options.optin = true,
+            "OPTOUT" => options.optout = true,
+            "NOLOOP" => options.noloop = true,
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    let client: Arc<Client> = Arc::clone(&ctx.session.client);
+    if !on {
+        ctx.state.tracking.disable(&client);
+        return Ok(RedisType::SimpleString("OK"));
+    }
+
+    let current: TrackingOptions = client.meta().tracking;
+    if current.enabled && current.bcast != options.bcast {
+        return Err(Error::new("ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode."));
+    }
+    if !options.prefixes.is_empty() && !options.bcast {
+        return Err(Error::new(
+            "ERR PREFIX option requires BCAST mode to be enabled",
+        ));
+    }
+    if options.optin && options.optout {
+        return Err(Error::new("ERR You can't use OPTIN and OPTOUT together"));
+    }
+    if options.bcast && (options.optin || options.optout) {
+        return Err(Error::new(
+            "ERR OPTIN and OPTOUT are not compatible with BCAST",
+        ));
+    }
+    if current.enabled && (current.optin != options.optin || current.optout != options.optout) {
+        return Err(Error::new("ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode."));
+    }
+
+    // prefixes of one client must not cover each other, or a key would be reported twice
+    for (n, prefix) in options.prefixes.iter().enumerate() {
+        let overlap: Option<Vec<u8>> = options.prefixes[..n]
+            .iter()
+            .find(|p| p.starts_with(prefix) || prefix.starts_with(p))
+            .cloned()
+            .or_else(|| ctx.state.tracking.overlapping_prefix(client.id, prefix));
+        if let Some(other) = overlap {
+            return Err(Error {
+                message: format!(
+                    "ERR Prefix '{}' overlaps with an existing prefix '{}'. Prefixes for a single client must not overlap.",
+                    String::from_utf8_lossy(prefix),
+                    String::from_utf8_lossy(&other)
+                ),
+            });
+        }
+    }
+
+    // turning tracking on again adds prefixes rather than replacing them
+    let mut prefixes: Vec<Vec<u8>> = current.prefixes;
+    prefixes.extend(options.prefixes.iter().cloned());
+    ctx.state.tracking.enable(&client, options);
+    client.update(|meta| meta.tracking.prefixes = prefixes);
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+/// CLIENT CACHING YES|NO, applying to the next command of an OPTIN or OPTOUT client.
+fn client_caching(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let tracking: TrackingOptions = ctx.session.client.meta().tracking;
+    if !tracking.enabled || !(tracking.optin || tracking.optout) {
+        return Err(Error::new("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled"));
+    }
+
+    match String::from_utf8_lossy(&args[2]).to_uppercase().as_str() {
+        "YES" if tracking.optin => (),
+        "YES" => {
+            return Err(Error::new(
+                "ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.",
+            ))
+        }
+        "NO" if tracking.optout => (),
+        "NO" => {
+            return Err(Error::new(
+                "ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.",
+            ))
+        }
+        _ => return Err(syntax_error()),
+    }
+    ctx.session
+        .client
+        .update(|meta| meta.tracking.caching = true);
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+fn client_trackinginfo(ctx: &mut Context) -> Result<RedisType<'static>, Error> {
+    let tracking: TrackingOptions = ctx.session.client.meta().tracking;
+
+    let mut flags: Vec<&str> = Vec::new();
+    if !tracking.enabled {
+        flags.push("off");
+    } else {
+        flags.push("on");
+        for (set, flag) in [
+            (tracking.bcast, "bcast"),
+            (tracking.optin, "optin"),
+            (tracking.optout, "optout"),
+            (tracking.caching && tracking.optin, "caching-yes"),
+            (tracking.caching && tracking.optout, "caching-no"),
+            (tracking.noloop, "noloop"),
+            (tracking.broken_redirect, "broken_redirect"),
+        ] {
+            if set {
+                flags.push(flag);
+            }
+        }
+    }
+    let redirect: i64 = match tracking.enabled {
+        true => tracking.redirect as i64,
+        false => -1,
+    };
+
+    return Ok(RedisType::Map(Box::new(vec![
+        (
+            RedisType::BulkString(String::from("flags")),
+            RedisType::Array(Box::new(
+                flags
+                    .into_iter()
+                    .map(|f| RedisType::BulkString(f.to_string()))
+                    .collect(),
+            )),
+        ),
+        (
+            RedisType::BulkString(String::from("redirect")),
+            RedisType::Integer(redirect.to_string()),
+        ),
+        (
+            RedisType::BulkString(String::from("prefixes")),
+            RedisType::Array(Box::new(
+                tracking
+                    .prefixes
+                    .into_iter()
+                    .map(RedisType::BulkBytes)
+                    .collect(),
+            )),
+        ),
+    ])));
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]]
+pub fn hello(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut protocol: u8 = ctx.session.client.protocol();
+    if args.len() > 1 {
+        protocol = match parse_i64(&args[1]) {
+            Ok(v) if v == RESP2 as i64 || v == RESP3 as i64 => v as u8,
+            Ok(_) => return Err(Error::new("NOPROTO unsupported protocol version")),
+            Err(_) => {
+                return Err(Error::new(
+                    "ERR Protocol version is not an integer or out of range",
+                ))
+            }
+        };
+    }
+
+    let mut name: Option<String> = None;
+    let mut i: usize = 2;
+    while i < args.len() {
+        let option: String = String::from_utf8_lossy(&args[i]).to_uppercase();
+        match option.as_str() {
+            // there are no ACL users yet: the default user takes any password
+            "AUTH" if i + 2 < args.len() => {
+                if args[i + 1].as_slice() != b"default" {
+                    return Err(Error::new(
+                        "WRONGPASS invalid username-password pair or user is disabled.",
+                    ));
+                }
+                i += 3;
+            }
+            "SETNAME" if i + 1 < args.len() => {
+                if args[i + 1].iter().any(|&c| !(b'!'..=b'~').contains(&c)) {
+                    return Err(Error::new(
+                        "ERR Client names cannot contain spaces, newlines or special characters.",
+                    ));
+                }
+                name = Some(String::from_utf8_lossy(&args[i + 1]).to_string());
+                i += 2;
+            }
+            _ => {
+                return Err(Error {
+                    message: format!(
+                        "ERR Syntax error in HELLO option '{}'",
+                        String::from_utf8_lossy(&args[i])
+                    ),
+                })
+            }
+        }
+    }
+
+    let client: &Arc<Client> = &ctx.session.client;
+    if let Some(name) = name {
+        client.update(|meta| meta.name = name);
+    }
+    client.set_protocol(protocol);
+
+    let field = |name: &str, value: RedisType<'static>| {
+        return (RedisType::BulkString(name.to_string()), value);
+    };
+    return Ok(RedisType::Map(Box::new(vec![
+        field("server", RedisType::BulkString(String::from("redis"))),
+        field("version", RedisType::BulkString(String::from("7.2.0"))),
+        field("proto", RedisType::Integer(protocol.to_string())),
+        field("id", RedisType::Integer(client.id.to_string())),
+        field("mode", RedisType::BulkString(String::from("standalone"))),
+        field("role", RedisType::BulkString(String::from("master"))),
+        field("modules", RedisType::Array(Box::new(Vec::new()))),
+    ])));
+}
+
+pub fn monitor(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    ctx.state.clients.add_monitor(&ctx.session.client);
+    return Ok(RedisType::SimpleString("OK"));
+}
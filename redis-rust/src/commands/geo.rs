@@ -0,0 +1,432 @@
+use crate::commands::{
+    create_if_missing, lookup_typed, parse_f64, parse_i64, syntax_error, Context,
+};
+use crate::geo::{
+    decode_score, distance, encode_score, geohash_string, search_areas, valid_position, GeoShape,
+};
+use crate::notify::{NOTIFY_GENERIC, NOTIFY_ZSET};
+use crate::redis_parser::RedisType;
+use crate::value::SortedSet;
+use crate::{Database, Error, Value};
+
+/// Meters per unit of a distance argument.
+fn parse_unit(arg: &[u8]) -> Result<f64, Error> {
+    return match arg.to_ascii_lowercase().as_slice() {
+        b"m" => Ok(1.0),
+        b"km" => Ok(1000.0),
+        b"ft" => Ok(0.3048),
+        b"mi" => Ok(1609.34),
+        _ => Err(Error::new(
+            "ERR unsupported unit provided. please use M, KM, FT, MI",
+        )),
+    };
+}
+
+/// Parses a `longitude latitude` pair, refusing positions outside the EPSG:3857 limits.
+fn parse_position(longitude: &[u8], latitude: &[u8]) -> Result<(f64, f64), Error> {
+    let longitude: f64 = parse_f64(longitude)?;
+    let latitude: f64 = parse_f64(latitude)?;
+    if !valid_position(longitude, latitude) {
+        return Err(Error::new(&format!(
+            "ERR invalid longitude,latitude pair {:.6},{:.6}",
+            longitude, latitude
+        )));
+    }
+    return Ok((longitude, latitude));
+}
+
+/// A non-negative float, with the error Redis gives for the argument it stands for.
+fn parse_length(arg: &[u8], name: &str) -> Result<f64, Error> {
+    return parse_f64(arg).map_err(|_| Error::new(&format!("ERR need numeric {}", name)));
+}
+
+/// Formats a coordinate with 17 decimals, dropping trailing zeros, like Redis'
+/// human readable long doubles.
+fn format_coordinate(value: f64) -> String {
+    let formatted: String = format!("{:.17}", value);
+    return formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string();
+}
+
+fn format_distance(meters: f64, unit: f64) -> RedisType<'static> {
+    return RedisType::BulkString(format!("{:.4}", meters / unit));
+}
+
+fn position_reply(score: f64) -> RedisType<'static> {
+    let (longitude, latitude) = decode_score(score);
+    return RedisType::Array(Box::new(vec![
+        RedisType::BulkString(format_coordinate(longitude)),
+        RedisType::BulkString(format_coordinate(latitude)),
+    ]));
+}
+
+/// GEOADD is a ZADD of geohash scores, so it takes the same NX, XX and CH flags.
+pub fn geoadd(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut nx: bool = false;
+    let mut xx: bool = false;
+    let mut ch: bool = false;
+    let mut i: usize = 2;
+    while i < args.len() {
+        match args[i].to_ascii_lowercase().as_slice() {
+            b"nx" => nx = true,
+            b"xx" => xx = true,
+            b"ch" => ch = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let triples: &[Vec<u8>] = &args[i..];
+    if triples.is_empty() || !triples.len().is_multiple_of(3) {
+        return Err(Error::new(
+            "ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ",
+        ));
+    }
+    if nx && xx {
+        return Err(Error::new(
+            "ERR XX and NX options at the same time are not compatible",
+        ));
+    }
+
+    // every position is checked before anything is written
+    let mut members: Vec<(f64, &Vec<u8>)> = Vec::new();
+    for triple in triples.chunks(3) {
+        let (longitude, latitude) = parse_position(&triple[0], &triple[1])?;
+        members.push((encode_score(longitude, latitude), &triple[2]));
+    }
+
+    let key: &[u8] = &args[1];
+    let db: &mut Database = ctx.db(key);
+    if xx && lookup_typed(db, key, "zset")?.is_none() {
+        return Ok(RedisType::Integer(String::from("0")));
+    }
+    create_if_missing(db, key, Value::ZSet(SortedSet::new()))?;
+
+    let mut added: usize = 0;
+    let mut updated: usize = 0;
+    db.modify(key, |value| {
+        if let Value::ZSet(zset) = value {
+            for (score, member) in &members {
+                match zset.score(member) {
+                    None if !xx => {
+                        zset.insert(member, *score);
+                        added += 1;
+                    }
+                    Some(old) if !nx && old != *score => {
+                        zset.insert(member, *score);
+                        updated += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    });
+    if added + updated > 0 {
+        db.notify(NOTIFY_ZSET, "zadd", key);
+    }
+    let reply: usize = if ch { added + updated } else { added };
+    return Ok(RedisType::Integer(reply.to_string()));
+}
+
+/// The stored score of each member, None for members or keys that do not exist.
+fn member_scores(
+    ctx: &mut Context,
+    key: &[u8],
+    members: &[Vec<u8>],
+) -> Result<Vec<Option<f64>>, Error> {
+    return match lookup_typed(ctx.db(key), key, "zset")? {
+        Some(Value::ZSet(zset)) => Ok(members.iter().map(|m| zset.score(m)).collect()),
+        _ => Ok(vec![None; members.len()]),
+    };
+}
+
+pub fn geopos(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let positions: Vec<RedisType> = member_scores(ctx, &args[1], &args[2..])?
+        .into_iter()
+        .map(|score| match score {
+            Some(score) => position_reply(score),
+            None => RedisType::NullArray,
+        })
+        .collect();
+    return Ok(RedisType::Array(Box::new(positions)));
+}
+
+pub fn geohash(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let hashes: Vec<RedisType> = member_scores(ctx, &args[1], &args[2..])?
+        .into_iter()
+        .map(|score| match score {
+            Some(score) => RedisType::BulkString(geohash_string(score)),
+            None => RedisType::NullBulk,
+        })
+        .collect();
+    return Ok(RedisType::Array(Box::new(hashes)));
+}
+
+pub fn geodist(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let unit: f64 = match args.len() {
+        4 => 1.0,
+        5 => parse_unit(&args[4])?,
+        _ => return Err(syntax_error()),
+    };
+
+    let scores: Vec<Option<f64>> = member_scores(ctx, &args[1], &args[2..4])?;
+    return match (scores[0], scores[1]) {
+        (Some(a), Some(b)) => {
+            let (long1, lat1) = decode_score(a);
+            let (long2, lat2) = decode_score(b);
+            Ok(format_distance(distance(long1, lat1, long2, lat2), unit))
+        }
+        _ => Ok(RedisType::NullBulk),
+    };
+}
+
+/// Where a search is centered.
+enum Origin {
+    Member(Vec<u8>),
+    Position(f64, f64),
+}
+
+/// The options of a GEOSEARCH or GEOSEARCHSTORE call.
+struct SearchOptions {
+    origin: Option<Origin>,
+    /// The shape in meters, with the unit its arguments were given in.
+    shape: Option<(GeoShape, f64)>,
+    /// Some(true) for DESC, Some(false) for ASC, None to leave the scan order.
+    desc: Option<bool>,
+    count: usize,
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool,
+}
+
+fn parse_search_options(name: &str, args: &[Vec<u8>], store: bool) -> Result<SearchOptions, Error> {
+    let mut options: SearchOptions = SearchOptions {
+        origin: None,
+        shape: None,
+        desc: None,
+        count: 0,
+        any: false,
+        with_coord: false,
+        with_dist: false,
+        with_hash: false,
+        store_dist: false,
+    };
+    let from_error: Error = Error::new(&format!(
+        "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for {}",
+        name
+    ));
+    let by_error: Error = Error::new(&format!(
+        "ERR exactly one of BYRADIUS and BYBOX can be specified for {}",
+        name
+    ));
+
+    let mut i: usize = 0;
+    while i < args.len() {
+        let option: String = String::from_utf8_lossy(&args[i]).to_uppercase();
+        let remaining: usize = args.len() - i - 1;
+        match option.as_str() {
+            "WITHCOORD" => options.with_coord = true,
+            "WITHDIST" => options.with_dist = true,
+            "WITHHASH" => options.with_hash = true,
+            "STOREDIST" if store => options.store_dist = true,
+            "ASC" => options.desc = Some(false),
+            "DESC" => options.desc = Some(true),
+            "ANY" => options.any = true,
+            "COUNT" if remaining >= 1 => {
+                let count: i64 = parse_i64(&args[i + 1])?;
+                if count <= 0 {
+                    return Err(Error::new("ERR COUNT must be > 0"));
+                }
+                options.count = count as usize;
+                i += 1;
+            }
+            "FROMMEMBER" if remaining >= 1 => {
+                if options.origin.is_some() {
+                    return Err(from_error);
+                }
+                options.origin = Some(Origin::Member(args[i + 1].clone()));
+                i += 1;
+            }
+            "FROMLONLAT" if remaining >= 2 => {
+                if options.origin.is_some() {
+                    return Err(from_error);
+                }
+                let (longitude, latitude) = parse_position(&args[i + 1], &args[i + 2])?;
+                options.origin = Some(Origin::Position(longitude, latitude));
+                i += 2;
+            }
+            "BYRADIUS" if remaining >= 2 => {
+                if options.shape.is_some() {
+                    return Err(by_error);
+                }
+                let radius: f64 = parse_length(&args[i + 1], "radius")?;
+                if radius < 0.0 {
+                    return Err(Error::new("ERR radius cannot be negative"));
+                }
+                let unit: f64 = parse_unit(&args[i + 2])?;
+                options.shape = Some((GeoShape::Radius(radius * unit), unit));
+                i += 2;
+            }
+            "BYBOX" if remaining >= 3 => {
+                if options.shape.is_some() {
+                    return Err(by_error);
+                }
+                let width: f64 = parse_length(&args[i + 1], "width")?;
+                let height: f64 = parse_length(&args[i + 2], "height")?;
+                if width < 0.0 || height < 0.0 {
+                    return Err(Error::new("ERR height or width cannot be negative"));
+                }
+                let unit: f64 = parse_unit(&args[i + 3])?;
+                let shape: GeoShape = GeoShape::Box {
+                    width: width * unit,
+                    height: height * unit,
+                };
+                options.shape = Some((shape, unit));
+                i += 3;
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    if store && (options.with_coord || options.with_dist || options.with_hash) {
+        return Err(Error::new(&format!(
+            "ERR {} is not compatible with WITHDIST, WITHHASH and WITHCOORD options",
+            name
+        )));
+    }
+    if options.origin.is_none() {
+        return Err(from_error);
+    }
+    if options.shape.is_none() {
+        return Err(by_error);
+    }
+    if options.any && options.count == 0 {
+        return Err(Error::new("ERR the ANY argument requires COUNT argument"));
+    }
+    // the N closest members only make sense sorted; ANY takes the first N found instead
+    if options.count > 0 && options.desc.is_none() && !options.any {
+        options.desc = Some(false);
+    }
+    return Ok(options);
+}
+
+/// A member found by a search.
+struct GeoPoint {
+    member: Vec<u8>,
+    score: f64,
+    /// Distance from the center, in meters.
+    dist: f64,
+}
+
+/// Runs the search on the sorted set at `key`. Like Redis it scans the score
+/// ranges of the geohash cells around the center rather than every member.
+fn search(ctx: &mut Context, key: &[u8], options: &SearchOptions) -> Result<Vec<GeoPoint>, Error> {
+    let zset: &SortedSet = match lookup_typed(ctx.db(key), key, "zset")? {
+        Some(Value::ZSet(zset)) => zset,
+        _ => return Ok(Vec::new()),
+    };
+    let center: (f64, f64) = match &options.origin {
+        Some(Origin::Member(member)) => match zset.score(member) {
+            Some(score) => decode_score(score),
+            None => return Err(Error::new("ERR could not decode requested zset member")),
+        },
+        Some(Origin::Position(longitude, latitude)) => (*longitude, *latitude),
+        None => return Ok(Vec::new()),
+    };
+    let shape: GeoShape = match options.shape {
+        Some((shape, _)) => shape,
+        None => return Ok(Vec::new()),
+    };
+
+    let limit: usize = if options.any { options.count } else { 0 };
+    let mut points: Vec<GeoPoint> = Vec::new();
+    'areas: for area in search_areas(&shape, center) {
+        let (min, max) = area.score_range();
+        for (member, score) in zset.range(min, max) {
+            if limit > 0 && points.len() >= limit {
+                break 'areas;
+            }
+            if let Some(dist) = shape.contains(center, decode_score(score)) {
+                points.push(GeoPoint {
+                    member: member.clone(),
+                    score,
+                    dist,
+                });
+            }
+        }
+    }
+
+    if let Some(desc) = options.desc {
+        points.sort_by(|a, b| match desc {
+            true => b.dist.total_cmp(&a.dist),
+            false => a.dist.total_cmp(&b.dist),
+        });
+    }
+    if options.count > 0 {
+        points.truncate(options.count);
+    }
+    return Ok(points);
+}
+
+pub fn geosearch(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    // the key type is checked before the options, like in Redis
+    lookup_typed(ctx.db(&args[1]), &args[1], "zset")?;
+    let options: SearchOptions = parse_search_options("GEOSEARCH", &args[2..], false)?;
+    let unit: f64 = options.shape.map_or(1.0, |(_, unit)| unit);
+
+    let points: Vec<GeoPoint> = search(ctx, &args[1], &options)?;
+    let plain: bool = !(options.with_coord || options.with_dist || options.with_hash);
+    let reply: Vec<RedisType> = points
+        .into_iter()
+        .map(|point| {
+            if plain {
+                return RedisType::BulkBytes(point.member);
+            }
+            let mut item: Vec<RedisType> = vec![RedisType::BulkBytes(point.member)];
+            if options.with_dist {
+                item.push(format_distance(point.dist, unit));
+            }
+            if options.with_hash {
+                item.push(RedisType::Integer((point.score as u64).to_string()));
+            }
+            if options.with_coord {
+                item.push(position_reply(point.score));
+            }
+            return RedisType::Array(Box::new(item));
+        })
+        .collect();
+    return Ok(RedisType::Array(Box::new(reply)));
+}
+
+pub fn geosearchstore(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    lookup_typed(ctx.db(&args[2]), &args[2], "zset")?;
+    let options: SearchOptions = parse_search_options("GEOSEARCHSTORE", &args[3..], true)?;
+    let unit: f64 = options.shape.map_or(1.0, |(_, unit)| unit);
+
+    let points: Vec<GeoPoint> = search(ctx, &args[2], &options)?;
+    let mut result: SortedSet = SortedSet::new();
+    for point in &points {
+        let score: f64 = match options.store_dist {
+            true => point.dist / unit,
+            false => point.score,
+        };
+        result.insert(&point.member, score);
+    }
+
+    let dest: &[u8] = &args[1];
+    let db: &mut Database = ctx.db(dest);
+    if result.is_empty() {
+        if db.remove(dest).is_some() {
+            db.notify(NOTIFY_GENERIC, "del", dest);
+        }
+    } else {
+        db.set(dest, Value::ZSet(result), None);
+        db.notify(NOTIFY_ZSET, "geosearchstore", dest);
+    }
+    return Ok(RedisType::Integer(points.len().to_string()));
+}
@@ -0,0 +1,78 @@
+use crate::commands::{create_if_missing, lookup_typed, wrong_arity, Context};
+use crate::notify::{NOTIFY_GENERIC, NOTIFY_HASH};
+use crate::redis_parser::RedisType;
+use crate::value::{wrong_type_error, EncodingLimits, Hash};
+use crate::{Database, Error, Value};
+
+pub fn hset(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    if !args.len().is_multiple_of(2) {
+        return Err(wrong_arity("hset"));
+    }
+
+    let db: &mut Database = ctx.db(&args[1]);
+    let key: &[u8] = &args[1];
+    create_if_missing(db, key, Value::Hash(Hash::new()))?;
+
+    let limits: EncodingLimits = db.encoding;
+    let mut added: usize = 0;
+    db.modify(key, |value| {
+        if let Value::Hash(hash) = value {
+            for pair in args[2..].chunks(2) {
+                if hash.insert(&pair[0], &pair[1], &limits) {
+                    added += 1;
+                }
+            }
+        }
+    });
+    db.notify(NOTIFY_HASH, "hset", key);
+    return Ok(RedisType::Integer(added.to_string()));
+}
+
+pub fn hget(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return match lookup_typed(ctx.db(&args[1]), &args[1], "hash")? {
+        Some(Value::Hash(hash)) => match hash.get(&args[2]) {
+            Some(value) => Ok(RedisType::BulkBytes(value.to_vec())),
+            None => Ok(RedisType::NullBulk),
+        },
+        _ => Ok(RedisType::NullBulk),
+    };
+}
+
+pub fn hdel(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let db: &mut Database = ctx.db(&args[1]);
+    let key: &[u8] = &args[1];
+    match db.lookup(key) {
+        Some(entry) if entry.value.type_name() != "hash" => return Err(wrong_type_error()),
+        Some(_) => (),
+        None => return Ok(RedisType::Integer(String::from("0"))),
+    }
+
+    let mut removed: usize = 0;
+    db.modify(key, |value| {
+        if let Value::Hash(hash) = value {
+            removed = args[2..].iter().filter(|field| hash.remove(field)).count();
+        }
+    });
+    if removed > 0 {
+        db.notify(NOTIFY_HASH, "hdel", key);
+        // removing the last field deleted the key
+        if db.peek(key).is_none() {
+            db.notify(NOTIFY_GENERIC, "del", key);
+        }
+    }
+    return Ok(RedisType::Integer(removed.to_string()));
+}
+
+pub fn hgetall(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut pairs: Vec<(RedisType, RedisType)> = Vec::new();
+    if let Some(Value::Hash(hash)) = lookup_typed(ctx.db(&args[1]), &args[1], "hash")? {
+        for (field, value) in hash.iter() {
+            pairs.push((
+                RedisType::BulkBytes(field.to_vec()),
+                RedisType::BulkBytes(value.to_vec()),
+            ));
+        }
+    }
+    // RESP2 clients get the pairs flattened into an array
+    return Ok(RedisType::Map(Box::new(pairs)));
+}
@@ -0,0 +1,446 @@
+use crate::commands::{
+    parse_db_index, parse_i64, syntax_error, unknown_subcommand, wrong_arity, Context,
+};
+use crate::db::now_ms;
+use crate::eviction::MaxmemoryPolicy;
+use crate::glob::glob_match;
+use crate::module::Modules;
+use crate::notify::NOTIFY_GENERIC;
+use crate::random::random_below;
+use crate::rdb::{dump_payload, restore_payload};
+use crate::redis_parser::RedisType;
+use crate::{Database, Entry, Error, Value};
+
+pub fn keys(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let db: usize = ctx.session.db;
+    let keys: Vec<RedisType> = ctx
+        .shards
+        .parts_of(db)
+        .flat_map(|part| part.get_keys())
+        .filter(|key| glob_match(&args[1], key, false))
+        .map(RedisType::BulkBytes)
+        .collect();
+
+    return Ok(RedisType::Array(Box::new(keys)));
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]. The cursor holds
+/// the shard being walked in its low digits, in base `SHARDS`, and the
+/// position in that shard's dict above them.
+pub fn scan(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let cursor: u64 = match std::str::from_utf8(&args[1])
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+    {
+        Some(cursor) => cursor,
+        None => return Err(Error::new("ERR invalid cursor")),
+    };
+    let mut pattern: Option<&[u8]> = None;
+    let mut count: usize = 10;
+    let mut type_name: Option<String> = None;
+    let mut i: usize = 2;
+    while i < args.len() {
+        if i + 1 == args.len() {
+            return Err(syntax_error());
+        }
+        match args[i].to_ascii_lowercase().as_slice() {
+            b"match" => pattern = Some(&args[i + 1]),
+            b"count" => {
+                let n: i64 = parse_i64(&args[i + 1])?;
+                if n < 1 {
+                    return Err(syntax_error());
+                }
+                count = n as usize;
+            }
+            b"type" => type_name = Some(String::from_utf8_lossy(&args[i + 1]).to_lowercase()),
+            _ => return Err(syntax_error()),
+        }
+        i += 2;
+    }
+
+    let db: usize = ctx.session.db;
+    let parts: Vec<&mut Database> = ctx.shards.parts_of(db).collect();
+    let shards: u64 = parts.len() as u64;
+    let mut shard: usize = (cursor % shards) as usize;
+    let mut position: usize = (cursor / shards) as usize;
+    // like Redis, COUNT bounds the keys visited rather than those returned
+    let mut visited: usize = 0;
+    let mut keys: Vec<RedisType> = Vec::new();
+    for _ in 0..count.saturating_mul(10) {
+        if shard >= parts.len() {
+            break;
+        }
+        position = parts[shard].scan(position, |key, entry| {
+            visited += 1;
+            if pattern.is_some_and(|p| !glob_match(p, key, false)) {
+                return;
+            }
+            if type_name
+                .as_ref()
+                .is_some_and(|t| t != entry.value.type_name())
+            {
+                return;
+            }
+            keys.push(RedisType::BulkBytes(key.clone()));
+        });
+        if position == 0 {
+            shard += 1;
+        }
+        if visited >= count {
+            break;
+        }
+    }
+
+    let next: u64 = match shard >= parts.len() {
+        true => 0,
+        false => position as u64 * shards + shard as u64,
+    };
+    return Ok(RedisType::Array(Box::new(vec![
+        RedisType::BulkString(next.to_string()),
+        RedisType::Array(Box::new(keys)),
+    ])));
+}
+
+/// Deletes the keys of DEL and UNLINK, handing large values to the lazy free
+/// thread when `lazy`.
+fn delete_keys(
+    ctx: &mut Context,
+    args: &[Vec<u8>],
+    lazy: bool,
+) -> Result<RedisType<'static>, Error> {
+    let mut deleted: usize = 0;
+    for key in &args[1..] {
+        let db: &mut Database = ctx.db(key);
+        // an expired key counts as already gone
+        if db.lookup_notouch(key).is_none() {
+            continue;
+        }
+        let entry: Entry = db.remove(key).unwrap();
+        db.notify(NOTIFY_GENERIC, "del", key);
+        deleted += 1;
+        if lazy {
+            ctx.state.lazyfree.free_value(entry.value);
+        }
+    }
+    return Ok(RedisType::Integer(deleted.to_string()));
+}
+
+pub fn del(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let lazy: bool = ctx.state.config.read().unwrap().lazyfree_lazy_user_del;
+    return delete_keys(ctx, args, lazy);
+}
+
+pub fn unlink(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return delete_keys(ctx, args, true);
+}
+
+pub fn exists(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    // a key named twice is counted twice
+    let found: usize = args[1..]
+        .iter()
+        .filter(|key| ctx.db(key).lookup_notouch(key).is_some())
+        .count();
+    return Ok(RedisType::Integer(found.to_string()));
+}
+
+pub fn expire(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let seconds: i64 = parse_i64(&args[2])?;
+    let now: u64 = now_ms();
+    let expires_at: i64 = match seconds
+        .checked_mul(1000)
+        .and_then(|ms| ms.checked_add(now as i64))
+    {
+        Some(at) => at,
+        None => return Err(Error::new("ERR invalid expire time in 'expire' command")),
+    };
+
+    let key: &[u8] = &args[1];
+    let db: &mut Database = ctx.db(key);
+    if db.lookup_notouch(key).is_none() {
+        return Ok(RedisType::Integer(String::from("0")));
+    }
+    // a time already past deletes the key right away
+    if expires_at <= now as i64 {
+        db.remove(key);
+        db.notify(NOTIFY_GENERIC, "del", key);
+    } else {
+        db.set_expires_at(key, Some(expires_at as u64));
+        db.notify(NOTIFY_GENERIC, "expire", key);
+    }
+    return Ok(RedisType::Integer(String::from("1")));
+}
+
+/// Seconds left before `key` expires, -1 without a TTL and -2 without the key.
+pub fn ttl(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let ttl: i64 = match ctx.db(&args[1]).lookup_notouch(&args[1]) {
+        Some(entry) => match entry.expires_at {
+            Some(at) => (at.saturating_sub(now_ms()) as i64 + 500) / 1000,
+            None => -1,
+        },
+        None => -2,
+    };
+    return Ok(RedisType::Integer(ttl.to_string()));
+}
+
+pub fn move_key(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let src: usize = ctx.session.db;
+    let dst: usize = parse_db_index(ctx, &args[2])?;
+    if src == dst {
+        return Err(Error::new(
+            "ERR source and destination objects are the same",
+        ));
+    }
+
+    let key: &[u8] = &args[1];
+    if ctx.db_at(src, key).lookup(key).is_none() || ctx.db_at(dst, key).lookup(key).is_some() {
+        return Ok(RedisType::Integer(String::from("0")));
+    }
+
+    // the key keeps its TTL in the destination database
+    let entry: Entry = ctx.db_at(src, key).remove(key).unwrap();
+    ctx.db_at(dst, key).set(key, entry.value, entry.expires_at);
+    ctx.db_at(src, key).notify(NOTIFY_GENERIC, "move_from", key);
+    ctx.db_at(dst, key).notify(NOTIFY_GENERIC, "move_to", key);
+    return Ok(RedisType::Integer(String::from("1")));
+}
+
+fn rename_generic(
+    ctx: &mut Context,
+    args: &[Vec<u8>],
+    nx: bool,
+) -> Result<RedisType<'static>, Error> {
+    let (src, dst): (&[u8], &[u8]) = (&args[1], &args[2]);
+    let done = |renamed: bool| match nx {
+        true => RedisType::Integer(String::from(if renamed { "1" } else { "0" })),
+        false => RedisType::SimpleString("OK"),
+    };
+
+    if ctx.db(src).lookup(src).is_none() {
+        return Err(Error::new("ERR no such key"));
+    }
+    if src == dst {
+        return Ok(done(false));
+    }
+    // the two names may live in different shards, both locked for the command
+    if ctx.db(dst).lookup_notouch(dst).is_some() {
+        if nx {
+            return Ok(done(false));
+        }
+        ctx.db(dst).remove(dst);
+    }
+
+    // the value keeps its TTL under the new name
+    let entry: Entry = ctx.db(src).remove(src).unwrap();
+    ctx.db(src).notify(NOTIFY_GENERIC, "rename_from", src);
+    let target: &mut Database = ctx.db(dst);
+    target.set(dst, entry.value, entry.expires_at);
+    target.notify(NOTIFY_GENERIC, "rename_to", dst);
+    return Ok(done(true));
+}
+
+pub fn rename(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return rename_generic(ctx, args, false);
+}
+
+pub fn renamenx(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return rename_generic(ctx, args, true);
+}
+
+pub fn copy(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut dst_db: usize = ctx.session.db;
+    let mut replace: bool = false;
+
+    let mut i: usize = 3;
+    while i < args.len() {
+        let option: String = String::from_utf8_lossy(&args[i]).to_uppercase();
+        match option.as_str() {
+            "REPLACE" => replace = true,
+            "DB" if i + 1 < args.len() => {
+                dst_db = parse_db_index(ctx, &args[i + 1])?;
+                i += 1;
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    let (src, dst): (&[u8], &[u8]) = (&args[1], &args[2]);
+    if src == dst && dst_db == ctx.session.db {
+        return Err(Error::new(
+            "ERR source and destination objects are the same",
+        ));
+    }
+
+    let entry: Entry = match ctx.db(src).lookup(src) {
+        Some(e) => e.clone(),
+        None => return Ok(RedisType::Integer(String::from("0"))),
+    };
+    let target: &mut Database = ctx.db_at(dst_db, dst);
+    if target.lookup_notouch(dst).is_some() {
+        if !replace {
+            return Ok(RedisType::Integer(String::from("0")));
+        }
+        target.remove(dst);
+    }
+
+    target.set(dst, entry.value, entry.expires_at);
+    target.notify(NOTIFY_GENERIC, "copy_to", dst);
+    return Ok(RedisType::Integer(String::from("1")));
+}
+
+pub fn dump(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let modules: &Modules = &ctx.state.modules;
+    return match ctx.db(&args[1]).lookup_read(&args[1]) {
+        Some(entry) => Ok(RedisType::BulkBytes(dump_payload(&entry.value, modules))),
+        None => Ok(RedisType::NullBulk),
+    };
+}
+
+pub fn restore(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut replace: bool = false;
+    let mut absolute_ttl: bool = false;
+    let mut idle_ms: Option<u64> = None;
+    let mut freq: Option<u8> = None;
+
+    let mut i: usize = 4;
+    while i < args.len() {
+        let option: String = String::from_utf8_lossy(&args[i]).to_uppercase();
+        match option.as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absolute_ttl = true,
+            "IDLETIME" if i + 1 < args.len() && freq.is_none() => {
+                let idle: i64 = parse_i64(&args[i + 1])?;
+                if idle < 0 {
+                    return Err(Error::new("ERR Invalid IDLETIME value, must be >= 0"));
+                }
+                idle_ms = Some((idle as u64).saturating_mul(1000));
+                i += 1;
+            }
+            "FREQ" if i + 1 < args.len() && idle_ms.is_none() => {
+                let value: i64 = parse_i64(&args[i + 1])?;
+                if !(0..=255).contains(&value) {
+                    return Err(Error::new(
+                        "ERR Invalid FREQ value, must be >= 0 and <= 255",
+                    ));
+                }
+                freq = Some(value as u8);
+                i += 1;
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+
+    let ttl: i64 = parse_i64(&args[2])?;
+    if ttl < 0 {
+        return Err(Error::new("ERR Invalid TTL value, must be >= 0"));
+    }
+
+    let key: &[u8] = &args[1];
+    let modules: &Modules = &ctx.state.modules;
+    let db: &mut Database = ctx.db(key);
+    let exists: bool = db.lookup_notouch(key).is_some();
+    if exists && !replace {
+        return Err(Error::new("BUSYKEY Target key name already exists."));
+    }
+    let value: Value = restore_payload(&args[3], &db.encoding, modules)?;
+
+    let expires_at: Option<u64> = match (ttl, absolute_ttl) {
+        (0, _) => None,
+        (ttl, true) => Some(ttl as u64),
+        (ttl, false) => Some(now_ms() + ttl as u64),
+    };
+    // a TTL already in the past only removes what the key replaced
+    if expires_at.is_some_and(|at| at <= now_ms()) {
+        if exists {
+            db.remove(key);
+            db.notify(NOTIFY_GENERIC, "del", key);
+        }
+        return Ok(RedisType::SimpleString("OK"));
+    }
+
+    db.set(key, value, expires_at);
+    db.set_access(key, idle_ms, freq);
+    db.notify(NOTIFY_GENERIC, "restore", key);
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn randomkey(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let db: usize = ctx.session.db;
+    let mut parts: Vec<&mut Database> = ctx.shards.parts_of(db).collect();
+
+    // pick a part in proportion to its size, so every key is as likely to come out
+    let total: usize = parts.iter().map(|part| part.len()).sum();
+    if total == 0 {
+        return Ok(RedisType::NullBulk);
+    }
+    let mut pick: usize = random_below(total);
+    for part in parts.iter_mut() {
+        if pick < part.len() {
+            return match part.random_key() {
+                Some(key) => Ok(RedisType::BulkBytes(key)),
+                None => Ok(RedisType::NullBulk),
+            };
+        }
+        pick -= part.len();
+    }
+    return Ok(RedisType::NullBulk);
+}
+
+pub fn touch(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let touched: usize = args[1..]
+        .iter()
+        .filter(|key| ctx.db(key).lookup(key).is_some())
+        .count();
+    return Ok(RedisType::Integer(touched.to_string()));
+}
+
+pub fn type_(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return match ctx.db(&args[1]).lookup_notouch(&args[1]) {
+        Some(entry) => Ok(RedisType::SimpleString(entry.value.type_name())),
+        None => Ok(RedisType::SimpleString("none")),
+    };
+}
+
+pub fn object(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+    if !matches!(
+        subcommand.as_str(),
+        "IDLETIME" | "FREQ" | "REFCOUNT" | "ENCODING"
+    ) {
+        return Err(unknown_subcommand(args));
+    }
+    if args.len() != 3 {
+        return Err(wrong_arity(&format!(
+            "object|{}",
+            subcommand.to_lowercase()
+        )));
+    }
+
+    let policy: MaxmemoryPolicy = ctx.state.config.read().unwrap().maxmemory_policy;
+    let db: &mut Database = ctx.db(&args[2]);
+    let lfu = db.lfu;
+    let entry: &Entry = match db.lookup_notouch(&args[2]) {
+        Some(e) => e,
+        None => return Ok(RedisType::NullBulk),
+    };
+
+    let now: u64 = now_ms();
+    match subcommand.as_str() {
+        // values are never shared between keys here
+        "REFCOUNT" => return Ok(RedisType::Integer(String::from("1"))),
+        "ENCODING" => return Ok(RedisType::BulkString(entry.value.encoding().to_string())),
+        _ => (),
+    }
+    if subcommand == "IDLETIME" {
+        if policy.is_lfu() {
+            return Err(Error::new("ERR An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust."));
+        }
+        return Ok(RedisType::Integer((entry.idle_ms(now) / 1000).to_string()));
+    }
+
+    if !policy.is_lfu() {
+        return Err(Error::new("ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust."));
+    }
+    return Ok(RedisType::Integer(entry.lfu_decayed(&lfu, now).to_string()));
+}
@@ -0,0 +1,110 @@
+use crate::commands::{
+    create_if_missing, index_range, lookup_typed, parse_i64, wrong_arity, Context,
+};
+use crate::notify::{NOTIFY_GENERIC, NOTIFY_LIST};
+use crate::redis_parser::RedisType;
+use crate::value::{wrong_type_error, EncodingLimits, List};
+use crate::{Database, Error, Value};
+
+fn push(ctx: &mut Context, args: &[Vec<u8>], front: bool) -> Result<RedisType<'static>, Error> {
+    let db: &mut Database = ctx.db(&args[1]);
+    let key: &[u8] = &args[1];
+    create_if_missing(db, key, Value::List(List::new()))?;
+
+    let limits: EncodingLimits = db.encoding;
+    let len: Option<usize> = db.modify(key, |value| {
+        if let Value::List(list) = value {
+            for item in &args[2..] {
+                list.push(item, front, &limits);
+            }
+        }
+        return value.len();
+    });
+    db.notify(NOTIFY_LIST, if front { "lpush" } else { "rpush" }, key);
+    return Ok(RedisType::Integer(len.unwrap_or(0).to_string()));
+}
+
+pub fn lpush(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return push(ctx, args, true);
+}
+
+pub fn rpush(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return push(ctx, args, false);
+}
+
+/// LPOP and RPOP: one item as a bulk string, or with a count up to that many
+/// items as an array.
+fn pop(ctx: &mut Context, args: &[Vec<u8>], front: bool) -> Result<RedisType<'static>, Error> {
+    let name: &str = if front { "lpop" } else { "rpop" };
+    let count: Option<usize> = match args.len() {
+        2 => None,
+        3 => match parse_i64(&args[2])? {
+            n if n < 0 => return Err(Error::new("ERR value is out of range, must be positive")),
+            n => Some(n as usize),
+        },
+        _ => return Err(wrong_arity(name)),
+    };
+
+    let db: &mut Database = ctx.db(&args[1]);
+    let key: &[u8] = &args[1];
+    match db.lookup(key) {
+        Some(entry) if entry.value.type_name() != "list" => return Err(wrong_type_error()),
+        Some(_) => (),
+        None if count.is_some() => return Ok(RedisType::NullArray),
+        None => return Ok(RedisType::NullBulk),
+    }
+    if count == Some(0) {
+        return Ok(RedisType::Array(Box::new(Vec::new())));
+    }
+
+    let items: Vec<Vec<u8>> = db
+        .modify(key, |value| match value {
+            Value::List(list) => (0..count.unwrap_or(1))
+                .map_while(|_| list.pop(front))
+                .collect(),
+            _ => Vec::new(),
+        })
+        .unwrap_or_default();
+    db.notify(NOTIFY_LIST, name, key);
+    // popping the last item deleted the key
+    if db.peek(key).is_none() {
+        db.notify(NOTIFY_GENERIC, "del", key);
+    }
+
+    let mut items: Vec<RedisType> = items.into_iter().map(RedisType::BulkBytes).collect();
+    return match count {
+        Some(_) => Ok(RedisType::Array(Box::new(items))),
+        None => Ok(items.pop().unwrap_or(RedisType::NullBulk)),
+    };
+}
+
+pub fn lpop(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return pop(ctx, args, true);
+}
+
+pub fn rpop(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return pop(ctx, args, false);
+}
+
+pub fn llen(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let len: usize = lookup_typed(ctx.db(&args[1]), &args[1], "list")?.map_or(0, |v| v.len());
+    return Ok(RedisType::Integer(len.to_string()));
+}
+
+pub fn lrange(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let start: i64 = parse_i64(&args[2])?;
+    let stop: i64 = parse_i64(&args[3])?;
+
+    let mut items: Vec<RedisType> = Vec::new();
+    if let Some(Value::List(list)) = lookup_typed(ctx.db(&args[1]), &args[1], "list")? {
+        if let Some((from, to)) = index_range(start, stop, list.len()) {
+            items = list
+                .iter()
+                .skip(from)
+                .take(to - from + 1)
+                .map(|item| RedisType::BulkBytes(item.to_vec()))
+                .collect();
+        }
+    }
+    return Ok(RedisType::Array(Box::new(items)));
+}
@@ -0,0 +1,1095 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::client::Client;
+use crate::db::now_ms;
+use crate::eviction::{oom_error, perform_evictions};
+use crate::keyspace::Shards;
+use crate::latency::add_sample_if_needed;
+use crate::lazyfree::free_detached;
+use crate::module::Modules;
+use crate::monitor::feed_monitors;
+use crate::notify::publish_events;
+use crate::redis_parser::{RedisType, RESP2};
+use crate::shutdown::{self, ShutdownOptions};
+use crate::tracking::TrackingOptions;
+use crate::value::wrong_type_error;
+use crate::{Database, Error, ServerState, Session, Value};
+
+mod connection;
+mod geo;
+mod hashes;
+mod keys;
+mod lists;
+mod multi;
+mod pubsub;
+mod replication;
+mod server;
+mod sets;
+mod sort;
+mod strings;
+mod zsets;
+
+pub type Handler = fn(&mut Context, &[Vec<u8>]) -> Result<RedisType<'static>, Error>;
+
+/// An entry in the command table.
+///
+/// `arity` follows Redis: a positive value is the exact number of arguments
+/// (including the command name), a negative value is the minimum.
+pub struct Command {
+    pub name: &'static str,
+    pub arity: i32,
+    /// A combination of the `CMD_*` flags.
+    pub flags: u32,
+    /// Positions of the key arguments as (first, last, step); a negative last
+    /// counts from the end and (0, 0, 0) means the command takes no keys.
+    pub keys: (i32, i32, i32),
+    pub handler: Handler,
+}
+
+/// The command may modify the keyspace.
+pub const CMD_WRITE: u32 = 1 << 0;
+/// The command only reads from the keyspace.
+pub const CMD_READONLY: u32 = 1 << 1;
+/// The command may grow memory usage, so it is refused when over `maxmemory`.
+pub const CMD_DENYOOM: u32 = 1 << 2;
+/// The command administers the server rather than touching data.
+pub const CMD_ADMIN: u32 = 1 << 3;
+/// The command never touches the keyspace, so it runs without locking any shard.
+pub const CMD_NOKEYSPACE: u32 = 1 << 4;
+/// The command may touch keys besides its key arguments, so it locks every shard.
+pub const CMD_KEYSPACE: u32 = 1 << 5;
+
+/// Commands accepted from a client that has active subscriptions.
+const SUBSCRIBED_MODE_COMMANDS: &[&str] = &[
+    "subscribe",
+    "psubscribe",
+    "unsubscribe",
+    "punsubscribe",
+    "ping",
+    "quit",
+    "reset",
+];
+
+/// Commands that run right away inside MULTI instead of being queued.
+const TRANSACTION_COMMANDS: &[&str] = &["discard", "exec", "multi", "watch"];
+
+/// Everything a command handler may touch while it runs.
+pub struct Context<'a> {
+    /// The shards locked for the command: those of its keys, or all of them
+    /// for the commands that work on the whole keyspace.
+    pub shards: &'a mut Shards,
+    pub session: &'a mut Session,
+    pub state: &'a Arc<ServerState>,
+}
+
+impl<'a> Context<'a> {
+    /// The part of the database selected by the client that holds `key`.
+    pub fn db(&mut self, key: &[u8]) -> &mut Database {
+        return self.shards.db(self.session.db, key);
+    }
+
+    /// The part of database `db` that holds `key`.
+    pub fn db_at(&mut self, db: usize, key: &[u8]) -> &mut Database {
+        return self.shards.db(db, key);
+    }
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "bgsave",
+        arity: -1,
+        flags: CMD_ADMIN,
+        keys: (0, 0, 0),
+        handler: server::bgsave,
+    },
+    Command {
+        name: "client",
+        arity: -2,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: connection::client,
+    },
+    Command {
+        name: "config",
+        arity: -2,
+        flags: CMD_ADMIN,
+        keys: (0, 0, 0),
+        handler: server::config,
+    },
+    Command {
+        name: "copy",
+        arity: -3,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 2, 1),
+        handler: keys::copy,
+    },
+    Command {
+        name: "dbsize",
+        arity: 1,
+        flags: CMD_READONLY,
+        keys: (0, 0, 0),
+        handler: server::dbsize,
+    },
+    Command {
+        name: "decr",
+        arity: 2,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: strings::decr,
+    },
+    Command {
+        name: "del",
+        arity: -2,
+        flags: CMD_WRITE,
+        keys: (1, -1, 1),
+        handler: keys::del,
+    },
+    Command {
+        name: "discard",
+        arity: 1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: multi::discard,
+    },
+    Command {
+        name: "dump",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: keys::dump,
+    },
+    Command {
+        name: "echo",
+        arity: 2,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: connection::echo,
+    },
+    Command {
+        name: "exec",
+        arity: 1,
+        flags: 0,
+        keys: (0, 0, 0),
+        handler: multi::exec,
+    },
+    Command {
+        name: "exists",
+        arity: -2,
+        flags: CMD_READONLY,
+        keys: (1, -1, 1),
+        handler: keys::exists,
+    },
+    Command {
+        name: "expire",
+        arity: 3,
+        flags: CMD_WRITE,
+        keys: (1, 1, 1),
+        handler: keys::expire,
+    },
+    Command {
+        name: "flushall",
+        arity: -1,
+        flags: CMD_WRITE,
+        keys: (0, 0, 0),
+        handler: server::flushall,
+    },
+    Command {
+        name: "flushdb",
+        arity: -1,
+        flags: CMD_WRITE,
+        keys: (0, 0, 0),
+        handler: server::flushdb,
+    },
+    Command {
+        name: "geoadd",
+        arity: -5,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: geo::geoadd,
+    },
+    Command {
+        name: "geodist",
+        arity: -4,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: geo::geodist,
+    },
+    Command {
+        name: "geohash",
+        arity: -2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: geo::geohash,
+    },
+    Command {
+        name: "geopos",
+        arity: -2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: geo::geopos,
+    },
+    Command {
+        name: "geosearch",
+        arity: -7,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: geo::geosearch,
+    },
+    Command {
+        name: "geosearchstore",
+        arity: -8,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 2, 1),
+        handler: geo::geosearchstore,
+    },
+    Command {
+        name: "get",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: strings::get,
+    },
+    Command {
+        name: "hdel",
+        arity: -3,
+        flags: CMD_WRITE,
+        keys: (1, 1, 1),
+        handler: hashes::hdel,
+    },
+    Command {
+        name: "hello",
+        arity: -1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: connection::hello,
+    },
+    Command {
+        name: "hget",
+        arity: 3,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: hashes::hget,
+    },
+    Command {
+        name: "hgetall",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: hashes::hgetall,
+    },
+    Command {
+        name: "hset",
+        arity: -4,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: hashes::hset,
+    },
+    Command {
+        name: "incr",
+        arity: 2,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: strings::incr,
+    },
+    Command {
+        name: "incrby",
+        arity: 3,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: strings::incrby,
+    },
+    Command {
+        name: "info",
+        arity: -1,
+        flags: 0,
+        keys: (0, 0, 0),
+        handler: server::info,
+    },
+    Command {
+        name: "keys",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (0, 0, 0),
+        handler: keys::keys,
+    },
+    Command {
+        name: "lastsave",
+        arity: 1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: server::lastsave,
+    },
+    Command {
+        name: "latency",
+        arity: -2,
+        flags: CMD_ADMIN | CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: server::latency,
+    },
+    Command {
+        name: "llen",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: lists::llen,
+    },
+    Command {
+        name: "lpop",
+        arity: -2,
+        flags: CMD_WRITE,
+        keys: (1, 1, 1),
+        handler: lists::lpop,
+    },
+    Command {
+        name: "lpush",
+        arity: -3,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: lists::lpush,
+    },
+    Command {
+        name: "lrange",
+        arity: 4,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: lists::lrange,
+    },
+    Command {
+        name: "memory",
+        arity: -2,
+        flags: CMD_READONLY | CMD_KEYSPACE,
+        keys: (2, 2, 1),
+        handler: server::memory,
+    },
+    Command {
+        name: "module",
+        arity: -2,
+        flags: CMD_ADMIN | CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: server::module,
+    },
+    Command {
+        name: "monitor",
+        arity: 1,
+        flags: CMD_ADMIN | CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: connection::monitor,
+    },
+    Command {
+        name: "move",
+        arity: 3,
+        flags: CMD_WRITE,
+        keys: (1, 1, 1),
+        handler: keys::move_key,
+    },
+    Command {
+        name: "multi",
+        arity: 1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: multi::multi,
+    },
+    Command {
+        name: "object",
+        arity: -2,
+        flags: CMD_READONLY,
+        keys: (2, 2, 1),
+        handler: keys::object,
+    },
+    Command {
+        name: "ping",
+        arity: -1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: connection::ping,
+    },
+    Command {
+        name: "psubscribe",
+        arity: -2,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: pubsub::psubscribe,
+    },
+    Command {
+        name: "psync",
+        arity: 3,
+        flags: CMD_ADMIN | CMD_KEYSPACE,
+        keys: (0, 0, 0),
+        handler: replication::psync,
+    },
+    Command {
+        name: "publish",
+        arity: 3,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: pubsub::publish,
+    },
+    Command {
+        name: "pubsub",
+        arity: -2,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: pubsub::pubsub,
+    },
+    Command {
+        name: "punsubscribe",
+        arity: -1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: pubsub::punsubscribe,
+    },
+    Command {
+        name: "randomkey",
+        arity: 1,
+        flags: CMD_READONLY,
+        keys: (0, 0, 0),
+        handler: keys::randomkey,
+    },
+    Command {
+        name: "replconf",
+        arity: -1,
+        flags: CMD_ADMIN | CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: replication::replconf,
+    },
+    Command {
+        name: "replicaof",
+        arity: 3,
+        flags: CMD_ADMIN | CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: replication::replicaof,
+    },
+    Command {
+        name: "rename",
+        arity: 3,
+        flags: CMD_WRITE,
+        keys: (1, 2, 1),
+        handler: keys::rename,
+    },
+    Command {
+        name: "renamenx",
+        arity: 3,
+        flags: CMD_WRITE,
+        keys: (1, 2, 1),
+        handler: keys::renamenx,
+    },
+    Command {
+        name: "restore",
+        arity: -4,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: keys::restore,
+    },
+    Command {
+        name: "role",
+        arity: 1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: replication::role,
+    },
+    Command {
+        name: "rpop",
+        arity: -2,
+        flags: CMD_WRITE,
+        keys: (1, 1, 1),
+        handler: lists::rpop,
+    },
+    Command {
+        name: "rpush",
+        arity: -3,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: lists::rpush,
+    },
+    Command {
+        name: "sadd",
+        arity: -3,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: sets::sadd,
+    },
+    Command {
+        name: "save",
+        arity: 1,
+        flags: CMD_ADMIN,
+        keys: (0, 0, 0),
+        handler: server::save,
+    },
+    Command {
+        name: "scan",
+        arity: -2,
+        flags: CMD_READONLY,
+        keys: (0, 0, 0),
+        handler: keys::scan,
+    },
+    Command {
+        name: "scard",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: sets::scard,
+    },
+    Command {
+        name: "select",
+        arity: 2,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: connection::select,
+    },
+    Command {
+        name: "set",
+        arity: -3,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: strings::set,
+    },
+    Command {
+        name: "shutdown",
+        arity: -1,
+        flags: CMD_ADMIN,
+        keys: (0, 0, 0),
+        handler: server::shutdown,
+    },
+    Command {
+        name: "slaveof",
+        arity: 3,
+        flags: CMD_ADMIN | CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: replication::replicaof,
+    },
+    Command {
+        name: "slowlog",
+        arity: -2,
+        flags: CMD_ADMIN | CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: server::slowlog,
+    },
+    Command {
+        name: "smembers",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: sets::smembers,
+    },
+    Command {
+        name: "sort",
+        arity: -2,
+        flags: CMD_WRITE | CMD_DENYOOM | CMD_KEYSPACE,
+        keys: (1, 1, 1),
+        handler: sort::sort,
+    },
+    Command {
+        name: "sort_ro",
+        arity: -2,
+        flags: CMD_READONLY | CMD_KEYSPACE,
+        keys: (1, 1, 1),
+        handler: sort::sort_ro,
+    },
+    Command {
+        name: "subscribe",
+        arity: -2,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: pubsub::subscribe,
+    },
+    Command {
+        name: "swapdb",
+        arity: 3,
+        flags: CMD_WRITE,
+        keys: (0, 0, 0),
+        handler: server::swapdb,
+    },
+    Command {
+        name: "touch",
+        arity: -2,
+        flags: CMD_READONLY,
+        keys: (1, -1, 1),
+        handler: keys::touch,
+    },
+    Command {
+        name: "ttl",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: keys::ttl,
+    },
+    Command {
+        name: "type",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: keys::type_,
+    },
+    Command {
+        name: "unlink",
+        arity: -2,
+        flags: CMD_WRITE,
+        keys: (1, -1, 1),
+        handler: keys::unlink,
+    },
+    Command {
+        name: "unsubscribe",
+        arity: -1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: pubsub::unsubscribe,
+    },
+    Command {
+        name: "unwatch",
+        arity: 1,
+        flags: CMD_NOKEYSPACE,
+        keys: (0, 0, 0),
+        handler: multi::unwatch,
+    },
+    Command {
+        name: "watch",
+        arity: -2,
+        flags: 0,
+        keys: (1, -1, 1),
+        handler: multi::watch,
+    },
+    Command {
+        name: "zadd",
+        arity: -4,
+        flags: CMD_WRITE | CMD_DENYOOM,
+        keys: (1, 1, 1),
+        handler: zsets::zadd,
+    },
+    Command {
+        name: "zcard",
+        arity: 2,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: zsets::zcard,
+    },
+    Command {
+        name: "zrange",
+        arity: -4,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: zsets::zrange,
+    },
+    Command {
+        name: "zscore",
+        arity: 3,
+        flags: CMD_READONLY,
+        keys: (1, 1, 1),
+        handler: zsets::zscore,
+    },
+];
+
+/// The key arguments of a call to `command`, following its key positions.
+pub fn command_keys<'a>(command: &Command, args: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+    let (first, last, step) = command.keys;
+    if first == 0 {
+        return Vec::new();
+    }
+    let last: usize = match last < 0 {
+        true => (args.len() as i32 + last).max(0) as usize,
+        false => (last as usize).min(args.len() - 1),
+    };
+    return (first as usize..=last)
+        .step_by(step as usize)
+        .map(|i| args[i].as_slice())
+        .collect();
+}
+
+/// The built-in command named `name`, or else the one a loaded module added.
+pub fn lookup(modules: &Modules, name: &[u8]) -> Option<&'static Command> {
+    return match COMMANDS
+        .iter()
+        .find(|c| c.name.as_bytes().eq_ignore_ascii_case(name))
+    {
+        Some(command) => Some(command),
+        None => modules.command(name),
+    };
+}
+
+/// Looks up the command named by `args[0]` and checks its arity.
+pub fn lookup_checked(modules: &Modules, args: &[Vec<u8>]) -> Result<&'static Command, Error> {
+    let command: &Command = match lookup(modules, &args[0]) {
+        Some(c) => c,
+        None => return Err(unknown_command(args)),
+    };
+
+    let argc: i32 = args.len() as i32;
+    if (command.arity > 0 && argc != command.arity) || argc < -command.arity {
+        return Err(wrong_arity(command.name));
+    }
+
+    return Ok(command);
+}
+
+/// Runs a single command for the client described by `session`.
+pub async fn execute(
+    args: &[Vec<u8>],
+    state: &Arc<ServerState>,
+    session: &mut Session,
+) -> Result<RedisType<'static>, Error> {
+    let command: &Command = match lookup_checked(&state.modules, args) {
+        Ok(c) => c,
+        Err(e) => {
+            // a command that can not even be queued dooms the transaction
+            if session.transaction.is_some() {
+                session.transaction_failed = true;
+            }
+            return Err(e);
+        }
+    };
+
+    // a RESP2 connection in subscribed mode can only manage its subscriptions
+    if session.client.protocol() == RESP2
+        && session.client.meta().subscriptions() > 0
+        && !SUBSCRIBED_MODE_COMMANDS.contains(&command.name)
+    {
+        return Err(Error {
+            message: format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                command.name
+            ),
+        });
+    }
+
+    // a replica only takes writes from its master
+    if command.flags & CMD_WRITE != 0 && state.replication.is_replica() && !session.master_link {
+        if session.transaction.is_some() {
+            session.transaction_failed = true;
+        }
+        return Err(Error::new(
+            "READONLY You can't write against a read only replica.",
+        ));
+    }
+
+    session.client.update(|meta| {
+        meta.last_cmd = command.name.to_string();
+        meta.last_interaction = Instant::now();
+    });
+
+    if let Some(queued) = session.transaction.as_mut() {
+        if !TRANSACTION_COMMANDS.contains(&command.name) {
+            queued.push(args.to_vec());
+            return Ok(RedisType::SimpleString("QUEUED"));
+        }
+    }
+
+    // EXEC behaves as the union of the commands it runs
+    let flags: u32 = match command.name {
+        "exec" => transaction_flags(&state.modules, session),
+        _ => command.flags,
+    };
+
+    // CLIENT PAUSE holds commands back before they get to see the data
+    state.clients.wait_if_paused(flags & CMD_WRITE != 0).await;
+
+    // make room before the command runs; only commands that may add data are refused
+    if !make_room(state).await && flags & CMD_DENYOOM != 0 {
+        return Err(oom_error());
+    }
+
+    // a saving SHUTDOWN waits for a background save before it locks every shard
+    if command.name == "shutdown" && !args.iter().any(|a| a.eq_ignore_ascii_case(b"NOSAVE")) {
+        shutdown::wait_for_bgsave(state).await;
+    }
+
+    let mut shards: Shards = state
+        .keyspace
+        .lock(shards_needed(state, command, args, session))
+        .await;
+    // the final snapshot was taken while this command waited for its shards
+    if state.shutdown.is_started() {
+        return Err(Error::new("ERR Server is shutting down"));
+    }
+    // a SHUTDOWN lets the replicas catch up while it holds every shard
+    if command.name == "shutdown" {
+        let given = |name: &[u8]| args.iter().any(|a| a.eq_ignore_ascii_case(name));
+        let options: ShutdownOptions = ShutdownOptions {
+            now: given(b"NOW"),
+            force: given(b"FORCE"),
+            ..ShutdownOptions::default()
+        };
+        shutdown::wait_for_replicas(state, options).await;
+    }
+    shards.set_recording(state.records_changes());
+
+    let mut ctx: Context = Context {
+        shards: &mut shards,
+        session,
+        state,
+    };
+    let result: Result<RedisType<'static>, Error> = call(&mut ctx, command, args);
+    let db: usize = ctx.session.db;
+    ctx.session.client.update(|meta| meta.db = db);
+
+    // client side caching and WATCH: report what changed, then remember what was read
+    state.signal_changes(&mut shards, session.client.id);
+    if state.tracking.is_active() {
+        track_reads(state, &session.client, command, args, result.is_ok());
+    }
+
+    publish_events(state, &mut shards.parts());
+    free_detached(state, &mut shards.parts());
+    return result;
+}
+
+/// Runs `command` in the shards already locked by `ctx`, feeding the replicas,
+/// MONITOR, the slow log and the latency monitor. EXEC calls it for every
+/// queued command.
+pub fn call(
+    ctx: &mut Context,
+    command: &Command,
+    args: &[Vec<u8>],
+) -> Result<RedisType<'static>, Error> {
+    let started: Instant = Instant::now();
+    let start_db: usize = ctx.session.db;
+    let result: Result<RedisType<'static>, Error> = (command.handler)(ctx, args);
+
+    // the shards are still locked, so replicas get writes to a key in the order they ran
+    if command.flags & CMD_WRITE != 0 && result.is_ok() {
+        ctx.state.replication.propagate(start_db, args);
+    }
+
+    // administrative commands are kept out of MONITOR, as in Redis
+    if command.flags & CMD_ADMIN == 0 {
+        feed_monitors(ctx.state, &ctx.session.client, start_db, started, args);
+    }
+
+    let elapsed: Duration = started.elapsed();
+    record_slow_command(ctx.state, &ctx.session.client, args, elapsed);
+    add_sample_if_needed(ctx.state, "command", elapsed);
+    return result;
+}
+
+/// The shards `command` must lock, `None` meaning all of them. EXEC locks the
+/// shards of every queued command and watched key at once, so the
+/// transaction runs atomically.
+fn shards_needed(
+    state: &ServerState,
+    command: &Command,
+    args: &[Vec<u8>],
+    session: &Session,
+) -> Option<Vec<usize>> {
+    if command.name == "exec" {
+        let mut needed: Vec<usize> = state
+            .watches
+            .watched(session.client.id)
+            .iter()
+            .map(|w| state.keyspace.shard_for(&w.key))
+            .collect();
+        for queued in session.transaction.iter().flatten() {
+            let queued_command: &Command = lookup(&state.modules, &queued[0])?;
+            needed.extend(shards_needed(state, queued_command, queued, session)?);
+        }
+        return Some(needed);
+    }
+    if command.flags & CMD_NOKEYSPACE != 0 {
+        return Some(Vec::new());
+    }
+    if command.flags & CMD_KEYSPACE != 0 || command.keys.0 == 0 {
+        return None;
+    }
+    return Some(
+        command_keys(command, args)
+            .iter()
+            .map(|key| state.keyspace.shard_for(key))
+            .collect(),
+    );
+}
+
+/// The flags of the commands queued by MULTI, combined.
+fn transaction_flags(modules: &Modules, session: &Session) -> u32 {
+    return session
+        .transaction
+        .iter()
+        .flatten()
+        .filter_map(|queued| lookup(modules, &queued[0]))
+        .fold(0, |flags, command| flags | command.flags);
+}
+
+/// Evicts keys until the dataset fits in `maxmemory` again. Returns false
+/// when it still does not. Eviction picks keys anywhere, so every shard is
+/// locked, but only when the limit is actually exceeded.
+async fn make_room(state: &ServerState) -> bool {
+    let maxmemory: usize = state.config.read().unwrap().maxmemory;
+    if maxmemory == 0 || state.keyspace.used_memory() <= maxmemory {
+        return true;
+    }
+
+    let mut shards: Shards = state.keyspace.lock_all().await;
+    shards.set_recording(state.records_changes());
+    let fits: bool = perform_evictions(state, &mut shards.parts());
+    state.signal_changes(&mut shards, 0);
+    publish_events(state, &mut shards.parts());
+    return fits;
+}
+
+/// Records the keys a tracking client read, so it hears when they change.
+fn track_reads(
+    state: &ServerState,
+    client: &Client,
+    command: &Command,
+    args: &[Vec<u8>],
+    succeeded: bool,
+) {
+    let tracking: TrackingOptions = client.meta().tracking;
+    if succeeded && command.flags & CMD_READONLY != 0 && tracking.tracks_reads() {
+        state
+            .tracking
+            .remember_keys(client.id, &command_keys(command, args));
+    }
+
+    // CLIENT CACHING only applies to the command that follows it
+    let is_caching: bool = command.name == "client" && args[1].eq_ignore_ascii_case(b"CACHING");
+    if tracking.caching && !is_caching {
+        client.update(|meta| meta.tracking.caching = false);
+    }
+}
+
+/// Adds the command to the slow log if it ran for at least `slowlog-log-slower-than`.
+fn record_slow_command(state: &ServerState, client: &Client, args: &[Vec<u8>], elapsed: Duration) {
+    let (threshold, max_len) = {
+        let config = state.config.read().unwrap();
+        (config.slowlog_log_slower_than, config.slowlog_max_len)
+    };
+    let duration_us: u64 = elapsed.as_micros() as u64;
+    if threshold < 0 || duration_us < threshold as u64 {
+        return;
+    }
+
+    state.slowlog.lock().unwrap().push(
+        args,
+        now_ms() / 1000,
+        duration_us,
+        &client.addr,
+        &client.meta().name,
+        max_len,
+    );
+}
+
+fn unknown_command(args: &[Vec<u8>]) -> Error {
+    let mut message: String = format!(
+        "ERR unknown command '{}', with args beginning with: ",
+        truncate(&args[0])
+    );
+    for arg in &args[1..] {
+        message.push_str(&format!("'{}' ", truncate(arg)));
+    }
+    return Error { message };
+}
+
+fn truncate(arg: &[u8]) -> String {
+    return String::from_utf8_lossy(&arg[..arg.len().min(128)]).to_string();
+}
+
+pub fn wrong_arity(name: &str) -> Error {
+    return Error {
+        message: format!("ERR wrong number of arguments for '{}' command", name),
+    };
+}
+
+pub fn syntax_error() -> Error {
+    return Error::new("ERR syntax error");
+}
+
+pub fn unknown_subcommand(args: &[Vec<u8>]) -> Error {
+    return Error {
+        message: format!(
+            "ERR unknown subcommand '{}'. Try {} HELP.",
+            truncate(&args[1]),
+            String::from_utf8_lossy(&args[0]).to_uppercase()
+        ),
+    };
+}
+
+pub fn arg_str(arg: &[u8]) -> Result<&str, Error> {
+    return std::str::from_utf8(arg).map_err(|_| syntax_error());
+}
+
+/// Parses a database index argument, checking it against the configured count.
+pub fn parse_db_index(ctx: &Context, arg: &[u8]) -> Result<usize, Error> {
+    let index: i64 = parse_i64(arg)?;
+    if index < 0 || index as usize >= ctx.state.keyspace.databases() {
+        return Err(Error::new("ERR DB index is out of range"));
+    }
+    return Ok(index as usize);
+}
+
+pub fn parse_i64(arg: &[u8]) -> Result<i64, Error> {
+    return match std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+    {
+        Some(n) => Ok(n),
+        None => Err(Error::new("ERR value is not an integer or out of range")),
+    };
+}
+
+/// Parses a score or other floating point argument; NaN is refused.
+pub fn parse_f64(arg: &[u8]) -> Result<f64, Error> {
+    return match std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|f| !f.is_nan())
+    {
+        Some(f) => Ok(f),
+        None => Err(Error::new("ERR value is not a valid float")),
+    };
+}
+
+/// Formats a score the way Redis replies with it.
+pub fn format_double(value: f64) -> String {
+    return match value {
+        f64::INFINITY => String::from("inf"),
+        f64::NEG_INFINITY => String::from("-inf"),
+        _ => value.to_string(),
+    };
+}
+
+/// Resolves the inclusive `start`/`stop` indices of LRANGE and ZRANGE, where
+/// negative values count from the end, into a range of `0..len`.
+pub fn index_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len: i64 = len as i64;
+    let start: i64 = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop: i64 = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return None;
+    }
+    return Some((start as usize, stop as usize));
+}
+
+/// Looks up `key` for a command that reads values of type `kind`, counting the
+/// keyspace hit or miss; WRONGTYPE if the key holds another type.
+pub fn lookup_typed<'d>(
+    db: &'d mut Database,
+    key: &[u8],
+    kind: &str,
+) -> Result<Option<&'d Value>, Error> {
+    return match db.lookup_read(key) {
+        Some(entry) if entry.value.type_name() != kind => Err(wrong_type_error()),
+        Some(entry) => Ok(Some(&entry.value)),
+        None => Ok(None),
+    };
+}
+
+/// Makes sure `key` holds a value of the type of `empty` before a write,
+/// storing `empty` if the key does not exist.
+pub fn create_if_missing(db: &mut Database, key: &[u8], empty: Value) -> Result<(), Error> {
+    match db.lookup(key) {
+        Some(entry) if entry.value.type_name() != empty.type_name() => {
+            return Err(wrong_type_error())
+        }
+        Some(_) => return Ok(()),
+        None => {
+            db.set(key, empty, None);
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::commands::{call, lookup_checked, Context};
+use crate::multi::WatchedKey;
+use crate::redis_parser::RedisType;
+use crate::Error;
+
+pub fn multi(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    if ctx.session.transaction.is_some() {
+        return Err(Error::new("ERR MULTI calls can not be nested"));
+    }
+    ctx.session.transaction = Some(Vec::new());
+    ctx.session.transaction_failed = false;
+    ctx.session.client.update(|meta| meta.multi = true);
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn exec(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let queued: Vec<Vec<Vec<u8>>> = match ctx.session.transaction.take() {
+        Some(queued) => queued,
+        None => return Err(Error::new("ERR EXEC without MULTI")),
+    };
+    ctx.session.client.update(|meta| meta.multi = false);
+    let client_id: u64 = ctx.session.client.id;
+
+    if std::mem::take(&mut ctx.session.transaction_failed) {
+        ctx.state.watches.unwatch_all(client_id);
+        return Err(Error::new(
+            "EXECABORT Transaction discarded because of previous errors.",
+        ));
+    }
+
+    // a watched key that changed, or expired since, aborts the transaction
+    let aborted: bool = ctx.state.watches.is_dirty(client_id)
+        || ctx
+            .state
+            .watches
+            .watched(client_id)
+            .iter()
+            .any(|w| !w.expired && ctx.shards.db(w.db, &w.key).is_expired(&w.key));
+    ctx.state.watches.unwatch_all(client_id);
+    if aborted {
+        return Ok(RedisType::NullArray);
+    }
+
+    let mut replies: Vec<RedisType<'static>> = Vec::with_capacity(queued.len());
+    for args in queued.iter() {
+        let reply: Result<RedisType<'static>, Error> =
+            match lookup_checked(&ctx.state.modules, args) {
+                Ok(command) => call(ctx, command, args),
+                Err(e) => Err(e),
+            };
+        replies.push(match reply {
+            Ok(r) => r,
+            Err(e) => RedisType::Error(e.message),
+        });
+    }
+    return Ok(RedisType::Array(Box::new(replies)));
+}
+
+pub fn discard(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    if ctx.session.transaction.take().is_none() {
+        return Err(Error::new("ERR DISCARD without MULTI"));
+    }
+    ctx.session.transaction_failed = false;
+    ctx.session.client.update(|meta| meta.multi = false);
+    ctx.state.watches.unwatch_all(ctx.session.client.id);
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn watch(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    if ctx.session.transaction.is_some() {
+        return Err(Error::new("ERR WATCH inside MULTI is not allowed"));
+    }
+
+    let db: usize = ctx.session.db;
+    for key in args[1..].iter() {
+        let expired: bool = ctx.db(key).is_expired(key);
+        ctx.state.watches.watch(
+            ctx.session.client.id,
+            WatchedKey {
+                db,
+                key: key.clone(),
+                expired,
+            },
+        );
+    }
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn unwatch(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    ctx.state.watches.unwatch_all(ctx.session.client.id);
+    return Ok(RedisType::SimpleString("OK"));
+}
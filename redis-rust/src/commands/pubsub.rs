@@ -0,0 +1,154 @@
+use crate::client::ClientMeta;
+use crate::commands::{unknown_subcommand, wrong_arity, Context};
+use crate::redis_parser::RedisType;
+use crate::Error;
+
+/// The confirmation sent for each (un)subscribed channel or pattern, a push in RESP3.
+fn subscription_reply(kind: &'static str, name: Option<&[u8]>, count: usize) -> RedisType<'static> {
+    let name: RedisType = match name {
+        Some(n) => RedisType::BulkBytes(n.to_vec()),
+        None => RedisType::NullBulk,
+    };
+    return RedisType::Push(Box::new(vec![
+        RedisType::BulkString(kind.to_string()),
+        name,
+        RedisType::Integer(count.to_string()),
+    ]));
+}
+
+pub fn subscribe(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut replies: Vec<RedisType> = Vec::new();
+    for channel in &args[1..] {
+        let mut count: usize = 0;
+        let mut added: bool = false;
+        ctx.session.client.update(|meta| {
+            added = meta.channels.insert(channel.clone());
+            count = meta.subscriptions();
+        });
+        if added {
+            ctx.state.pubsub.subscribe(&ctx.session.client, channel);
+        }
+        replies.push(subscription_reply("subscribe", Some(channel), count));
+    }
+    return Ok(RedisType::Sequence(replies));
+}
+
+pub fn psubscribe(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut replies: Vec<RedisType> = Vec::new();
+    for pattern in &args[1..] {
+        let mut count: usize = 0;
+        let mut added: bool = false;
+        ctx.session.client.update(|meta| {
+            added = meta.patterns.insert(pattern.clone());
+            count = meta.subscriptions();
+        });
+        if added {
+            ctx.state.pubsub.psubscribe(&ctx.session.client, pattern);
+        }
+        replies.push(subscription_reply("psubscribe", Some(pattern), count));
+    }
+    return Ok(RedisType::Sequence(replies));
+}
+
+pub fn unsubscribe(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let meta: ClientMeta = ctx.session.client.meta();
+
+    // without arguments every channel is dropped
+    let channels: Vec<Vec<u8>> = match args.len() {
+        1 => meta.channels.iter().cloned().collect(),
+        _ => args[1..].to_vec(),
+    };
+    if channels.is_empty() {
+        return Ok(subscription_reply(
+            "unsubscribe",
+            None,
+            meta.subscriptions(),
+        ));
+    }
+
+    let mut replies: Vec<RedisType> = Vec::new();
+    for channel in channels {
+        let mut count: usize = 0;
+        ctx.session.client.update(|meta| {
+            meta.channels.remove(&channel);
+            count = meta.subscriptions();
+        });
+        ctx.state
+            .pubsub
+            .unsubscribe(ctx.session.client.id, &channel);
+        replies.push(subscription_reply("unsubscribe", Some(&channel), count));
+    }
+    return Ok(RedisType::Sequence(replies));
+}
+
+pub fn punsubscribe(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let meta: ClientMeta = ctx.session.client.meta();
+
+    let patterns: Vec<Vec<u8>> = match args.len() {
+        1 => meta.patterns.iter().cloned().collect(),
+        _ => args[1..].to_vec(),
+    };
+    if patterns.is_empty() {
+        return Ok(subscription_reply(
+            "punsubscribe",
+            None,
+            meta.subscriptions(),
+        ));
+    }
+
+    let mut replies: Vec<RedisType> = Vec::new();
+    for pattern in patterns {
+        let mut count: usize = 0;
+        ctx.session.client.update(|meta| {
+            meta.patterns.remove(&pattern);
+            count = meta.subscriptions();
+        });
+        ctx.state
+            .pubsub
+            .punsubscribe(ctx.session.client.id, &pattern);
+        replies.push(subscription_reply("punsubscribe", Some(&pattern), count));
+    }
+    return Ok(RedisType::Sequence(replies));
+}
+
+pub fn publish(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let receivers: usize = ctx.state.pubsub.publish(&args[1], &args[2]);
+    return Ok(RedisType::Integer(receivers.to_string()));
+}
+
+pub fn pubsub(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+
+    match subcommand.as_str() {
+        "CHANNELS" => {
+            if args.len() > 3 {
+                return Err(wrong_arity("pubsub|channels"));
+            }
+            let channels: Vec<RedisType> = ctx
+                .state
+                .pubsub
+                .channels(args.get(2).map(|p| p.as_slice()))
+                .into_iter()
+                .map(RedisType::BulkBytes)
+                .collect();
+            return Ok(RedisType::Array(Box::new(channels)));
+        }
+        "NUMSUB" => {
+            let mut res: Vec<RedisType> = Vec::new();
+            for channel in &args[2..] {
+                res.push(RedisType::BulkBytes(channel.clone()));
+                res.push(RedisType::Integer(
+                    ctx.state.pubsub.numsub(channel).to_string(),
+                ));
+            }
+            return Ok(RedisType::Array(Box::new(res)));
+        }
+        "NUMPAT" => {
+            if args.len() != 2 {
+                return Err(wrong_arity("pubsub|numpat"));
+            }
+            return Ok(RedisType::Integer(ctx.state.pubsub.numpat().to_string()));
+        }
+        _ => return Err(unknown_subcommand(args)),
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use crate::client::Client;
+use crate::commands::{arg_str, syntax_error, Context};
+use crate::rdb;
+use crate::redis_parser::RedisType;
+use crate::replication::{self, MasterAddr, ReplicationStatus};
+use crate::Error;
+
+pub fn replicaof(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let host: &str = arg_str(&args[1])?;
+    let port: &str = arg_str(&args[2])?;
+
+    let master: Option<MasterAddr> =
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            None
+        } else {
+            let port: u16 = match port.parse::<u16>() {
+                Ok(p) => p,
+                Err(_) => return Err(Error::new("ERR Invalid master port")),
+            };
+            Some(MasterAddr::new(host, port))
+        };
+
+    let replicaof: String = match &master {
+        Some(addr) => format!("{} {}", addr.host, addr.port),
+        None => String::new(),
+    };
+    if !replication::replicaof(ctx.state, master.clone()) {
+        return Ok(match master {
+            Some(_) => RedisType::SimpleString("OK Already connected to specified master"),
+            None => RedisType::SimpleString("OK"),
+        });
+    }
+    ctx.state.config.write().unwrap().replicaof = replicaof;
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn role(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let status: ReplicationStatus = ctx.state.replication.status();
+    let reply: Vec<RedisType> = match status.master {
+        Some(master) => vec![
+            RedisType::BulkString(String::from("slave")),
+            RedisType::BulkString(master.host),
+            RedisType::Integer(master.port.to_string()),
+            RedisType::BulkString(status.link_state.name().to_string()),
+            RedisType::Integer(status.offset.to_string()),
+        ],
+        None => {
+            // replicas do not acknowledge what they applied, so they are all
+            // reported at the master's offset
+            let replicas: Vec<RedisType> = status
+                .replicas
+                .into_iter()
+                .map(|(ip, port)| {
+                    RedisType::Array(Box::new(vec![
+                        RedisType::BulkString(ip),
+                        RedisType::BulkString(port.to_string()),
+                        RedisType::BulkString(status.offset.to_string()),
+                    ]))
+                })
+                .collect();
+            vec![
+                RedisType::BulkString(String::from("master")),
+                RedisType::Integer(status.offset.to_string()),
+                RedisType::Array(Box::new(replicas)),
+            ]
+        }
+    };
+    return Ok(RedisType::Array(Box::new(reply)));
+}
+
+pub fn replconf(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    // options come in name value pairs
+    if args.len().is_multiple_of(2) {
+        return Err(syntax_error());
+    }
+
+    for pair in args[1..].chunks(2) {
+        let option: String = String::from_utf8_lossy(&pair[0]).to_lowercase();
+        match option.as_str() {
+            "listening-port" => {
+                let port: u16 = match arg_str(&pair[1])?.parse::<u16>() {
+                    Ok(p) => p,
+                    Err(_) => {
+                        return Err(Error::new("ERR value is not an integer or out of range"))
+                    }
+                };
+                ctx.session.client.update(|meta| meta.listening_port = port);
+            }
+            // capabilities and acknowledgements carry nothing a full resync needs
+            "capa" | "ack" => {}
+            _ => {
+                return Err(Error {
+                    message: format!("ERR Unrecognized REPLCONF option: {}", option),
+                })
+            }
+        }
+    }
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn psync(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    if ctx.session.transaction.is_some() {
+        return Err(Error::new("ERR Command not allowed inside a transaction"));
+    }
+    // a replica can only pass on a dataset it actually received
+    if ctx.state.replication.is_replica()
+        && ctx.state.replication.link_state() != replication::LinkState::Connected
+    {
+        return Err(Error::new(
+            "NOMASTERLINK Can't SYNC while not connected with my master",
+        ));
+    }
+
+    // every shard is locked, so no write gets in between the snapshot and the stream
+    let payload: Vec<u8> = rdb::encode(&ctx.shards.views(), &ctx.state.modules);
+    let client: &Arc<Client> = &ctx.session.client;
+    let port: u16 = client.meta().listening_port;
+    let (replid, offset) = ctx.state.replication.add_replica(client, port);
+    println!(
+        "Replica {} asks for synchronization, starting a full resync",
+        client.addr
+    );
+    // the snapshot follows the +FULLRESYNC reply, ahead of the command stream
+    client.send(RedisType::BulkBytes(payload));
+    return Ok(RedisType::Status(format!(
+        "FULLRESYNC {} {}",
+        replid, offset
+    )));
+}
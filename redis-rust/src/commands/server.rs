@@ -0,0 +1,466 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+use crate::commands::{arg_str, parse_i64, syntax_error, unknown_subcommand, wrong_arity, Context};
+use crate::config::{CONFIG_NAMES, IMMUTABLE_CONFIGS};
+use crate::db::{entry_memory, now_ms};
+use crate::glob::glob_match;
+use crate::info::{generate_info, select_sections};
+use crate::latency::add_sample_if_needed;
+use crate::memory::MemoryStats;
+use crate::rdb;
+use crate::redis_parser::RedisType;
+use crate::shutdown::{self, ShutdownOptions};
+use crate::{Config, Database, Error, ServerState};
+
+pub fn config(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+
+    match subcommand.as_str() {
+        "GET" => {
+            if args.len() < 3 {
+                return Err(wrong_arity("config|get"));
+            }
+
+            let config = ctx.state.config.read().unwrap();
+            let mut names: Vec<&str> = Vec::new();
+            for pattern in &args[2..] {
+                for name in CONFIG_NAMES {
+                    if glob_match(pattern, name.as_bytes(), true) && !names.contains(name) {
+                        names.push(name);
+                    }
+                }
+            }
+
+            let mut res: Vec<RedisType> = Vec::new();
+            for name in names {
+                res.push(RedisType::BulkString(name.to_string()));
+                res.push(RedisType::BulkString(config.get(name).unwrap_or_default()));
+            }
+            return Ok(RedisType::Array(Box::new(res)));
+        }
+        "SET" => {
+            if args.len() < 4 || !args.len().is_multiple_of(2) {
+                return Err(wrong_arity("config|set"));
+            }
+
+            // apply every pair to a copy so a bad value leaves the config untouched
+            let mut config = ctx.state.config.write().unwrap();
+            let mut updated: Config = config.clone();
+            for pair in args[2..].chunks(2) {
+                let name: &str = arg_str(&pair[0])?;
+                if IMMUTABLE_CONFIGS.contains(&name.to_lowercase().as_str()) {
+                    return Err(Error {
+                        message: format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                            name
+                        ),
+                    });
+                }
+                updated.set(name, arg_str(&pair[1])?)?;
+            }
+            *config = updated;
+
+            // the databases keep their own copy of some parameters
+            for db in ctx
+                .shards
+                .parts()
+                .into_iter()
+                .flat_map(|dbs| dbs.iter_mut())
+            {
+                config.apply_to(db);
+            }
+
+            return Ok(RedisType::SimpleString("OK"));
+        }
+        "RESETSTAT" => {
+            if args.len() != 2 {
+                return Err(wrong_arity("config|resetstat"));
+            }
+
+            ctx.state.stats.reset();
+            for db in ctx
+                .shards
+                .parts()
+                .into_iter()
+                .flat_map(|dbs| dbs.iter_mut())
+            {
+                db.stats.hits = 0;
+                db.stats.misses = 0;
+                db.stats.expired = 0;
+            }
+            return Ok(RedisType::SimpleString("OK"));
+        }
+        _ => return Err(unknown_subcommand(args)),
+    }
+}
+
+pub fn dbsize(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let db: usize = ctx.session.db;
+    let len: usize = ctx.shards.parts_of(db).map(|part| part.len()).sum();
+    return Ok(RedisType::Integer(len.to_string()));
+}
+
+/// Parses the optional ASYNC/SYNC flag of FLUSHDB and FLUSHALL; without one
+/// `lazyfree-lazy-user-flush` decides.
+fn flush_is_async(ctx: &Context, args: &[Vec<u8>]) -> Result<bool, Error> {
+    return match args.len() {
+        1 => Ok(ctx.state.config.read().unwrap().lazyfree_lazy_user_flush),
+        2 => match String::from_utf8_lossy(&args[1]).to_uppercase().as_str() {
+            "ASYNC" => Ok(true),
+            "SYNC" => Ok(false),
+            _ => Err(syntax_error()),
+        },
+        _ => Err(syntax_error()),
+    };
+}
+
+/// Drops the flushed contents, on the lazy free thread when ASYNC was requested.
+fn drop_flushed(ctx: &Context, old: Vec<Database>, lazy: bool) {
+    if lazy {
+        ctx.state.lazyfree.free_databases(old);
+    }
+}
+
+pub fn flushdb(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let lazy: bool = flush_is_async(ctx, args)?;
+    let db: usize = ctx.session.db;
+    let old: Vec<Database> = ctx.shards.parts_of(db).map(|part| part.take()).collect();
+    drop_flushed(ctx, old, lazy);
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn flushall(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let lazy: bool = flush_is_async(ctx, args)?;
+    let old: Vec<Database> = ctx
+        .shards
+        .parts()
+        .into_iter()
+        .flat_map(|dbs| dbs.iter_mut().map(|db| db.take()))
+        .collect();
+    drop_flushed(ctx, old, lazy);
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn swapdb(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let first: i64 = parse_i64(&args[1]).map_err(|_| Error::new("ERR invalid first DB index"))?;
+    let second: i64 = parse_i64(&args[2]).map_err(|_| Error::new("ERR invalid second DB index"))?;
+
+    let count: i64 = ctx.state.keyspace.databases() as i64;
+    if first < 0 || first >= count || second < 0 || second >= count {
+        return Err(Error::new("ERR DB index is out of range"));
+    }
+
+    // clients keep their selected index, so they now see the other dataset
+    for dbs in ctx.shards.parts() {
+        dbs.swap(first as usize, second as usize);
+    }
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn save(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    if ctx.state.bgsave_in_progress.load(Ordering::SeqCst) {
+        return Err(Error::new("ERR Background save already in progress"));
+    }
+
+    match rdb::save(
+        &ctx.shards.views(),
+        &ctx.state.modules,
+        &ctx.state.rdb_path(),
+    ) {
+        Ok(fsync) => add_sample_if_needed(ctx.state, "rdb-fsync", fsync),
+        Err(e) => {
+            eprintln!("Error saving DB on disk: {}", e.message);
+            return Err(Error::new("ERR"));
+        }
+    }
+
+    ctx.state.lastsave.store(now_ms() / 1000, Ordering::SeqCst);
+    ctx.state
+        .dirty_at_last_save
+        .store(total_dirty(&ctx.shards.views()), Ordering::SeqCst);
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+pub fn bgsave(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    if args.len() > 2 || (args.len() == 2 && !args[1].eq_ignore_ascii_case(b"SCHEDULE")) {
+        return Err(syntax_error());
+    }
+
+    if ctx.state.bgsave_in_progress.swap(true, Ordering::SeqCst) {
+        return Err(Error::new("ERR Background save already in progress"));
+    }
+
+    // snapshot under the lock, serialize and write without it
+    let snapshot: Vec<Vec<Database>> = ctx.shards.views().into_iter().cloned().collect();
+    let state: Arc<ServerState> = Arc::clone(ctx.state);
+    thread::spawn(move || {
+        let started: u64 = now_ms();
+        let snapshot: Vec<&Vec<Database>> = snapshot.iter().collect();
+        match rdb::save(&snapshot, &state.modules, &state.rdb_path()) {
+            Ok(fsync) => {
+                add_sample_if_needed(&state, "rdb-fsync", fsync);
+                state.lastsave.store(now_ms() / 1000, Ordering::SeqCst);
+                state
+                    .dirty_at_last_save
+                    .store(total_dirty(&snapshot), Ordering::SeqCst);
+                state.last_bgsave_ok.store(true, Ordering::SeqCst);
+            }
+            Err(e) => {
+                eprintln!("Background saving error: {}", e.message);
+                state.last_bgsave_ok.store(false, Ordering::SeqCst);
+            }
+        }
+        state
+            .last_bgsave_time_sec
+            .store(((now_ms() - started) / 1000) as i64, Ordering::SeqCst);
+        state.bgsave_in_progress.store(false, Ordering::SeqCst);
+    });
+
+    return Ok(RedisType::SimpleString("Background saving started"));
+}
+
+pub fn shutdown(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut options: ShutdownOptions = ShutdownOptions::default();
+    let mut abort: bool = false;
+    for arg in &args[1..] {
+        match String::from_utf8_lossy(arg).to_uppercase().as_str() {
+            "NOSAVE" if options.save.is_none() => options.save = Some(false),
+            "SAVE" if options.save.is_none() => options.save = Some(true),
+            "NOW" => options.now = true,
+            "FORCE" => options.force = true,
+            "ABORT" => abort = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    if abort {
+        if args.len() > 2 {
+            return Err(syntax_error());
+        }
+        // the wait for the replicas holds every shard, so no ABORT can run during it
+        return Err(Error::new("ERR No shutdown in progress."));
+    }
+
+    println!("User requested shutdown...");
+    shutdown::prepare(ctx.state, &ctx.shards.views(), options)?;
+    // the connection is closed without a reply
+    return Ok(RedisType::NullBulk);
+}
+
+pub fn lastsave(ctx: &mut Context, _args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return Ok(RedisType::Integer(
+        ctx.state.lastsave.load(Ordering::SeqCst).to_string(),
+    ));
+}
+
+fn total_dirty(shards: &[&Vec<Database>]) -> u64 {
+    return shards
+        .iter()
+        .flat_map(|dbs| dbs.iter())
+        .map(|db| db.stats.dirty)
+        .sum();
+}
+
+pub fn info(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let names: Vec<String> = args[1..]
+        .iter()
+        .map(|a| String::from_utf8_lossy(a).to_string())
+        .collect();
+
+    let sections: Vec<&str> = select_sections(&names);
+    return Ok(RedisType::BulkString(generate_info(
+        ctx.state,
+        &ctx.shards.views(),
+        &sections,
+    )));
+}
+
+pub fn memory(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+
+    match subcommand.as_str() {
+        "USAGE" if args.len() >= 3 => {
+            // every element is counted, so SAMPLES is only checked
+            let mut i: usize = 3;
+            while i < args.len() {
+                if !args[i].eq_ignore_ascii_case(b"SAMPLES") || i + 1 == args.len() {
+                    return Err(syntax_error());
+                }
+                if parse_i64(&args[i + 1])? < 0 {
+                    return Err(syntax_error());
+                }
+                i += 2;
+            }
+
+            let key: &[u8] = &args[2];
+            return match ctx.db(key).lookup_notouch(key) {
+                Some(entry) => Ok(RedisType::Integer(entry_memory(key, entry).to_string())),
+                None => Ok(RedisType::NullBulk),
+            };
+        }
+        "STATS" if args.len() == 2 => {
+            return Ok(MemoryStats::collect(ctx.state, &ctx.shards.views()).to_reply());
+        }
+        "DOCTOR" if args.len() == 2 => {
+            let stats: MemoryStats = MemoryStats::collect(ctx.state, &ctx.shards.views());
+            return Ok(RedisType::BulkString(stats.doctor()));
+        }
+        "MALLOC-STATS" if args.len() == 2 => {
+            return Ok(RedisType::BulkString(String::from(
+                "Stats not supported for the current allocator",
+            )));
+        }
+        "PURGE" if args.len() == 2 => return Ok(RedisType::SimpleString("OK")),
+        "USAGE" | "STATS" | "DOCTOR" | "MALLOC-STATS" | "PURGE" => {
+            return Err(wrong_arity(&format!(
+                "memory|{}",
+                subcommand.to_lowercase()
+            )))
+        }
+        _ => return Err(unknown_subcommand(args)),
+    }
+}
+
+pub fn slowlog(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+    let mut slowlog = ctx.state.slowlog.lock().unwrap();
+
+    match subcommand.as_str() {
+        "GET" if args.len() <= 3 => {
+            // a negative count returns the whole log
+            let count: usize = match args.get(2) {
+                None => 10,
+                Some(arg) => match parse_i64(arg) {
+                    Ok(n) if n < -1 => {
+                        return Err(Error::new(
+                            "ERR count should be greater than or equal to -1",
+                        ))
+                    }
+                    Ok(-1) => usize::MAX,
+                    Ok(n) => n as usize,
+                    Err(e) => return Err(e),
+                },
+            };
+            return Ok(slowlog.get(count));
+        }
+        "LEN" if args.len() == 2 => return Ok(RedisType::Integer(slowlog.len().to_string())),
+        "RESET" if args.len() == 2 => {
+            slowlog.reset();
+            return Ok(RedisType::SimpleString("OK"));
+        }
+        "GET" | "LEN" | "RESET" => {
+            return Err(wrong_arity(&format!(
+                "slowlog|{}",
+                subcommand.to_lowercase()
+            )))
+        }
+        _ => return Err(unknown_subcommand(args)),
+    }
+}
+
+pub fn latency(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+    let mut monitor = ctx.state.latency.lock().unwrap();
+
+    match subcommand.as_str() {
+        "LATEST" if args.len() == 2 => return Ok(monitor.latest()),
+        "HISTORY" if args.len() == 3 => {
+            let samples: Vec<RedisType> = monitor
+                .history(&String::from_utf8_lossy(&args[2]))
+                .into_iter()
+                .map(|s| {
+                    RedisType::Array(Box::new(vec![
+                        RedisType::Integer(s.time.to_string()),
+                        RedisType::Integer(s.latency.to_string()),
+                    ]))
+                })
+                .collect();
+            return Ok(RedisType::Array(Box::new(samples)));
+        }
+        "RESET" => {
+            let events: Vec<String> = args[2..]
+                .iter()
+                .map(|a| String::from_utf8_lossy(a).to_string())
+                .collect();
+            return Ok(RedisType::Integer(monitor.reset(&events).to_string()));
+        }
+        "LATEST" | "HISTORY" => {
+            return Err(wrong_arity(&format!(
+                "latency|{}",
+                subcommand.to_lowercase()
+            )))
+        }
+        _ => return Err(unknown_subcommand(args)),
+    }
+}
+
+pub fn module(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+
+    match subcommand.as_str() {
+        "LIST" if args.len() == 2 => {
+            let modules: Vec<RedisType> = ctx
+                .state
+                .modules
+                .loaded()
+                .into_iter()
+                .map(|m| {
+                    RedisType::Map(Box::new(vec![
+                        (
+                            RedisType::BulkString(String::from("name")),
+                            RedisType::BulkString(m.name),
+                        ),
+                        (
+                            RedisType::BulkString(String::from("ver")),
+                            RedisType::Integer(m.version.to_string()),
+                        ),
+                        (
+                            RedisType::BulkString(String::from("args")),
+                            RedisType::Array(Box::new(
+                                m.args.into_iter().map(RedisType::BulkString).collect(),
+                            )),
+                        ),
+                    ]))
+                })
+                .collect();
+            return Ok(RedisType::Array(Box::new(modules)));
+        }
+        "LOAD" if args.len() >= 3 => {
+            let name: &str = arg_str(&args[2])?;
+            let module_args: Vec<String> = args[3..]
+                .iter()
+                .map(|a| String::from_utf8_lossy(a).to_string())
+                .collect();
+            // the reason goes to the log, as in Redis
+            if let Err(e) = ctx.state.modules.load(name, &module_args) {
+                eprintln!("{}", e.message);
+                return Err(Error::new(
+                    "ERR Error loading the extension. Please check the server logs.",
+                ));
+            }
+            println!("Module '{}' loaded", name);
+            return Ok(RedisType::SimpleString("OK"));
+        }
+        "UNLOAD" if args.len() == 3 => {
+            let name: String = String::from_utf8_lossy(&args[2]).to_string();
+            if !ctx.state.modules.is_loaded(&name) {
+                return Err(Error::new(
+                    "ERR Error unloading module: no such module with that name",
+                ));
+            }
+            // values of its types may still be around, so its code stays
+            return Err(Error::new(
+                "ERR Error unloading module: operation not possible.",
+            ));
+        }
+        "LIST" | "LOAD" | "UNLOAD" => {
+            return Err(wrong_arity(&format!(
+                "module|{}",
+                subcommand.to_lowercase()
+            )))
+        }
+        _ => return Err(unknown_subcommand(args)),
+    }
+}
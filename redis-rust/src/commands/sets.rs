@@ -0,0 +1,43 @@
+use crate::commands::{create_if_missing, lookup_typed, Context};
+use crate::notify::NOTIFY_SET;
+use crate::redis_parser::RedisType;
+use crate::value::{EncodingLimits, Set};
+use crate::{Database, Error, Value};
+
+pub fn sadd(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let db: &mut Database = ctx.db(&args[1]);
+    let key: &[u8] = &args[1];
+    create_if_missing(db, key, Value::Set(Set::new()))?;
+
+    let limits: EncodingLimits = db.encoding;
+    let mut added: usize = 0;
+    db.modify(key, |value| {
+        if let Value::Set(set) = value {
+            for member in &args[2..] {
+                if set.insert(member, &limits) {
+                    added += 1;
+                }
+            }
+        }
+    });
+    if added > 0 {
+        db.notify(NOTIFY_SET, "sadd", key);
+    }
+    return Ok(RedisType::Integer(added.to_string()));
+}
+
+pub fn scard(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let len: usize = lookup_typed(ctx.db(&args[1]), &args[1], "set")?.map_or(0, |v| v.len());
+    return Ok(RedisType::Integer(len.to_string()));
+}
+
+pub fn smembers(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let members: Vec<RedisType> = match lookup_typed(ctx.db(&args[1]), &args[1], "set")? {
+        Some(Value::Set(set)) => set
+            .iter()
+            .map(|m| RedisType::BulkBytes(m.into_owned()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    return Ok(RedisType::Array(Box::new(members)));
+}
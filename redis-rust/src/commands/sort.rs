@@ -0,0 +1,252 @@
+use std::cmp::Ordering;
+
+use crate::commands::{parse_i64, syntax_error, Context};
+use crate::notify::{NOTIFY_GENERIC, NOTIFY_LIST};
+use crate::redis_parser::RedisType;
+use crate::value::{wrong_type_error, List};
+use crate::{Database, Error, Value};
+
+/// The options of a SORT or SORT_RO call.
+struct SortOptions {
+    by: Option<Vec<u8>>,
+    /// BY with a pattern lacking `*` skips sorting altogether.
+    dont_sort: bool,
+    limit: Option<(i64, i64)>,
+    get: Vec<Vec<u8>>,
+    desc: bool,
+    alpha: bool,
+    store: Option<Vec<u8>>,
+}
+
+fn parse_options(args: &[Vec<u8>], read_only: bool) -> Result<SortOptions, Error> {
+    let mut options: SortOptions = SortOptions {
+        by: None,
+        dont_sort: false,
+        limit: None,
+        get: Vec::new(),
+        desc: false,
+        alpha: false,
+        store: None,
+    };
+
+    let mut i: usize = 2;
+    while i < args.len() {
+        let option: String = String::from_utf8_lossy(&args[i]).to_uppercase();
+        let remaining: usize = args.len() - i - 1;
+        match option.as_str() {
+            "ASC" => options.desc = false,
+            "DESC" => options.desc = true,
+            "ALPHA" => options.alpha = true,
+            "LIMIT" if remaining >= 2 => {
+                options.limit = Some((parse_i64(&args[i + 1])?, parse_i64(&args[i + 2])?));
+                i += 2;
+            }
+            "STORE" if remaining >= 1 && !read_only => {
+                options.store = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "BY" if remaining >= 1 => {
+                options.dont_sort = !args[i + 1].contains(&b'*');
+                options.by = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "GET" if remaining >= 1 => {
+                options.get.push(args[i + 1].clone());
+                i += 1;
+            }
+            _ => return Err(syntax_error()),
+        }
+        i += 1;
+    }
+    return Ok(options);
+}
+
+/// Resolves a BY or GET pattern for one element: the first `*` is replaced by
+/// the element, and a trailing `->field` reads a hash field instead of a string.
+fn lookup_by_pattern(ctx: &mut Context, pattern: &[u8], element: &[u8]) -> Option<Vec<u8>> {
+    if pattern == b"#" {
+        return Some(element.to_vec());
+    }
+
+    let star: usize = pattern.iter().position(|&b| b == b'*')?;
+    let arrow: Option<usize> = pattern[star + 1..]
+        .windows(2)
+        .position(|w| w == b"->")
+        .map(|p| star + 1 + p)
+        .filter(|&p| p + 2 < pattern.len());
+    let (key_pattern, field) = match arrow {
+        Some(p) => (&pattern[..p], Some(&pattern[p + 2..])),
+        None => (pattern, None),
+    };
+
+    let mut key: Vec<u8> = key_pattern[..star].to_vec();
+    key.extend_from_slice(element);
+    key.extend_from_slice(&key_pattern[star + 1..]);
+
+    return match (&ctx.db(&key).lookup_read(&key)?.value, field) {
+        (Value::String(s), None) => Some(s.clone()),
+        (Value::Hash(hash), Some(field)) => hash.get(field).map(|v| v.to_vec()),
+        _ => None,
+    };
+}
+
+/// An element with the weight it is sorted by.
+struct SortItem {
+    element: Vec<u8>,
+    score: f64,
+    /// The BY value compared when sorting with ALPHA.
+    alpha_key: Option<Vec<u8>>,
+}
+
+fn parse_score(bytes: &[u8]) -> Option<f64> {
+    return std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.trim().parse::<f64>().ok())
+        .filter(|f| !f.is_nan());
+}
+
+fn compare(a: &SortItem, b: &SortItem, alpha: bool, by: bool) -> Ordering {
+    if !alpha {
+        // equal scores fall back to the elements so the order is deterministic
+        return a
+            .score
+            .total_cmp(&b.score)
+            .then_with(|| a.element.cmp(&b.element));
+    }
+    if by {
+        // a missing BY value sorts first
+        return a.alpha_key.cmp(&b.alpha_key);
+    }
+    return a.element.cmp(&b.element);
+}
+
+fn sort_generic(
+    ctx: &mut Context,
+    args: &[Vec<u8>],
+    read_only: bool,
+) -> Result<RedisType<'static>, Error> {
+    let mut options: SortOptions = parse_options(args, read_only)?;
+
+    let (mut elements, is_zset): (Vec<Vec<u8>>, bool) = match ctx.db(&args[1]).lookup_read(&args[1])
+    {
+        None => (Vec::new(), false),
+        Some(entry) => match &entry.value {
+            Value::List(list) => (list.iter().map(|item| item.to_vec()).collect(), false),
+            Value::Set(set) => {
+                // a set has no order of its own, so a stored result is sorted anyway
+                if options.dont_sort && options.store.is_some() {
+                    options.dont_sort = false;
+                    options.alpha = true;
+                    options.by = None;
+                }
+                (set.iter().map(|m| m.into_owned()).collect(), false)
+            }
+            Value::ZSet(zset) => (zset.iter().map(|(m, _)| m.clone()).collect(), true),
+            _ => return Err(wrong_type_error()),
+        },
+    };
+
+    if options.dont_sort {
+        // without sorting a sorted set keeps its score order, reversed by DESC
+        if is_zset && options.desc {
+            elements.reverse();
+        }
+    } else {
+        let mut items: Vec<SortItem> = Vec::with_capacity(elements.len());
+        for element in elements {
+            let weight: Option<Vec<u8>> = match &options.by {
+                Some(pattern) => lookup_by_pattern(ctx, pattern, &element),
+                None => None,
+            };
+            let mut item: SortItem = SortItem {
+                element,
+                score: 0.0,
+                alpha_key: None,
+            };
+            if options.alpha {
+                item.alpha_key = weight;
+            } else {
+                let source: Option<&[u8]> = match &options.by {
+                    Some(_) => weight.as_deref(),
+                    None => Some(&item.element),
+                };
+                if let Some(bytes) = source {
+                    item.score = parse_score(bytes).ok_or_else(|| {
+                        Error::new("ERR One or more scores can't be converted into double")
+                    })?;
+                }
+            }
+            items.push(item);
+        }
+
+        let by: bool = options.by.is_some();
+        items.sort_by(|a, b| {
+            let ordering: Ordering = compare(a, b, options.alpha, by);
+            return if options.desc {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+        });
+        elements = items.into_iter().map(|item| item.element).collect();
+    }
+
+    // LIMIT clamps like Redis: a negative offset starts at 0, a negative count takes the rest
+    if let Some((offset, count)) = options.limit {
+        let len: i64 = elements.len() as i64;
+        let start: i64 = offset.max(0).min(len);
+        let end: i64 = match count < 0 {
+            true => len,
+            false => start.saturating_add(count).min(len),
+        };
+        elements = elements.drain(start as usize..end as usize).collect();
+    }
+
+    let mut values: Vec<Option<Vec<u8>>> = Vec::new();
+    for element in elements {
+        if options.get.is_empty() {
+            values.push(Some(element));
+            continue;
+        }
+        for pattern in &options.get {
+            values.push(lookup_by_pattern(ctx, pattern, &element));
+        }
+    }
+
+    let dest: Vec<u8> = match options.store {
+        Some(dest) => dest,
+        None => {
+            let reply: Vec<RedisType> = values
+                .into_iter()
+                .map(|v| match v {
+                    Some(bytes) => RedisType::BulkBytes(bytes),
+                    None => RedisType::NullBulk,
+                })
+                .collect();
+            return Ok(RedisType::Array(Box::new(reply)));
+        }
+    };
+
+    let len: usize = values.len();
+    let db: &mut Database = ctx.db(&dest);
+    if len == 0 {
+        if db.remove(&dest).is_some() {
+            db.notify(NOTIFY_GENERIC, "del", &dest);
+        }
+    } else {
+        // missing GET values are stored as empty strings
+        let items = values.into_iter().map(|v| v.unwrap_or_default());
+        let list: List = List::from_items(items, &db.encoding);
+        db.set(&dest, Value::List(list), None);
+        db.notify(NOTIFY_LIST, "sortstore", &dest);
+    }
+    return Ok(RedisType::Integer(len.to_string()));
+}
+
+pub fn sort(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return sort_generic(ctx, args, false);
+}
+
+pub fn sort_ro(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return sort_generic(ctx, args, true);
+}
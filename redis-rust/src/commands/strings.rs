@@ -0,0 +1,88 @@
+use crate::commands::{lookup_typed, parse_i64, syntax_error, Context};
+use crate::db::now_ms;
+use crate::notify::{NOTIFY_GENERIC, NOTIFY_STRING};
+use crate::redis_parser::RedisType;
+use crate::value::wrong_type_error;
+use crate::{Database, Error, Value};
+
+pub fn get(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return match lookup_typed(ctx.db(&args[1]), &args[1], "string")? {
+        Some(Value::String(value)) => Ok(RedisType::BulkBytes(value.clone())),
+        _ => Ok(RedisType::NullBulk),
+    };
+}
+
+pub fn set(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let mut expire_ms: Option<u64> = None;
+
+    let mut i: usize = 3;
+    while i < args.len() {
+        let option: String = String::from_utf8_lossy(&args[i]).to_uppercase();
+        let multiplier: i64 = match option.as_str() {
+            "EX" => 1000,
+            "PX" => 1,
+            _ => return Err(syntax_error()),
+        };
+
+        if expire_ms.is_some() || i + 1 >= args.len() {
+            return Err(syntax_error());
+        }
+
+        let time: i64 = parse_i64(&args[i + 1])?;
+        if time <= 0 || time.checked_mul(multiplier).is_none() {
+            return Err(Error::new("ERR invalid expire time in 'set' command"));
+        }
+        expire_ms = Some((time * multiplier) as u64);
+        i += 2;
+    }
+
+    let expires_at: Option<u64> = expire_ms.map(|ms| now_ms() + ms);
+    let db: &mut Database = ctx.db(&args[1]);
+    db.set(&args[1], args[2].clone(), expires_at);
+    db.notify(NOTIFY_STRING, "set", &args[1]);
+    if expires_at.is_some() {
+        db.notify(NOTIFY_GENERIC, "expire", &args[1]);
+    }
+
+    return Ok(RedisType::SimpleString("OK"));
+}
+
+/// Adds `by` to the integer held by the string at `key`, a missing key
+/// counting as zero. The key keeps its TTL.
+fn incr_by(ctx: &mut Context, key: &[u8], by: i64) -> Result<RedisType<'static>, Error> {
+    let db: &mut Database = ctx.db(key);
+    let current: Option<i64> = match db.lookup(key) {
+        Some(entry) => match &entry.value {
+            Value::String(s) => Some(parse_i64(s)?),
+            _ => return Err(wrong_type_error()),
+        },
+        None => None,
+    };
+    let value: i64 = match current.unwrap_or(0).checked_add(by) {
+        Some(v) => v,
+        None => return Err(Error::new("ERR increment or decrement would overflow")),
+    };
+
+    let bytes: Vec<u8> = value.to_string().into_bytes();
+    match current {
+        Some(_) => {
+            db.modify(key, |v| *v = Value::String(bytes));
+        }
+        None => db.set(key, bytes, None),
+    }
+    db.notify(NOTIFY_STRING, "incrby", key);
+    return Ok(RedisType::Integer(value.to_string()));
+}
+
+pub fn incr(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return incr_by(ctx, &args[1], 1);
+}
+
+pub fn incrby(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let by: i64 = parse_i64(&args[2])?;
+    return incr_by(ctx, &args[1], by);
+}
+
+pub fn decr(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return incr_by(ctx, &args[1], -1);
+}
@@ -0,0 +1,74 @@
+use crate::commands::{
+    create_if_missing, format_double, index_range, lookup_typed, parse_f64, parse_i64,
+    syntax_error, Context,
+};
+use crate::notify::NOTIFY_ZSET;
+use crate::redis_parser::RedisType;
+use crate::value::SortedSet;
+use crate::{Database, Error, Value};
+
+pub fn zadd(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    if !args.len().is_multiple_of(2) {
+        return Err(syntax_error());
+    }
+    // every score is checked before anything is written
+    let mut pairs: Vec<(f64, &Vec<u8>)> = Vec::new();
+    for pair in args[2..].chunks(2) {
+        pairs.push((parse_f64(&pair[0])?, &pair[1]));
+    }
+
+    let db: &mut Database = ctx.db(&args[1]);
+    let key: &[u8] = &args[1];
+    create_if_missing(db, key, Value::ZSet(SortedSet::new()))?;
+
+    let mut added: usize = 0;
+    db.modify(key, |value| {
+        if let Value::ZSet(zset) = value {
+            for (score, member) in &pairs {
+                if zset.insert(member, *score) {
+                    added += 1;
+                }
+            }
+        }
+    });
+    db.notify(NOTIFY_ZSET, "zadd", key);
+    return Ok(RedisType::Integer(added.to_string()));
+}
+
+pub fn zcard(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let len: usize = lookup_typed(ctx.db(&args[1]), &args[1], "zset")?.map_or(0, |v| v.len());
+    return Ok(RedisType::Integer(len.to_string()));
+}
+
+pub fn zscore(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    return match lookup_typed(ctx.db(&args[1]), &args[1], "zset")? {
+        Some(Value::ZSet(zset)) => match zset.score(&args[2]) {
+            Some(score) => Ok(RedisType::BulkString(format_double(score))),
+            None => Ok(RedisType::NullBulk),
+        },
+        _ => Ok(RedisType::NullBulk),
+    };
+}
+
+pub fn zrange(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let start: i64 = parse_i64(&args[2])?;
+    let stop: i64 = parse_i64(&args[3])?;
+    let with_scores: bool = match args.len() {
+        4 => false,
+        5 if args[4].eq_ignore_ascii_case(b"WITHSCORES") => true,
+        _ => return Err(syntax_error()),
+    };
+
+    let mut items: Vec<RedisType> = Vec::new();
+    if let Some(Value::ZSet(zset)) = lookup_typed(ctx.db(&args[1]), &args[1], "zset")? {
+        if let Some((from, to)) = index_range(start, stop, zset.len()) {
+            for (member, score) in zset.iter().skip(from).take(to - from + 1) {
+                items.push(RedisType::BulkBytes(member.clone()));
+                if with_scores {
+                    items.push(RedisType::BulkString(format_double(score)));
+                }
+            }
+        }
+    }
+    return Ok(RedisType::Array(Box::new(items)));
+}
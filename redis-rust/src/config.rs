@@ -0,0 +1,575 @@
+use crate::db::LfuSettings;
+use crate::eviction::MaxmemoryPolicy;
+use crate::lazyfree::LazyfreeSettings;
+use crate::notify::{flags_to_string, parse_flags};
+use crate::replication::MasterAddr;
+use crate::tls::TlsAuthClients;
+use crate::value::EncodingLimits;
+use crate::{Database, Error};
+
+/// Server configuration, settable from the command line (`--name value`) and at
+/// runtime through CONFIG SET.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub dir: String,
+    pub dbfilename: String,
+    pub proto_max_bulk_len: usize,
+    pub client_query_buffer_limit: usize,
+    pub databases: usize,
+    /// Dataset size limit in bytes; 0 means unlimited.
+    pub maxmemory: usize,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    pub maxmemory_samples: usize,
+    pub lfu_log_factor: u32,
+    pub lfu_decay_time: u64,
+    /// Size limits of the compact encodings of small collections.
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+    /// Entries per list node when positive, or -1 to -5 for 4 to 64 kb nodes.
+    pub list_max_listpack_size: i64,
+    /// Which deletions hand large values to the background thread, see `lazyfree`.
+    pub lazyfree_lazy_eviction: bool,
+    pub lazyfree_lazy_expire: bool,
+    pub lazyfree_lazy_server_del: bool,
+    pub lazyfree_lazy_user_del: bool,
+    pub lazyfree_lazy_user_flush: bool,
+    /// Whether the maintenance task helps databases that grow or shrink
+    /// move their keys to the new table, see `Dict`.
+    pub activerehashing: bool,
+    /// Seconds after which an idle client is closed; 0 disables the timeout.
+    pub timeout: u64,
+    /// Enabled keyspace notification classes, see `notify`.
+    pub notify_keyspace_events: u32,
+    /// Commands running at least this many microseconds are logged; negative disables the log.
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: usize,
+    /// Spikes of at least this many milliseconds are sampled; 0 disables the monitor.
+    pub latency_monitor_threshold: u64,
+    /// TCP port the server listens on; 0 disables TCP.
+    pub port: u16,
+    /// Addresses the TCP port is bound on, IPv4 or IPv6.
+    pub bind: Vec<String>,
+    /// Path of a Unix domain socket to listen on as well; empty for none.
+    pub unixsocket: String,
+    /// Permission bits of the Unix socket file; 0 leaves the umask default.
+    pub unixsocketperm: u32,
+    /// TCP port of the TLS listener, on the same addresses as `port`; 0 disables TLS.
+    pub tls_port: u16,
+    /// PEM files with the server certificate chain and its private key.
+    pub tls_cert_file: String,
+    pub tls_key_file: String,
+    /// PEM file with the CA certificates client certificates are checked against.
+    pub tls_ca_cert_file: String,
+    pub tls_auth_clients: TlsAuthClients,
+    /// TCP port of the HTTP listener serving Prometheus metrics at `/metrics`,
+    /// on the same addresses as `port`; 0 disables it.
+    pub metrics_port: u16,
+    /// File the process id is written to while the server runs; empty for none.
+    pub pidfile: String,
+    /// `host port` of the master to replicate at startup; empty for a master.
+    pub replicaof: String,
+    /// Preference of Sentinel when promoting a replica, lower first; 0 never.
+    pub replica_priority: u64,
+    /// `name [args]` of each module loaded at startup, see `module`. Every
+    /// `loadmodule` adds one, as in redis.conf.
+    pub loadmodule: Vec<String>,
+}
+
+/// Every parameter name understood by `Config::get` and `Config::set`.
+pub const CONFIG_NAMES: &[&str] = &[
+    "dir",
+    "dbfilename",
+    "proto-max-bulk-len",
+    "client-query-buffer-limit",
+    "databases",
+    "maxmemory",
+    "maxmemory-policy",
+    "maxmemory-samples",
+    "lfu-log-factor",
+    "lfu-decay-time",
+    "hash-max-listpack-entries",
+    "hash-max-listpack-value",
+    "set-max-intset-entries",
+    "set-max-listpack-entries",
+    "set-max-listpack-value",
+    "list-max-listpack-size",
+    "lazyfree-lazy-eviction",
+    "lazyfree-lazy-expire",
+    "lazyfree-lazy-server-del",
+    "lazyfree-lazy-user-del",
+    "lazyfree-lazy-user-flush",
+    "activerehashing",
+    "timeout",
+    "notify-keyspace-events",
+    "slowlog-log-slower-than",
+    "slowlog-max-len",
+    "latency-monitor-threshold",
+    "port",
+    "bind",
+    "unixsocket",
+    "unixsocketperm",
+    "tls-port",
+    "tls-cert-file",
+    "tls-key-file",
+    "tls-ca-cert-file",
+    "tls-auth-clients",
+    "metrics-port",
+    "pidfile",
+    "replicaof",
+    "replica-priority",
+];
+
+/// Parameters that can only be given at startup.
+pub const IMMUTABLE_CONFIGS: &[&str] = &[
+    "databases",
+    "port",
+    "bind",
+    "unixsocket",
+    "unixsocketperm",
+    "tls-port",
+    "tls-cert-file",
+    "tls-key-file",
+    "tls-ca-cert-file",
+    "tls-auth-clients",
+    "metrics-port",
+    "pidfile",
+    "replicaof",
+    "loadmodule",
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        let dir: String = match std::env::current_dir() {
+            Ok(d) => d.to_string_lossy().to_string(),
+            Err(_) => String::from("."),
+        };
+
+        return Config {
+            dir,
+            dbfilename: String::from("dump.rdb"),
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            databases: 16,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+            lazyfree_lazy_eviction: false,
+            lazyfree_lazy_expire: false,
+            lazyfree_lazy_server_del: false,
+            lazyfree_lazy_user_del: false,
+            lazyfree_lazy_user_flush: false,
+            activerehashing: true,
+            timeout: 0,
+            notify_keyspace_events: 0,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            latency_monitor_threshold: 0,
+            port: 6379,
+            bind: vec![String::from("127.0.0.1")],
+            unixsocket: String::new(),
+            unixsocketperm: 0,
+            tls_port: 0,
+            tls_cert_file: String::new(),
+            tls_key_file: String::new(),
+            tls_ca_cert_file: String::new(),
+            tls_auth_clients: TlsAuthClients::Yes,
+            metrics_port: 0,
+            pidfile: String::new(),
+            replicaof: String::new(),
+            replica_priority: 100,
+            loadmodule: Vec::new(),
+        };
+    }
+}
+
+impl Config {
+    /// Builds a config from `--name value` pairs, as passed to the server binary.
+    pub fn from_args(args: &[String]) -> Result<Self, Error> {
+        let mut config: Config = Config::default();
+
+        let mut i: usize = 0;
+        while i < args.len() {
+            let name: &str = match args[i].strip_prefix("--") {
+                Some(n) => n,
+                None => {
+                    return Err(Error {
+                        message: format!("Unexpected argument '{}'", args[i]),
+                    })
+                }
+            };
+
+            let value: &String = match args.get(i + 1) {
+                Some(v) => v,
+                None => {
+                    return Err(Error {
+                        message: format!("Missing value for '--{}'", name),
+                    })
+                }
+            };
+
+            config.set(name, value)?;
+            i += 2;
+        }
+
+        return Ok(config);
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        return match name.to_lowercase().as_str() {
+            "dir" => Some(self.dir.clone()),
+            "dbfilename" => Some(self.dbfilename.clone()),
+            "proto-max-bulk-len" => Some(self.proto_max_bulk_len.to_string()),
+            "client-query-buffer-limit" => Some(self.client_query_buffer_limit.to_string()),
+            "databases" => Some(self.databases.to_string()),
+            "maxmemory" => Some(self.maxmemory.to_string()),
+            "maxmemory-policy" => Some(self.maxmemory_policy.name().to_string()),
+            "maxmemory-samples" => Some(self.maxmemory_samples.to_string()),
+            "lfu-log-factor" => Some(self.lfu_log_factor.to_string()),
+            "lfu-decay-time" => Some(self.lfu_decay_time.to_string()),
+            "hash-max-listpack-entries" => Some(self.hash_max_listpack_entries.to_string()),
+            "hash-max-listpack-value" => Some(self.hash_max_listpack_value.to_string()),
+            "set-max-intset-entries" => Some(self.set_max_intset_entries.to_string()),
+            "set-max-listpack-entries" => Some(self.set_max_listpack_entries.to_string()),
+            "set-max-listpack-value" => Some(self.set_max_listpack_value.to_string()),
+            "list-max-listpack-size" => Some(self.list_max_listpack_size.to_string()),
+            "lazyfree-lazy-eviction" => Some(yes_no(self.lazyfree_lazy_eviction)),
+            "lazyfree-lazy-expire" => Some(yes_no(self.lazyfree_lazy_expire)),
+            "lazyfree-lazy-server-del" => Some(yes_no(self.lazyfree_lazy_server_del)),
+            "lazyfree-lazy-user-del" => Some(yes_no(self.lazyfree_lazy_user_del)),
+            "lazyfree-lazy-user-flush" => Some(yes_no(self.lazyfree_lazy_user_flush)),
+            "activerehashing" => Some(yes_no(self.activerehashing)),
+            "timeout" => Some(self.timeout.to_string()),
+            "notify-keyspace-events" => Some(flags_to_string(self.notify_keyspace_events)),
+            "slowlog-log-slower-than" => Some(self.slowlog_log_slower_than.to_string()),
+            "slowlog-max-len" => Some(self.slowlog_max_len.to_string()),
+            "latency-monitor-threshold" => Some(self.latency_monitor_threshold.to_string()),
+            "port" => Some(self.port.to_string()),
+            "bind" => Some(self.bind.join(" ")),
+            "unixsocket" => Some(self.unixsocket.clone()),
+            "unixsocketperm" => Some(format!("{:o}", self.unixsocketperm)),
+            "tls-port" => Some(self.tls_port.to_string()),
+            "tls-cert-file" => Some(self.tls_cert_file.clone()),
+            "tls-key-file" => Some(self.tls_key_file.clone()),
+            "tls-ca-cert-file" => Some(self.tls_ca_cert_file.clone()),
+            "tls-auth-clients" => Some(self.tls_auth_clients.name().to_string()),
+            "metrics-port" => Some(self.metrics_port.to_string()),
+            "pidfile" => Some(self.pidfile.clone()),
+            "replicaof" => Some(self.replicaof.clone()),
+            "replica-priority" => Some(self.replica_priority.to_string()),
+            _ => None,
+        };
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        match name.to_lowercase().as_str() {
+            "dir" => self.dir = value.to_string(),
+            "dbfilename" => self.dbfilename = value.to_string(),
+            "proto-max-bulk-len" => {
+                self.proto_max_bulk_len = parse_config_memory(name, value, 1024 * 1024)?
+            }
+            "client-query-buffer-limit" => {
+                self.client_query_buffer_limit = parse_config_memory(name, value, 1024 * 1024)?
+            }
+            "databases" => self.databases = parse_config_int(name, value, 1, i32::MAX as usize)?,
+            "maxmemory" => self.maxmemory = parse_config_memory(name, value, 0)?,
+            "maxmemory-policy" => {
+                self.maxmemory_policy = match MaxmemoryPolicy::from_name(value) {
+                    Some(p) => p,
+                    None => {
+                        return Err(Error {
+                            message: format!(
+                                "ERR CONFIG SET failed (possibly related to argument '{}') - argument(s) must be one of the following: {}",
+                                name,
+                                MaxmemoryPolicy::all_names()
+                            ),
+                        })
+                    }
+                }
+            }
+            "maxmemory-samples" => self.maxmemory_samples = parse_config_int(name, value, 1, 64)?,
+            "lfu-log-factor" => {
+                self.lfu_log_factor = parse_config_int(name, value, 0, i32::MAX as usize)? as u32
+            }
+            "lfu-decay-time" => {
+                self.lfu_decay_time = parse_config_int(name, value, 0, i32::MAX as usize)? as u64
+            }
+            "hash-max-listpack-entries" => {
+                self.hash_max_listpack_entries = parse_config_int(name, value, 0, i64::MAX as usize)?
+            }
+            "hash-max-listpack-value" => {
+                self.hash_max_listpack_value = parse_config_int(name, value, 0, i64::MAX as usize)?
+            }
+            "set-max-intset-entries" => {
+                self.set_max_intset_entries = parse_config_int(name, value, 0, i64::MAX as usize)?
+            }
+            "set-max-listpack-entries" => {
+                self.set_max_listpack_entries = parse_config_int(name, value, 0, i64::MAX as usize)?
+            }
+            "set-max-listpack-value" => {
+                self.set_max_listpack_value = parse_config_int(name, value, 0, i64::MAX as usize)?
+            }
+            "list-max-listpack-size" => {
+                self.list_max_listpack_size = parse_config_i64(name, value, -5, i32::MAX as i64)?
+            }
+            "lazyfree-lazy-eviction" => self.lazyfree_lazy_eviction = parse_config_bool(name, value)?,
+            "lazyfree-lazy-expire" => self.lazyfree_lazy_expire = parse_config_bool(name, value)?,
+            "lazyfree-lazy-server-del" => {
+                self.lazyfree_lazy_server_del = parse_config_bool(name, value)?
+            }
+            "lazyfree-lazy-user-del" => self.lazyfree_lazy_user_del = parse_config_bool(name, value)?,
+            "lazyfree-lazy-user-flush" => {
+                self.lazyfree_lazy_user_flush = parse_config_bool(name, value)?
+            }
+            "activerehashing" => self.activerehashing = parse_config_bool(name, value)?,
+            "timeout" => self.timeout = parse_config_int(name, value, 0, i32::MAX as usize)? as u64,
+            "notify-keyspace-events" => {
+                self.notify_keyspace_events = match parse_flags(value) {
+                    Some(flags) => flags,
+                    None => {
+                        return Err(Error {
+                            message: format!(
+                                "ERR CONFIG SET failed (possibly related to argument '{}') - Invalid event class character. Use 'Ag$lshzxeKEtmdn'.",
+                                name
+                            ),
+                        })
+                    }
+                }
+            }
+            "slowlog-log-slower-than" => {
+                self.slowlog_log_slower_than = parse_config_i64(name, value, -1, i64::MAX)?
+            }
+            "slowlog-max-len" => {
+                self.slowlog_max_len = parse_config_int(name, value, 0, i64::MAX as usize)?
+            }
+            "latency-monitor-threshold" => {
+                self.latency_monitor_threshold =
+                    parse_config_int(name, value, 0, i64::MAX as usize)? as u64
+            }
+            "port" => self.port = parse_config_int(name, value, 0, u16::MAX as usize)? as u16,
+            // several addresses are separated by spaces, as in redis.conf
+            "bind" => {
+                self.bind = value.split_whitespace().map(String::from).collect();
+                if self.bind.is_empty() {
+                    return Err(Error {
+                        message: format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - at least one address is required",
+                            name
+                        ),
+                    });
+                }
+            }
+            "unixsocket" => self.unixsocket = value.to_string(),
+            "unixsocketperm" => {
+                self.unixsocketperm = match u32::from_str_radix(value, 8) {
+                    Ok(perm) if perm <= 0o777 => perm,
+                    _ => {
+                        return Err(Error {
+                            message: format!(
+                                "ERR CONFIG SET failed (possibly related to argument '{}') - argument must be an octal permission mode up to 777",
+                                name
+                            ),
+                        })
+                    }
+                }
+            }
+            "tls-port" => {
+                self.tls_port = parse_config_int(name, value, 0, u16::MAX as usize)? as u16
+            }
+            "tls-cert-file" => self.tls_cert_file = value.to_string(),
+            "tls-key-file" => self.tls_key_file = value.to_string(),
+            "tls-ca-cert-file" => self.tls_ca_cert_file = value.to_string(),
+            "tls-auth-clients" => {
+                self.tls_auth_clients = match TlsAuthClients::from_name(value) {
+                    Some(auth) => auth,
+                    None => {
+                        return Err(Error {
+                            message: format!(
+                                "ERR CONFIG SET failed (possibly related to argument '{}') - argument(s) must be one of the following: yes, no, optional",
+                                name
+                            ),
+                        })
+                    }
+                }
+            }
+            "metrics-port" => {
+                self.metrics_port = parse_config_int(name, value, 0, u16::MAX as usize)? as u16
+            }
+            "pidfile" => self.pidfile = value.to_string(),
+            "replicaof" => {
+                if !value.is_empty() && parse_replicaof(value).is_none() {
+                    return Err(Error {
+                        message: format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - argument must be 'host port'",
+                            name
+                        ),
+                    });
+                }
+                self.replicaof = value.to_string();
+            }
+            "replica-priority" => {
+                self.replica_priority = parse_config_int(name, value, 0, i32::MAX as usize)? as u64
+            }
+            "loadmodule" => {
+                if value.trim().is_empty() {
+                    return Err(Error {
+                        message: format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - argument must be a module name",
+                            name
+                        ),
+                    });
+                }
+                self.loadmodule.push(value.to_string());
+            }
+            _ => {
+                return Err(Error {
+                    message: format!(
+                        "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                        name
+                    ),
+                })
+            }
+        };
+
+        return Ok(());
+    }
+
+    /// The LFU parameters in the form each `Database` keeps a copy of.
+    pub fn lfu_settings(&self) -> LfuSettings {
+        return LfuSettings {
+            log_factor: self.lfu_log_factor,
+            decay_time: self.lfu_decay_time,
+        };
+    }
+
+    /// The compact encoding limits in the form each `Database` keeps a copy of.
+    pub fn encoding_limits(&self) -> EncodingLimits {
+        return EncodingLimits {
+            hash_max_listpack_entries: self.hash_max_listpack_entries,
+            hash_max_listpack_value: self.hash_max_listpack_value,
+            set_max_intset_entries: self.set_max_intset_entries,
+            set_max_listpack_entries: self.set_max_listpack_entries,
+            set_max_listpack_value: self.set_max_listpack_value,
+            list_max_listpack_size: self.list_max_listpack_size,
+        };
+    }
+
+    /// The lazy freeing options in the form each `Database` keeps a copy of.
+    pub fn lazyfree_settings(&self) -> LazyfreeSettings {
+        return LazyfreeSettings {
+            expire: self.lazyfree_lazy_expire,
+            server_del: self.lazyfree_lazy_server_del,
+        };
+    }
+
+    /// Copies the parameters each `Database` keeps locally into `db`.
+    pub fn apply_to(&self, db: &mut Database) {
+        db.lfu = self.lfu_settings();
+        db.encoding = self.encoding_limits();
+        db.lazyfree = self.lazyfree_settings();
+        db.notify_flags = self.notify_keyspace_events;
+    }
+
+    /// The master given with `replicaof`, if any.
+    pub fn master_addr(&self) -> Option<MasterAddr> {
+        return parse_replicaof(&self.replicaof);
+    }
+}
+
+fn parse_replicaof(value: &str) -> Option<MasterAddr> {
+    let (host, port) = value.trim().split_once(' ')?;
+    let port: u16 = port.trim().parse().ok()?;
+    return Some(MasterAddr::new(host, port));
+}
+
+fn parse_config_memory(name: &str, value: &str, min: usize) -> Result<usize, Error> {
+    return match parse_memory(value) {
+        Some(n) if n >= min => Ok(n),
+        _ => Err(Error {
+            message: format!(
+                "ERR CONFIG SET failed (possibly related to argument '{}') - argument must be a memory value of at least {}",
+                name, min
+            ),
+        }),
+    };
+}
+
+fn parse_config_bool(name: &str, value: &str) -> Result<bool, Error> {
+    return match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(Error {
+            message: format!(
+                "ERR CONFIG SET failed (possibly related to argument '{}') - argument must be 'yes' or 'no'",
+                name
+            ),
+        }),
+    };
+}
+
+fn yes_no(value: bool) -> String {
+    return String::from(match value {
+        true => "yes",
+        false => "no",
+    });
+}
+
+fn parse_config_int(name: &str, value: &str, min: usize, max: usize) -> Result<usize, Error> {
+    return match value.parse::<usize>() {
+        Ok(n) if n >= min && n <= max => Ok(n),
+        _ => Err(Error {
+            message: format!(
+                "ERR CONFIG SET failed (possibly related to argument '{}') - argument must be between {} and {} inclusive",
+                name, min, max
+            ),
+        }),
+    };
+}
+
+fn parse_config_i64(name: &str, value: &str, min: i64, max: i64) -> Result<i64, Error> {
+    return match value.parse::<i64>() {
+        Ok(n) if n >= min && n <= max => Ok(n),
+        _ => Err(Error {
+            message: format!(
+                "ERR CONFIG SET failed (possibly related to argument '{}') - argument must be between {} and {} inclusive",
+                name, min, max
+            ),
+        }),
+    };
+}
+
+/// Parses a memory amount such as `512mb`, `1gb` or `100` into bytes.
+///
+/// Units follow redis.conf: `k`/`m`/`g` are powers of 1000, `kb`/`mb`/`gb` powers of 1024.
+pub fn parse_memory(value: &str) -> Option<usize> {
+    let value: String = value.to_lowercase();
+    let split: usize = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+
+    let (digits, unit) = value.split_at(split);
+    let number: usize = digits.parse::<usize>().ok()?;
+
+    let multiplier: usize = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    return number.checked_mul(multiplier);
+}
@@ -0,0 +1,111 @@
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::redis_parser::FrameParser;
+use crate::Error;
+
+const READ_CHUNK: usize = 16 * 1024;
+
+/// A client connection with a growable read buffer.
+///
+/// Bytes are accumulated until at least one complete frame is available, so values
+/// larger than a single read and pipelined batches are both handled. The
+/// arguments of an incomplete frame wait in the parser, and the rest of it in
+/// the buffer, until the next reads complete it.
+pub struct Connection<S> {
+    stream: S,
+    buffer: BytesMut,
+    /// The command being received, kept between reads.
+    parser: FrameParser,
+}
+
+/// Every complete command parsed from a single read, in arrival order.
+///
+/// `error` is set when a protocol error was hit after those commands; the caller
+/// should run the commands, reply with the error and close the connection.
+pub struct Batch {
+    pub commands: Vec<Vec<Vec<u8>>>,
+    pub error: Option<Error>,
+    /// Bytes received from the socket while assembling this batch.
+    pub bytes_read: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(stream: S) -> Self {
+        return Connection {
+            stream,
+            buffer: BytesMut::with_capacity(READ_CHUNK),
+            parser: FrameParser::default(),
+        };
+    }
+
+    /// Reads until at least one full command (or a protocol error) is buffered.
+    ///
+    /// Returns `Ok(None)` once the peer closes the connection, and an error when the
+    /// unparsed query buffer grows beyond `max_query_buffer` bytes.
+    pub async fn read_batch(
+        &mut self,
+        max_bulk_len: usize,
+        max_query_buffer: usize,
+    ) -> Result<Option<Batch>, Error> {
+        let mut bytes_read: usize = 0;
+        loop {
+            let mut batch: Batch = self.parse_buffered(max_bulk_len);
+            if !batch.commands.is_empty() || batch.error.is_some() {
+                batch.bytes_read = bytes_read;
+                return Ok(Some(batch));
+            }
+
+            if self.buffer.len() + self.parser.pending_bytes() > max_query_buffer {
+                return Err(Error::new(
+                    "closing client that reached max query buffer length",
+                ));
+            }
+
+            if self.buffer.capacity() - self.buffer.len() < READ_CHUNK {
+                self.buffer.reserve(READ_CHUNK);
+            }
+
+            let n: usize = self.stream.read_buf(&mut self.buffer).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            bytes_read += n;
+        }
+    }
+
+    fn parse_buffered(&mut self, max_bulk_len: usize) -> Batch {
+        let mut batch: Batch = Batch {
+            commands: Vec::new(),
+            error: None,
+            bytes_read: 0,
+        };
+
+        loop {
+            match self.parser.parse(&self.buffer, max_bulk_len) {
+                Ok((Some(args), used)) => {
+                    self.buffer.advance(used);
+                    if !args.is_empty() {
+                        batch.commands.push(args);
+                    }
+                }
+                Ok((None, used)) => {
+                    self.buffer.advance(used);
+                    break;
+                }
+                Err(e) => {
+                    batch.error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        return batch;
+    }
+
+    pub async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(bytes).await?;
+        self.stream.flush().await?;
+        return Ok(());
+    }
+}
@@ -1,36 +1,645 @@
-use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::dict::Dict;
+use crate::lazyfree::{LazyfreeSettings, LAZYFREE_THRESHOLD};
+use crate::notify::{
+    KeyspaceEvent, NOTIFY_EXPIRED, NOTIFY_KEYEVENT, NOTIFY_KEYSPACE, NOTIFY_KEY_MISS, NOTIFY_NEW,
+};
+use crate::random::random_f64;
+use crate::value::{EncodingLimits, Value};
+
+/// A stored value together with its absolute expiry time in unix milliseconds.
+///
+/// The access metadata feeds the eviction policies: `last_access` for LRU and the
+/// logarithmic counter with its last decrement time for LFU.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub value: Value,
+    pub expires_at: Option<u64>,
+    /// Unix milliseconds of the last access.
+    pub last_access: u64,
+    pub lfu_counter: u8,
+    /// Minutes clock (modulo 2^16) when the LFU counter was last decremented.
+    pub lfu_decr_time: u16,
+}
+
+/// Rough per-key bookkeeping cost (hash table slot, entry header, allocation headers)
+/// added on top of the key and value lengths when estimating memory usage.
+pub const ENTRY_OVERHEAD: usize = 64;
+
+/// Counter given to new keys so they are not evicted before they had a chance to be used.
+pub const LFU_INIT_VAL: u8 = 5;
+
+/// Expired keys RANDOMKEY deletes before giving up and returning one anyway.
+const RANDOM_KEY_MAX_TRIES: usize = 100;
+
+/// Keys sampled per round of the active expiry cycle.
+const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+
+/// Counters reported by INFO, kept per database and summed when reported.
+#[derive(Clone, Debug, Default)]
+pub struct DbStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub expired: u64,
+    /// Number of changes made to the data set, used for rdb_changes_since_last_save.
+    pub dirty: u64,
+}
+
+/// The lfu-log-factor and lfu-decay-time parameters, copied into every database.
+#[derive(Clone, Copy, Debug)]
+pub struct LfuSettings {
+    pub log_factor: u32,
+    /// Idle minutes per decrement of the counter; 0 disables the decay.
+    pub decay_time: u64,
+}
+
+impl Default for LfuSettings {
+    fn default() -> Self {
+        return LfuSettings {
+            log_factor: 10,
+            decay_time: 1,
+        };
+    }
+}
+
+/// One logical database. Keys with a TTL are also kept in a second dict so the
+/// active expiry cycle can sample them without scanning the whole keyspace.
+#[derive(Clone)]
 pub struct Database {
-    data: HashMap<String, String>,
+    data: Dict<Vec<u8>, Entry>,
+    expires: Dict<Vec<u8>, u64>,
+    /// Estimated bytes held by the keys and values, maintained on every change.
+    used_memory: usize,
+    pub lfu: LfuSettings,
+    /// Limits of the compact encodings new and growing collections use.
+    pub encoding: EncodingLimits,
+    /// Copy of `notify-keyspace-events`, so disabled classes are not even recorded.
+    pub notify_flags: u32,
+    /// Keyspace events waiting for `notify::publish_events`.
+    events: Vec<KeyspaceEvent>,
+    /// Set while some client uses CLIENT TRACKING, so changed keys get recorded.
+    pub track_modified: bool,
+    /// Copy of the `lazyfree-lazy-expire/server-del` options.
+    pub lazyfree: LazyfreeSettings,
+    /// Large values deleted lazily, waiting for `lazyfree::free_detached`.
+    detached: Vec<Value>,
+    /// Keys changed since the last `take_modified`, and whether the database was flushed.
+    modified: Vec<Vec<u8>>,
+    flushed: bool,
+    pub stats: DbStats,
+}
+
+pub fn now_ms() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+}
+
+pub fn now_us() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0);
+}
+
+fn lfu_time_in_minutes(now: u64) -> u16 {
+    return ((now / 60_000) & 65535) as u16;
+}
+
+impl Entry {
+    pub fn new(value: Value, expires_at: Option<u64>) -> Self {
+        let now: u64 = now_ms();
+        return Entry {
+            value,
+            expires_at,
+            last_access: now,
+            lfu_counter: LFU_INIT_VAL,
+            lfu_decr_time: lfu_time_in_minutes(now),
+        };
+    }
+
+    /// Milliseconds since the entry was last accessed.
+    pub fn idle_ms(&self, now: u64) -> u64 {
+        return now.saturating_sub(self.last_access);
+    }
+
+    /// The LFU counter after applying the decay owed since its last decrement,
+    /// without storing it.
+    pub fn lfu_decayed(&self, lfu: &LfuSettings, now: u64) -> u8 {
+        if lfu.decay_time == 0 {
+            return self.lfu_counter;
+        }
+
+        // the minutes clock wraps around every ~45 days
+        let current: u16 = lfu_time_in_minutes(now);
+        let elapsed: u64 = current.wrapping_sub(self.lfu_decr_time) as u64;
+        let periods: u64 = elapsed / lfu.decay_time;
+        return self.lfu_counter.saturating_sub(periods.min(255) as u8);
+    }
+
+    /// Records an access for both the LRU clock and the LFU counter.
+    fn touch(&mut self, lfu: &LfuSettings, now: u64) {
+        let mut counter: u8 = self.lfu_decayed(lfu, now);
+
+        // logarithmic increment: the higher the counter, the less likely it grows
+        if counter < 255 {
+            let base: f64 = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            if random_f64() < 1.0 / (base * lfu.log_factor as f64 + 1.0) {
+                counter += 1;
+            }
+        }
+
+        self.lfu_counter = counter;
+        self.lfu_decr_time = lfu_time_in_minutes(now);
+        self.last_access = now;
+    }
+}
+
+/// Estimated bytes used by `key` and its entry, as MEMORY USAGE reports it.
+pub fn entry_memory(key: &[u8], entry: &Entry) -> usize {
+    return key.len() + entry.value.memory() + ENTRY_OVERHEAD;
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        return Database::new();
+    }
 }
 
 impl Database {
     pub fn new() -> Self {
         return Database {
-            data: HashMap::new(),
+            data: Dict::new(),
+            expires: Dict::new(),
+            used_memory: 0,
+            lfu: LfuSettings::default(),
+            encoding: EncodingLimits::default(),
+            notify_flags: 0,
+            events: Vec::new(),
+            track_modified: false,
+            lazyfree: LazyfreeSettings::default(),
+            detached: Vec::new(),
+            modified: Vec::new(),
+            flushed: false,
+            stats: DbStats::default(),
         };
     }
 
-    pub fn add(&mut self, key: &str, value: &str) {
-        self.data.insert(key.to_string(), value.to_string());
+    /// Records that `key` changed, for the client side caching invalidations.
+    fn signal_modified(&mut self, key: &[u8]) {
+        if self.track_modified {
+            self.modified.push(key.to_vec());
+        }
+    }
+
+    /// Hands over the keys changed since the last call, and whether a flush happened.
+    pub fn take_modified(&mut self) -> (Vec<Vec<u8>>, bool) {
+        let flushed: bool = std::mem::take(&mut self.flushed);
+        return (std::mem::take(&mut self.modified), flushed);
+    }
+
+    /// Records a keyspace event if its class is enabled.
+    pub fn notify(&mut self, class: u32, event: &'static str, key: &[u8]) {
+        if self.notify_flags & class == 0
+            || self.notify_flags & (NOTIFY_KEYSPACE | NOTIFY_KEYEVENT) == 0
+        {
+            return;
+        }
+        self.events.push(KeyspaceEvent {
+            class,
+            event,
+            key: key.to_vec(),
+        });
     }
 
-    pub fn get(&self, key: &str) -> Option<String> {
-        return Some(self.data.get(key).unwrap().clone());
+    /// Hands over the events recorded since the last call.
+    pub fn take_events(&mut self) -> Vec<KeyspaceEvent> {
+        return std::mem::take(&mut self.events);
     }
 
-    pub fn remove(&mut self, key: &str) {
-        self.data.remove(key);
+    /// Stores `value` under `key`, discarding any previous TTL.
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        self.set(key, value.to_vec(), None);
     }
 
-    pub fn try_get(&self, key: &str) -> Option<()> {
-        match self.data.get(key) {
-            Some(_) => Some(()),
-            None => None,
+    pub fn set<V: Into<Value>>(&mut self, key: &[u8], value: V, expires_at: Option<u64>) {
+        match self.unlink(key) {
+            Some(old) => self.discard(old.value, self.lazyfree.server_del),
+            None => self.notify(NOTIFY_NEW, "new", key),
         }
+        if let Some(at) = expires_at {
+            self.expires.insert(key.to_vec(), at);
+        }
+        let entry: Entry = Entry::new(value.into(), expires_at);
+        self.used_memory += entry_memory(key, &entry);
+        self.data.insert(key.to_vec(), entry);
+        self.stats.dirty += 1;
+        self.signal_modified(key);
+    }
+
+    /// The value of a string key; other types read as missing.
+    pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        return match self.lookup_read(key) {
+            Some(Entry {
+                value: Value::String(s),
+                ..
+            }) => Some(s.clone()),
+            _ => None,
+        };
+    }
+
+    /// Like `lookup`, but counts the access as a keyspace hit or miss.
+    pub fn lookup_read(&mut self, key: &[u8]) -> Option<&Entry> {
+        match self.lookup(key) {
+            Some(_) => self.stats.hits += 1,
+            None => {
+                self.stats.misses += 1;
+                self.notify(NOTIFY_KEY_MISS, "keymiss", key);
+            }
+        }
+        return self.data.get(key);
+    }
+
+    /// Returns the live entry for `key`, deleting it first if it has expired.
+    /// The access is recorded for the eviction policies.
+    pub fn lookup(&mut self, key: &[u8]) -> Option<&Entry> {
+        let now: u64 = now_ms();
+        self.expire_if_needed(key, now);
+        let lfu: LfuSettings = self.lfu;
+        let entry: &mut Entry = self.data.get_mut(key)?;
+        entry.touch(&lfu, now);
+        return Some(entry);
+    }
+
+    /// Like `lookup`, but leaves the access metadata untouched, as OBJECT needs.
+    pub fn lookup_notouch(&mut self, key: &[u8]) -> Option<&Entry> {
+        self.expire_if_needed(key, now_ms());
+        return self.data.get(key);
+    }
+
+    /// Changes the value of `key` in place, keeping the memory estimate in step.
+    /// An aggregate left without elements deletes the key. Returns `None`
+    /// when there is no such key.
+    pub fn modify<R, F: FnOnce(&mut Value) -> R>(&mut self, key: &[u8], f: F) -> Option<R> {
+        let entry: &mut Entry = self.data.get_mut(key)?;
+        let before: usize = entry.value.memory();
+        let result: R = f(&mut entry.value);
+        let after: usize = entry.value.memory();
+        let emptied: bool = entry.value.is_empty();
+
+        self.used_memory = self.used_memory + after - before;
+        self.stats.dirty += 1;
+        self.signal_modified(key);
+        if emptied {
+            self.unlink(key);
+        }
+        return Some(result);
+    }
+
+    /// Sets or clears the expiry time of `key`, returning false when there is no such key.
+    pub fn set_expires_at(&mut self, key: &[u8], expires_at: Option<u64>) -> bool {
+        let entry: &mut Entry = match self.data.get_mut(key) {
+            Some(e) => e,
+            None => return false,
+        };
+        entry.expires_at = expires_at;
+        match expires_at {
+            Some(at) => {
+                self.expires.insert(key.to_vec(), at);
+            }
+            None => {
+                self.expires.remove(key);
+            }
+        }
+        self.stats.dirty += 1;
+        self.signal_modified(key);
+        return true;
+    }
+
+    /// Sets the access metadata of `key`, as RESTORE IDLETIME and FREQ do.
+    pub fn set_access(&mut self, key: &[u8], idle_ms: Option<u64>, lfu_counter: Option<u8>) {
+        if let Some(entry) = self.data.get_mut(key) {
+            if let Some(idle) = idle_ms {
+                entry.last_access = now_ms().saturating_sub(idle);
+            }
+            if let Some(counter) = lfu_counter {
+                entry.lfu_counter = counter;
+            }
+        }
+    }
+
+    /// A random live key, deleting the expired keys it comes across.
+    pub fn random_key(&mut self) -> Option<Vec<u8>> {
+        let now: u64 = now_ms();
+        for _ in 0..RANDOM_KEY_MAX_TRIES {
+            let key: Vec<u8> = self.data.random_entry()?.0.clone();
+            if !self.expire_if_needed(&key, now) {
+                return Some(key);
+            }
+        }
+        // a keyspace of only expired keys would loop for a long time
+        return self.data.random_entry().map(|(k, _)| k.clone());
+    }
+
+    /// Returns the stored entry for `key` as is, even if it is logically expired.
+    pub fn peek(&self, key: &[u8]) -> Option<&Entry> {
+        return self.data.get(key);
     }
 
-    pub fn get_keys(&self) -> Vec<String> {
-        return self.data.iter().skip(2).map(|(k, _)| k.clone()).collect();
+    /// Whether `key` is still stored although its TTL has passed.
+    pub fn is_expired(&self, key: &[u8]) -> bool {
+        return self.data.get(key).is_some_and(|e| is_expired(e, now_ms()));
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Entry> {
+        let entry: Entry = self.unlink(key)?;
+        self.stats.dirty += 1;
+        self.signal_modified(key);
+        return Some(entry);
+    }
+
+    /// Detaches `key` from the keyspace and expiry index without touching the counters.
+    fn unlink(&mut self, key: &[u8]) -> Option<Entry> {
+        let (key, entry) = self.data.remove(key)?;
+        if entry.expires_at.is_some() {
+            self.expires.remove(&key);
+        }
+        self.used_memory -= entry_memory(&key, &entry);
+        return Some(entry);
+    }
+
+    /// Drops a deleted value, or keeps it for the background thread when `lazy`
+    /// and it is large enough to be worth it.
+    fn discard(&mut self, value: Value, lazy: bool) {
+        if lazy && value.free_effort() > LAZYFREE_THRESHOLD {
+            self.detached.push(value);
+        }
+    }
+
+    /// Hands over the values deleted lazily since the last call.
+    pub fn take_detached(&mut self) -> Vec<Value> {
+        return std::mem::take(&mut self.detached);
+    }
+
+    pub fn try_get(&mut self, key: &[u8]) -> Option<()> {
+        return self.lookup(key).map(|_| ());
+    }
+
+    pub fn get_keys(&self) -> Vec<Vec<u8>> {
+        let now: u64 = now_ms();
+        return self
+            .data
+            .iter()
+            .filter(|(_, e)| !is_expired(e, now))
+            .map(|(k, _)| k.clone())
+            .collect();
+    }
+
+    /// Calls `f` with the live entries of the buckets at `cursor`, returning the
+    /// cursor to continue from, zero once the whole database was visited.
+    pub fn scan<F: FnMut(&Vec<u8>, &Entry)>(&self, cursor: usize, mut f: F) -> usize {
+        let now: u64 = now_ms();
+        return self.data.scan(cursor, |key, entry| {
+            if !is_expired(entry, now) {
+                f(key, entry);
+            }
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        return self.data.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.data.is_empty();
+    }
+
+    pub fn expires_len(&self) -> usize {
+        return self.expires.len();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Entry)> {
+        return self.data.iter();
+    }
+
+    /// A random key of the keyspace, used by the eviction sampling.
+    pub fn random_entry(&self) -> Option<(&Vec<u8>, &Entry)> {
+        let (key, _) = self.data.random_entry()?;
+        return self.data.get(key).map(|e| (key, e));
+    }
+
+    /// A random key among those with a TTL.
+    pub fn random_volatile_entry(&self) -> Option<(&Vec<u8>, &Entry)> {
+        let (key, _) = self.expires.random_entry()?;
+        return self.data.get(key).map(|e| (key, e));
+    }
+
+    /// Sum of the TTLs still remaining on volatile keys, in milliseconds.
+    pub fn total_ttl(&self, now: u64) -> u64 {
+        return self
+            .expires
+            .iter()
+            .map(|(_, at)| at.saturating_sub(now))
+            .sum();
+    }
+
+    /// Estimated bytes used by the keys and values of this database.
+    pub fn memory_usage(&self) -> usize {
+        return self.used_memory;
+    }
+
+    /// Empties the database, handing back the old contents so the caller can
+    /// decide where they get dropped. The counters stay with this database.
+    pub fn take(&mut self) -> Database {
+        self.stats.dirty += self.data.len() as u64;
+        self.flushed = self.track_modified;
+        self.modified.clear();
+        return Database {
+            data: std::mem::take(&mut self.data),
+            expires: std::mem::take(&mut self.expires),
+            used_memory: std::mem::take(&mut self.used_memory),
+            lfu: self.lfu,
+            encoding: self.encoding,
+            notify_flags: self.notify_flags,
+            events: Vec::new(),
+            track_modified: false,
+            lazyfree: self.lazyfree,
+            detached: Vec::new(),
+            modified: Vec::new(),
+            flushed: false,
+            stats: DbStats::default(),
+        };
+    }
+
+    /// Spends up to `budget` moving the keys of a growing or shrinking
+    /// keyspace to its new table, the main one first, like Redis' active
+    /// rehashing. Returns whether there is more to move.
+    pub fn rehash_for(&mut self, budget: Duration) -> bool {
+        if self.data.needs_rehash() {
+            return self.data.rehash_for(budget) || self.expires.needs_rehash();
+        }
+        return self.expires.rehash_for(budget);
+    }
+
+    fn expire_if_needed(&mut self, key: &[u8], now: u64) -> bool {
+        let expired: bool = match self.data.get(key) {
+            Some(e) => is_expired(e, now),
+            None => false,
+        };
+        if expired {
+            if let Some(entry) = self.remove(key) {
+                self.discard(entry.value, self.lazyfree.expire);
+            }
+            self.stats.expired += 1;
+            self.notify(NOTIFY_EXPIRED, "expired", key);
+        }
+        return expired;
+    }
+
+    /// Samples volatile keys and deletes the expired ones, repeating while more than
+    /// a quarter of a sample had expired, like Redis' active expire cycle. At most
+    /// `limit` keys are removed per call; returns how many were.
+    pub fn active_expire(&mut self, now: u64, limit: usize) -> usize {
+        let mut removed: usize = 0;
+        while removed < limit {
+            let sampled: usize = self.expires.len().min(ACTIVE_EXPIRE_SAMPLES);
+            if sampled == 0 {
+                break;
+            }
+
+            let mut expired: usize = 0;
+            for _ in 0..sampled {
+                let key: Vec<u8> = match self.expires.random_entry() {
+                    Some((key, at)) if *at <= now => key.clone(),
+                    _ => continue,
+                };
+                if let Some(entry) = self.remove(&key) {
+                    self.discard(entry.value, self.lazyfree.expire);
+                }
+                self.stats.expired += 1;
+                self.notify(NOTIFY_EXPIRED, "expired", &key);
+                expired += 1;
+            }
+
+            removed += expired;
+            if expired * 4 <= sampled {
+                break;
+            }
+        }
+        return removed;
+    }
+}
+
+fn is_expired(entry: &Entry, now: u64) -> bool {
+    return matches!(entry.expires_at, Some(at) if at <= now);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_accounting_test() {
+        let mut db: Database = Database::new();
+        db.add(b"key", b"value");
+        db.set(b"ttl", vec![0; 100], Some(now_ms() + 60_000));
+        assert_eq!(db.memory_usage(), 3 + 5 + 3 + 100 + 2 * ENTRY_OVERHEAD);
+
+        db.add(b"key", b"v");
+        db.remove(b"ttl");
+        assert_eq!(db.memory_usage(), 3 + 1 + ENTRY_OVERHEAD);
+
+        let old: Database = db.take();
+        assert_eq!(db.memory_usage(), 0);
+        assert_eq!(old.memory_usage(), 3 + 1 + ENTRY_OVERHEAD);
+    }
+
+    #[test]
+    fn active_expire_test() {
+        let mut db: Database = Database::new();
+        for i in 0..100 {
+            db.set(format!("old{}", i).as_bytes(), vec![], Some(1));
+        }
+        db.set(b"live", vec![], Some(now_ms() + 60_000));
+        db.add(b"plain", b"");
+
+        // a sample can miss the few expired keys left, so one call finding
+        // nothing does not mean they are gone
+        while db.expires_len() > 1 {
+            db.active_expire(now_ms(), 1000);
+        }
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.expires_len(), 1);
+        assert_eq!(db.stats.expired, 100);
+    }
+
+    #[test]
+    fn notify_test() {
+        let mut db: Database = Database::new();
+        db.add(b"quiet", b"1");
+        assert!(db.take_events().is_empty());
+
+        db.notify_flags = crate::notify::parse_flags("Exn").unwrap();
+        db.add(b"fresh", b"1");
+        db.add(b"fresh", b"2");
+        db.set(b"gone", vec![], Some(1));
+        db.get(b"gone");
+        assert_eq!(
+            db.take_events()
+                .iter()
+                .map(|e| (e.event, e.key.as_slice()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("new", b"fresh".as_slice()),
+                ("new", b"gone".as_slice()),
+                ("expired", b"gone".as_slice()),
+            ]
+        );
+    }
+
+    #[test]
+    fn lazyfree_test() {
+        let limits: EncodingLimits = EncodingLimits::default();
+        let members: Vec<String> = (0..200).map(|i| format!("m{}", i)).collect();
+        let big = || Value::Set(crate::value::Set::from_members(&members, &limits));
+
+        let mut db: Database = Database::new();
+        db.set(b"big", big(), Some(1));
+        db.get(b"big");
+        db.set(b"big", big(), None);
+        db.set(b"big", b"small".to_vec(), None);
+        assert!(db.take_detached().is_empty());
+
+        db.lazyfree = LazyfreeSettings {
+            expire: true,
+            server_del: true,
+        };
+        db.set(b"big", big(), None);
+        db.set(b"big", big(), Some(1));
+        db.get(b"big");
+        // a small value is dropped on the spot all the same
+        db.set(b"small", b"x".to_vec(), Some(1));
+        db.get(b"small");
+        assert_eq!(db.take_detached().len(), 2);
+        assert_eq!(db.memory_usage(), 0);
+    }
+
+    #[test]
+    fn lfu_counter_test() {
+        let lfu: LfuSettings = LfuSettings::default();
+        let now: u64 = now_ms();
+        let mut entry: Entry = Entry::new(Value::String(vec![]), None);
+        for _ in 0..1000 {
+            entry.touch(&lfu, now);
+        }
+        // with the default log factor a thousand hits land well below saturation
+        assert!(entry.lfu_counter > LFU_INIT_VAL && entry.lfu_counter < 30);
+
+        // one point of decay per idle minute
+        let counter: u8 = entry.lfu_counter;
+        assert_eq!(entry.lfu_decayed(&lfu, now + 3 * 60_000), counter - 3);
+        assert_eq!(entry.lfu_decayed(&lfu, now + 1000 * 60_000), 0);
     }
 }
@@ -0,0 +1,752 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+
+use crate::random::random_below;
+
+/// Smallest table allocated once the first entry is inserted.
+const INITIAL_SIZE: usize = 4;
+
+/// Shrink when fewer than one in this many buckets would be used.
+const MIN_FILL_RATIO: usize = 10;
+
+/// Empty buckets a rehash step may skip for each bucket it is asked to move,
+/// so a step over a sparse table stays short.
+const EMPTY_VISITS: usize = 10;
+
+/// Buckets moved per round of `rehash_for`, between two looks at the clock.
+const REHASH_ROUND: usize = 100;
+
+/// Entries per block of the entry storage.
+const CHUNK_SIZE: usize = 1024;
+
+/// A chained hash table in the style of the Redis dict.
+///
+/// Unlike `std::collections::HashMap` it exposes its bucket layout, which gives
+/// constant time random sampling for the eviction and expiry algorithms.
+///
+/// Growing or shrinking never happens all at once: a second table is
+/// allocated and every write moves one bucket of the old table to it, as
+/// `rehash_for` does in the background, so no single operation pays for the
+/// whole table however large it is. Lookups search both tables meanwhile.
+/// Bucket heads are plain indices, so a new table is allocated as zeroed
+/// memory, and entries live in fixed size blocks that are never moved or
+/// copied as the dict grows. Freed entries are reused by the following
+/// inserts, first block first. Once a shrink completes, the following rehash
+/// steps move the entries of the last blocks into the free slots of the first
+/// ones, a few at a time, and release the blocks left empty.
+#[derive(Clone)]
+pub struct Dict<K, V> {
+    /// Chain heads of the buckets, as `Link`s. `tables[1]` is only used
+    /// while rehashing, when new entries go there.
+    tables: [Vec<Link>; 2],
+    /// The next bucket of `tables[0]` to move to `tables[1]`, while rehashing.
+    rehash_index: Option<usize>,
+    chunks: Vec<Vec<Slot<K, V>>>,
+    /// Used slots of each chunk, so iterating skips the empty ones.
+    live: Vec<usize>,
+    /// The most recently freed slot of each chunk; the freed slots of a chunk
+    /// are chained through `Slot::Free`.
+    free: Vec<Link>,
+    /// The chunks with a freed slot. New entries go to the first one.
+    free_chunks: BTreeSet<usize>,
+    /// Set once a shrink completes, until the entries fit in as few chunks as
+    /// they need.
+    compacting: bool,
+    /// Slots of the last chunk the compaction already went past.
+    compacted: usize,
+    len: usize,
+    hasher: RandomState,
+}
+
+/// Position of a slot in the entry storage plus one, so zero is no slot and
+/// an empty table is zeroed memory.
+type Link = usize;
+
+const NIL: Link = 0;
+
+#[derive(Clone)]
+enum Slot<K, V> {
+    Used(Node<K, V>),
+    /// A freed slot, and the slot of the same chunk freed before it.
+    Free(Link),
+}
+
+#[derive(Clone)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// The next entry of the same bucket.
+    next: Link,
+}
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        return Dict {
+            tables: [Vec::new(), Vec::new()],
+            rehash_index: None,
+            chunks: Vec::new(),
+            live: Vec::new(),
+            free: Vec::new(),
+            free_chunks: BTreeSet::new(),
+            compacting: false,
+            compacted: 0,
+            len: 0,
+            hasher: RandomState::new(),
+        };
+    }
+}
+
+fn node<K, V>(chunks: &[Vec<Slot<K, V>>], link: Link) -> &Node<K, V> {
+    return match &chunks[(link - 1) / CHUNK_SIZE][(link - 1) % CHUNK_SIZE] {
+        Slot::Used(node) => node,
+        Slot::Free(_) => unreachable!("link to a free slot"),
+    };
+}
+
+fn node_mut<K, V>(chunks: &mut [Vec<Slot<K, V>>], link: Link) -> &mut Node<K, V> {
+    return match &mut chunks[(link - 1) / CHUNK_SIZE][(link - 1) % CHUNK_SIZE] {
+        Slot::Used(node) => node,
+        Slot::Free(_) => unreachable!("link to a free slot"),
+    };
+}
+
+/// Increments the bits of `cursor` under `mask`, from the highest one down.
+fn next_cursor(cursor: usize, mask: usize) -> usize {
+    let reversed: usize = (cursor | !mask).reverse_bits();
+    return reversed.wrapping_add(1).reverse_bits();
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    pub fn new() -> Self {
+        return Dict::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    pub fn is_rehashing(&self) -> bool {
+        return self.rehash_index.is_some();
+    }
+
+    /// Whether `rehash` has work left, moving buckets or compacting after a shrink.
+    pub fn needs_rehash(&self) -> bool {
+        return self.rehash_index.is_some() || self.compacting;
+    }
+
+    /// The tables that may hold entries: the second one only while rehashing.
+    fn live_tables(&self) -> usize {
+        return match self.rehash_index {
+            Some(_) => 2,
+            None => 1,
+        };
+    }
+
+    fn bucket_of(&self, hash: u64, table: usize) -> usize {
+        return (hash as usize) & (self.tables[table].len() - 1);
+    }
+
+    /// The slot holding `key`, or `NIL`.
+    fn find<Q>(&self, key: &Q) -> Link
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return NIL;
+        }
+        return self.find_hashed(self.hasher.hash_one(key), key);
+    }
+
+    fn find_hashed<Q>(&self, hash: u64, key: &Q) -> Link
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return NIL;
+        }
+        for table in 0..self.live_tables() {
+            let mut link: Link = self.tables[table][self.bucket_of(hash, table)];
+            while link != NIL {
+                let node: &Node<K, V> = node(&self.chunks, link);
+                if node.key.borrow() == key {
+                    return link;
+                }
+                link = node.next;
+            }
+        }
+        return NIL;
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        return match self.find(key) {
+            NIL => None,
+            link => Some(&node(&self.chunks, link).value),
+        };
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        return match self.find(key) {
+            NIL => None,
+            link => Some(&mut node_mut(&mut self.chunks, link).value),
+        };
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        return self.find(key) != NIL;
+    }
+
+    /// Inserts or replaces `key`, returning the previous value.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.rehash(1);
+        let hash: u64 = self.hasher.hash_one(&key);
+        let existing: Link = self.find_hashed(hash, &key);
+        if existing != NIL {
+            let node: &mut Node<K, V> = node_mut(&mut self.chunks, existing);
+            return Some(std::mem::replace(&mut node.value, value));
+        }
+
+        if !self.is_rehashing() && self.len >= self.tables[0].len() {
+            self.resize((self.len * 2).max(INITIAL_SIZE));
+        }
+
+        // while rehashing, new entries go straight to the new table
+        let table: usize = self.live_tables() - 1;
+        let bucket: usize = self.bucket_of(hash, table);
+        let next: Link = self.tables[table][bucket];
+        let link: Link = self.allocate(Node { key, value, next });
+        self.tables[table][bucket] = link;
+        self.len += 1;
+        return None;
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        self.rehash(1);
+
+        let hash: u64 = self.hasher.hash_one(key);
+        for table in 0..self.live_tables() {
+            let bucket: usize = self.bucket_of(hash, table);
+            let mut previous: Link = NIL;
+            let mut link: Link = self.tables[table][bucket];
+            while link != NIL {
+                let node: &Node<K, V> = node(&self.chunks, link);
+                if node.key.borrow() != key {
+                    previous = link;
+                    link = node.next;
+                    continue;
+                }
+
+                let next: Link = node.next;
+                match previous {
+                    NIL => self.tables[table][bucket] = next,
+                    _ => node_mut(&mut self.chunks, previous).next = next,
+                }
+                let removed: Node<K, V> = self.release(link);
+                self.len -= 1;
+
+                if self.len == 0 {
+                    self.clear();
+                } else if !self.is_rehashing()
+                    && self.tables[0].len() > INITIAL_SIZE
+                    && self.len * MIN_FILL_RATIO < self.tables[0].len()
+                {
+                    self.resize(self.len.max(INITIAL_SIZE));
+                }
+                return Some((removed.key, removed.value));
+            }
+        }
+        return None;
+    }
+
+    /// Stores `node` in a free slot of the first chunk that has one, or a new
+    /// slot at the end of the storage.
+    fn allocate(&mut self, node: Node<K, V>) -> Link {
+        if let Some(&chunk) = self.free_chunks.first() {
+            let link: Link = self.free[chunk];
+            let slot: &mut Slot<K, V> = &mut self.chunks[chunk][(link - 1) % CHUNK_SIZE];
+            if let Slot::Free(previous) = slot {
+                self.free[chunk] = *previous;
+            }
+            *slot = Slot::Used(node);
+            if self.free[chunk] == NIL {
+                self.free_chunks.remove(&chunk);
+            }
+            self.live[chunk] += 1;
+            return link;
+        }
+
+        if self
+            .chunks
+            .last()
+            .is_none_or(|chunk| chunk.len() == CHUNK_SIZE)
+        {
+            self.chunks.push(Vec::with_capacity(CHUNK_SIZE));
+            self.live.push(0);
+            self.free.push(NIL);
+        }
+        let full: usize = (self.chunks.len() - 1) * CHUNK_SIZE;
+        let chunk: &mut Vec<Slot<K, V>> = self.chunks.last_mut().unwrap();
+        chunk.push(Slot::Used(node));
+        *self.live.last_mut().unwrap() += 1;
+        return full + chunk.len();
+    }
+
+    fn release(&mut self, link: Link) -> Node<K, V> {
+        let chunk: usize = (link - 1) / CHUNK_SIZE;
+        let slot: &mut Slot<K, V> = &mut self.chunks[chunk][(link - 1) % CHUNK_SIZE];
+        let freed: Slot<K, V> = std::mem::replace(slot, Slot::Free(self.free[chunk]));
+        self.free[chunk] = link;
+        self.free_chunks.insert(chunk);
+        self.live[chunk] -= 1;
+        return match freed {
+            Slot::Used(node) => node,
+            Slot::Free(_) => unreachable!("released a free slot"),
+        };
+    }
+
+    /// Starts moving the entries to a table of at least `size` buckets
+    /// (rounded up to a power of two). The first table is simply allocated.
+    fn resize(&mut self, size: usize) {
+        let size: usize = size.next_power_of_two();
+        // zeroed memory, which the allocator hands out without touching it
+        let table: Vec<Link> = vec![NIL; size];
+        if self.tables[0].is_empty() {
+            self.tables[0] = table;
+            return;
+        }
+        self.tables[1] = table;
+        self.rehash_index = Some(0);
+    }
+
+    /// Moves up to `buckets` non empty buckets to the new table, like
+    /// `dictRehash`, or after a shrink up to `buckets` entries of the chunks
+    /// to release. Returns whether there is more to move.
+    pub fn rehash(&mut self, buckets: usize) -> bool {
+        let mut index: usize = match self.rehash_index {
+            Some(i) => i,
+            None if self.compacting => return self.compact(buckets),
+            None => return false,
+        };
+
+        let mut empty_visits: usize = buckets * EMPTY_VISITS;
+        let mut moved: usize = 0;
+        while moved < buckets && index < self.tables[0].len() {
+            let mut link: Link = std::mem::replace(&mut self.tables[0][index], NIL);
+            index += 1;
+            if link == NIL {
+                empty_visits -= 1;
+                if empty_visits == 0 {
+                    break;
+                }
+                continue;
+            }
+
+            while link != NIL {
+                let node: &mut Node<K, V> = node_mut(&mut self.chunks, link);
+                let next: Link = node.next;
+                let hash: u64 = self.hasher.hash_one(&node.key);
+                let bucket: usize = (hash as usize) & (self.tables[1].len() - 1);
+                node.next = self.tables[1][bucket];
+                self.tables[1][bucket] = link;
+                link = next;
+            }
+            moved += 1;
+        }
+
+        if index == self.tables[0].len() {
+            let shrunk: bool = self.tables[1].len() < self.tables[0].len();
+            self.tables[0] = std::mem::take(&mut self.tables[1]);
+            self.rehash_index = None;
+            // the entries left are spread over the chunks the larger dict needed
+            self.compacting = self.compacting || shrunk;
+            return self.compacting;
+        }
+        self.rehash_index = Some(index);
+        return true;
+    }
+
+    /// Moves up to `entries` entries of the last chunk into free slots of the
+    /// first ones, releasing the last chunk once it is empty, until there are
+    /// no more chunks than `len` entries need. Every entry moved is found
+    /// again from its bucket, so a step costs about `entries` lookups. Returns
+    /// whether there is more to move.
+    fn compact(&mut self, entries: usize) -> bool {
+        let mut empty_visits: usize = entries * EMPTY_VISITS;
+        let mut moved: usize = 0;
+        while self.chunks.len() > self.len.div_ceil(CHUNK_SIZE) {
+            let last: usize = self.chunks.len() - 1;
+            if self.live[last] == 0 {
+                self.chunks.pop();
+                self.live.pop();
+                self.free.pop();
+                self.free_chunks.remove(&last);
+                self.compacted = 0;
+                continue;
+            }
+            if moved == entries || empty_visits == 0 {
+                return true;
+            }
+
+            // inserts between two steps may have used slots the compaction went past
+            if self.compacted == self.chunks[last].len() {
+                self.compacted = 0;
+            }
+            let link: Link = last * CHUNK_SIZE + self.compacted + 1;
+            self.compacted += 1;
+            if let Slot::Free(_) = self.chunks[last][(link - 1) % CHUNK_SIZE] {
+                empty_visits -= 1;
+                continue;
+            }
+
+            // the chunks before the last hold fewer than `len` entries, so one has room
+            let node: Node<K, V> = self.release(link);
+            let hash: u64 = self.hasher.hash_one(&node.key);
+            let moved_to: Link = self.allocate(node);
+            self.relink(hash, link, moved_to);
+            moved += 1;
+        }
+        self.compacting = false;
+        self.compacted = 0;
+        return false;
+    }
+
+    /// Points the bucket head or the entry that linked to the entry of key
+    /// hash `hash` at `from` to `to`, where the entry was moved.
+    fn relink(&mut self, hash: u64, from: Link, to: Link) {
+        for table in 0..self.live_tables() {
+            let bucket: usize = self.bucket_of(hash, table);
+            if self.tables[table][bucket] == from {
+                self.tables[table][bucket] = to;
+                return;
+            }
+            let mut link: Link = self.tables[table][bucket];
+            while link != NIL {
+                let node: &mut Node<K, V> = node_mut(&mut self.chunks, link);
+                if node.next == from {
+                    node.next = to;
+                    return;
+                }
+                link = node.next;
+            }
+        }
+    }
+
+    /// Rehashes for about `budget`, like `dictRehashMilliseconds`. Returns
+    /// whether there is more to move.
+    pub fn rehash_for(&mut self, budget: Duration) -> bool {
+        let started: Instant = Instant::now();
+        while self.rehash(REHASH_ROUND) {
+            if started.elapsed() >= budget {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        return self
+            .chunks
+            .iter()
+            .zip(self.live.iter())
+            .filter(|(_, live)| **live > 0)
+            .flat_map(|(chunk, _)| chunk)
+            .filter_map(|slot| match slot {
+                Slot::Used(node) => Some((&node.key, &node.value)),
+                Slot::Free(_) => None,
+            });
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        return self
+            .chunks
+            .iter_mut()
+            .zip(self.live.iter())
+            .filter(|(_, live)| **live > 0)
+            .flat_map(|(chunk, _)| chunk)
+            .filter_map(|slot| match slot {
+                Slot::Used(node) => Some((&node.key, &mut node.value)),
+                Slot::Free(_) => None,
+            });
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        return self.iter().map(|(k, _)| k);
+    }
+
+    /// Calls `f` with the entries of the buckets at `cursor` and returns the
+    /// cursor to continue from, zero once every bucket was visited, like
+    /// `dictScan`. The cursor counts with its bits reversed, so an entry
+    /// present for the whole scan is visited even if the table is resized in
+    /// between, though possibly more than once.
+    pub fn scan<F: FnMut(&K, &V)>(&self, cursor: usize, mut f: F) -> usize {
+        if self.len == 0 {
+            return 0;
+        }
+
+        let mut cursor: usize = cursor;
+        let visit = |table: &Vec<Link>, bucket: usize, f: &mut F| {
+            let mut link: Link = table[bucket];
+            while link != NIL {
+                let node: &Node<K, V> = node(&self.chunks, link);
+                f(&node.key, &node.value);
+                link = node.next;
+            }
+        };
+        if !self.is_rehashing() {
+            let mask: usize = self.tables[0].len() - 1;
+            visit(&self.tables[0], cursor & mask, &mut f);
+            return next_cursor(cursor, mask);
+        }
+
+        // the buckets of the larger table that the smaller one's bucket splits into
+        let (small, large) = match self.tables[0].len() <= self.tables[1].len() {
+            true => (&self.tables[0], &self.tables[1]),
+            false => (&self.tables[1], &self.tables[0]),
+        };
+        let small_mask: usize = small.len() - 1;
+        let large_mask: usize = large.len() - 1;
+        visit(small, cursor & small_mask, &mut f);
+        loop {
+            visit(large, cursor & large_mask, &mut f);
+            cursor = next_cursor(cursor, large_mask);
+            if cursor & (small_mask ^ large_mask) == 0 {
+                return cursor;
+            }
+        }
+    }
+
+    /// Returns a uniformly chosen bucket's random entry, like `dictGetRandomKey`.
+    pub fn random_entry(&self) -> Option<(&K, &V)> {
+        if self.len == 0 {
+            return None;
+        }
+
+        // the table is kept at least 10% full, so this terminates quickly
+        loop {
+            let (table, bucket) = match self.rehash_index {
+                // the buckets before the rehash index are empty
+                Some(index) => {
+                    let old: usize = self.tables[0].len();
+                    let i: usize = index + random_below(old + self.tables[1].len() - index);
+                    match i < old {
+                        true => (0, i),
+                        false => (1, i - old),
+                    }
+                }
+                None => (0, random_below(self.tables[0].len())),
+            };
+            let head: Link = self.tables[table][bucket];
+            if head == NIL {
+                continue;
+            }
+
+            let mut chain: Vec<&Node<K, V>> = Vec::new();
+            let mut link: Link = head;
+            while link != NIL {
+                let node: &Node<K, V> = node(&self.chunks, link);
+                chain.push(node);
+                link = node.next;
+            }
+            let node: &Node<K, V> = chain[random_below(chain.len())];
+            return Some((&node.key, &node.value));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tables = [Vec::new(), Vec::new()];
+        self.rehash_index = None;
+        self.chunks = Vec::new();
+        self.live = Vec::new();
+        self.free = Vec::new();
+        self.free_chunks = BTreeSet::new();
+        self.compacting = false;
+        self.compacted = 0;
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove_test() {
+        let mut dict: Dict<Vec<u8>, u32> = Dict::new();
+        for i in 0..1000u32 {
+            assert!(dict.insert(i.to_string().into_bytes(), i).is_none());
+        }
+        assert_eq!(dict.len(), 1000);
+        assert_eq!(dict.insert(b"7".to_vec(), 70), Some(7));
+        assert_eq!(dict.get(b"7".as_slice()), Some(&70));
+
+        for i in 0..990u32 {
+            assert!(dict.remove(i.to_string().as_bytes()).is_some());
+        }
+        assert_eq!(dict.len(), 10);
+        assert_eq!(dict.iter().count(), 10);
+        assert!(dict.get(b"5".as_slice()).is_none());
+        assert_eq!(dict.get(b"995".as_slice()), Some(&995));
+    }
+
+    #[test]
+    fn random_entry_test() {
+        let mut dict: Dict<u32, u32> = Dict::new();
+        assert!(dict.random_entry().is_none());
+
+        for i in 0..64 {
+            dict.insert(i, i * 2);
+        }
+        for _ in 0..100 {
+            let (k, v) = dict.random_entry().unwrap();
+            assert_eq!(*v, *k * 2);
+        }
+    }
+
+    #[test]
+    fn shrink_releases_chunks_test() {
+        let mut dict: Dict<u32, u32> = Dict::new();
+        for i in 0..10_000 {
+            dict.insert(i, i);
+        }
+        assert_eq!(dict.chunks.len(), 10);
+
+        // the keys left were stored in the last chunk
+        for i in 0..9_995 {
+            dict.remove(&i);
+        }
+        while dict.is_rehashing() {
+            dict.rehash(1);
+        }
+
+        // the following steps move them to the first chunk one at a time
+        let mut left: usize = dict.live[1..].iter().sum();
+        assert_eq!(left, 5);
+        while dict.rehash(1) {
+            let now: usize = dict.live[1..].iter().sum();
+            assert!(left - now <= 1);
+            left = now;
+        }
+        assert_eq!(dict.chunks.len(), 1);
+        assert_eq!(dict.live, vec![5]);
+        for i in 9_995..10_000 {
+            assert_eq!(dict.get(&i), Some(&i));
+        }
+        assert_eq!(dict.iter().count(), 5);
+
+        // the slots freed by the compaction are reused before a new chunk
+        for i in 0..1_019 {
+            dict.insert(20_000 + i, i);
+        }
+        assert_eq!(dict.chunks.len(), 1);
+        dict.insert(30_000, 0);
+        assert_eq!(dict.chunks.len(), 2);
+        assert_eq!(dict.iter().count(), 1_025);
+    }
+
+    #[test]
+    fn scan_test() {
+        let mut dict: Dict<u32, u32> = Dict::new();
+        assert_eq!(dict.scan(0, |_, _| {}), 0);
+        for i in 0..500 {
+            dict.insert(i, i);
+        }
+
+        let mut seen: std::collections::HashSet<u32> = std::collections::HashSet::new();
+        let mut cursor: usize = 0;
+        let mut steps: usize = 0;
+        loop {
+            cursor = dict.scan(cursor, |k, _| {
+                seen.insert(*k);
+            });
+            steps += 1;
+            // the table grows half way through, and again while rehashing
+            if steps == 100 || steps == 150 {
+                for i in 0..1000 {
+                    dict.insert(10_000 + steps as u32 * 1000 + i, 0);
+                }
+            }
+            if cursor == 0 {
+                break;
+            }
+        }
+        assert!(steps > 100);
+        assert!((0..500).all(|i| seen.contains(&i)));
+    }
+
+    #[test]
+    fn incremental_rehash_test() {
+        let mut dict: Dict<u32, u32> = Dict::new();
+        for i in 0..1024 {
+            dict.insert(i, i);
+        }
+        assert!(!dict.is_rehashing());
+
+        // the table is full, so the next insert starts a rehash it does not finish
+        dict.insert(1024, 1024);
+        assert!(dict.is_rehashing());
+        dict.insert(1025, 1025);
+        assert!(dict.remove(&3).is_some());
+        assert!(dict.remove(&1025).is_some());
+        assert!(dict.is_rehashing());
+
+        // every entry stays reachable, whichever table holds it
+        for i in (0..1025).filter(|i| *i != 3) {
+            assert_eq!(dict.get(&i), Some(&i));
+        }
+        assert_eq!(dict.iter().count(), 1024);
+        for _ in 0..100 {
+            let (k, v) = dict.random_entry().unwrap();
+            assert_eq!(k, v);
+        }
+
+        assert!(!dict.rehash_for(Duration::from_secs(1)));
+        assert!(!dict.is_rehashing());
+        assert_eq!(dict.get(&1024), Some(&1024));
+
+        // removing most entries shrinks the table the same way
+        for i in 0..1000 {
+            dict.remove(&i);
+        }
+        while dict.rehash(1) {}
+        assert_eq!(dict.len(), 25);
+        assert_eq!(dict.keys().count(), 25);
+
+        // the shrink moved the entries into the first chunk, whose freed
+        // slots are reused, and an empty dict releases everything
+        assert_eq!(dict.chunks.len(), 1);
+        dict.insert(5000, 5000);
+        assert_eq!(dict.chunks.iter().map(Vec::len).sum::<usize>(), CHUNK_SIZE);
+        for i in 1000..1025 {
+            dict.remove(&i);
+        }
+        dict.remove(&5000);
+        assert!(dict.chunks.is_empty());
+    }
+}
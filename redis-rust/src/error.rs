@@ -5,6 +5,14 @@ pub struct Error {
     pub message: String,
 }
 
+impl Error {
+    pub fn new(message: &str) -> Self {
+        return Error {
+            message: message.to_string(),
+        };
+    }
+}
+
 impl Clone for Error {
     fn clone(&self) -> Self {
         return Error {
@@ -18,3 +26,13 @@ impl Display for Error {
         write!(f, "Error: {}", self.message)
     }
 }
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        return Error {
+            message: e.to_string(),
+        };
+    }
+}
@@ -0,0 +1,328 @@
+use std::sync::atomic::Ordering;
+
+use crate::db::now_ms;
+use crate::notify::NOTIFY_EVICTED;
+use crate::{Database, Entry, Error, ServerState};
+
+/// Number of candidates remembered between eviction rounds, as in Redis.
+const EVPOOL_SIZE: usize = 16;
+
+/// What to do when a write would take the dataset over `maxmemory`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    VolatileTtl,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    NoEviction,
+}
+
+const POLICY_NAMES: &[(MaxmemoryPolicy, &str)] = &[
+    (MaxmemoryPolicy::VolatileLru, "volatile-lru"),
+    (MaxmemoryPolicy::VolatileLfu, "volatile-lfu"),
+    (MaxmemoryPolicy::VolatileRandom, "volatile-random"),
+    (MaxmemoryPolicy::VolatileTtl, "volatile-ttl"),
+    (MaxmemoryPolicy::AllKeysLru, "allkeys-lru"),
+    (MaxmemoryPolicy::AllKeysLfu, "allkeys-lfu"),
+    (MaxmemoryPolicy::AllKeysRandom, "allkeys-random"),
+    (MaxmemoryPolicy::NoEviction, "noeviction"),
+];
+
+impl MaxmemoryPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        return POLICY_NAMES
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(name))
+            .map(|(p, _)| *p);
+    }
+
+    pub fn name(&self) -> &'static str {
+        return POLICY_NAMES.iter().find(|(p, _)| p == self).unwrap().1;
+    }
+
+    /// Every accepted name, comma separated, for error messages.
+    pub fn all_names() -> String {
+        return POLICY_NAMES
+            .iter()
+            .map(|(_, n)| *n)
+            .collect::<Vec<&str>>()
+            .join(", ");
+    }
+
+    pub fn is_lfu(&self) -> bool {
+        return matches!(
+            self,
+            MaxmemoryPolicy::VolatileLfu | MaxmemoryPolicy::AllKeysLfu
+        );
+    }
+
+    /// Whether only keys with a TTL may be evicted.
+    fn is_volatile(&self) -> bool {
+        return matches!(
+            self,
+            MaxmemoryPolicy::VolatileLru
+                | MaxmemoryPolicy::VolatileLfu
+                | MaxmemoryPolicy::VolatileRandom
+                | MaxmemoryPolicy::VolatileTtl
+        );
+    }
+}
+
+struct PoolEntry {
+    /// Higher is a better eviction candidate.
+    score: u64,
+    db: usize,
+    key: Vec<u8>,
+}
+
+/// Eviction bookkeeping kept across calls: the candidate pool of the approximated
+/// LRU/LFU/TTL policies, and the database the random policies visit next.
+#[derive(Default)]
+pub struct EvictionState {
+    /// Sorted by ascending score, so the best candidate is last.
+    pool: Vec<PoolEntry>,
+    next_db: usize,
+}
+
+impl EvictionState {
+    pub fn new() -> Self {
+        return EvictionState::default();
+    }
+
+    /// Samples keys of `db` and merges the good candidates into the pool.
+    fn populate(
+        &mut self,
+        index: usize,
+        db: &Database,
+        policy: MaxmemoryPolicy,
+        samples: usize,
+        now: u64,
+    ) {
+        for _ in 0..samples {
+            let sampled: Option<(&Vec<u8>, &Entry)> = match policy.is_volatile() {
+                true => db.random_volatile_entry(),
+                false => db.random_entry(),
+            };
+            let (key, entry) = match sampled {
+                Some(s) => s,
+                None => return,
+            };
+
+            let score: u64 = match policy {
+                MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu => {
+                    255 - entry.lfu_decayed(&db.lfu, now) as u64
+                }
+                MaxmemoryPolicy::VolatileTtl => u64::MAX - entry.expires_at.unwrap_or(u64::MAX),
+                _ => entry.idle_ms(now),
+            };
+
+            if self.pool.iter().any(|e| e.db == index && e.key == *key) {
+                continue;
+            }
+            if self.pool.len() == EVPOOL_SIZE {
+                if score <= self.pool[0].score {
+                    continue;
+                }
+                self.pool.remove(0);
+            }
+
+            let position: usize = self.pool.partition_point(|e| e.score < score);
+            self.pool.insert(
+                position,
+                PoolEntry {
+                    score,
+                    db: index,
+                    key: key.clone(),
+                },
+            );
+        }
+    }
+
+    /// Picks the next key to evict, or `None` when the policy has nothing to offer.
+    fn select_key(
+        &mut self,
+        dbs: &[&mut Database],
+        policy: MaxmemoryPolicy,
+        samples: usize,
+    ) -> Option<(usize, Vec<u8>)> {
+        if matches!(
+            policy,
+            MaxmemoryPolicy::AllKeysRandom | MaxmemoryPolicy::VolatileRandom
+        ) {
+            // visit the databases in turn so a single one is not emptied first
+            for _ in 0..dbs.len() {
+                self.next_db = (self.next_db + 1) % dbs.len();
+                let db: &Database = dbs[self.next_db];
+                let sampled: Option<(&Vec<u8>, &Entry)> = match policy.is_volatile() {
+                    true => db.random_volatile_entry(),
+                    false => db.random_entry(),
+                };
+                if let Some((key, _)) = sampled {
+                    return Some((self.next_db, key.clone()));
+                }
+            }
+            return None;
+        }
+
+        let now: u64 = now_ms();
+        for (index, db) in dbs.iter().enumerate() {
+            self.populate(index, db, policy, samples, now);
+        }
+
+        // pooled keys may have been deleted or persisted since they were sampled
+        while let Some(candidate) = self.pool.pop() {
+            let valid: bool = match dbs.get(candidate.db).and_then(|db| db.peek(&candidate.key)) {
+                Some(entry) => !policy.is_volatile() || entry.expires_at.is_some(),
+                None => false,
+            };
+            if valid {
+                return Some((candidate.db, candidate.key));
+            }
+        }
+        return None;
+    }
+}
+
+/// Estimated memory used by the dataset, compared against `maxmemory`.
+pub fn used_memory<'a, I: IntoIterator<Item = &'a Database>>(dbs: I) -> usize {
+    return dbs.into_iter().map(|db| db.memory_usage()).sum();
+}
+
+/// Evicts keys according to the configured policy until the dataset fits in
+/// `maxmemory`. Returns false when the limit is still exceeded, in which case
+/// commands that may grow the dataset must be refused.
+///
+/// `shards` must hold the whole keyspace: candidates are remembered by their
+/// position among the databases of every shard.
+pub fn perform_evictions(state: &ServerState, shards: &mut [&mut Vec<Database>]) -> bool {
+    let (maxmemory, policy, samples, lazy) = {
+        let config = state.config.read().unwrap();
+        (
+            config.maxmemory,
+            config.maxmemory_policy,
+            config.maxmemory_samples,
+            config.lazyfree_lazy_eviction,
+        )
+    };
+
+    if maxmemory == 0 {
+        return true;
+    }
+
+    let mut dbs: Vec<&mut Database> = shards.iter_mut().flat_map(|dbs| dbs.iter_mut()).collect();
+    let mut eviction = state.eviction.lock().unwrap();
+    while used_memory(dbs.iter().map(|db| &**db)) > maxmemory {
+        if policy == MaxmemoryPolicy::NoEviction {
+            return false;
+        }
+
+        let (index, key) = match eviction.select_key(&dbs, policy, samples) {
+            Some(k) => k,
+            None => return false,
+        };
+        // the memory estimate drops as soon as the key is detached
+        let entry: Option<Entry> = dbs[index].remove(&key);
+        if let (true, Some(entry)) = (lazy, entry) {
+            state.lazyfree.free_value(entry.value);
+        }
+        dbs[index].notify(NOTIFY_EVICTED, "evicted", &key);
+        state.stats.evicted_keys.fetch_add(1, Ordering::Relaxed);
+    }
+    return true;
+}
+
+pub fn oom_error() -> Error {
+    return Error::new("OOM command not allowed when used memory > 'maxmemory'.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn state_with(policy: &str, maxmemory: usize) -> ServerState {
+        let mut config: Config = Config::default();
+        config.set("maxmemory-policy", policy).unwrap();
+        config.maxmemory = maxmemory;
+        // sample generously so the tiny test keyspaces are always fully covered
+        config.maxmemory_samples = 64;
+        return ServerState::new(config);
+    }
+
+    #[test]
+    fn policy_names_test() {
+        for (policy, name) in POLICY_NAMES {
+            assert_eq!(MaxmemoryPolicy::from_name(name), Some(*policy));
+            assert_eq!(policy.name(), *name);
+        }
+        assert_eq!(
+            MaxmemoryPolicy::from_name("ALLKEYS-LRU"),
+            Some(MaxmemoryPolicy::AllKeysLru)
+        );
+        assert!(MaxmemoryPolicy::from_name("lru").is_none());
+    }
+
+    #[test]
+    fn noeviction_test() {
+        let state: ServerState = state_with("noeviction", 1000);
+        let mut dbs: Vec<Database> = vec![Database::new()];
+        dbs[0].add(b"big", &[0; 2000]);
+        assert!(!perform_evictions(&state, &mut [&mut dbs]));
+        assert_eq!(dbs[0].len(), 1);
+    }
+
+    #[test]
+    fn allkeys_lru_test() {
+        let state: ServerState = state_with("allkeys-lru", 100 * 200);
+        let mut dbs: Vec<Database> = vec![Database::new(), Database::new()];
+        for i in 0..300 {
+            dbs[i % 2].add(format!("key:{}", i).as_bytes(), &[0; 100]);
+        }
+        assert!(perform_evictions(&state, &mut [&mut dbs]));
+        assert!(used_memory(&dbs) <= 100 * 200);
+        assert_eq!(
+            dbs[0].len() + dbs[1].len() + state.stats.evicted_keys.load(Ordering::Relaxed) as usize,
+            300
+        );
+    }
+
+    #[test]
+    fn volatile_policies_only_evict_volatile_keys_test() {
+        for policy in [
+            "volatile-lru",
+            "volatile-random",
+            "volatile-ttl",
+            "volatile-lfu",
+        ] {
+            let state: ServerState = state_with(policy, 1);
+            let mut dbs: Vec<Database> = vec![Database::new()];
+            dbs[0].add(b"persistent", b"value");
+            for i in 0..10 {
+                dbs[0].set(
+                    format!("ttl{}", i).as_bytes(),
+                    vec![],
+                    Some(now_ms() + 60_000),
+                );
+            }
+
+            // every volatile key goes, then there is nothing left to evict
+            assert!(!perform_evictions(&state, &mut [&mut dbs]));
+            assert_eq!(dbs[0].get_keys(), vec![b"persistent".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn volatile_ttl_evicts_nearest_expiry_first_test() {
+        let now: u64 = now_ms();
+        let mut dbs: Vec<Database> = vec![Database::new()];
+        dbs[0].set(b"soon", vec![0; 10], Some(now + 1_000));
+        dbs[0].set(b"later", vec![0; 10], Some(now + 60_000));
+        let state: ServerState = state_with("volatile-ttl", used_memory(&dbs) - 1);
+
+        assert!(perform_evictions(&state, &mut [&mut dbs]));
+        assert_eq!(dbs[0].get_keys(), vec![b"later".to_vec()]);
+    }
+}
@@ -0,0 +1,433 @@
+//! Geohash encoding and the distance math behind the GEO commands.
+//!
+//! Positions are stored in sorted sets, scored by a 52 bit interleaved geohash
+//! of the longitude and latitude, exactly like Redis does, so the coordinates
+//! read back, the distances and the search results match Redis bit for bit.
+
+use std::f64::consts::PI;
+
+/// Bits per coordinate; a score holds twice as many.
+pub const GEO_STEP_MAX: u8 = 26;
+
+/// Latitudes beyond these limits cannot be projected with EPSG:3857.
+pub const GEO_LAT_MIN: f64 = -85.05112878;
+pub const GEO_LAT_MAX: f64 = 85.05112878;
+pub const GEO_LONG_MIN: f64 = -180.0;
+pub const GEO_LONG_MAX: f64 = 180.0;
+
+/// The earth radius Redis uses for its haversine distances.
+pub const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+
+/// Half the circumference of the earth in the Mercator projection.
+const MERCATOR_MAX: f64 = 20037726.37;
+
+const GEO_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// An interleaved geohash of `step` bits per coordinate: latitude in the even
+/// bits, longitude in the odd ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GeoHash {
+    pub bits: u64,
+    pub step: u8,
+}
+
+impl GeoHash {
+    /// The score range `[min, max)` of the members inside this cell.
+    pub fn score_range(&self) -> (f64, f64) {
+        let shift: u8 = GEO_STEP_MAX * 2 - self.step * 2;
+        let min: u64 = self.bits << shift;
+        let max: u64 = (self.bits + 1) << shift;
+        return (min as f64, max as f64);
+    }
+
+    fn is_zero(&self) -> bool {
+        return self.bits == 0 && self.step == 0;
+    }
+
+    /// The neighbouring cell `dx` cells east and `dy` cells north, wrapping around.
+    fn moved(&self, dx: i8, dy: i8) -> GeoHash {
+        let mut hash: GeoHash = *self;
+        hash.move_x(dx);
+        hash.move_y(dy);
+        return hash;
+    }
+
+    fn move_x(&mut self, d: i8) {
+        if d == 0 {
+            return;
+        }
+        let mut x: u64 = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let y: u64 = self.bits & 0x5555555555555555;
+        let zz: u64 = 0x5555555555555555 >> (64 - self.step as u32 * 2);
+        if d > 0 {
+            x = x.wrapping_add(zz + 1);
+        } else {
+            x |= zz;
+            x = x.wrapping_sub(zz + 1);
+        }
+        x &= 0xaaaaaaaaaaaaaaaa >> (64 - self.step as u32 * 2);
+        self.bits = x | y;
+    }
+
+    fn move_y(&mut self, d: i8) {
+        if d == 0 {
+            return;
+        }
+        let x: u64 = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let mut y: u64 = self.bits & 0x5555555555555555;
+        let zz: u64 = 0xaaaaaaaaaaaaaaaa >> (64 - self.step as u32 * 2);
+        if d > 0 {
+            y = y.wrapping_add(zz + 1);
+        } else {
+            y |= zz;
+            y = y.wrapping_sub(zz + 1);
+        }
+        y &= 0x5555555555555555 >> (64 - self.step as u32 * 2);
+        self.bits = x | y;
+    }
+}
+
+/// The area covered by a geohash cell.
+#[derive(Clone, Copy, Debug)]
+struct GeoArea {
+    long_min: f64,
+    long_max: f64,
+    lat_min: f64,
+    lat_max: f64,
+}
+
+/// Spreads the low 32 bits of `x` over the even bits and those of `y` over the odd bits.
+fn interleave(x: u32, y: u32) -> u64 {
+    const B: [u64; 5] = [
+        0x5555555555555555,
+        0x3333333333333333,
+        0x0F0F0F0F0F0F0F0F,
+        0x00FF00FF00FF00FF,
+        0x0000FFFF0000FFFF,
+    ];
+    const S: [u32; 5] = [1, 2, 4, 8, 16];
+
+    let mut x: u64 = x as u64;
+    let mut y: u64 = y as u64;
+    for i in (0..5).rev() {
+        x = (x | (x << S[i])) & B[i];
+        y = (y | (y << S[i])) & B[i];
+    }
+    return x | (y << 1);
+}
+
+/// The inverse of `interleave`: the even bits end up in the low half, the odd ones in the high half.
+fn deinterleave(interleaved: u64) -> u64 {
+    const B: [u64; 6] = [
+        0x5555555555555555,
+        0x3333333333333333,
+        0x0F0F0F0F0F0F0F0F,
+        0x00FF00FF00FF00FF,
+        0x0000FFFF0000FFFF,
+        0x00000000FFFFFFFF,
+    ];
+    const S: [u32; 6] = [0, 1, 2, 4, 8, 16];
+
+    let mut x: u64 = interleaved;
+    let mut y: u64 = interleaved >> 1;
+    for i in 0..6 {
+        x = (x | (x >> S[i])) & B[i];
+        y = (y | (y >> S[i])) & B[i];
+    }
+    return x | (y << 32);
+}
+
+/// Whether a position can be stored, following the EPSG:3857 limits.
+pub fn valid_position(longitude: f64, latitude: f64) -> bool {
+    return (GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude)
+        && (GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude);
+}
+
+fn encode_in(lat_range: (f64, f64), longitude: f64, latitude: f64, step: u8) -> GeoHash {
+    let scale: f64 = (1u64 << step) as f64;
+    let lat_offset: f64 = (latitude - lat_range.0) / (lat_range.1 - lat_range.0) * scale;
+    let long_offset: f64 = (longitude - GEO_LONG_MIN) / (GEO_LONG_MAX - GEO_LONG_MIN) * scale;
+    return GeoHash {
+        bits: interleave(lat_offset as u32, long_offset as u32),
+        step,
+    };
+}
+
+/// Encodes a position with `step` bits per coordinate.
+pub fn encode(longitude: f64, latitude: f64, step: u8) -> GeoHash {
+    return encode_in((GEO_LAT_MIN, GEO_LAT_MAX), longitude, latitude, step);
+}
+
+/// The score a member at this position is stored with.
+pub fn encode_score(longitude: f64, latitude: f64) -> f64 {
+    return encode(longitude, latitude, GEO_STEP_MAX).bits as f64;
+}
+
+fn decode_area(hash: GeoHash) -> GeoArea {
+    let separated: u64 = deinterleave(hash.bits);
+    let lat_cell: f64 = (separated & 0xffffffff) as f64;
+    let long_cell: f64 = (separated >> 32) as f64;
+    let cells: f64 = (1u64 << hash.step) as f64;
+    let lat_scale: f64 = GEO_LAT_MAX - GEO_LAT_MIN;
+    let long_scale: f64 = GEO_LONG_MAX - GEO_LONG_MIN;
+    return GeoArea {
+        lat_min: GEO_LAT_MIN + (lat_cell / cells) * lat_scale,
+        lat_max: GEO_LAT_MIN + ((lat_cell + 1.0) / cells) * lat_scale,
+        long_min: GEO_LONG_MIN + (long_cell / cells) * long_scale,
+        long_max: GEO_LONG_MIN + ((long_cell + 1.0) / cells) * long_scale,
+    };
+}
+
+/// The position at the center of the cell a score designates.
+pub fn decode_score(score: f64) -> (f64, f64) {
+    let area: GeoArea = decode_area(GeoHash {
+        bits: score as u64,
+        step: GEO_STEP_MAX,
+    });
+    let longitude: f64 = ((area.long_min + area.long_max) / 2.0).clamp(GEO_LONG_MIN, GEO_LONG_MAX);
+    let latitude: f64 = ((area.lat_min + area.lat_max) / 2.0).clamp(GEO_LAT_MIN, GEO_LAT_MAX);
+    return (longitude, latitude);
+}
+
+/// The 11 character base32 geohash GEOHASH replies with. Unlike scores it is
+/// computed over the standard -90..90 latitude range, so other tools read it.
+pub fn geohash_string(score: f64) -> String {
+    let (longitude, latitude) = decode_score(score);
+    let hash: GeoHash = encode_in((-90.0, 90.0), longitude, latitude, GEO_STEP_MAX);
+    return (0..11)
+        .map(|i| {
+            // 52 bits only fill 10 characters, the last one is always '0'
+            let index: u64 = match i {
+                10 => 0,
+                _ => (hash.bits >> (52 - (i + 1) * 5)) & 0x1f,
+            };
+            return GEO_ALPHABET[index as usize] as char;
+        })
+        .collect();
+}
+
+fn deg_rad(angle: f64) -> f64 {
+    return angle * (PI / 180.0);
+}
+
+fn rad_deg(angle: f64) -> f64 {
+    return angle / (PI / 180.0);
+}
+
+/// Distance in meters between two latitudes on the same meridian.
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    return EARTH_RADIUS_IN_METERS * (deg_rad(lat2) - deg_rad(lat1)).abs();
+}
+
+/// Haversine distance in meters between two positions.
+pub fn distance(long1: f64, lat1: f64, long2: f64, lat2: f64) -> f64 {
+    let v: f64 = ((deg_rad(long2) - deg_rad(long1)) / 2.0).sin();
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let lat1r: f64 = deg_rad(lat1);
+    let lat2r: f64 = deg_rad(lat2);
+    let u: f64 = ((lat2r - lat1r) / 2.0).sin();
+    let a: f64 = u * u + lat1r.cos() * lat2r.cos() * v * v;
+    return 2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin();
+}
+
+/// The area a GEOSEARCH looks into, in meters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GeoShape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+impl GeoShape {
+    /// The distance from `center` to a point if the point lies within the shape.
+    pub fn contains(&self, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+        match *self {
+            GeoShape::Radius(radius) => {
+                let d: f64 = distance(center.0, center.1, point.0, point.1);
+                return if d > radius { None } else { Some(d) };
+            }
+            GeoShape::Box { width, height } => {
+                if lat_distance(point.1, center.1) > height / 2.0 {
+                    return None;
+                }
+                if distance(point.0, point.1, center.0, point.1) > width / 2.0 {
+                    return None;
+                }
+                return Some(distance(center.0, center.1, point.0, point.1));
+            }
+        }
+    }
+
+    /// Distance from the center to the farthest point of the shape.
+    fn reach(&self) -> f64 {
+        return match *self {
+            GeoShape::Radius(radius) => radius,
+            GeoShape::Box { width, height } => {
+                ((width / 2.0) * (width / 2.0) + (height / 2.0) * (height / 2.0)).sqrt()
+            }
+        };
+    }
+
+    /// The `(min_long, min_lat, max_long, max_lat)` box around the shape.
+    fn bounding_box(&self, center: (f64, f64)) -> (f64, f64, f64, f64) {
+        let (longitude, latitude) = center;
+        let (half_width, half_height) = match *self {
+            GeoShape::Radius(radius) => (radius, radius),
+            GeoShape::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+        let lat_delta: f64 = rad_deg(half_height / EARTH_RADIUS_IN_METERS);
+        let long_delta_top: f64 =
+            rad_deg(half_width / EARTH_RADIUS_IN_METERS / deg_rad(latitude + lat_delta).cos());
+        let long_delta_bottom: f64 =
+            rad_deg(half_width / EARTH_RADIUS_IN_METERS / deg_rad(latitude - lat_delta).cos());
+        // the widest edge is the one nearer to the equator
+        let long_delta: f64 = match latitude < 0.0 {
+            true => long_delta_bottom,
+            false => long_delta_top,
+        };
+        return (
+            longitude - long_delta,
+            latitude - lat_delta,
+            longitude + long_delta,
+            latitude + lat_delta,
+        );
+    }
+}
+
+/// The precision at which a cell and its neighbours cover `range` meters around `latitude`.
+fn estimate_steps(range: f64, latitude: f64) -> u8 {
+    if range == 0.0 {
+        return GEO_STEP_MAX;
+    }
+    let mut range: f64 = range;
+    let mut step: i32 = 1;
+    while range < MERCATOR_MAX {
+        range *= 2.0;
+        step += 1;
+    }
+    // make sure the neighbours cover the whole range
+    step -= 2;
+
+    // cells get narrower near the poles
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    return step.clamp(1, GEO_STEP_MAX as i32) as u8;
+}
+
+/// The cells to scan for a search around `center`, in the order Redis visits
+/// them: the center cell, then N, S, E, W, NE, NW, SE and SW. Cells that cannot
+/// hold a match are left out.
+pub fn search_areas(shape: &GeoShape, center: (f64, f64)) -> Vec<GeoHash> {
+    let (longitude, latitude) = center;
+    let (min_long, min_lat, max_long, max_lat) = shape.bounding_box(center);
+
+    let mut steps: u8 = estimate_steps(shape.reach(), latitude);
+    let mut hash: GeoHash = encode(longitude, latitude, steps);
+    let mut neighbours: [GeoHash; 9] = neighbours(hash);
+    let mut area: GeoArea = decode_area(hash);
+
+    // the estimate can fall short of the bounding box, one step less doubles the cells
+    let too_small: bool = decode_area(neighbours[1]).lat_max < max_lat
+        || decode_area(neighbours[2]).lat_min > min_lat
+        || decode_area(neighbours[3]).long_max < max_long
+        || decode_area(neighbours[4]).long_min > min_long;
+    if steps > 1 && too_small {
+        steps -= 1;
+        hash = encode(longitude, latitude, steps);
+        neighbours = self::neighbours(hash);
+        area = decode_area(hash);
+    }
+
+    if steps >= 2 {
+        let mut useless: Vec<usize> = Vec::new();
+        if area.lat_min < min_lat {
+            useless.extend([2, 7, 8]);
+        }
+        if area.lat_max > max_lat {
+            useless.extend([1, 5, 6]);
+        }
+        if area.long_min < min_long {
+            useless.extend([4, 8, 6]);
+        }
+        if area.long_max > max_long {
+            useless.extend([3, 7, 5]);
+        }
+        for i in useless {
+            neighbours[i] = GeoHash::default();
+        }
+    }
+
+    let mut areas: Vec<GeoHash> = Vec::with_capacity(9);
+    for cell in neighbours {
+        // with huge radii neighbours can be the same cell, which is scanned once
+        if cell.is_zero() || areas.last() == Some(&cell) {
+            continue;
+        }
+        areas.push(cell);
+    }
+    return areas;
+}
+
+fn neighbours(hash: GeoHash) -> [GeoHash; 9] {
+    return [
+        hash,
+        hash.moved(0, 1),
+        hash.moved(0, -1),
+        hash.moved(1, 0),
+        hash.moved(-1, 0),
+        hash.moved(1, 1),
+        hash.moved(-1, 1),
+        hash.moved(1, -1),
+        hash.moved(-1, -1),
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_test() {
+        // the Palermo and Catania examples from the GEOADD documentation
+        let palermo: f64 = encode_score(13.361389, 38.115556);
+        assert_eq!(palermo, 3479099956230698.0);
+        let (longitude, latitude) = decode_score(palermo);
+        assert!((longitude - 13.361389338970184).abs() < 1e-12);
+        assert!((latitude - 38.1155563954963).abs() < 1e-12);
+        assert_eq!(geohash_string(palermo), "sqc8b49rny0");
+
+        let catania: f64 = encode_score(15.087269, 37.502669);
+        assert_eq!(geohash_string(catania), "sqdtr74hyu0");
+        let (long2, lat2) = decode_score(catania);
+        let meters: f64 = distance(longitude, latitude, long2, lat2);
+        assert_eq!(format!("{:.4}", meters), "166274.1516");
+    }
+
+    #[test]
+    fn search_areas_test() {
+        let center: (f64, f64) = (15.0, 37.0);
+        let shape: GeoShape = GeoShape::Radius(200_000.0);
+        let areas: Vec<GeoHash> = search_areas(&shape, center);
+        assert!(!areas.is_empty() && areas.len() <= 9);
+
+        // every cell within the radius is covered by one of the areas
+        let catania: f64 = encode_score(15.087269, 37.502669);
+        assert!(areas.iter().any(|a| {
+            let (min, max) = a.score_range();
+            return (min..max).contains(&catania);
+        }));
+        assert_eq!(
+            GeoShape::Box {
+                width: 10.0,
+                height: 10.0
+            }
+            .contains(center, (15.0, 37.1)),
+            None
+        );
+    }
+}
@@ -0,0 +1,150 @@
+/// Glob-style matching as used by KEYS and CONFIG GET.
+///
+/// Supports `*`, `?`, `[...]` (with `^` negation and `a-z` ranges) and `\` escapes.
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let mut p: usize = 0;
+    let mut s: usize = 0;
+
+    // position to resume from when the last `*` needs to swallow another byte
+    let mut star: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    while p < pattern.len() && pattern[p] == b'*' {
+                        p += 1;
+                    }
+                    if p == pattern.len() {
+                        return true;
+                    }
+                    star = Some((p, s));
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    s += 1;
+                    continue;
+                }
+                b'[' => {
+                    if let Some(next) = match_class(pattern, p, string[s], nocase) {
+                        p = next;
+                        s += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if eq(pattern[p + 1], string[s], nocase) {
+                        p += 2;
+                        s += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if eq(c, string[s], nocase) {
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        match star {
+            Some((star_p, star_s)) => {
+                p = star_p;
+                s = star_s + 1;
+                star = Some((star_p, star_s + 1));
+            }
+            None => return false,
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == b'*' {
+        p += 1;
+    }
+    return p == pattern.len();
+}
+
+/// Matches `c` against the class starting at `pattern[start] == b'['`, returning the
+/// index just past the closing `]` on success.
+fn match_class(pattern: &[u8], start: usize, c: u8, nocase: bool) -> Option<usize> {
+    let mut p: usize = start + 1;
+    let negate: bool = p < pattern.len() && pattern[p] == b'^';
+    if negate {
+        p += 1;
+    }
+
+    let mut matched: bool = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            p += 1;
+            if eq(pattern[p], c, nocase) {
+                matched = true;
+            }
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (mut low, mut high) = (pattern[p], pattern[p + 2]);
+            if low > high {
+                std::mem::swap(&mut low, &mut high);
+            }
+            let (low, high, c) = match nocase {
+                true => (
+                    low.to_ascii_lowercase(),
+                    high.to_ascii_lowercase(),
+                    c.to_ascii_lowercase(),
+                ),
+                false => (low, high, c),
+            };
+            if c >= low && c <= high {
+                matched = true;
+            }
+            p += 2;
+        } else if eq(pattern[p], c, nocase) {
+            matched = true;
+        }
+        p += 1;
+    }
+
+    // an unterminated class is treated as ending at the end of the pattern
+    let end: usize = (p + 1).min(pattern.len());
+    if matched != negate {
+        return Some(end);
+    }
+    return None;
+}
+
+fn eq(a: u8, b: u8, nocase: bool) -> bool {
+    if nocase {
+        return a.eq_ignore_ascii_case(&b);
+    }
+    return a == b;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_and_question_test() {
+        assert!(glob_match(b"*", b"anything", false));
+        assert!(glob_match(b"h?llo", b"hello", false));
+        assert!(glob_match(b"h*llo", b"heeeello", false));
+        assert!(!glob_match(b"h*llo", b"heeeell", false));
+        assert!(glob_match(b"*o*o*", b"foo:bar:boo", false));
+    }
+
+    #[test]
+    fn class_test() {
+        assert!(glob_match(b"h[ae]llo", b"hallo", false));
+        assert!(!glob_match(b"h[^e]llo", b"hello", false));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo", false));
+        assert!(!glob_match(b"h[a-b]llo", b"hcllo", false));
+    }
+
+    #[test]
+    fn escape_and_nocase_test() {
+        assert!(glob_match(b"h\\*llo", b"h*llo", false));
+        assert!(!glob_match(b"h\\*llo", b"hello", false));
+        assert!(glob_match(b"MAXMEMORY*", b"maxmemory-policy", true));
+    }
+}
@@ -0,0 +1,426 @@
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+use crate::db::now_ms;
+use crate::module::LoadedModule;
+use crate::replication::{LinkState, ReplicationStatus};
+use crate::{Database, ServerState};
+
+/// Sections returned by a plain INFO, in output order.
+pub const DEFAULT_SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "cpu",
+    "keyspace",
+];
+
+/// Sections added by INFO ALL on top of the defaults.
+pub const EXTRA_SECTIONS: &[&str] = &["modules"];
+
+/// Resolves the section arguments of INFO into the list of sections to render.
+pub fn select_sections(args: &[String]) -> Vec<&'static str> {
+    if args.is_empty() {
+        return DEFAULT_SECTIONS.to_vec();
+    }
+
+    let mut sections: Vec<&'static str> = Vec::new();
+    for arg in args {
+        let wanted: Vec<&'static str> = match arg.to_lowercase().as_str() {
+            "default" => DEFAULT_SECTIONS.to_vec(),
+            "all" | "everything" => [DEFAULT_SECTIONS, EXTRA_SECTIONS].concat(),
+            other => DEFAULT_SECTIONS
+                .iter()
+                .chain(EXTRA_SECTIONS.iter())
+                .filter(|s| **s == other)
+                .copied()
+                .collect(),
+        };
+        for section in wanted {
+            if !sections.contains(&section) {
+                sections.push(section);
+            }
+        }
+    }
+    return sections;
+}
+
+/// Renders the requested INFO sections as `key:value` lines, from the
+/// databases of every shard of the keyspace.
+pub fn generate_info(state: &ServerState, shards: &[&Vec<Database>], sections: &[&str]) -> String {
+    let dbs: Vec<&Database> = shards.iter().flat_map(|dbs| dbs.iter()).collect();
+    let mut out: String = String::new();
+
+    for (i, section) in sections.iter().enumerate() {
+        if i > 0 {
+            out.push_str("\r\n");
+        }
+
+        let mut title: String = section.to_string();
+        title[..1].make_ascii_uppercase();
+        let _ = write!(out, "# {}\r\n", title);
+
+        let fields: Vec<(String, String)> = match *section {
+            "server" => server_section(state),
+            "clients" => clients_section(state),
+            "memory" => memory_section(state, &dbs),
+            "persistence" => persistence_section(state, &dbs),
+            "stats" => stats_section(state, &dbs),
+            "replication" => replication_section(state),
+            "cpu" => cpu_section(),
+            "keyspace" => keyspace_section(shards),
+            "modules" => modules_section(state),
+            _ => Vec::new(),
+        };
+
+        for (name, value) in fields {
+            let _ = write!(out, "{}:{}\r\n", name, value);
+        }
+    }
+
+    return out;
+}
+
+fn field(name: &str, value: impl ToString) -> (String, String) {
+    return (name.to_string(), value.to_string());
+}
+
+fn server_section(state: &ServerState) -> Vec<(String, String)> {
+    let uptime: u64 = state.stats.started.elapsed().as_secs();
+    let executable: String = std::env::current_exe()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    return vec![
+        field("redis_version", "7.2.0"),
+        field("redis_mode", "standalone"),
+        field(
+            "os",
+            format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        ),
+        field("arch_bits", usize::BITS),
+        field("process_id", std::process::id()),
+        field("run_id", &state.run_id),
+        field("uptime_in_seconds", uptime),
+        field("uptime_in_days", uptime / 86400),
+        field("hz", 10),
+        field("executable", executable),
+    ];
+}
+
+fn clients_section(state: &ServerState) -> Vec<(String, String)> {
+    return vec![
+        field(
+            "connected_clients",
+            state.stats.connected_clients.load(Ordering::Relaxed),
+        ),
+        field("blocked_clients", 0),
+    ];
+}
+
+fn memory_section(state: &ServerState, dbs: &[&Database]) -> Vec<(String, String)> {
+    let used: usize = dbs.iter().map(|db| db.memory_usage()).sum();
+    let peak: u64 = state
+        .stats
+        .used_memory_peak
+        .fetch_max(used as u64, Ordering::Relaxed)
+        .max(used as u64);
+    let (maxmemory, policy) = {
+        let config = state.config.read().unwrap();
+        (config.maxmemory, config.maxmemory_policy)
+    };
+    let rss: usize = resident_memory();
+    let fragmentation: f64 = match used {
+        0 => 0.0,
+        _ => rss as f64 / used as f64,
+    };
+
+    return vec![
+        field("used_memory", used),
+        field("used_memory_human", bytes_to_human(used as u64)),
+        field("used_memory_rss", rss),
+        field("used_memory_rss_human", bytes_to_human(rss as u64)),
+        field("used_memory_peak", peak),
+        field("used_memory_peak_human", bytes_to_human(peak)),
+        field("maxmemory", maxmemory),
+        field("maxmemory_human", bytes_to_human(maxmemory as u64)),
+        field("maxmemory_policy", policy.name()),
+        field("mem_fragmentation_ratio", format!("{:.2}", fragmentation)),
+        field("lazyfree_pending_objects", state.lazyfree.pending_objects()),
+        field("lazyfreed_objects", state.lazyfree.freed_objects()),
+    ];
+}
+
+fn persistence_section(state: &ServerState, dbs: &[&Database]) -> Vec<(String, String)> {
+    let dirty: u64 = dbs.iter().map(|db| db.stats.dirty).sum();
+    let changes: u64 = dirty.saturating_sub(state.dirty_at_last_save.load(Ordering::Relaxed));
+    let status: &str = match state.last_bgsave_ok.load(Ordering::Relaxed) {
+        true => "ok",
+        false => "err",
+    };
+
+    return vec![
+        field("loading", 0),
+        field("rdb_changes_since_last_save", changes),
+        field(
+            "rdb_bgsave_in_progress",
+            state.bgsave_in_progress.load(Ordering::Relaxed) as u8,
+        ),
+        field("rdb_last_save_time", state.lastsave.load(Ordering::Relaxed)),
+        field("rdb_last_bgsave_status", status),
+        field(
+            "rdb_last_bgsave_time_sec",
+            state.last_bgsave_time_sec.load(Ordering::Relaxed),
+        ),
+        field("aof_enabled", 0),
+        field("aof_rewrite_in_progress", 0),
+    ];
+}
+
+fn stats_section(state: &ServerState, dbs: &[&Database]) -> Vec<(String, String)> {
+    let stats = &state.stats;
+    return vec![
+        field(
+            "total_connections_received",
+            stats.total_connections_received.load(Ordering::Relaxed),
+        ),
+        field(
+            "total_commands_processed",
+            stats.total_commands_processed.load(Ordering::Relaxed),
+        ),
+        field(
+            "instantaneous_ops_per_sec",
+            stats.instantaneous_ops_per_sec(),
+        ),
+        field(
+            "total_net_input_bytes",
+            stats.total_net_input_bytes.load(Ordering::Relaxed),
+        ),
+        field(
+            "total_net_output_bytes",
+            stats.total_net_output_bytes.load(Ordering::Relaxed),
+        ),
+        field(
+            "instantaneous_input_kbps",
+            format!("{:.2}", stats.instantaneous_input_kbps()),
+        ),
+        field(
+            "instantaneous_output_kbps",
+            format!("{:.2}", stats.instantaneous_output_kbps()),
+        ),
+        field(
+            "expired_keys",
+            dbs.iter().map(|db| db.stats.expired).sum::<u64>(),
+        ),
+        field("evicted_keys", stats.evicted_keys.load(Ordering::Relaxed)),
+        field(
+            "keyspace_hits",
+            dbs.iter().map(|db| db.stats.hits).sum::<u64>(),
+        ),
+        field(
+            "keyspace_misses",
+            dbs.iter().map(|db| db.stats.misses).sum::<u64>(),
+        ),
+    ];
+}
+
+fn replication_section(state: &ServerState) -> Vec<(String, String)> {
+    let status: ReplicationStatus = state.replication.status();
+    let mut fields: Vec<(String, String)> = Vec::new();
+    match &status.master {
+        Some(master) => {
+            let priority: u64 = state.config.read().unwrap().replica_priority;
+            let connected: bool = status.link_state == LinkState::Connected;
+            fields.push(field("role", "slave"));
+            fields.push(field("master_host", &master.host));
+            fields.push(field("master_port", master.port));
+            fields.push(field(
+                "master_link_status",
+                if connected { "up" } else { "down" },
+            ));
+            fields.push(field(
+                "master_last_io_seconds_ago",
+                match connected {
+                    true => status.last_io_secs as i64,
+                    false => -1,
+                },
+            ));
+            fields.push(field(
+                "master_sync_in_progress",
+                (status.link_state == LinkState::Sync) as u8,
+            ));
+            fields.push(field("slave_repl_offset", status.offset));
+            if let Some(secs) = status.link_down_secs {
+                fields.push(field("master_link_down_since_seconds", secs));
+            }
+            fields.push(field("slave_priority", priority));
+            fields.push(field("slave_read_only", 1));
+        }
+        None => fields.push(field("role", "master")),
+    }
+
+    fields.push(field("connected_slaves", status.replicas.len()));
+    for (i, (ip, port)) in status.replicas.iter().enumerate() {
+        fields.push(field(
+            &format!("slave{}", i),
+            format!(
+                "ip={},port={},state=online,offset={},lag=0",
+                ip, port, status.offset
+            ),
+        ));
+    }
+    fields.push(field("master_replid", &status.replid));
+    fields.push(field("master_repl_offset", status.offset));
+    return fields;
+}
+
+fn cpu_section() -> Vec<(String, String)> {
+    let (user, sys) = cpu_times();
+    return vec![
+        field("used_cpu_sys", format!("{:.6}", sys)),
+        field("used_cpu_user", format!("{:.6}", user)),
+    ];
+}
+
+fn modules_section(state: &ServerState) -> Vec<(String, String)> {
+    return state
+        .modules
+        .loaded()
+        .iter()
+        .map(|m: &LoadedModule| {
+            field(
+                "module",
+                format!(
+                    "name={},ver={},args=[{}]",
+                    m.name,
+                    m.version,
+                    m.args.join(" ")
+                ),
+            )
+        })
+        .collect();
+}
+
+fn keyspace_section(shards: &[&Vec<Database>]) -> Vec<(String, String)> {
+    let now: u64 = now_ms();
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let databases: usize = shards.first().map(|dbs| dbs.len()).unwrap_or(0);
+
+    for i in 0..databases {
+        let (keys, expires, total_ttl) = shards.iter().fold((0, 0, 0), |(k, e, t), dbs| {
+            return (
+                k + dbs[i].len(),
+                e + dbs[i].expires_len(),
+                t + dbs[i].total_ttl(now),
+            );
+        });
+        if keys == 0 {
+            continue;
+        }
+
+        let avg_ttl: u64 = match expires {
+            0 => 0,
+            n => total_ttl / n as u64,
+        };
+        fields.push(field(
+            &format!("db{}", i),
+            format!("keys={},expires={},avg_ttl={}", keys, expires, avg_ttl),
+        ));
+    }
+
+    return fields;
+}
+
+/// Formats a byte count the way INFO does, e.g. `1.50M`.
+pub fn bytes_to_human(bytes: u64) -> String {
+    let units: [(u64, &str); 5] = [
+        (1 << 50, "P"),
+        (1 << 40, "T"),
+        (1 << 30, "G"),
+        (1 << 20, "M"),
+        (1 << 10, "K"),
+    ];
+    for (size, unit) in units {
+        if bytes >= size {
+            return format!("{:.2}{}", bytes as f64 / size as f64, unit);
+        }
+    }
+    return format!("{}B", bytes);
+}
+
+/// Resident set size of this process in bytes, or 0 where /proc is unavailable.
+pub fn resident_memory() -> usize {
+    let statm: String = std::fs::read_to_string("/proc/self/statm").unwrap_or_default();
+    let pages: usize = statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(0);
+    return pages * 4096;
+}
+
+/// User and system CPU seconds consumed by this process, read from /proc.
+fn cpu_times() -> (f64, f64) {
+    let stat: String = std::fs::read_to_string("/proc/self/stat").unwrap_or_default();
+
+    // the command name may contain spaces, so count fields after its closing paren
+    let fields: Vec<&str> = match stat.rfind(')') {
+        Some(i) => stat[i + 1..].split_whitespace().collect(),
+        None => return (0.0, 0.0),
+    };
+    let ticks = |i: usize| -> f64 {
+        return fields
+            .get(i)
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(0.0)
+            / 100.0;
+    };
+
+    // utime and stime are fields 14 and 15 of /proc/<pid>/stat
+    return (ticks(11), ticks(12));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn select_sections_test() {
+        assert_eq!(select_sections(&[]), DEFAULT_SECTIONS.to_vec());
+        assert_eq!(
+            select_sections(&["Keyspace".to_string(), "server".to_string()]),
+            vec!["keyspace", "server"]
+        );
+        assert!(select_sections(&["nope".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn keyspace_and_stats_test() {
+        let state: ServerState = ServerState::new(Config::default());
+        let mut dbs: Vec<Database> = vec![Database::new(), Database::new(), Database::new()];
+        dbs[0].add(b"a", b"1");
+        dbs[2].add(b"b", b"2");
+        dbs[2].set(b"c", b"3".to_vec(), Some(now_ms() + 100_000));
+        dbs[0].get(b"a");
+        dbs[0].get(b"missing");
+
+        let info: String = generate_info(&state, &[&dbs], &["keyspace", "stats"]);
+        assert!(info.starts_with("# Keyspace\r\ndb0:keys=1,expires=0,avg_ttl=0\r\n"));
+        assert!(info.contains("db2:keys=2,expires=1,avg_ttl="));
+        assert!(!info.contains("db1:"));
+        assert!(info.contains("\r\n\r\n# Stats\r\n"));
+        assert!(info.contains("keyspace_hits:1\r\n"));
+        assert!(info.contains("keyspace_misses:1\r\n"));
+    }
+
+    #[test]
+    fn bytes_to_human_test() {
+        assert_eq!(bytes_to_human(512), "512B");
+        assert_eq!(bytes_to_human(1536), "1.50K");
+        assert_eq!(bytes_to_human(3 * 1024 * 1024), "3.00M");
+    }
+}
@@ -0,0 +1,166 @@
+/// Bytes of header Redis puts in front of every intset (encoding and length).
+pub const INTSET_HEADER: usize = 8;
+
+/// A sorted set of integers stored in a single array, in the style of the Redis
+/// intset.
+///
+/// Every element takes the same width, 2, 4 or 8 bytes, the smallest that holds
+/// them all; adding a larger integer upgrades the whole array. Membership is a
+/// binary search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Intset {
+    width: usize,
+    contents: Vec<u8>,
+}
+
+impl Default for Intset {
+    fn default() -> Self {
+        return Intset {
+            width: 2,
+            contents: Vec::new(),
+        };
+    }
+}
+
+fn width_of(value: i64) -> usize {
+    if i16::try_from(value).is_ok() {
+        return 2;
+    }
+    if i32::try_from(value).is_ok() {
+        return 4;
+    }
+    return 8;
+}
+
+/// Parses `member` when it is an integer Redis would store in an intset, i.e.
+/// written exactly as it would be printed back.
+pub fn parse_member(member: &[u8]) -> Option<i64> {
+    if member.len() > 20 {
+        return None;
+    }
+    let value: i64 = std::str::from_utf8(member).ok()?.parse::<i64>().ok()?;
+    return match value.to_string().as_bytes() == member {
+        true => Some(value),
+        false => None,
+    };
+}
+
+impl Intset {
+    pub fn new() -> Self {
+        return Intset::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.contents.len() / self.width;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.contents.is_empty();
+    }
+
+    /// Size of the array plus the header, as Redis reports it.
+    pub fn bytes(&self) -> usize {
+        return INTSET_HEADER + self.contents.len();
+    }
+
+    fn get(&self, index: usize) -> i64 {
+        let bytes: &[u8] = &self.contents[index * self.width..(index + 1) * self.width];
+        return match self.width {
+            2 => i16::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            4 => i32::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(bytes.try_into().unwrap()),
+        };
+    }
+
+    fn encode(value: i64, width: usize) -> Vec<u8> {
+        return match width {
+            2 => (value as i16).to_le_bytes().to_vec(),
+            4 => (value as i32).to_le_bytes().to_vec(),
+            _ => value.to_le_bytes().to_vec(),
+        };
+    }
+
+    /// Where `value` is, or where it would go.
+    fn search(&self, value: i64) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid: usize = (low + high) / 2;
+            match self.get(mid).cmp(&value) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
+        return Err(low);
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        return width_of(value) <= self.width && self.search(value).is_ok();
+    }
+
+    /// Adds `value`, returning false when it was already there.
+    pub fn insert(&mut self, value: i64) -> bool {
+        let width: usize = width_of(value);
+        if width > self.width {
+            let values: Vec<i64> = self.iter().collect();
+            self.contents = values
+                .into_iter()
+                .flat_map(|v| Intset::encode(v, width))
+                .collect();
+            self.width = width;
+        }
+
+        return match self.search(value) {
+            Ok(_) => false,
+            Err(index) => {
+                let at: usize = index * self.width;
+                self.contents
+                    .splice(at..at, Intset::encode(value, self.width));
+                true
+            }
+        };
+    }
+
+    /// The integers in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = i64> + '_ {
+        return (0..self.len()).map(|i| self.get(i));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_upgrade_test() {
+        let mut set: Intset = Intset::new();
+        assert!(set.insert(5));
+        assert!(set.insert(-3));
+        assert!(!set.insert(5));
+        assert_eq!(set.bytes(), INTSET_HEADER + 4);
+
+        // a value that needs 4 bytes widens every element
+        assert!(set.insert(100_000));
+        assert_eq!(set.bytes(), INTSET_HEADER + 12);
+        assert!(set.insert(i64::MIN));
+        assert_eq!(set.bytes(), INTSET_HEADER + 32);
+
+        assert_eq!(
+            set.iter().collect::<Vec<i64>>(),
+            vec![i64::MIN, -3, 5, 100_000]
+        );
+        assert!(set.contains(-3));
+        assert!(!set.contains(4));
+        assert_eq!(set.len(), 4);
+    }
+
+    #[test]
+    fn parse_member_test() {
+        assert_eq!(parse_member(b"42"), Some(42));
+        assert_eq!(parse_member(b"-9223372036854775808"), Some(i64::MIN));
+        assert_eq!(parse_member(b"042"), None);
+        assert_eq!(parse_member(b"+1"), None);
+        assert_eq!(parse_member(b"1.0"), None);
+        assert_eq!(parse_member(b"9223372036854775808"), None);
+    }
+}
@@ -0,0 +1,257 @@
+//! The keyspace, split into independently locked shards.
+//!
+//! Every shard holds one `Database` per logical database with the keys that
+//! hash to it, so clients working on different keys do not wait for each
+//! other. A command locks the shards of all its keys before it runs, which
+//! keeps multi-key commands and transactions atomic; commands that look at the
+//! whole keyspace lock every shard. Shards are always locked in ascending
+//! order, so two commands can never deadlock.
+//!
+//! Keys are spread like Redis Cluster spreads them over hash slots: only the
+//! part between the first `{` and the next `}` is hashed when it is not empty,
+//! so `{user:1}:name` and `{user:1}:email` always share a shard.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::{Config, Database};
+
+/// Number of shards the keys are spread over.
+pub const SHARDS: usize = 16;
+
+/// Number of hash slots, as in Redis Cluster.
+const HASH_SLOTS: u16 = 16384;
+
+/// CRC16-CCITT (XMODEM), the checksum Redis Cluster hashes keys with.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    return crc;
+}
+
+/// The hash slot of `key`, honouring `{hash tags}`.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed: &[u8] = match key.iter().position(|&b| b == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &key[open + 1..open + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+    return crc16(hashed) % HASH_SLOTS;
+}
+
+/// The shard holding `key` among `shards` shards.
+pub fn shard_for(key: &[u8], shards: usize) -> usize {
+    return key_hash_slot(key) as usize % shards;
+}
+
+pub struct Keyspace {
+    shards: Vec<Arc<Mutex<Vec<Database>>>>,
+    /// Estimated memory of every shard, refreshed whenever its lock is released,
+    /// so the total can be checked against `maxmemory` without locking anything.
+    memory: Arc<Vec<AtomicUsize>>,
+    databases: usize,
+}
+
+impl Keyspace {
+    pub fn new(config: &Config) -> Self {
+        let shards: Vec<Arc<Mutex<Vec<Database>>>> = (0..SHARDS)
+            .map(|_| {
+                let mut dbs: Vec<Database> =
+                    (0..config.databases).map(|_| Database::new()).collect();
+                for db in dbs.iter_mut() {
+                    config.apply_to(db);
+                }
+                return Arc::new(Mutex::new(dbs));
+            })
+            .collect();
+
+        return Keyspace {
+            shards,
+            memory: Arc::new((0..SHARDS).map(|_| AtomicUsize::new(0)).collect()),
+            databases: config.databases,
+        };
+    }
+
+    /// Number of logical databases.
+    pub fn databases(&self) -> usize {
+        return self.databases;
+    }
+
+    pub fn shard_for(&self, key: &[u8]) -> usize {
+        return shard_for(key, self.shards.len());
+    }
+
+    /// Estimated bytes used by the whole dataset, as of the last release of every shard.
+    pub fn used_memory(&self) -> usize {
+        return self.memory.iter().map(|m| m.load(Ordering::Relaxed)).sum();
+    }
+
+    /// Locks the shards in `needed`, or every shard when it is `None`.
+    pub async fn lock(&self, needed: Option<Vec<usize>>) -> Shards {
+        let mut indexes: Vec<usize> = match needed {
+            Some(indexes) => indexes,
+            None => (0..self.shards.len()).collect(),
+        };
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        let mut guards: Vec<(usize, OwnedMutexGuard<Vec<Database>>)> =
+            Vec::with_capacity(indexes.len());
+        for index in indexes {
+            guards.push((index, Arc::clone(&self.shards[index]).lock_owned().await));
+        }
+        return Shards {
+            guards,
+            count: self.shards.len(),
+            memory: Arc::clone(&self.memory),
+        };
+    }
+
+    pub async fn lock_all(&self) -> Shards {
+        return self.lock(None).await;
+    }
+
+    /// Number of shards.
+    pub fn len(&self) -> usize {
+        return self.shards.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.shards.is_empty();
+    }
+}
+
+/// The shards a command holds while it runs.
+pub struct Shards {
+    /// Sorted by shard index.
+    guards: Vec<(usize, OwnedMutexGuard<Vec<Database>>)>,
+    count: usize,
+    memory: Arc<Vec<AtomicUsize>>,
+}
+
+impl Shards {
+    /// The part of database `db` that holds `key`.
+    ///
+    /// Panics if the shard of `key` is not locked, which means the key
+    /// positions of the running command are wrong.
+    pub fn db(&mut self, db: usize, key: &[u8]) -> &mut Database {
+        let shard: usize = shard_for(key, self.count);
+        let index: usize = self
+            .guards
+            .binary_search_by_key(&shard, |(i, _)| *i)
+            .unwrap_or_else(|_| panic!("shard {} is not locked by this command", shard));
+        return &mut self.guards[index].1[db];
+    }
+
+    /// Whether `key` is stored in database `db`, expired or not.
+    pub fn contains(&self, db: usize, key: &[u8]) -> bool {
+        let shard: usize = shard_for(key, self.count);
+        return match self.guards.binary_search_by_key(&shard, |(i, _)| *i) {
+            Ok(index) => self.guards[index].1[db].peek(key).is_some(),
+            Err(_) => false,
+        };
+    }
+
+    /// Whether every shard is locked.
+    pub fn is_complete(&self) -> bool {
+        return self.guards.len() == self.count;
+    }
+
+    /// The locked parts of database `db`.
+    pub fn parts_of(&mut self, db: usize) -> impl Iterator<Item = &mut Database> {
+        return self.guards.iter_mut().map(move |(_, dbs)| &mut dbs[db]);
+    }
+
+    /// The locked shards, each with all its logical databases.
+    pub fn parts(&mut self) -> Vec<&mut Vec<Database>> {
+        return self.guards.iter_mut().map(|(_, dbs)| &mut **dbs).collect();
+    }
+
+    pub fn views(&self) -> Vec<&Vec<Database>> {
+        return self.guards.iter().map(|(_, dbs)| &**dbs).collect();
+    }
+
+    /// Switches the recording of modified keys on or off in every locked database.
+    pub fn set_recording(&mut self, record: bool) {
+        for (_, dbs) in self.guards.iter_mut() {
+            for db in dbs.iter_mut() {
+                db.track_modified = record;
+            }
+        }
+    }
+
+    /// Hands over the keys changed in the locked databases, each with its
+    /// database index, and the indexes of the databases that were flushed.
+    pub fn take_changes(&mut self) -> (Vec<(usize, Vec<u8>)>, Vec<usize>) {
+        let mut changed: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut flushed: Vec<usize> = Vec::new();
+        for (_, dbs) in self.guards.iter_mut() {
+            for (index, db) in dbs.iter_mut().enumerate() {
+                let (keys, db_flushed) = db.take_modified();
+                changed.extend(keys.into_iter().map(|key| (index, key)));
+                if db_flushed && !flushed.contains(&index) {
+                    flushed.push(index);
+                }
+            }
+        }
+        return (changed, flushed);
+    }
+}
+
+impl Drop for Shards {
+    fn drop(&mut self) {
+        for (index, dbs) in self.guards.iter() {
+            let used: usize = dbs.iter().map(|db| db.memory_usage()).sum();
+            self.memory[*index].store(used, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_hash_slot_test() {
+        // the reference values of the Redis Cluster specification
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"{user1000}.followers")
+        );
+        assert_eq!(key_hash_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % 16384);
+        assert_eq!(key_hash_slot(b"foo{{bar}}zap"), crc16(b"{bar") % 16384);
+    }
+
+    #[tokio::test]
+    async fn lock_test() {
+        let keyspace: Keyspace = Keyspace::new(&Config::default());
+        let first: usize = keyspace.shard_for(b"foo");
+        let second: usize = (first + 1) % keyspace.len();
+
+        // disjoint shards are held at the same time
+        let mut a: Shards = keyspace.lock(Some(vec![first])).await;
+        let b: Shards = keyspace.lock(Some(vec![second])).await;
+        a.db(0, b"foo").add(b"foo", b"bar");
+        assert!(!a.is_complete());
+        drop(a);
+        drop(b);
+        assert!(keyspace.used_memory() > 0);
+
+        let mut all: Shards = keyspace.lock_all().await;
+        assert!(all.is_complete());
+        assert_eq!(all.parts_of(0).map(|db| db.len()).sum::<usize>(), 1);
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::db::now_ms;
+use crate::redis_parser::RedisType;
+use crate::ServerState;
+
+/// Samples kept per event, one per second at most.
+const LATENCY_TS_LEN: usize = 160;
+
+/// A latency spike: unix time in seconds and the latency in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencySample {
+    pub time: u64,
+    pub latency: u64,
+}
+
+#[derive(Default)]
+struct EventHistory {
+    samples: Vec<LatencySample>,
+    /// Index of the next slot to write once `samples` is full.
+    next: usize,
+    max: u64,
+}
+
+impl EventHistory {
+    fn latest(&self) -> Option<LatencySample> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let index: usize = (self.next + self.samples.len() - 1) % self.samples.len();
+        return Some(self.samples[index]);
+    }
+
+    /// Samples in chronological order.
+    fn history(&self) -> Vec<LatencySample> {
+        let (newer, older) = self.samples.split_at(self.next);
+        return older.iter().chain(newer.iter()).copied().collect();
+    }
+}
+
+/// Per-event history of latency spikes above `latency-monitor-threshold`,
+/// as reported by LATENCY LATEST and LATENCY HISTORY.
+#[derive(Default)]
+pub struct LatencyMonitor {
+    events: BTreeMap<String, EventHistory>,
+}
+
+impl LatencyMonitor {
+    pub fn new() -> Self {
+        return LatencyMonitor::default();
+    }
+
+    /// Records a sample; spikes within the same second keep only the highest.
+    pub fn add_sample(&mut self, event: &str, time: u64, latency: u64) {
+        let history: &mut EventHistory = self.events.entry(event.to_string()).or_default();
+        history.max = history.max.max(latency);
+
+        if let Some(last) = history.latest() {
+            if last.time == time {
+                let len: usize = history.samples.len();
+                let index: usize = (history.next + len - 1) % len;
+                history.samples[index].latency = last.latency.max(latency);
+                return;
+            }
+        }
+
+        let sample: LatencySample = LatencySample { time, latency };
+        if history.samples.len() < LATENCY_TS_LEN {
+            history.samples.push(sample);
+        } else {
+            history.samples[history.next] = sample;
+            history.next = (history.next + 1) % LATENCY_TS_LEN;
+        }
+    }
+
+    /// LATENCY LATEST: event name, time and latency of its latest spike, all-time max.
+    pub fn latest(&self) -> RedisType<'static> {
+        let mut res: Vec<RedisType> = Vec::new();
+        for (event, history) in &self.events {
+            if let Some(last) = history.latest() {
+                res.push(RedisType::Array(Box::new(vec![
+                    RedisType::BulkString(event.clone()),
+                    RedisType::Integer(last.time.to_string()),
+                    RedisType::Integer(last.latency.to_string()),
+                    RedisType::Integer(history.max.to_string()),
+                ])));
+            }
+        }
+        return RedisType::Array(Box::new(res));
+    }
+
+    pub fn history(&self, event: &str) -> Vec<LatencySample> {
+        return self
+            .events
+            .get(event)
+            .map(|h| h.history())
+            .unwrap_or_default();
+    }
+
+    /// Forgets the given events, or every event when `events` is empty.
+    /// Returns the number of events that were reset.
+    pub fn reset(&mut self, events: &[String]) -> usize {
+        if events.is_empty() {
+            let count: usize = self.events.len();
+            self.events.clear();
+            return count;
+        }
+        return events
+            .iter()
+            .filter(|e| self.events.remove(e.as_str()).is_some())
+            .count();
+    }
+}
+
+/// Records `elapsed` for `event` when the latency monitor is enabled and the
+/// duration reaches `latency-monitor-threshold`.
+pub fn add_sample_if_needed(state: &ServerState, event: &str, elapsed: Duration) {
+    let threshold: u64 = state.config.read().unwrap().latency_monitor_threshold;
+    let latency: u64 = elapsed.as_millis() as u64;
+    if threshold > 0 && latency >= threshold {
+        state
+            .latency
+            .lock()
+            .unwrap()
+            .add_sample(event, now_ms() / 1000, latency);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_test() {
+        let mut monitor: LatencyMonitor = LatencyMonitor::new();
+        monitor.add_sample("command", 100, 5);
+        monitor.add_sample("command", 100, 12);
+        monitor.add_sample("command", 101, 7);
+        assert_eq!(
+            monitor.history("command"),
+            vec![
+                LatencySample {
+                    time: 100,
+                    latency: 12
+                },
+                LatencySample {
+                    time: 101,
+                    latency: 7
+                },
+            ]
+        );
+
+        // the ring keeps the most recent samples in order
+        for t in 0..200 {
+            monitor.add_sample("expire-cycle", 1000 + t, t);
+        }
+        let history: Vec<LatencySample> = monitor.history("expire-cycle");
+        assert_eq!(history.len(), LATENCY_TS_LEN);
+        assert_eq!(history[0].time, 1040);
+        assert_eq!(history[LATENCY_TS_LEN - 1].time, 1199);
+
+        assert_eq!(
+            monitor.reset(&[String::from("command"), String::from("nope")]),
+            1
+        );
+        assert_eq!(monitor.reset(&[]), 1);
+        assert!(monitor.history("expire-cycle").is_empty());
+    }
+}
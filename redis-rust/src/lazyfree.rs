@@ -0,0 +1,159 @@
+//! Freeing of large values away from the keyspace locks.
+//!
+//! Dropping a value with millions of elements takes long enough to stall every
+//! client waiting on its shard. UNLINK, FLUSHDB/FLUSHALL ASYNC and the
+//! `lazyfree-lazy-*` options detach such values from the keyspace and hand them
+//! to a background thread instead; small values are still dropped on the spot,
+//! as sending them over would cost more than freeing them.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::{Database, ServerState, Value};
+
+/// Values needing more frees than this go to the background thread, as in Redis.
+pub const LAZYFREE_THRESHOLD: usize = 64;
+
+/// Which implicit deletions free lazily, copied into every `Database`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LazyfreeSettings {
+    /// Keys deleted because their TTL passed (`lazyfree-lazy-expire`).
+    pub expire: bool,
+    /// Values replaced by SET, RENAME, RESTORE and the like (`lazyfree-lazy-server-del`).
+    pub server_del: bool,
+}
+
+/// Something to drop on the background thread, with the number of objects it holds.
+struct Job {
+    garbage: Box<dyn Send>,
+    objects: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    pending: AtomicU64,
+    freed: AtomicU64,
+}
+
+/// The background thread and its counters; the thread starts with the first job.
+#[derive(Default)]
+pub struct LazyFree {
+    sender: Mutex<Option<Sender<Job>>>,
+    counters: Arc<Counters>,
+}
+
+impl LazyFree {
+    pub fn new() -> Self {
+        return LazyFree::default();
+    }
+
+    fn submit(&self, garbage: Box<dyn Send>, objects: u64) {
+        self.counters.pending.fetch_add(objects, Ordering::Relaxed);
+        let mut sender = self.sender.lock().unwrap();
+        let tx: &Sender<Job> = sender.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel::<Job>();
+            let counters: Arc<Counters> = Arc::clone(&self.counters);
+            thread::spawn(move || {
+                for job in rx {
+                    drop(job.garbage);
+                    counters.pending.fetch_sub(job.objects, Ordering::Relaxed);
+                    counters.freed.fetch_add(job.objects, Ordering::Relaxed);
+                }
+            });
+            return tx;
+        });
+        // the thread only goes away with the process
+        let _ = tx.send(Job { garbage, objects });
+    }
+
+    /// Drops `value` in the background when it is large enough to be worth it.
+    pub fn free_value(&self, value: Value) {
+        if value.free_effort() > LAZYFREE_THRESHOLD {
+            self.submit(Box::new(value), 1);
+        }
+    }
+
+    /// Drops `values` in the background, each one counting as an object.
+    pub fn free_values(&self, values: Vec<Value>) {
+        if !values.is_empty() {
+            let objects: u64 = values.len() as u64;
+            self.submit(Box::new(values), objects);
+        }
+    }
+
+    /// Drops the contents of flushed databases in the background.
+    pub fn free_databases(&self, dbs: Vec<Database>) {
+        let objects: u64 = dbs.iter().map(|db| db.len() as u64).sum();
+        if objects > 0 {
+            self.submit(Box::new(dbs), objects);
+        }
+    }
+
+    /// Objects handed over and not freed yet.
+    pub fn pending_objects(&self) -> u64 {
+        return self.counters.pending.load(Ordering::Relaxed);
+    }
+
+    /// Objects freed by the background thread since startup.
+    pub fn freed_objects(&self) -> u64 {
+        return self.counters.freed.load(Ordering::Relaxed);
+    }
+}
+
+/// Hands the values the databases of `shards` detached since the last call
+/// over to the background thread.
+pub fn free_detached(state: &ServerState, shards: &mut [&mut Vec<Database>]) {
+    let detached: Vec<Value> = shards
+        .iter_mut()
+        .flat_map(|dbs| dbs.iter_mut())
+        .flat_map(|db| db.take_detached())
+        .collect();
+    state.lazyfree.free_values(detached);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{EncodingLimits, Set};
+    use std::time::{Duration, Instant};
+
+    fn wait_for_freed(lazyfree: &LazyFree, objects: u64) {
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        while lazyfree.freed_objects() < objects && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn free_value_test() {
+        let lazyfree: LazyFree = LazyFree::new();
+        let limits: EncodingLimits = EncodingLimits::default();
+        let members: Vec<String> = (0..1000).map(|i| format!("m{}", i)).collect();
+
+        // a compact value is a single allocation, so it is dropped right away
+        lazyfree.free_value(Value::Set(Set::from_members(&members[..10], &limits)));
+        lazyfree.free_value(Value::from(b"small".to_vec()));
+        assert_eq!(lazyfree.pending_objects(), 0);
+        assert!(lazyfree.sender.lock().unwrap().is_none());
+
+        lazyfree.free_value(Value::Set(Set::from_members(&members, &limits)));
+        wait_for_freed(&lazyfree, 1);
+        assert_eq!(lazyfree.freed_objects(), 1);
+        assert_eq!(lazyfree.pending_objects(), 0);
+    }
+
+    #[test]
+    fn free_databases_test() {
+        let lazyfree: LazyFree = LazyFree::new();
+        let mut dbs: Vec<Database> = vec![Database::new(), Database::new()];
+        dbs[0].add(b"a", b"1");
+        dbs[1].add(b"b", b"2");
+        dbs[1].add(b"c", b"3");
+        lazyfree.free_databases(dbs);
+        lazyfree.free_databases(vec![Database::new()]);
+        wait_for_freed(&lazyfree, 3);
+        assert_eq!(lazyfree.freed_objects(), 3);
+    }
+}
@@ -1,8 +1,58 @@
+#![allow(clippy::needless_return)]
+
 pub mod redis_parser;
-use crate::redis_parser::*;
+pub use crate::redis_parser::*;
 
 pub mod db;
-use crate::db::*;
+pub use crate::db::*;
+
+pub mod value;
+pub use crate::value::*;
 
 pub mod error;
-use crate::error::*;
+pub use crate::error::*;
+
+pub mod config;
+pub use crate::config::*;
+
+pub mod state;
+pub use crate::state::*;
+
+pub mod session;
+pub use crate::session::*;
+
+pub mod stats;
+pub use crate::stats::*;
+
+pub mod connection;
+pub use crate::connection::*;
+
+pub mod client;
+pub mod commands;
+pub mod dict;
+pub mod eviction;
+pub mod geo;
+pub mod glob;
+pub mod info;
+pub mod intset;
+pub mod keyspace;
+pub mod latency;
+pub mod lazyfree;
+pub mod listpack;
+pub mod memory;
+pub mod metrics;
+pub mod module;
+pub mod monitor;
+pub mod multi;
+pub mod notify;
+pub mod pubsub;
+pub mod random;
+pub mod rdb;
+pub mod redis_client;
+pub mod replication;
+pub mod sentinel;
+pub mod server;
+pub mod shutdown;
+pub mod slowlog;
+pub mod tls;
+pub mod tracking;
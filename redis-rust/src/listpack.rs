@@ -0,0 +1,195 @@
+/// Bytes of header Redis puts in front of every listpack (total size and count).
+pub const LISTPACK_HEADER: usize = 6;
+
+/// A sequence of strings packed into a single allocation, in the style of the
+/// Redis listpack.
+///
+/// Each entry is its length as a LEB128 varint followed by its bytes, so a small
+/// collection costs one allocation instead of one per element. Lookups walk the
+/// entries, which is why small hashes, sets and lists only use it up to the
+/// `*-max-listpack-*` limits.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Listpack {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Decodes the varint at the start of `buf`, returning it and its size.
+fn read_varint(buf: &[u8]) -> (usize, usize) {
+    let mut n: usize = 0;
+    let mut shift: u32 = 0;
+    for (i, byte) in buf.iter().enumerate() {
+        n |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return (n, i + 1);
+        }
+        shift += 7;
+    }
+    return (n, buf.len());
+}
+
+fn encode_entry(item: &[u8]) -> Vec<u8> {
+    let mut entry: Vec<u8> = Vec::with_capacity(item.len() + 2);
+    write_varint(&mut entry, item.len());
+    entry.extend_from_slice(item);
+    return entry;
+}
+
+impl Listpack {
+    pub fn new() -> Self {
+        return Listpack::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    /// Size of the packed entries plus the header, as Redis reports it.
+    pub fn bytes(&self) -> usize {
+        return LISTPACK_HEADER + self.buf.len();
+    }
+
+    pub fn push_back(&mut self, item: &[u8]) {
+        self.buf.extend(encode_entry(item));
+        self.len += 1;
+    }
+
+    pub fn push_front(&mut self, item: &[u8]) {
+        self.buf.splice(0..0, encode_entry(item));
+        self.len += 1;
+    }
+
+    /// Byte range of the entry at `index`, header included.
+    fn entry_span(&self, index: usize) -> Option<(usize, usize)> {
+        let mut offset: usize = 0;
+        for i in 0..self.len {
+            let (len, header) = read_varint(&self.buf[offset..]);
+            let end: usize = offset + header + len;
+            if i == index {
+                return Some((offset, end));
+            }
+            offset = end;
+        }
+        return None;
+    }
+
+    /// Replaces the entry at `index`; false when there is no such entry.
+    pub fn replace(&mut self, index: usize, item: &[u8]) -> bool {
+        return match self.entry_span(index) {
+            Some((start, end)) => {
+                self.buf.splice(start..end, encode_entry(item));
+                true
+            }
+            None => false,
+        };
+    }
+
+    /// Removes the entry at `index` and returns it.
+    pub fn remove(&mut self, index: usize) -> Option<Vec<u8>> {
+        let (start, end) = self.entry_span(index)?;
+        let (_, header) = read_varint(&self.buf[start..]);
+        let item: Vec<u8> = self.buf[start + header..end].to_vec();
+        self.buf.drain(start..end);
+        self.len -= 1;
+        return Some(item);
+    }
+
+    /// Position of the first entry equal to `item`, looking only at every
+    /// `step`th entry so field/value pairs can be searched by field.
+    pub fn position(&self, item: &[u8], step: usize) -> Option<usize> {
+        return self
+            .iter()
+            .enumerate()
+            .step_by(step)
+            .find(|(_, entry)| *entry == item)
+            .map(|(i, _)| i);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        return self.entry_span(index).map(|(start, end)| {
+            let (_, header) = read_varint(&self.buf[start..]);
+            &self.buf[start + header..end]
+        });
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        return Iter { rest: &self.buf };
+    }
+}
+
+/// The entries of a listpack, front to back.
+pub struct Iter<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (len, header) = read_varint(self.rest);
+        let item: &'a [u8] = &self.rest[header..header + len];
+        self.rest = &self.rest[header + len..];
+        return Some(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_replace_test() {
+        let mut lp: Listpack = Listpack::new();
+        lp.push_back(b"b");
+        lp.push_front(b"a");
+        lp.push_back(&[b'x'; 300]);
+        lp.push_back(b"");
+        assert_eq!(lp.len(), 4);
+        // 2 + 2 + 302 + 1 bytes of entries, length headers included
+        assert_eq!(lp.bytes(), LISTPACK_HEADER + 307);
+
+        let items: Vec<&[u8]> = lp.iter().collect();
+        assert_eq!(items, vec![b"a".as_slice(), b"b", &[b'x'; 300], b""]);
+
+        assert!(lp.replace(2, b"c"));
+        assert!(!lp.replace(4, b"d"));
+        assert_eq!(lp.get(2), Some(b"c".as_slice()));
+        assert_eq!(lp.get(3), Some(b"".as_slice()));
+        assert_eq!(lp.get(4), None);
+        assert_eq!(lp.bytes(), LISTPACK_HEADER + 7);
+
+        assert_eq!(lp.remove(1), Some(b"b".to_vec()));
+        assert_eq!(lp.remove(3), None);
+        let items: Vec<&[u8]> = lp.iter().collect();
+        assert_eq!(items, vec![b"a".as_slice(), b"c", b""]);
+        assert_eq!(lp.bytes(), LISTPACK_HEADER + 5);
+    }
+
+    #[test]
+    fn position_test() {
+        let mut lp: Listpack = Listpack::new();
+        for item in [b"f1", b"v1", b"v1", b"f2"] {
+            lp.push_back(item);
+        }
+        assert_eq!(lp.position(b"v1", 1), Some(1));
+        // only fields, at even positions, are looked at
+        assert_eq!(lp.position(b"v1", 2), Some(2));
+        assert_eq!(lp.position(b"f2", 2), None);
+        assert_eq!(lp.position(b"f2", 1), Some(3));
+    }
+}
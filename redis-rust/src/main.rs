@@ -1,115 +1,51 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
 use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
 
-//#![allow(unused_imports)]
 use anyhow::Error;
-use redis_starter_rust::db::Database;
-use redis_starter_rust::redis_parser::*;
-use std::env;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::Mutex;
+use redis_starter_rust::keyspace::Shards;
+use redis_starter_rust::sentinel::{self, SentinelConfig};
+use redis_starter_rust::shutdown::{self, ShutdownOptions};
+use redis_starter_rust::{server, Config, ServerState};
+use tokio::signal::unix::{signal, Signal, SignalKind};
 
-const PORT: &str = "127.0.0.1:6379";
 #[tokio::main]
-//#[allow(unreachable_code)]
-
 async fn main() -> Result<(), Error> {
-    // get the dir and file name from env
+    // server parameters are passed as --name value pairs, e.g. --dir /tmp --dbfilename dump.rdb
     let args: Vec<String> = env::args().collect();
-
-    let data: Arc<Mutex<Database>> = Arc::new(Mutex::new(Database::new()));
-    // add args to the map
-    if args.len() == 5 {
-        data.lock().await.add("dir".into(), args.get(2).unwrap());
-
-        data.lock()
-            .await
-            .add("dbfilename".into(), args.get(4).unwrap());
+    // `--sentinel` runs a failover monitor instead of a server
+    if args.get(1).map(String::as_str) == Some("--sentinel") {
+        sentinel::run(SentinelConfig::from_args(&args[2..])?).await?;
+        return Ok(());
     }
+    let config: Config = Config::from_args(&args[1..])?;
 
-    let (tx, mut rx): (Sender<Message>, Receiver<Message>) = channel(10);
-
-    let tx = Arc::new(Mutex::new(tx));
+    let state: Arc<ServerState> = Arc::new(ServerState::new(config));
+    let sigterm: Signal = signal(SignalKind::terminate())?;
+    let sigint: Signal = signal(SignalKind::interrupt())?;
+    tokio::spawn(handle_signals(Arc::clone(&state), sigterm, sigint));
 
-    let listener: TcpListener = TcpListener::bind(PORT).await?;
-    println!("Listening on {PORT}");
-
-    let data_copy = Arc::clone(&data);
-    tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            thread::sleep(Duration::from_millis(msg.time));
-            data_copy.lock().await.remove(&msg.key);
-            println!("Key value pair has been removed");
-        }
-    });
-
-    loop {
-        let (client, _addr) = listener.accept().await?;
-        handle_connection(client, Arc::clone(&data), Arc::clone(&tx))?;
-    }
+    server::run(state).await?;
+    return Ok(());
 }
 
-fn handle_connection(
-    mut client: TcpStream,
-    data: Arc<Mutex<Database>>,
-    tx: Arc<Mutex<Sender<Message>>>,
-) -> Result<(), Error> {
-    tokio::spawn(async move {
-        let mut buffer: [u8; 1024] = [0; 1024];
-
-        loop {
-            // read the bytes into the buffer
-            let bytes_read = client
-                .read(&mut buffer)
-                .await
-                .expect("Failed to read data from client");
-
-            // if no bytes were read the conntection is closed
-            if bytes_read <= 0 {
-                return ();
-            }
-
-            // Convert request into a string
-            let request: String = String::from_utf8(buffer.to_vec()).unwrap();
-
-            println!("Request: {}", request);
-
-            // Get the response based on the redis type
-            let response: RedisType = match get_redis_response(&request, Arc::clone(&data)).await {
-                Ok(r) => r,
-                Err(e) => {
-                    eprintln!("{}", e.message);
-                    RedisType::Error(e.message.to_string())
-                }
-            };
-
-            // if the response requires delayed operation
-            if response.is_delay() {
-                match &response {
-                    RedisType::Delay(message) => {
-                        let res: RedisType = RedisType::SimpleString("OK");
-                        client
-                            .write(res.to_string().as_bytes())
-                            .await
-                            .expect("Failed to write to client");
-
-                        let _ = Arc::clone(&tx).lock().await.send(message.clone()).await;
-                        continue;
-                    }
-                    _ => (),
-                }
-                return ();
-            }
-
-            client
-                .write(response.to_string().as_bytes())
-                .await
-                .expect("Failed to write to client");
+/// Shuts down like a plain SHUTDOWN on SIGTERM or SIGINT. If the final save
+/// fails the server keeps running and the next signal tries again.
+async fn handle_signals(state: Arc<ServerState>, mut sigterm: Signal, mut sigint: Signal) {
+    loop {
+        let name: &str = tokio::select! {
+            _ = sigterm.recv() => "SIGTERM",
+            _ = sigint.recv() => "SIGINT",
+        };
+
+        println!("Received {name}, scheduling shutdown...");
+        shutdown::wait_for_bgsave(&state).await;
+        let shards: Shards = state.keyspace.lock_all().await;
+        shutdown::wait_for_replicas(&state, ShutdownOptions::default()).await;
+        match shutdown::prepare(&state, &shards.views(), ShutdownOptions::default()) {
+            Ok(()) => return,
+            Err(e) => eprintln!("{}", e.message),
         }
-    });
-
-    return Ok(());
+    }
 }
@@ -0,0 +1,186 @@
+use std::sync::atomic::Ordering;
+
+use crate::db::ENTRY_OVERHEAD;
+use crate::info::resident_memory;
+use crate::redis_parser::RedisType;
+use crate::{Database, ServerState};
+
+/// Estimated cost of a key in the table of TTLs: the key slot and the timestamp.
+const EXPIRE_ENTRY_OVERHEAD: usize = 24;
+
+/// Below this much data MEMORY DOCTOR has nothing meaningful to say.
+const DOCTOR_MIN_MEMORY: usize = 5 * 1024 * 1024;
+
+/// The figures MEMORY STATS reports and MEMORY DOCTOR looks at.
+#[derive(Debug, Default)]
+pub struct MemoryStats {
+    pub peak_allocated: usize,
+    pub total_allocated: usize,
+    /// For each non-empty database: its index and the overhead of its main and TTL tables.
+    pub dbs: Vec<(usize, usize, usize)>,
+    pub overhead_total: usize,
+    pub keys_count: usize,
+    pub rss: usize,
+}
+
+impl MemoryStats {
+    pub fn collect(state: &ServerState, shards: &[&Vec<Database>]) -> Self {
+        let mut stats: MemoryStats = MemoryStats::default();
+        let databases: usize = shards.first().map(|dbs| dbs.len()).unwrap_or(0);
+        for i in 0..databases {
+            let (keys, expires, used) = shards.iter().fold((0, 0, 0), |(k, e, u), dbs| {
+                return (
+                    k + dbs[i].len(),
+                    e + dbs[i].expires_len(),
+                    u + dbs[i].memory_usage(),
+                );
+            });
+            stats.total_allocated += used;
+            if keys == 0 {
+                continue;
+            }
+            let main: usize = keys * ENTRY_OVERHEAD;
+            let expires: usize = expires * EXPIRE_ENTRY_OVERHEAD;
+            stats.dbs.push((i, main, expires));
+            stats.overhead_total += main + expires;
+            stats.keys_count += keys;
+        }
+
+        stats.peak_allocated = state
+            .stats
+            .used_memory_peak
+            .fetch_max(stats.total_allocated as u64, Ordering::Relaxed)
+            .max(stats.total_allocated as u64) as usize;
+        stats.rss = resident_memory();
+        return stats;
+    }
+
+    pub fn dataset_bytes(&self) -> usize {
+        return self.total_allocated.saturating_sub(self.overhead_total);
+    }
+
+    fn percentage(part: usize, whole: usize) -> f64 {
+        return match whole {
+            0 => 0.0,
+            _ => part as f64 * 100.0 / whole as f64,
+        };
+    }
+
+    /// The reply of MEMORY STATS, a map as in Redis.
+    pub fn to_reply(&self) -> RedisType<'static> {
+        let number = |n: usize| RedisType::Integer(n.to_string());
+        let float = |f: f64| RedisType::BulkString(format!("{}", f));
+        let name = |s: &str| RedisType::BulkString(s.to_string());
+
+        let mut pairs: Vec<(RedisType, RedisType)> = vec![
+            (name("peak.allocated"), number(self.peak_allocated)),
+            (name("total.allocated"), number(self.total_allocated)),
+        ];
+        for (db, main, expires) in &self.dbs {
+            let overhead: Vec<(RedisType, RedisType)> = vec![
+                (name("overhead.hashtable.main"), number(*main)),
+                (name("overhead.hashtable.expires"), number(*expires)),
+            ];
+            pairs.push((
+                name(&format!("db.{}", db)),
+                RedisType::Map(Box::new(overhead)),
+            ));
+        }
+        let per_key: usize = match self.keys_count {
+            0 => 0,
+            n => self.total_allocated / n,
+        };
+        pairs.extend([
+            (name("overhead.total"), number(self.overhead_total)),
+            (name("keys.count"), number(self.keys_count)),
+            (name("keys.bytes-per-key"), number(per_key)),
+            (name("dataset.bytes"), number(self.dataset_bytes())),
+            (
+                name("dataset.percentage"),
+                float(MemoryStats::percentage(
+                    self.dataset_bytes(),
+                    self.total_allocated,
+                )),
+            ),
+            (
+                name("peak.percentage"),
+                float(MemoryStats::percentage(
+                    self.total_allocated,
+                    self.peak_allocated,
+                )),
+            ),
+        ]);
+        return RedisType::Map(Box::new(pairs));
+    }
+
+    /// The report of MEMORY DOCTOR, with the wording Redis uses.
+    pub fn doctor(&self) -> String {
+        if self.total_allocated < DOCTOR_MIN_MEMORY {
+            return String::from("Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used in these conditions. Please, leave for your mission on Earth and fill it with some data. The new Sam and I will be back to our programming as soon as I finished rebooting.");
+        }
+
+        let mut issues: Vec<&str> = Vec::new();
+        if self.peak_allocated as f64 > self.total_allocated as f64 * 1.5 {
+            issues.push(" * Peak memory: In the past this instance used more than 150% the memory that is currently using. The allocator is normally not able to release memory after a peak, so you can expect to see a big fragmentation ratio, however this is actually harmless and is only due to the memory peak, and if the Redis instance Resident Set Size (RSS) is currently bigger than expected, the memory will be used as soon as you fill the Redis instance with more data. If the memory peak was only occasional and you want to try to reclaim memory, please try the MEMORY PURGE command, otherwise the only other option is to shutdown and restart the instance.\n\n");
+        }
+        if self.rss as f64 > self.total_allocated as f64 * 1.4 {
+            issues.push(" * High total RSS: This instance has a memory fragmentation and RSS overhead greater than 1.4 (this means that the Resident Set Size of the Redis process is much larger than the sum of the logical allocations Redis performed). This problem is usually due either to a large peak memory (check if there is a peak memory entry above in the report) or may result from a workload that causes the allocator to fragment memory a lot. If the problem is a large peak memory, then there is no issue. Otherwise, make sure you are using the Jemalloc allocator and not the default libc malloc. Note: The currently used allocator is \"libc\".\n\n");
+        }
+
+        if issues.is_empty() {
+            return String::from("Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs on this base.");
+        }
+        return format!(
+            "Sam, I detected a few issues in this Redis instance memory implants:\n\n{}I'm here to keep you safe, Sam. I want to help you.\n",
+            issues.concat()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doctor_test() {
+        let mut stats: MemoryStats = MemoryStats {
+            total_allocated: 1000,
+            ..MemoryStats::default()
+        };
+        assert!(stats.doctor().starts_with("Hi Sam, this instance is empty"));
+
+        stats.total_allocated = 10 * DOCTOR_MIN_MEMORY;
+        stats.peak_allocated = stats.total_allocated;
+        stats.rss = stats.total_allocated;
+        assert!(stats
+            .doctor()
+            .starts_with("Hi Sam, I can't find any memory issue"));
+
+        stats.peak_allocated = 2 * stats.total_allocated;
+        let report: String = stats.doctor();
+        assert!(report.contains(" * Peak memory"));
+        assert!(!report.contains(" * High total RSS"));
+    }
+
+    #[test]
+    fn stats_reply_test() {
+        let stats: MemoryStats = MemoryStats {
+            peak_allocated: 400,
+            total_allocated: 200,
+            dbs: vec![(0, 128, 24)],
+            overhead_total: 152,
+            keys_count: 2,
+            rss: 0,
+        };
+        assert_eq!(stats.dataset_bytes(), 48);
+
+        let mut out: Vec<u8> = Vec::new();
+        stats.to_reply().encode(&mut out);
+        let text: String = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("*18\r\n$14\r\npeak.allocated\r\n:400\r\n"));
+        assert!(text.contains("$4\r\ndb.0\r\n*4\r\n$23\r\noverhead.hashtable.main\r\n:128\r\n"));
+        assert!(text.contains("$18\r\nkeys.bytes-per-key\r\n:100\r\n"));
+        assert!(text.contains("$18\r\ndataset.percentage\r\n$2\r\n24\r\n"));
+        assert!(text.ends_with("$15\r\npeak.percentage\r\n$2\r\n50\r\n"));
+    }
+}
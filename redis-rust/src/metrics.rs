@@ -0,0 +1,390 @@
+//! Prometheus metrics over HTTP.
+//!
+//! With `metrics-port` set, the server also listens for plain HTTP on that
+//! port and answers `GET /metrics` with the text exposition format: command
+//! calls, failures and latency histograms counted by the connection handler,
+//! the connected clients, the size of every database, memory usage, expired
+//! and evicted keys and the state of the snapshot. Every scrape renders the
+//! current values, much like INFO does.
+
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::info::resident_memory;
+use crate::keyspace::Shards;
+use crate::stats::{CommandStats, LATENCY_BUCKETS_US};
+use crate::{Database, Error, ServerState};
+
+/// The longest request head read before the request is rejected.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// How long a scraper may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Answers every HTTP client connecting to `listener` in its own task until the shutdown starts.
+pub async fn accept(listener: TcpListener, state: Arc<ServerState>) {
+    loop {
+        let client: TcpStream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((client, _addr)) => client,
+                Err(e) => {
+                    eprintln!("Accepting metrics connection: {}", e);
+                    continue;
+                }
+            },
+            _ = state.shutdown.wait() => return,
+        };
+        tokio::spawn(respond(client, Arc::clone(&state)));
+    }
+}
+
+/// Reads one request and answers it, then closes the connection.
+async fn respond(mut client: TcpStream, state: Arc<ServerState>) {
+    let head: Vec<u8> = match tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut client)).await {
+        Ok(Ok(head)) => head,
+        // the scraper went away or never finished its request
+        _ => return,
+    };
+
+    let response: Vec<u8> = match parse_request_line(&head) {
+        Some(("GET", "/metrics")) | Some(("HEAD", "/metrics")) => {
+            let body: String = {
+                let shards: Shards = state.keyspace.lock_all().await;
+                render(&state, &shards.views())
+            };
+            let mut response: Vec<u8> = http_head("200 OK", CONTENT_TYPE, body.len());
+            if !head.starts_with(b"HEAD") {
+                response.extend_from_slice(body.as_bytes());
+            }
+            response
+        }
+        Some(("GET", _)) | Some(("HEAD", _)) => http_error("404 Not Found"),
+        Some(_) => http_error("405 Method Not Allowed"),
+        None => http_error("400 Bad Request"),
+    };
+    let _ = client.write_all(&response).await;
+    let _ = client.shutdown().await;
+}
+
+/// Reads up to the blank line ending the request head; the body, if any, is ignored.
+async fn read_head(client: &mut TcpStream) -> Result<Vec<u8>, Error> {
+    let mut head: Vec<u8> = Vec::new();
+    let mut buf: [u8; 1024] = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(Error::new("request head too long"));
+        }
+        let n: usize = client.read(&mut buf).await?;
+        if n == 0 {
+            return Err(Error::new("connection closed"));
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    return Ok(head);
+}
+
+/// The method and path of the request, without the query string.
+fn parse_request_line(head: &[u8]) -> Option<(&str, &str)> {
+    let line: &[u8] = head.split(|b| *b == b'\r').next()?;
+    let mut parts = std::str::from_utf8(line).ok()?.split(' ');
+    let method: &str = parts.next()?;
+    let target: &str = parts.next()?;
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    return Some((method, target.split('?').next()?));
+}
+
+fn http_head(status: &str, content_type: &str, len: usize) -> Vec<u8> {
+    return format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, len
+    )
+    .into_bytes();
+}
+
+fn http_error(status: &str) -> Vec<u8> {
+    let body: String = format!("{}\n", status);
+    let mut response: Vec<u8> = http_head(status, "text/plain; charset=utf-8", body.len());
+    response.extend_from_slice(body.as_bytes());
+    return response;
+}
+
+/// Renders every metric in the Prometheus text format, from the databases
+/// of every shard of the keyspace.
+pub fn render(state: &ServerState, shards: &[&Vec<Database>]) -> String {
+    let dbs: Vec<&Database> = shards.iter().flat_map(|dbs| dbs.iter()).collect();
+    let stats = &state.stats;
+    let mut out: String = String::new();
+
+    let uptime: u64 = stats.started.elapsed().as_secs();
+    gauge(
+        &mut out,
+        "redis_uptime_seconds",
+        "Seconds since the server started.",
+        uptime,
+    );
+    gauge(
+        &mut out,
+        "redis_connected_clients",
+        "Clients currently connected.",
+        stats.connected_clients.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "redis_connections_received_total",
+        "Connections accepted.",
+        stats.total_connections_received.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "redis_commands_processed_total",
+        "Commands received, unknown ones included.",
+        stats.total_commands_processed.load(Ordering::Relaxed),
+    );
+    render_commands(&mut out, &stats.command_stats());
+
+    render_keyspace(&mut out, shards);
+    let used: usize = dbs.iter().map(|db| db.memory_usage()).sum();
+    let peak: u64 = stats
+        .used_memory_peak
+        .fetch_max(used as u64, Ordering::Relaxed)
+        .max(used as u64);
+    gauge(
+        &mut out,
+        "redis_memory_used_bytes",
+        "Estimated bytes used by the dataset.",
+        used,
+    );
+    gauge(
+        &mut out,
+        "redis_memory_used_peak_bytes",
+        "Highest dataset size seen.",
+        peak,
+    );
+    gauge(
+        &mut out,
+        "redis_memory_used_rss_bytes",
+        "Resident set size of the process.",
+        resident_memory(),
+    );
+    gauge(
+        &mut out,
+        "redis_memory_max_bytes",
+        "The maxmemory limit, 0 for none.",
+        state.config.read().unwrap().maxmemory,
+    );
+    counter(
+        &mut out,
+        "redis_expired_keys_total",
+        "Keys deleted because their TTL passed.",
+        dbs.iter().map(|db| db.stats.expired).sum::<u64>(),
+    );
+    counter(
+        &mut out,
+        "redis_evicted_keys_total",
+        "Keys evicted to stay under maxmemory.",
+        stats.evicted_keys.load(Ordering::Relaxed),
+    );
+
+    let dirty: u64 = dbs.iter().map(|db| db.stats.dirty).sum();
+    gauge(
+        &mut out,
+        "redis_rdb_changes_since_last_save",
+        "Changes to the dataset since the last snapshot.",
+        dirty.saturating_sub(state.dirty_at_last_save.load(Ordering::Relaxed)),
+    );
+    gauge(
+        &mut out,
+        "redis_rdb_bgsave_in_progress",
+        "Whether a background save is running.",
+        state.bgsave_in_progress.load(Ordering::Relaxed) as u8,
+    );
+    gauge(
+        &mut out,
+        "redis_rdb_last_save_timestamp_seconds",
+        "Unix time of the last successful save.",
+        state.lastsave.load(Ordering::Relaxed),
+    );
+    gauge(
+        &mut out,
+        "redis_rdb_last_bgsave_status",
+        "Whether the last background save succeeded.",
+        state.last_bgsave_ok.load(Ordering::Relaxed) as u8,
+    );
+    gauge(
+        &mut out,
+        "redis_rdb_last_bgsave_duration_seconds",
+        "Duration of the last background save, -1 if there was none.",
+        state.last_bgsave_time_sec.load(Ordering::Relaxed),
+    );
+    return out;
+}
+
+fn render_commands(out: &mut String, commands: &[(&'static str, CommandStats)]) {
+    family(out, "redis_commands_total", "counter", "Calls per command.");
+    for (name, stats) in commands {
+        let _ = writeln!(
+            out,
+            "redis_commands_total{{cmd=\"{}\"}} {}",
+            escape_label(name),
+            stats.calls
+        );
+    }
+    family(
+        out,
+        "redis_commands_failed_total",
+        "counter",
+        "Calls per command answered with an error.",
+    );
+    for (name, stats) in commands {
+        let _ = writeln!(
+            out,
+            "redis_commands_failed_total{{cmd=\"{}\"}} {}",
+            escape_label(name),
+            stats.failed_calls
+        );
+    }
+
+    family(
+        out,
+        "redis_command_duration_seconds",
+        "histogram",
+        "Time from reading a command to its reply, waiting for its keys included.",
+    );
+    for (name, stats) in commands {
+        let name: String = escape_label(name);
+        let mut cumulative: u64 = 0;
+        for (bound, count) in LATENCY_BUCKETS_US.iter().zip(stats.latency_buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "redis_command_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}",
+                name,
+                *bound as f64 / 1e6,
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "redis_command_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} {}",
+            name, stats.calls
+        );
+        let _ = writeln!(
+            out,
+            "redis_command_duration_seconds_sum{{cmd=\"{}\"}} {}",
+            name,
+            stats.usec as f64 / 1e6
+        );
+        let _ = writeln!(
+            out,
+            "redis_command_duration_seconds_count{{cmd=\"{}\"}} {}",
+            name, stats.calls
+        );
+    }
+}
+
+/// The keys of every logical database, summed over the shards, empty ones included.
+fn render_keyspace(out: &mut String, shards: &[&Vec<Database>]) {
+    let databases: usize = shards.first().map(|dbs| dbs.len()).unwrap_or(0);
+    family(out, "redis_db_keys", "gauge", "Keys per database.");
+    for i in 0..databases {
+        let keys: usize = shards.iter().map(|dbs| dbs[i].len()).sum();
+        let _ = writeln!(out, "redis_db_keys{{db=\"db{}\"}} {}", i, keys);
+    }
+    family(
+        out,
+        "redis_db_keys_expiring",
+        "gauge",
+        "Keys with a TTL per database.",
+    );
+    for i in 0..databases {
+        let expires: usize = shards.iter().map(|dbs| dbs[i].expires_len()).sum();
+        let _ = writeln!(out, "redis_db_keys_expiring{{db=\"db{}\"}} {}", i, expires);
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    family(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    family(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Escapes a label value as the text format requires.
+fn escape_label(value: &str) -> String {
+    return value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn parse_request_line_test() {
+        assert_eq!(
+            parse_request_line(b"GET /metrics?x=1 HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Some(("GET", "/metrics"))
+        );
+        assert_eq!(
+            parse_request_line(b"POST / HTTP/1.0\r\n\r\n"),
+            Some(("POST", "/"))
+        );
+        assert_eq!(parse_request_line(b"GET /metrics\r\n\r\n"), None);
+        assert_eq!(parse_request_line(b"\xff\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn render_test() {
+        let state: ServerState = ServerState::new(Config::default());
+        state
+            .stats
+            .record_command("get", Duration::from_micros(30), false);
+        state
+            .stats
+            .record_command("get", Duration::from_secs(2), true);
+        let mut dbs: Vec<Database> = vec![Database::new(), Database::new()];
+        dbs[1].add(b"key", b"value");
+        dbs[1].set(b"ttl", b"value".to_vec(), Some(u64::MAX));
+
+        let out: String = render(&state, &[&dbs]);
+        let lines: Vec<&str> = out.lines().collect();
+        for expected in [
+            "redis_commands_total{cmd=\"get\"} 2",
+            "redis_commands_failed_total{cmd=\"get\"} 1",
+            "redis_command_duration_seconds_bucket{cmd=\"get\",le=\"0.00001\"} 0",
+            "redis_command_duration_seconds_bucket{cmd=\"get\",le=\"0.00005\"} 1",
+            "redis_command_duration_seconds_bucket{cmd=\"get\",le=\"1\"} 1",
+            "redis_command_duration_seconds_bucket{cmd=\"get\",le=\"+Inf\"} 2",
+            "redis_command_duration_seconds_sum{cmd=\"get\"} 2.00003",
+            "redis_command_duration_seconds_count{cmd=\"get\"} 2",
+            "redis_db_keys{db=\"db0\"} 0",
+            "redis_db_keys{db=\"db1\"} 2",
+            "redis_db_keys_expiring{db=\"db1\"} 1",
+            "redis_rdb_last_bgsave_status 1",
+            "# TYPE redis_command_duration_seconds histogram",
+        ] {
+            assert!(lines.contains(&expected), "missing {}", expected);
+        }
+        assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
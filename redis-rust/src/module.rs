@@ -0,0 +1,504 @@
+//! Plugins adding commands and data types, in the spirit of Redis modules.
+//!
+//! Rust has no stable ABI to load code at runtime, so a plugin is a type
+//! implementing `Module` compiled into the binary that embeds the server.
+//! `register` makes it available under its name; `loadmodule <name> [args]`
+//! in the config loads it at startup, or MODULE LOAD at runtime. Loading
+//! calls `Module::load`, which adds commands to the command table and value
+//! types to the keyspace through a `ModuleLoader`.
+//!
+//! A module command is a `Command` like any other: it has an arity, flags and
+//! key positions, and its handler gets the same `Context`. Values of a module
+//! type are stored as `Value::Module`; the type gives them a name TYPE
+//! reports and the RDB encoding of Redis modules (`RDB_TYPE_MODULE_2`), so they
+//! are saved, loaded, dumped and sent to replicas with the rest of the dataset.
+//!
+//! Registered modules are process-wide, like the code they come with, but
+//! loading one is not: every server keeps the modules it loaded, with their
+//! commands and types, in its `Modules`. Servers running in the same process
+//! only see the modules they loaded themselves.
+
+use std::any::Any;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+use crate::commands::{self, Command};
+use crate::rdb::{write_length, write_string, RdbReader};
+use crate::Error;
+
+/// Characters of a type name, each encoded in 6 bits of the module type id.
+const TYPE_NAME_CHARSET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const TYPE_NAME_LEN: usize = 9;
+const MAX_ENCODING_VERSION: u32 = (1 << 10) - 1;
+
+/// Opcodes tagging each field a module saves, as in Redis RDB files.
+const RDB_MODULE_OPCODE_EOF: u64 = 0;
+const RDB_MODULE_OPCODE_SINT: u64 = 1;
+const RDB_MODULE_OPCODE_UINT: u64 = 2;
+const RDB_MODULE_OPCODE_FLOAT: u64 = 3;
+const RDB_MODULE_OPCODE_DOUBLE: u64 = 4;
+const RDB_MODULE_OPCODE_STRING: u64 = 5;
+
+/// A plugin, registered with `register` and loaded by name.
+pub trait Module: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The version MODULE LIST reports.
+    fn version(&self) -> i64 {
+        return 1;
+    }
+
+    /// Registers the commands and types of the module. `args` are the words
+    /// following the name in `loadmodule` or MODULE LOAD.
+    fn load(&self, loader: &mut ModuleLoader, args: &[String]) -> Result<(), Error>;
+}
+
+/// A value type defined by a module, which knows how to load its values.
+pub trait ModuleType: Send + Sync {
+    /// Exactly nine characters out of `A-Z a-z 0-9 - _`, unique among the
+    /// loaded types. TYPE reports it and RDB files identify values with it.
+    fn name(&self) -> &'static str;
+
+    /// Version of the serialization written by `ModuleValue::rdb_save`, up to
+    /// 1023. It is stored along each value and passed back to `rdb_load`.
+    fn encoding_version(&self) -> u32;
+
+    fn rdb_load(&self, io: &mut ModuleReader, encver: u32) -> Result<Box<dyn ModuleValue>, Error>;
+}
+
+/// A value of a module type, held by a key.
+pub trait ModuleValue: Any + Debug + Send + Sync {
+    /// The name of the `ModuleType` the value belongs to.
+    fn type_name(&self) -> &'static str;
+
+    fn clone_value(&self) -> Box<dyn ModuleValue>;
+
+    /// Serializes the value for RDB files, DUMP and full resyncs.
+    fn rdb_save(&self, io: &mut ModuleWriter);
+
+    /// The commands that recreate the value under `key`, for an append only
+    /// file rewrite.
+    fn aof_rewrite(&self, key: &[u8]) -> Vec<Vec<Vec<u8>>>;
+
+    /// Estimated bytes used by the value; the size of its serialization
+    /// unless the type knows better.
+    fn memory(&self) -> usize {
+        let mut out: Vec<u8> = Vec::new();
+        self.rdb_save(&mut ModuleWriter { out: &mut out });
+        return out.len();
+    }
+}
+
+/// The value of a key holding a module type.
+#[derive(Debug)]
+pub struct ModuleObject(Box<dyn ModuleValue>);
+
+impl ModuleObject {
+    pub fn new<T: ModuleValue>(value: T) -> Self {
+        return ModuleObject(Box::new(value));
+    }
+
+    pub fn value(&self) -> &dyn ModuleValue {
+        return self.0.as_ref();
+    }
+
+    pub fn downcast_ref<T: ModuleValue>(&self) -> Option<&T> {
+        let value: &dyn Any = self.0.as_ref();
+        return value.downcast_ref::<T>();
+    }
+
+    pub fn downcast_mut<T: ModuleValue>(&mut self) -> Option<&mut T> {
+        let value: &mut dyn Any = self.0.as_mut();
+        return value.downcast_mut::<T>();
+    }
+}
+
+impl From<Box<dyn ModuleValue>> for ModuleObject {
+    fn from(value: Box<dyn ModuleValue>) -> Self {
+        return ModuleObject(value);
+    }
+}
+
+impl Clone for ModuleObject {
+    fn clone(&self) -> Self {
+        return ModuleObject(self.0.clone_value());
+    }
+}
+
+/// Values are equal when they are of the same type and serialize alike.
+impl PartialEq for ModuleObject {
+    fn eq(&self, other: &Self) -> bool {
+        let serialize = |value: &dyn ModuleValue| -> Vec<u8> {
+            let mut out: Vec<u8> = Vec::new();
+            value.rdb_save(&mut ModuleWriter { out: &mut out });
+            return out;
+        };
+        return self.0.type_name() == other.0.type_name()
+            && serialize(self.value()) == serialize(other.value());
+    }
+}
+
+/// Collects what `Module::load` registers; nothing is visible to clients
+/// unless the module loads successfully.
+pub struct ModuleLoader<'m> {
+    /// The modules the server already loaded.
+    modules: &'m Modules,
+    commands: Vec<Command>,
+    types: Vec<Arc<dyn ModuleType>>,
+}
+
+impl ModuleLoader<'_> {
+    /// Adds a command to the command table. Its name must not be taken by
+    /// another command.
+    pub fn create_command(&mut self, command: Command) -> Result<(), Error> {
+        if commands::lookup(self.modules, command.name.as_bytes()).is_some()
+            || self
+                .commands
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(command.name))
+        {
+            return Err(Error {
+                message: format!("command '{}' already exists", command.name),
+            });
+        }
+        self.commands.push(command);
+        return Ok(());
+    }
+
+    /// Adds a value type; its name must be valid and not taken by another type.
+    pub fn create_data_type(&mut self, data_type: Arc<dyn ModuleType>) -> Result<(), Error> {
+        let name: &str = data_type.name();
+        if name.len() != TYPE_NAME_LEN || !name.bytes().all(|c| TYPE_NAME_CHARSET.contains(&c)) {
+            return Err(Error {
+                message: format!(
+                    "invalid type name '{}': it must be 9 characters out of A-Z, a-z, 0-9, - and _",
+                    name
+                ),
+            });
+        }
+        if data_type.encoding_version() > MAX_ENCODING_VERSION {
+            return Err(Error {
+                message: format!("encoding version of type '{}' is above 1023", name),
+            });
+        }
+        if self.modules.data_type(name).is_some() || self.types.iter().any(|t| t.name() == name) {
+            return Err(Error {
+                message: format!("type '{}' already exists", name),
+            });
+        }
+        self.types.push(data_type);
+        return Ok(());
+    }
+}
+
+/// A module that was loaded, as MODULE LIST reports it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadedModule {
+    pub name: String,
+    pub version: i64,
+    pub args: Vec<String>,
+}
+
+/// Modules compiled into the binary, available to every server of the process.
+static AVAILABLE: RwLock<Vec<Arc<dyn Module>>> = RwLock::new(Vec::new());
+
+/// Makes `module` available to `loadmodule` and MODULE LOAD. Registering a
+/// name again replaces the module for the servers that load it later.
+pub fn register(module: Arc<dyn Module>) {
+    let mut available = AVAILABLE.write().unwrap();
+    available.retain(|m| m.name() != module.name());
+    available.push(module);
+}
+
+/// The modules one server loaded, with the commands and types they added.
+#[derive(Default)]
+pub struct Modules {
+    inner: RwLock<LoadedModules>,
+}
+
+#[derive(Default)]
+struct LoadedModules {
+    loaded: Vec<LoadedModule>,
+    commands: Vec<&'static Command>,
+    types: Vec<Arc<dyn ModuleType>>,
+}
+
+impl Modules {
+    pub fn new() -> Self {
+        return Modules::default();
+    }
+
+    /// Loads the registered module `name`, adding its commands and types.
+    pub fn load(&self, name: &str, args: &[String]) -> Result<(), Error> {
+        if self.is_loaded(name) {
+            return Err(Error {
+                message: format!("Module {} is already loaded", name),
+            });
+        }
+        let module: Arc<dyn Module> =
+            match AVAILABLE.read().unwrap().iter().find(|m| m.name() == name) {
+                Some(m) => Arc::clone(m),
+                None => {
+                    return Err(Error {
+                        message: format!("Module {} is not registered", name),
+                    })
+                }
+            };
+
+        let mut loader: ModuleLoader = ModuleLoader {
+            modules: self,
+            commands: Vec::new(),
+            types: Vec::new(),
+        };
+        if let Err(e) = module.load(&mut loader, args) {
+            return Err(Error {
+                message: format!("Module {} failed to load: {}", name, e.message),
+            });
+        }
+        let (commands, types) = (loader.commands, loader.types);
+
+        let mut inner = self.inner.write().unwrap();
+        // a concurrent MODULE LOAD may have loaded it meanwhile
+        if inner.loaded.iter().any(|m| m.name == name) {
+            return Err(Error {
+                message: format!("Module {} is already loaded", name),
+            });
+        }
+        // handlers look commands up as `&'static Command`, so they are never freed
+        for command in commands {
+            inner.commands.push(Box::leak(Box::new(command)));
+        }
+        inner.types.extend(types);
+        inner.loaded.push(LoadedModule {
+            name: name.to_string(),
+            version: module.version(),
+            args: args.to_vec(),
+        });
+        return Ok(());
+    }
+
+    /// Loads the modules of the `loadmodule` config entries at startup.
+    pub fn load_configured(&self, entries: &[String]) -> Result<(), Error> {
+        for entry in entries {
+            let mut words = entry.split_whitespace().map(String::from);
+            let name: String = match words.next() {
+                Some(n) => n,
+                None => continue,
+            };
+            self.load(&name, &words.collect::<Vec<String>>())?;
+            println!("Module '{}' loaded", name);
+        }
+        return Ok(());
+    }
+
+    pub fn loaded(&self) -> Vec<LoadedModule> {
+        return self.inner.read().unwrap().loaded.clone();
+    }
+
+    pub fn is_loaded(&self, name: &str) -> bool {
+        return self
+            .inner
+            .read()
+            .unwrap()
+            .loaded
+            .iter()
+            .any(|m| m.name == name);
+    }
+
+    /// The module command named `name`, if a loaded module added one.
+    pub fn command(&self, name: &[u8]) -> Option<&'static Command> {
+        return self
+            .inner
+            .read()
+            .unwrap()
+            .commands
+            .iter()
+            .find(|c| c.name.as_bytes().eq_ignore_ascii_case(name))
+            .copied();
+    }
+
+    fn data_type(&self, name: &str) -> Option<Arc<dyn ModuleType>> {
+        return self
+            .inner
+            .read()
+            .unwrap()
+            .types
+            .iter()
+            .find(|t| t.name() == name)
+            .cloned();
+    }
+}
+
+/// The 64 bit id of a type in RDB files: its name in 6 bit characters
+/// followed by the 10 bit encoding version.
+fn type_id(name: &str, encver: u32) -> u64 {
+    let mut id: u64 = 0;
+    for c in name.bytes() {
+        let index: usize = TYPE_NAME_CHARSET.iter().position(|x| *x == c).unwrap_or(0);
+        id = (id << 6) | index as u64;
+    }
+    return (id << 10) | encver as u64;
+}
+
+fn parse_type_id(id: u64) -> (String, u32) {
+    let encver: u32 = (id & MAX_ENCODING_VERSION as u64) as u32;
+    let mut name: Vec<u8> = Vec::with_capacity(TYPE_NAME_LEN);
+    for i in (0..TYPE_NAME_LEN).rev() {
+        let index: u64 = (id >> (10 + 6 * i)) & 63;
+        name.push(TYPE_NAME_CHARSET[index as usize]);
+    }
+    return (String::from_utf8(name).unwrap(), encver);
+}
+
+/// Writes a module value after its `RDB_TYPE_MODULE_2` type byte: the type
+/// id, the fields saved by the type and an end marker.
+pub fn write_value(out: &mut Vec<u8>, object: &ModuleObject, modules: &Modules) {
+    let name: &str = object.0.type_name();
+    let encver: u32 = modules
+        .data_type(name)
+        .map(|t| t.encoding_version())
+        .unwrap_or(0);
+    write_length(out, type_id(name, encver));
+    object.0.rdb_save(&mut ModuleWriter { out });
+    write_length(out, RDB_MODULE_OPCODE_EOF);
+}
+
+/// Reads a value written by `write_value`; its type must be loaded.
+pub fn read_value(reader: &mut RdbReader, modules: &Modules) -> Result<ModuleObject, Error> {
+    let (name, encver) = parse_type_id(reader.length()?);
+    let data_type: Arc<dyn ModuleType> = match modules.data_type(&name) {
+        Some(t) => t,
+        None => {
+            return Err(Error {
+                message: format!(
+                    "The RDB file contains module data for the module type '{}', that the responsible module is not able to load. Check for modules log above for additional clues.",
+                    name
+                ),
+            })
+        }
+    };
+    let value: Box<dyn ModuleValue> = data_type.rdb_load(&mut ModuleReader { reader }, encver)?;
+    if reader.length()? != RDB_MODULE_OPCODE_EOF {
+        return Err(Error {
+            message: format!("The module type '{}' left data unread in the RDB", name),
+        });
+    }
+    return Ok(ModuleObject(value));
+}
+
+/// What `ModuleValue::rdb_save` writes fields with; each field is tagged
+/// with its kind, so loading checks it reads them back in the same order.
+pub struct ModuleWriter<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> ModuleWriter<'a> {
+    pub fn save_unsigned(&mut self, value: u64) {
+        write_length(self.out, RDB_MODULE_OPCODE_UINT);
+        write_length(self.out, value);
+    }
+
+    pub fn save_signed(&mut self, value: i64) {
+        write_length(self.out, RDB_MODULE_OPCODE_SINT);
+        write_length(self.out, value as u64);
+    }
+
+    pub fn save_string(&mut self, value: &[u8]) {
+        write_length(self.out, RDB_MODULE_OPCODE_STRING);
+        write_string(self.out, value);
+    }
+
+    pub fn save_double(&mut self, value: f64) {
+        write_length(self.out, RDB_MODULE_OPCODE_DOUBLE);
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn save_float(&mut self, value: f32) {
+        write_length(self.out, RDB_MODULE_OPCODE_FLOAT);
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// What `ModuleType::rdb_load` reads fields with, in the order they were saved.
+pub struct ModuleReader<'r, 'a> {
+    reader: &'r mut RdbReader<'a>,
+}
+
+impl<'r, 'a> ModuleReader<'r, 'a> {
+    fn expect(&mut self, opcode: u64, kind: &str) -> Result<(), Error> {
+        if self.reader.length()? != opcode {
+            return Err(Error {
+                message: format!("Error loading module data: expected {}", kind),
+            });
+        }
+        return Ok(());
+    }
+
+    pub fn load_unsigned(&mut self) -> Result<u64, Error> {
+        self.expect(RDB_MODULE_OPCODE_UINT, "an unsigned integer")?;
+        return self.reader.length();
+    }
+
+    pub fn load_signed(&mut self) -> Result<i64, Error> {
+        self.expect(RDB_MODULE_OPCODE_SINT, "a signed integer")?;
+        return Ok(self.reader.length()? as i64);
+    }
+
+    pub fn load_string(&mut self) -> Result<Vec<u8>, Error> {
+        self.expect(RDB_MODULE_OPCODE_STRING, "a string")?;
+        return self.reader.string();
+    }
+
+    pub fn load_double(&mut self) -> Result<f64, Error> {
+        self.expect(RDB_MODULE_OPCODE_DOUBLE, "a double")?;
+        return Ok(f64::from_le_bytes(self.reader.take(8)?.try_into().unwrap()));
+    }
+
+    pub fn load_float(&mut self) -> Result<f32, Error> {
+        self.expect(RDB_MODULE_OPCODE_FLOAT, "a float")?;
+        return Ok(f32::from_le_bytes(self.reader.take(4)?.try_into().unwrap()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_id_test() {
+        let id: u64 = type_id("hellotype", 3);
+        assert_eq!(parse_type_id(id), (String::from("hellotype"), 3));
+        assert_eq!(
+            parse_type_id(type_id("A-_z09xyZ", 1023)),
+            (String::from("A-_z09xyZ"), 1023)
+        );
+    }
+
+    #[test]
+    fn fields_round_trip_test() {
+        let mut out: Vec<u8> = Vec::new();
+        let mut writer: ModuleWriter = ModuleWriter { out: &mut out };
+        writer.save_unsigned(u64::MAX);
+        writer.save_signed(-42);
+        writer.save_string(b"field");
+        writer.save_double(1.5);
+        writer.save_float(-0.25);
+        writer.save_unsigned(7);
+
+        let mut reader: RdbReader = RdbReader { buf: &out, pos: 0 };
+        let mut io: ModuleReader = ModuleReader {
+            reader: &mut reader,
+        };
+        assert_eq!(io.load_unsigned().unwrap(), u64::MAX);
+        assert_eq!(io.load_signed().unwrap(), -42);
+        assert_eq!(io.load_string().unwrap(), b"field");
+        assert_eq!(io.load_double().unwrap(), 1.5);
+        assert_eq!(io.load_float().unwrap(), -0.25);
+        // a field of another kind than the one saved is an error
+        assert_eq!(
+            io.load_string().unwrap_err().message,
+            "Error loading module data: expected a string"
+        );
+    }
+}
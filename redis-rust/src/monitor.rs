@@ -0,0 +1,93 @@
+use std::fmt::Write;
+use std::time::Instant;
+
+use crate::client::Client;
+use crate::db::now_us;
+use crate::redis_parser::RedisType;
+use crate::ServerState;
+
+/// Quotes `bytes` the way Redis' `sdscatrepr` does, escaping quotes, backslashes,
+/// control characters and non-printable bytes.
+pub fn repr(bytes: &[u8]) -> String {
+    let mut out: String = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            0x20..=0x7e => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\x{:02x}", b);
+            }
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+/// The line sent to monitors for a command, without the leading `+`.
+pub fn monitor_line(time_us: u64, db: usize, addr: &str, args: &[Vec<u8>]) -> String {
+    let mut line: String = format!(
+        "{}.{:06} [{} {}]",
+        time_us / 1_000_000,
+        time_us % 1_000_000,
+        db,
+        addr
+    );
+    for arg in args {
+        line.push(' ');
+        line.push_str(&repr(arg));
+    }
+    return line;
+}
+
+/// Streams a processed command to every MONITOR client; `db` and `started` are
+/// the database and instant the command started with.
+pub fn feed_monitors(
+    state: &ServerState,
+    client: &Client,
+    db: usize,
+    started: Instant,
+    args: &[Vec<u8>],
+) {
+    // building the line is skipped entirely while nobody is watching
+    if !state.clients.has_monitors() {
+        return;
+    }
+
+    let time_us: u64 = now_us().saturating_sub(started.elapsed().as_micros() as u64);
+    let line: String = monitor_line(time_us, db, &client.addr, args);
+    for monitor in state.clients.monitors() {
+        monitor.send(RedisType::Status(line.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repr_test() {
+        assert_eq!(repr(b"plain"), "\"plain\"");
+        assert_eq!(repr(b"a \"b\"\\"), "\"a \\\"b\\\"\\\\\"");
+        assert_eq!(repr(b"\r\n\t\x07\x08"), "\"\\r\\n\\t\\a\\b\"");
+        assert_eq!(repr(&[0x00, 0xff, b'x']), "\"\\x00\\xffx\"");
+    }
+
+    #[test]
+    fn monitor_line_test() {
+        assert_eq!(
+            monitor_line(
+                1339518083107412,
+                0,
+                "127.0.0.1:60866",
+                &[b"keys".to_vec(), b"*".to_vec()]
+            ),
+            "1339518083.107412 [0 127.0.0.1:60866] \"keys\" \"*\""
+        );
+    }
+}
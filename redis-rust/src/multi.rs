@@ -0,0 +1,177 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// A key watched by a client, in a given database.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchedKey {
+    pub db: usize,
+    pub key: Vec<u8>,
+    /// Whether the key was already logically expired when it was watched, so
+    /// that its deletion does not count as a change.
+    pub expired: bool,
+}
+
+/// The keys clients WATCH, and the clients that saw one of them change.
+#[derive(Default)]
+pub struct Watches {
+    table: Mutex<WatchTable>,
+    /// Number of clients watching keys, checked before taking the lock.
+    clients: AtomicUsize,
+}
+
+#[derive(Default)]
+struct WatchTable {
+    keys: HashMap<(usize, Vec<u8>), BTreeSet<u64>>,
+    by_client: HashMap<u64, Vec<WatchedKey>>,
+    /// Clients whose next EXEC must fail.
+    dirty: HashSet<u64>,
+}
+
+impl Watches {
+    pub fn new() -> Self {
+        return Watches::default();
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.clients.load(Ordering::Relaxed) > 0;
+    }
+
+    pub fn watch(&self, client_id: u64, watched: WatchedKey) {
+        let mut table = self.table.lock().unwrap();
+        let keys: &mut Vec<WatchedKey> = table.by_client.entry(client_id).or_default();
+        if keys
+            .iter()
+            .any(|k| k.db == watched.db && k.key == watched.key)
+        {
+            return;
+        }
+        if keys.is_empty() {
+            self.clients.fetch_add(1, Ordering::Relaxed);
+        }
+        keys.push(watched.clone());
+        table
+            .keys
+            .entry((watched.db, watched.key))
+            .or_default()
+            .insert(client_id);
+    }
+
+    /// The keys `client_id` watches.
+    pub fn watched(&self, client_id: u64) -> Vec<WatchedKey> {
+        let table = self.table.lock().unwrap();
+        return table.by_client.get(&client_id).cloned().unwrap_or_default();
+    }
+
+    /// Forgets every key `client_id` watches, as EXEC, DISCARD and UNWATCH do.
+    pub fn unwatch_all(&self, client_id: u64) {
+        let mut table = self.table.lock().unwrap();
+        table.dirty.remove(&client_id);
+        let keys: Vec<WatchedKey> = match table.by_client.remove(&client_id) {
+            Some(keys) => keys,
+            None => return,
+        };
+        self.clients.fetch_sub(1, Ordering::Relaxed);
+        for watched in keys {
+            let slot: (usize, Vec<u8>) = (watched.db, watched.key);
+            if let Some(ids) = table.keys.get_mut(&slot) {
+                ids.remove(&client_id);
+                if ids.is_empty() {
+                    table.keys.remove(&slot);
+                }
+            }
+        }
+    }
+
+    /// Marks the clients watching the changed keys, and those watching any key
+    /// of a flushed database, so their transaction fails. `exists` tells
+    /// whether a changed key is still there: the deletion of a key that had
+    /// already expired when it was watched is not a change.
+    pub fn touch<F: Fn(usize, &[u8]) -> bool>(
+        &self,
+        changed: &[(usize, Vec<u8>)],
+        flushed: &[usize],
+        exists: F,
+    ) {
+        let mut guard = self.table.lock().unwrap();
+        let table: &mut WatchTable = &mut guard;
+        let mut touched: Vec<u64> = Vec::new();
+        for slot in changed {
+            let ids: &BTreeSet<u64> = match table.keys.get(slot) {
+                Some(ids) => ids,
+                None => continue,
+            };
+            for id in ids {
+                let watched: Option<&mut WatchedKey> = table
+                    .by_client
+                    .get_mut(id)
+                    .and_then(|keys| keys.iter_mut().find(|k| k.db == slot.0 && k.key == slot.1));
+                match watched {
+                    Some(watched) if watched.expired && !exists(slot.0, &slot.1) => {
+                        watched.expired = false;
+                    }
+                    _ => touched.push(*id),
+                }
+            }
+        }
+        for ((db, _), ids) in table.keys.iter() {
+            if flushed.contains(db) {
+                touched.extend(ids);
+            }
+        }
+        table.dirty.extend(touched);
+    }
+
+    pub fn is_dirty(&self, client_id: u64) -> bool {
+        return self.table.lock().unwrap().dirty.contains(&client_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watched(db: usize, key: &[u8]) -> WatchedKey {
+        return WatchedKey {
+            db,
+            key: key.to_vec(),
+            expired: false,
+        };
+    }
+
+    #[test]
+    fn watch_test() {
+        let watches: Watches = Watches::new();
+        watches.watch(1, watched(0, b"foo"));
+        watches.watch(1, watched(0, b"foo"));
+        watches.watch(2, watched(1, b"foo"));
+        assert_eq!(watches.watched(1).len(), 1);
+
+        // the same name in another database is another key
+        watches.touch(&[(0, b"foo".to_vec())], &[], |_, _| true);
+        assert!(watches.is_dirty(1));
+        assert!(!watches.is_dirty(2));
+
+        watches.touch(&[], &[1], |_, _| true);
+        assert!(watches.is_dirty(2));
+
+        // deleting a key that was watched once expired changes nothing
+        watches.watch(
+            3,
+            WatchedKey {
+                expired: true,
+                ..watched(0, b"bar")
+            },
+        );
+        watches.touch(&[(0, b"bar".to_vec())], &[], |_, _| false);
+        assert!(!watches.is_dirty(3));
+        watches.touch(&[(0, b"bar".to_vec())], &[], |_, _| true);
+        assert!(watches.is_dirty(3));
+
+        watches.unwatch_all(1);
+        watches.unwatch_all(2);
+        watches.unwatch_all(3);
+        assert!(!watches.is_dirty(1));
+        assert!(!watches.is_active());
+    }
+}
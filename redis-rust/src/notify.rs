@@ -0,0 +1,132 @@
+use crate::{Database, ServerState};
+
+/// Keyspace notification classes, selected with the `notify-keyspace-events` letters.
+pub const NOTIFY_KEYSPACE: u32 = 1 << 0; // K
+pub const NOTIFY_KEYEVENT: u32 = 1 << 1; // E
+pub const NOTIFY_GENERIC: u32 = 1 << 2; // g
+pub const NOTIFY_STRING: u32 = 1 << 3; // $
+pub const NOTIFY_LIST: u32 = 1 << 4; // l
+pub const NOTIFY_SET: u32 = 1 << 5; // s
+pub const NOTIFY_HASH: u32 = 1 << 6; // h
+pub const NOTIFY_ZSET: u32 = 1 << 7; // z
+pub const NOTIFY_EXPIRED: u32 = 1 << 8; // x
+pub const NOTIFY_EVICTED: u32 = 1 << 9; // e
+pub const NOTIFY_STREAM: u32 = 1 << 10; // t
+pub const NOTIFY_KEY_MISS: u32 = 1 << 11; // m
+pub const NOTIFY_MODULE: u32 = 1 << 12; // d
+pub const NOTIFY_NEW: u32 = 1 << 13; // n
+
+/// The classes enabled by the `A` alias; key misses and new keys must be asked for explicitly.
+pub const NOTIFY_ALL: u32 = NOTIFY_GENERIC
+    | NOTIFY_STRING
+    | NOTIFY_LIST
+    | NOTIFY_SET
+    | NOTIFY_HASH
+    | NOTIFY_ZSET
+    | NOTIFY_EXPIRED
+    | NOTIFY_EVICTED
+    | NOTIFY_STREAM
+    | NOTIFY_MODULE;
+
+/// Letter of each class, in the order Redis prints them back.
+const CLASS_LETTERS: &[(u32, char)] = &[
+    (NOTIFY_GENERIC, 'g'),
+    (NOTIFY_STRING, '$'),
+    (NOTIFY_LIST, 'l'),
+    (NOTIFY_SET, 's'),
+    (NOTIFY_HASH, 'h'),
+    (NOTIFY_ZSET, 'z'),
+    (NOTIFY_EXPIRED, 'x'),
+    (NOTIFY_EVICTED, 'e'),
+    (NOTIFY_STREAM, 't'),
+    (NOTIFY_MODULE, 'd'),
+];
+
+/// A change recorded by a `Database`, waiting to be published.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyspaceEvent {
+    pub class: u32,
+    pub event: &'static str,
+    pub key: Vec<u8>,
+}
+
+/// Parses a `notify-keyspace-events` value such as `KEA` or `Kx$`.
+pub fn parse_flags(value: &str) -> Option<u32> {
+    let mut flags: u32 = 0;
+    for c in value.chars() {
+        flags |= match c {
+            'A' => NOTIFY_ALL,
+            'K' => NOTIFY_KEYSPACE,
+            'E' => NOTIFY_KEYEVENT,
+            'm' => NOTIFY_KEY_MISS,
+            'n' => NOTIFY_NEW,
+            _ => CLASS_LETTERS.iter().find(|(_, l)| *l == c)?.0,
+        };
+    }
+    return Some(flags);
+}
+
+/// The canonical string for `flags`, as CONFIG GET shows it.
+pub fn flags_to_string(flags: u32) -> String {
+    let mut out: String = String::new();
+    if flags & NOTIFY_ALL == NOTIFY_ALL {
+        out.push('A');
+    } else {
+        for (class, letter) in CLASS_LETTERS {
+            if flags & class != 0 {
+                out.push(*letter);
+            }
+        }
+    }
+    for (class, letter) in [
+        (NOTIFY_KEYSPACE, 'K'),
+        (NOTIFY_KEYEVENT, 'E'),
+        (NOTIFY_KEY_MISS, 'm'),
+        (NOTIFY_NEW, 'n'),
+    ] {
+        if flags & class != 0 {
+            out.push(letter);
+        }
+    }
+    return out;
+}
+
+/// Publishes the events recorded by the databases of `shards` since the last
+/// call, on `__keyspace@<db>__:<key>` and `__keyevent@<db>__:<event>`.
+pub fn publish_events(state: &ServerState, shards: &mut [&mut Vec<Database>]) {
+    let flags: u32 = state.config.read().unwrap().notify_keyspace_events;
+
+    let dbs = shards.iter_mut().flat_map(|dbs| dbs.iter_mut().enumerate());
+    for (index, db) in dbs {
+        for event in db.take_events() {
+            if flags & NOTIFY_KEYSPACE != 0 {
+                let mut channel: Vec<u8> = format!("__keyspace@{}__:", index).into_bytes();
+                channel.extend_from_slice(&event.key);
+                state.pubsub.publish(&channel, event.event.as_bytes());
+            }
+            if flags & NOTIFY_KEYEVENT != 0 {
+                let channel: String = format!("__keyevent@{}__:{}", index, event.event);
+                state.pubsub.publish(channel.as_bytes(), &event.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_test() {
+        assert_eq!(parse_flags(""), Some(0));
+        assert_eq!(
+            parse_flags("KEA"),
+            Some(NOTIFY_KEYSPACE | NOTIFY_KEYEVENT | NOTIFY_ALL)
+        );
+        assert_eq!(parse_flags("Kq"), None);
+
+        assert_eq!(flags_to_string(parse_flags("EAK").unwrap()), "AKE");
+        assert_eq!(flags_to_string(parse_flags("Ex$g").unwrap()), "g$xE");
+        assert_eq!(flags_to_string(parse_flags("Kmn").unwrap()), "Kmn");
+    }
+}
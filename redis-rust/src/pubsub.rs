@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::client::Client;
+use crate::glob::glob_match;
+use crate::redis_parser::RedisType;
+
+type Subscribers = BTreeMap<u64, Arc<Client>>;
+
+/// Channel and pattern subscriptions of every client.
+///
+/// The per-client view (what SUBSCRIBE replies count) lives in `ClientMeta`;
+/// this is the reverse index PUBLISH uses to find the receivers.
+#[derive(Default)]
+pub struct PubSub {
+    channels: Mutex<HashMap<Vec<u8>, Subscribers>>,
+    patterns: Mutex<BTreeMap<Vec<u8>, Subscribers>>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        return PubSub::default();
+    }
+
+    pub fn subscribe(&self, client: &Arc<Client>, channel: &[u8]) {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(channel.to_vec())
+            .or_default()
+            .insert(client.id, Arc::clone(client));
+    }
+
+    pub fn unsubscribe(&self, client_id: u64, channel: &[u8]) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(subscribers) = channels.get_mut(channel) {
+            subscribers.remove(&client_id);
+            if subscribers.is_empty() {
+                channels.remove(channel);
+            }
+        }
+    }
+
+    pub fn psubscribe(&self, client: &Arc<Client>, pattern: &[u8]) {
+        self.patterns
+            .lock()
+            .unwrap()
+            .entry(pattern.to_vec())
+            .or_default()
+            .insert(client.id, Arc::clone(client));
+    }
+
+    pub fn punsubscribe(&self, client_id: u64, pattern: &[u8]) {
+        let mut patterns = self.patterns.lock().unwrap();
+        if let Some(subscribers) = patterns.get_mut(pattern) {
+            subscribers.remove(&client_id);
+            if subscribers.is_empty() {
+                patterns.remove(pattern);
+            }
+        }
+    }
+
+    /// Drops every subscription of a disconnecting client.
+    pub fn remove_client(&self, client: &Client) {
+        let meta = client.meta();
+        for channel in &meta.channels {
+            self.unsubscribe(client.id, channel);
+        }
+        for pattern in &meta.patterns {
+            self.punsubscribe(client.id, pattern);
+        }
+    }
+
+    /// Delivers `message` to the subscribers of `channel` and of every matching
+    /// pattern, returning the number of deliveries.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let mut receivers: usize = 0;
+
+        if let Some(subscribers) = self.channels.lock().unwrap().get(channel) {
+            for client in subscribers.values() {
+                client.send(RedisType::Push(Box::new(vec![
+                    RedisType::BulkString(String::from("message")),
+                    RedisType::BulkBytes(channel.to_vec()),
+                    RedisType::BulkBytes(message.to_vec()),
+                ])));
+                receivers += 1;
+            }
+        }
+
+        for (pattern, subscribers) in self.patterns.lock().unwrap().iter() {
+            if !glob_match(pattern, channel, false) {
+                continue;
+            }
+            for client in subscribers.values() {
+                client.send(RedisType::Push(Box::new(vec![
+                    RedisType::BulkString(String::from("pmessage")),
+                    RedisType::BulkBytes(pattern.clone()),
+                    RedisType::BulkBytes(channel.to_vec()),
+                    RedisType::BulkBytes(message.to_vec()),
+                ])));
+                receivers += 1;
+            }
+        }
+
+        return receivers;
+    }
+
+    /// Channels with at least one subscriber, optionally filtered by a glob pattern.
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let mut channels: Vec<Vec<u8>> = self
+            .channels
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|c| pattern.is_none_or(|p| glob_match(p, c, false)))
+            .cloned()
+            .collect();
+        channels.sort();
+        return channels;
+    }
+
+    pub fn numsub(&self, channel: &[u8]) -> usize {
+        return self
+            .channels
+            .lock()
+            .unwrap()
+            .get(channel)
+            .map_or(0, |s| s.len());
+    }
+
+    /// Number of distinct patterns subscribed to by any client.
+    pub fn numpat(&self) -> usize {
+        return self.patterns.lock().unwrap().len();
+    }
+
+    /// Whether anyone could receive a message; lets publishers skip building it.
+    pub fn has_subscribers(&self) -> bool {
+        return !self.channels.lock().unwrap().is_empty()
+            || !self.patterns.lock().unwrap().is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_test() {
+        let pubsub: PubSub = PubSub::new();
+        let exact: Arc<Client> = Arc::new(Client::new(1, "", "", -1));
+        let pattern: Arc<Client> = Arc::new(Client::new(2, "", "", -1));
+        let mut exact_inbox = exact.take_inbox().unwrap();
+        let mut pattern_inbox = pattern.take_inbox().unwrap();
+
+        pubsub.subscribe(&exact, b"news.tech");
+        pubsub.psubscribe(&pattern, b"news.*");
+        assert_eq!(pubsub.channels(None), vec![b"news.tech".to_vec()]);
+        assert_eq!(pubsub.numsub(b"news.tech"), 1);
+        assert_eq!(pubsub.numpat(), 1);
+
+        assert_eq!(pubsub.publish(b"news.tech", b"hello"), 2);
+        assert_eq!(pubsub.publish(b"news.art", b"hi"), 1);
+        assert_eq!(pubsub.publish(b"weather", b"rain"), 0);
+
+        assert_eq!(
+            exact_inbox.try_recv().unwrap(),
+            RedisType::Push(Box::new(vec![
+                RedisType::BulkString(String::from("message")),
+                RedisType::BulkString(String::from("news.tech")),
+                RedisType::BulkString(String::from("hello")),
+            ]))
+        );
+        assert!(exact_inbox.try_recv().is_err());
+        assert_eq!(
+            pattern_inbox.try_recv().unwrap(),
+            RedisType::Push(Box::new(vec![
+                RedisType::BulkString(String::from("pmessage")),
+                RedisType::BulkString(String::from("news.*")),
+                RedisType::BulkString(String::from("news.tech")),
+                RedisType::BulkString(String::from("hello")),
+            ]))
+        );
+
+        pubsub.unsubscribe(1, b"news.tech");
+        pubsub.punsubscribe(2, b"news.*");
+        assert!(!pubsub.has_subscribers());
+    }
+}
@@ -0,0 +1,46 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0x9E37_79B9_7F4A_7C15);
+    // xorshift must never be seeded with zero
+    return hasher.finish() | 1;
+}
+
+/// Fast non-cryptographic random number (xorshift64*), used for sampling keys.
+pub fn random_u64() -> u64 {
+    return STATE.with(|state| {
+        let mut x: u64 = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        return x.wrapping_mul(0x2545_F491_4F6C_DD1D);
+    });
+}
+
+/// Random index in `0..n`; `n` must be non-zero.
+pub fn random_below(n: usize) -> usize {
+    return (random_u64() % n as u64) as usize;
+}
+
+/// Random float in `[0, 1)`.
+pub fn random_f64() -> f64 {
+    return (random_u64() >> 11) as f64 / (1u64 << 53) as f64;
+}
+
+/// Random lowercase hex string.
+pub fn random_hex(len: usize) -> String {
+    let mut out: String = String::new();
+    while out.len() < len {
+        out.push_str(&format!("{:016x}", random_u64()));
+    }
+    out.truncate(len);
+    return out;
+}
@@ -0,0 +1,775 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::db::now_ms;
+use crate::keyspace::shard_for;
+use crate::module::{self, Modules};
+use crate::value::{EncodingLimits, Hash, List, Set, SortedSet};
+use crate::{Database, Error, Value};
+
+pub const RDB_VERSION: u32 = 11;
+
+const RDB_OPCODE_MODULE_AUX: u8 = 0xF7;
+const RDB_OPCODE_IDLE: u8 = 0xF8;
+const RDB_OPCODE_FREQ: u8 = 0xF9;
+const RDB_OPCODE_AUX: u8 = 0xFA;
+const RDB_OPCODE_RESIZEDB: u8 = 0xFB;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const RDB_OPCODE_EXPIRETIME: u8 = 0xFD;
+const RDB_OPCODE_SELECTDB: u8 = 0xFE;
+const RDB_OPCODE_EOF: u8 = 0xFF;
+
+pub const RDB_TYPE_STRING: u8 = 0;
+pub const RDB_TYPE_LIST: u8 = 1;
+pub const RDB_TYPE_SET: u8 = 2;
+pub const RDB_TYPE_ZSET: u8 = 3;
+pub const RDB_TYPE_HASH: u8 = 4;
+pub const RDB_TYPE_ZSET_2: u8 = 5;
+pub const RDB_TYPE_MODULE_2: u8 = 7;
+const RDB_TYPE_HASH_ZIPMAP: u8 = 9;
+const RDB_TYPE_LIST_ZIPLIST: u8 = 10;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
+const RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+/// Serializes every database into an RDB file image, including the CRC64 footer.
+///
+/// `shards` holds the parts of the keyspace, each with every logical
+/// database; the parts of a database are written as a single one.
+pub fn encode(shards: &[&Vec<Database>], modules: &Modules) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(format!("REDIS{:04}", RDB_VERSION).as_bytes());
+
+    write_aux(&mut out, "redis-ver", "7.2.0");
+    write_aux(&mut out, "redis-bits", "64");
+    write_aux(&mut out, "ctime", &(now_ms() / 1000).to_string());
+    write_aux(&mut out, "aof-base", "0");
+
+    let databases: usize = shards.first().map(|dbs| dbs.len()).unwrap_or(0);
+    for index in 0..databases {
+        let parts: Vec<&Database> = shards.iter().map(|dbs| &dbs[index]).collect();
+        let len: usize = parts.iter().map(|db| db.len()).sum();
+        if len == 0 {
+            continue;
+        }
+
+        out.push(RDB_OPCODE_SELECTDB);
+        write_length(&mut out, index as u64);
+        out.push(RDB_OPCODE_RESIZEDB);
+        write_length(&mut out, len as u64);
+        write_length(
+            &mut out,
+            parts.iter().map(|db| db.expires_len()).sum::<usize>() as u64,
+        );
+
+        for (key, entry) in parts.iter().flat_map(|db| db.iter()) {
+            if let Some(at) = entry.expires_at {
+                out.push(RDB_OPCODE_EXPIRETIME_MS);
+                out.extend_from_slice(&at.to_le_bytes());
+            }
+            out.push(value_type(&entry.value));
+            write_string(&mut out, key);
+            write_value(&mut out, &entry.value, modules);
+        }
+    }
+
+    out.push(RDB_OPCODE_EOF);
+    let checksum: u64 = crc64(0, &out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    return out;
+}
+
+/// Writes a snapshot to `path` through a temporary file so a crash never leaves a
+/// truncated dump behind.
+///
+/// Returns how long the fsync took, for the latency monitor.
+pub fn save(shards: &[&Vec<Database>], modules: &Modules, path: &Path) -> Result<Duration, Error> {
+    let image: Vec<u8> = encode(shards, modules);
+
+    let tmp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let mut file: fs::File = fs::File::create(&tmp)?;
+    file.write_all(&image)?;
+    let started: Instant = Instant::now();
+    file.sync_all()?;
+    let fsync: Duration = started.elapsed();
+    fs::rename(&tmp, path)?;
+
+    return Ok(fsync);
+}
+
+/// Loads `path` into `shards`, returning `Ok(false)` when there is no file to load.
+pub fn load(
+    path: &Path,
+    shards: &mut [&mut Vec<Database>],
+    modules: &Modules,
+) -> Result<bool, Error> {
+    let contents: Vec<u8> = match fs::read(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    decode(&contents, shards, modules)?;
+
+    // what was just loaded is by definition already on disk
+    for db in shards.iter_mut().flat_map(|dbs| dbs.iter_mut()) {
+        db.stats.dirty = 0;
+    }
+    return Ok(true);
+}
+
+/// Parses an RDB image into `shards`, putting every key in the shard it hashes
+/// to. Keys that already expired are skipped.
+///
+/// Only images holding aggregates in the plain encodings this server writes can
+/// be loaded: the ziplist, listpack, intset and quicklist encodings a Redis
+/// server uses for small values fail with an "unsupported encoding" error.
+pub fn decode(
+    buf: &[u8],
+    shards: &mut [&mut Vec<Database>],
+    modules: &Modules,
+) -> Result<(), Error> {
+    let databases: usize = shards.first().map(|dbs| dbs.len()).unwrap_or(0);
+    let mut reader: RdbReader = RdbReader { buf, pos: 0 };
+
+    let header: &[u8] = reader.take(9)?;
+    if &header[..5] != b"REDIS" {
+        return Err(Error::new("Wrong signature trying to load DB from file"));
+    }
+    let version: u32 = std::str::from_utf8(&header[5..])
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .ok_or_else(|| Error::new("Invalid RDB version"))?;
+    if version > RDB_VERSION {
+        return Err(Error {
+            message: format!("Can't handle RDB format version {}", version),
+        });
+    }
+
+    let now: u64 = now_ms();
+    let mut db_index: usize = 0;
+    let mut expires_at: Option<u64> = None;
+
+    loop {
+        let opcode: u8 = reader.byte()?;
+        match opcode {
+            RDB_OPCODE_EOF => break,
+            RDB_OPCODE_SELECTDB => {
+                db_index = reader.length()? as usize;
+                if db_index >= databases {
+                    return Err(Error {
+                        message: format!(
+                            "FATAL: Data file was created with a Redis server configured to handle more than {} databases",
+                            databases
+                        ),
+                    });
+                }
+            }
+            RDB_OPCODE_RESIZEDB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            RDB_OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            RDB_OPCODE_EXPIRETIME_MS => {
+                let bytes: [u8; 8] = reader.take(8)?.try_into().unwrap();
+                expires_at = Some(u64::from_le_bytes(bytes));
+            }
+            RDB_OPCODE_EXPIRETIME => {
+                let bytes: [u8; 4] = reader.take(4)?.try_into().unwrap();
+                expires_at = Some(u32::from_le_bytes(bytes) as u64 * 1000);
+            }
+            RDB_OPCODE_FREQ => {
+                reader.byte()?;
+            }
+            RDB_OPCODE_IDLE => {
+                reader.length()?;
+            }
+            RDB_OPCODE_MODULE_AUX => {
+                return Err(Error::new("Module aux data in RDB is not supported"));
+            }
+            RDB_TYPE_STRING..=RDB_TYPE_SET_LISTPACK => {
+                let key: Vec<u8> = reader.string()?;
+                let shard: usize = shard_for(&key, shards.len());
+                let db: &mut Database = &mut shards[shard][db_index];
+                let value: Value = read_value(&mut reader, opcode, &db.encoding, modules)?;
+                match expires_at.take() {
+                    Some(at) if at <= now => (),
+                    at => db.set(&key, value, at),
+                }
+            }
+            other => {
+                return Err(Error {
+                    message: format!("Unsupported RDB object type {}", other),
+                })
+            }
+        }
+    }
+
+    // version 5 and later carry a checksum; zero means checksumming was disabled
+    if version >= 5 && reader.pos + 8 <= buf.len() {
+        let expected: u64 = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        if expected != 0 && expected != crc64(0, &buf[..buf.len() - 8]) {
+            return Err(Error::new("Wrong RDB checksum"));
+        }
+    }
+
+    return Ok(());
+}
+
+/// The object type byte written before a key of this value.
+fn value_type(value: &Value) -> u8 {
+    return match value {
+        Value::String(_) => RDB_TYPE_STRING,
+        Value::List(_) => RDB_TYPE_LIST,
+        Value::Set(_) => RDB_TYPE_SET,
+        Value::Hash(_) => RDB_TYPE_HASH,
+        Value::ZSet(_) => RDB_TYPE_ZSET_2,
+        Value::Module(_) => RDB_TYPE_MODULE_2,
+    };
+}
+
+/// Serializes a value in the plain (non listpack) RDB encodings, which every
+/// Redis version can still load.
+pub fn write_value(out: &mut Vec<u8>, value: &Value, modules: &Modules) {
+    match value {
+        Value::String(s) => write_string(out, s),
+        Value::List(list) => {
+            write_length(out, list.len() as u64);
+            for item in list.iter() {
+                write_string(out, item);
+            }
+        }
+        Value::Set(set) => {
+            write_length(out, set.len() as u64);
+            for member in set.iter() {
+                write_string(out, &member);
+            }
+        }
+        Value::Hash(hash) => {
+            write_length(out, hash.len() as u64);
+            for (field, value) in hash.iter() {
+                write_string(out, field);
+                write_string(out, value);
+            }
+        }
+        Value::ZSet(zset) => {
+            write_length(out, zset.len() as u64);
+            for (member, score) in zset.iter() {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Module(object) => module::write_value(out, object, modules),
+    }
+}
+
+/// Reads a value of the object type `kind`, in the most compact encoding `limits` allow.
+///
+/// The packed encodings Redis writes for small aggregates are not decoded and
+/// fail with an "unsupported encoding" error instead.
+pub fn read_value(
+    reader: &mut RdbReader,
+    kind: u8,
+    limits: &EncodingLimits,
+    modules: &Modules,
+) -> Result<Value, Error> {
+    match kind {
+        RDB_TYPE_STRING => return Ok(Value::String(reader.string()?)),
+        RDB_TYPE_LIST => {
+            let len: u64 = reader.length()?;
+            let mut list: List = List::new();
+            for _ in 0..len {
+                list.push(&reader.string()?, false, limits);
+            }
+            return Ok(Value::List(list));
+        }
+        RDB_TYPE_SET => {
+            let len: u64 = reader.length()?;
+            let mut set: Set = Set::new();
+            for _ in 0..len {
+                set.insert(&reader.string()?, limits);
+            }
+            return Ok(Value::Set(set));
+        }
+        RDB_TYPE_HASH => {
+            let len: u64 = reader.length()?;
+            let mut hash: Hash = Hash::new();
+            for _ in 0..len {
+                let field: Vec<u8> = reader.string()?;
+                hash.insert(&field, &reader.string()?, limits);
+            }
+            return Ok(Value::Hash(hash));
+        }
+        RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+            let len: u64 = reader.length()?;
+            let mut zset: SortedSet = SortedSet::new();
+            for _ in 0..len {
+                let member: Vec<u8> = reader.string()?;
+                let score: f64 = match kind {
+                    RDB_TYPE_ZSET_2 => f64::from_le_bytes(reader.take(8)?.try_into().unwrap()),
+                    _ => reader.legacy_double()?,
+                };
+                zset.insert(&member, score);
+            }
+            return Ok(Value::ZSet(zset));
+        }
+        RDB_TYPE_MODULE_2 => return Ok(Value::Module(module::read_value(reader, modules)?)),
+        RDB_TYPE_HASH_ZIPMAP
+        | RDB_TYPE_LIST_ZIPLIST
+        | RDB_TYPE_SET_INTSET
+        | RDB_TYPE_ZSET_ZIPLIST
+        | RDB_TYPE_HASH_ZIPLIST
+        | RDB_TYPE_LIST_QUICKLIST
+        | RDB_TYPE_HASH_LISTPACK
+        | RDB_TYPE_ZSET_LISTPACK
+        | RDB_TYPE_LIST_QUICKLIST_2
+        | RDB_TYPE_SET_LISTPACK => {
+            let encoding: &str = match kind {
+                RDB_TYPE_HASH_ZIPMAP => "zipmap",
+                RDB_TYPE_LIST_ZIPLIST | RDB_TYPE_ZSET_ZIPLIST | RDB_TYPE_HASH_ZIPLIST => "ziplist",
+                RDB_TYPE_SET_INTSET => "intset",
+                RDB_TYPE_HASH_LISTPACK | RDB_TYPE_ZSET_LISTPACK | RDB_TYPE_SET_LISTPACK => {
+                    "listpack"
+                }
+                _ => "quicklist",
+            };
+            return Err(Error {
+                message: format!(
+                    "Unsupported RDB encoding {} for object type {}",
+                    encoding, kind
+                ),
+            });
+        }
+        other => {
+            return Err(Error {
+                message: format!("Unsupported RDB object type {}", other),
+            })
+        }
+    }
+}
+
+/// The DUMP serialization of a value: type byte, value, RDB version and CRC64.
+pub fn dump_payload(value: &Value, modules: &Modules) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![value_type(value)];
+    write_value(&mut out, value, modules);
+    out.extend_from_slice(&(RDB_VERSION as u16).to_le_bytes());
+    let checksum: u64 = crc64(0, &out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    return out;
+}
+
+/// Decodes a DUMP payload, checking its version and checksum first.
+pub fn restore_payload(
+    payload: &[u8],
+    limits: &EncodingLimits,
+    modules: &Modules,
+) -> Result<Value, Error> {
+    let invalid = || Error::new("ERR DUMP payload version or checksum are wrong");
+    if payload.len() < 10 {
+        return Err(invalid());
+    }
+    let (body, footer) = payload.split_at(payload.len() - 10);
+    let version: u16 = u16::from_le_bytes(footer[..2].try_into().unwrap());
+    let checksum: u64 = u64::from_le_bytes(footer[2..].try_into().unwrap());
+    if version as u32 > RDB_VERSION || checksum != crc64(0, &payload[..payload.len() - 8]) {
+        return Err(invalid());
+    }
+
+    let bad_format = || Error::new("ERR Bad data format");
+    let mut reader: RdbReader = RdbReader { buf: body, pos: 0 };
+    let kind: u8 = reader.byte().map_err(|_| bad_format())?;
+    let value: Value = read_value(&mut reader, kind, limits, modules).map_err(|_| bad_format())?;
+    if reader.pos != body.len() {
+        return Err(bad_format());
+    }
+    return Ok(value);
+}
+
+fn write_aux(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.push(RDB_OPCODE_AUX);
+    write_string(out, key.as_bytes());
+    write_string(out, value.as_bytes());
+}
+
+pub fn write_length(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.push(((len >> 8) as u8) | 0x40);
+        out.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Writes a string, using the compact integer encodings when it is a canonical number.
+pub fn write_string(out: &mut Vec<u8>, value: &[u8]) {
+    if value.len() <= 11 {
+        if let Some(n) = std::str::from_utf8(value)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .filter(|n| n.to_string().as_bytes() == value)
+        {
+            if let Ok(n) = i8::try_from(n) {
+                out.push(0xC0 | RDB_ENC_INT8);
+                out.extend_from_slice(&n.to_le_bytes());
+                return;
+            } else if let Ok(n) = i16::try_from(n) {
+                out.push(0xC0 | RDB_ENC_INT16);
+                out.extend_from_slice(&n.to_le_bytes());
+                return;
+            } else if let Ok(n) = i32::try_from(n) {
+                out.push(0xC0 | RDB_ENC_INT32);
+                out.extend_from_slice(&n.to_le_bytes());
+                return;
+            }
+        }
+    }
+
+    write_length(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+/// A cursor over an in-memory RDB image.
+pub struct RdbReader<'a> {
+    pub buf: &'a [u8],
+    pub pos: usize,
+}
+
+impl<'a> RdbReader<'a> {
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        // the length comes from the input, so `self.pos + n` may overflow
+        if n > self.buf.len() - self.pos {
+            return Err(Error::new(
+                "Short read or OOM loading DB. Unrecoverable error",
+            ));
+        }
+        let bytes: &[u8] = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        return Ok(bytes);
+    }
+
+    pub fn byte(&mut self) -> Result<u8, Error> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    /// Reads a length, returning the special encoding id instead when the top bits are `11`.
+    fn length_or_encoding(&mut self) -> Result<(u64, bool), Error> {
+        let first: u8 = self.byte()?;
+        return match first >> 6 {
+            0 => Ok(((first & 0x3F) as u64, false)),
+            1 => Ok(((((first & 0x3F) as u64) << 8) | self.byte()? as u64, false)),
+            2 => match first {
+                0x80 => Ok((
+                    u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+                    false,
+                )),
+                0x81 => Ok((u64::from_be_bytes(self.take(8)?.try_into().unwrap()), false)),
+                _ => Err(Error::new("Unknown length encoding in rdbLoadLen()")),
+            },
+            _ => Ok(((first & 0x3F) as u64, true)),
+        };
+    }
+
+    pub fn length(&mut self) -> Result<u64, Error> {
+        let (len, encoded) = self.length_or_encoding()?;
+        if encoded {
+            return Err(Error::new(
+                "Unexpected string encoding where a length was expected",
+            ));
+        }
+        return Ok(len);
+    }
+
+    /// Reads a score of the original sorted set type: a length byte followed by
+    /// the number in text, with 253..255 standing for NaN and the infinities.
+    fn legacy_double(&mut self) -> Result<f64, Error> {
+        return match self.byte()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => std::str::from_utf8(self.take(len as usize)?)
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .ok_or_else(|| Error::new("Invalid double value in RDB")),
+        };
+    }
+
+    pub fn string(&mut self) -> Result<Vec<u8>, Error> {
+        let (len, encoded) = self.length_or_encoding()?;
+        if !encoded {
+            return Ok(self.take(len as usize)?.to_vec());
+        }
+
+        return match len as u8 {
+            RDB_ENC_INT8 => Ok((self.byte()? as i8).to_string().into_bytes()),
+            RDB_ENC_INT16 => {
+                let n: i16 = i16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                Ok(n.to_string().into_bytes())
+            }
+            RDB_ENC_INT32 => {
+                let n: i32 = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+                Ok(n.to_string().into_bytes())
+            }
+            RDB_ENC_LZF => {
+                let compressed_len: usize = self.length()? as usize;
+                let len: usize = self.length()? as usize;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            _ => Err(Error::new("Unknown RDB string encoding type")),
+        };
+    }
+}
+
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    let corrupt = || Error::new("Invalid LZF compressed string");
+    // `len` comes from the input too; a back reference of 3 bytes expands to
+    // at most 264, which bounds what the input can really decompress to
+    if len > input.len().saturating_mul(88) {
+        return Err(corrupt());
+    }
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    let mut i: usize = 0;
+
+    while i < input.len() {
+        let ctrl: usize = input[i] as usize;
+        i += 1;
+
+        if ctrl < 32 {
+            // literal run of ctrl + 1 bytes
+            let end: usize = i + ctrl + 1;
+            if end > input.len() {
+                return Err(corrupt());
+            }
+            out.extend_from_slice(&input[i..end]);
+            i = end;
+        } else {
+            // back reference into the already decompressed output
+            let mut run: usize = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(corrupt)? as usize;
+                i += 1;
+            }
+            let offset: usize =
+                ((ctrl & 0x1F) << 8) + *input.get(i).ok_or_else(corrupt)? as usize + 1;
+            i += 1;
+
+            if offset > out.len() {
+                return Err(corrupt());
+            }
+            let start: usize = out.len() - offset;
+            for j in 0..run + 2 {
+                out.push(out[start + j]);
+            }
+        }
+    }
+
+    if out.len() != len {
+        return Err(corrupt());
+    }
+    return Ok(out);
+}
+
+const fn crc64_table() -> [u64; 256] {
+    // reflected form of the Jones polynomial 0xad93d23594c935a9 used by Redis
+    let poly: u64 = 0x95AC9329AC4BC9B5;
+    let mut table: [u64; 256] = [0; 256];
+    let mut i: usize = 0;
+    while i < 256 {
+        let mut crc: u64 = i as u64;
+        let mut bit: usize = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ poly
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    return table;
+}
+
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+/// CRC-64/Jones as used for RDB and DUMP payload checksums.
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for &b in data {
+        crc = CRC64_TABLE[((crc ^ b as u64) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    return crc;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc64_test() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn round_trip_keeps_db_indices_test() {
+        let mut dbs: Vec<Database> = vec![Database::new(), Database::new(), Database::new()];
+        dbs[0].add(b"foo", b"bar");
+        dbs[2].add(b"count", b"12345");
+        dbs[2].set(b"ttl", b"x".repeat(20000), Some(now_ms() + 60_000));
+
+        let image: Vec<u8> = encode(&[&dbs], &Modules::new());
+
+        let mut loaded: Vec<Database> = vec![Database::new(), Database::new(), Database::new()];
+        decode(&image, &mut [&mut loaded], &Modules::new()).unwrap();
+
+        assert_eq!(loaded[0].get(b"foo").unwrap(), b"bar".to_vec());
+        assert!(loaded[1].is_empty());
+        assert_eq!(loaded[2].get(b"count").unwrap(), b"12345".to_vec());
+        assert_eq!(loaded[2].get(b"ttl").unwrap().len(), 20000);
+        assert_eq!(loaded[2].expires_len(), 1);
+    }
+
+    #[test]
+    fn decode_rejects_bad_checksum_test() {
+        let mut dbs: Vec<Database> = vec![Database::new()];
+        dbs[0].add(b"foo", b"bar");
+
+        let mut image: Vec<u8> = encode(&[&dbs], &Modules::new());
+        let last: usize = image.len() - 1;
+        image[last] ^= 0xFF;
+
+        assert!(decode(&image, &mut [&mut vec![Database::new()]], &Modules::new()).is_err());
+    }
+
+    #[test]
+    fn decode_expired_and_too_many_dbs_test() {
+        let mut dbs: Vec<Database> = vec![Database::new(), Database::new()];
+        dbs[0].set(b"old", b"v".to_vec(), Some(1));
+        dbs[1].add(b"foo", b"bar");
+
+        let image: Vec<u8> = encode(&[&dbs], &Modules::new());
+
+        let mut loaded: Vec<Database> = vec![Database::new(), Database::new()];
+        decode(&image, &mut [&mut loaded], &Modules::new()).unwrap();
+        assert!(loaded[0].is_empty());
+
+        assert!(decode(&image, &mut [&mut vec![Database::new()]], &Modules::new()).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_newer_version_test() {
+        let mut image: Vec<u8> = encode(&[&vec![Database::new()]], &Modules::new());
+        image[5..9].copy_from_slice(format!("{:04}", RDB_VERSION + 1).as_bytes());
+
+        let error: Error =
+            decode(&image, &mut [&mut vec![Database::new()]], &Modules::new()).unwrap_err();
+        assert_eq!(error.message, "Can't handle RDB format version 12");
+    }
+
+    #[test]
+    fn decode_rejects_packed_encodings_test() {
+        // a set of 1 and 2 as Redis writes it: an intset of 16 bit integers
+        let mut image: Vec<u8> = b"REDIS0011\xfe\x00".to_vec();
+        image.push(RDB_TYPE_SET_INTSET);
+        write_string(&mut image, b"numbers");
+        write_string(
+            &mut image,
+            b"\x02\x00\x00\x00\x02\x00\x00\x00\x01\x00\x02\x00",
+        );
+        image.push(RDB_OPCODE_EOF);
+
+        let error: Error =
+            decode(&image, &mut [&mut vec![Database::new()]], &Modules::new()).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Unsupported RDB encoding intset for object type 11"
+        );
+    }
+
+    #[test]
+    fn aggregate_round_trip_test() {
+        let mut zset: SortedSet = SortedSet::new();
+        zset.insert(b"one", 1.0);
+        zset.insert(b"half", 0.5);
+        let limits: EncodingLimits = EncodingLimits::default();
+        let values: Vec<Value> = vec![
+            Value::List(List::from_items([b"a", b"b"], &limits)),
+            Value::Set(Set::from_members([b"x", b"y"], &limits)),
+            Value::Set(Set::from_members([b"1".as_slice(), b"-20"], &limits)),
+            Value::Hash(Hash::from_pairs([(b"f", b"v")], &limits)),
+            Value::ZSet(zset),
+        ];
+
+        let mut dbs: Vec<Database> = vec![Database::new()];
+        for (i, value) in values.iter().enumerate() {
+            dbs[0].set(i.to_string().as_bytes(), value.clone(), None);
+        }
+        let mut loaded: Vec<Database> = vec![Database::new()];
+        decode(
+            &encode(&[&dbs], &Modules::new()),
+            &mut [&mut loaded],
+            &Modules::new(),
+        )
+        .unwrap();
+
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(
+                &loaded[0].peek(i.to_string().as_bytes()).unwrap().value,
+                value
+            );
+            assert_eq!(
+                &restore_payload(
+                    &dump_payload(value, &Modules::new()),
+                    &limits,
+                    &Modules::new()
+                )
+                .unwrap(),
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn dump_payload_test() {
+        // what Redis 7.2 answers to DUMP of a key holding "bar"
+        let payload: Vec<u8> = dump_payload(&Value::String(b"bar".to_vec()), &Modules::new());
+        assert_eq!(&payload[..7], b"\x00\x03bar\x0b\x00");
+        assert_eq!(payload.len(), 15);
+
+        let mut corrupt: Vec<u8> = payload.clone();
+        corrupt[2] = b'c';
+        let limits: EncodingLimits = EncodingLimits::default();
+        assert!(restore_payload(&corrupt, &limits, &Modules::new()).is_err());
+        assert!(restore_payload(b"short", &limits, &Modules::new()).is_err());
+    }
+
+    #[test]
+    fn lzf_decompress_test() {
+        // "aaaaaaaaaa": one literal byte then a back reference of length 9
+        let compressed: Vec<u8> = vec![0x00, b'a', 0xE0, 0x00, 0x00];
+        assert_eq!(
+            lzf_decompress(&compressed, 10).unwrap(),
+            b"aaaaaaaaaa".to_vec()
+        );
+    }
+}
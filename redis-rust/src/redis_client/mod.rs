@@ -0,0 +1,652 @@
+//! An async client for talking to the server from Rust.
+//!
+//! Requests are encoded and replies decoded with `RedisType`, the same type
+//! the server writes its replies with. `RedisClient` is one connection with
+//! typed helpers for the common commands; anything else goes through `Cmd`
+//! and `query`. Several commands can be sent in one write with a `Pipeline`,
+//! optionally wrapped in MULTI/EXEC. `Subscriber` is a dedicated Pub/Sub
+//! connection and `Pool` shares a bounded set of connections between tasks.
+//!
+//! A request whose connection breaks fails with the I/O error: the server
+//! may already have run it, so it is never sent again. The next request opens
+//! a new connection first, trying up to `ClientOptions::reconnect_attempts`
+//! times. The selected database is restored on reconnect; keys watched with
+//! WATCH are not, so a pending transaction fails instead.
+
+mod pipeline;
+mod pool;
+mod pubsub;
+
+pub use pipeline::Pipeline;
+pub use pool::{Pool, PooledClient};
+pub use pubsub::{Message, Subscriber};
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::redis_parser::{parse_reply, RedisType};
+use crate::Error;
+
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Where and how to connect.
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// `host:port` of the server.
+    pub addr: String,
+    /// Database selected right after connecting.
+    pub db: usize,
+    /// How many times the next request tries to open a broken connection again.
+    pub reconnect_attempts: usize,
+    /// Pause before each new connection attempt.
+    pub reconnect_delay: Duration,
+    pub connect_timeout: Duration,
+}
+
+impl ClientOptions {
+    pub fn new(addr: &str) -> Self {
+        return ClientOptions {
+            addr: addr.to_string(),
+            db: 0,
+            reconnect_attempts: 3,
+            reconnect_delay: Duration::from_millis(100),
+            connect_timeout: Duration::from_secs(5),
+        };
+    }
+}
+
+/// Something that can be sent as a command argument.
+pub trait ToArg {
+    fn to_arg(&self) -> Vec<u8>;
+}
+
+impl ToArg for [u8] {
+    fn to_arg(&self) -> Vec<u8> {
+        return self.to_vec();
+    }
+}
+
+impl ToArg for str {
+    fn to_arg(&self) -> Vec<u8> {
+        return self.as_bytes().to_vec();
+    }
+}
+
+impl ToArg for Vec<u8> {
+    fn to_arg(&self) -> Vec<u8> {
+        return self.clone();
+    }
+}
+
+impl ToArg for String {
+    fn to_arg(&self) -> Vec<u8> {
+        return self.as_bytes().to_vec();
+    }
+}
+
+impl<const N: usize> ToArg for [u8; N] {
+    fn to_arg(&self) -> Vec<u8> {
+        return self.to_vec();
+    }
+}
+
+macro_rules! display_arg {
+    ($($t:ty),*) => {
+        $(impl ToArg for $t {
+            fn to_arg(&self) -> Vec<u8> {
+                return self.to_string().into_bytes();
+            }
+        })*
+    };
+}
+
+display_arg!(i32, i64, u32, u64, usize, f64);
+
+impl<T: ToArg + ?Sized> ToArg for &T {
+    fn to_arg(&self) -> Vec<u8> {
+        return (**self).to_arg();
+    }
+}
+
+/// A command and its arguments, e.g. `Cmd::new("SET").arg("key").arg(42)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cmd {
+    args: Vec<Vec<u8>>,
+}
+
+impl Cmd {
+    pub fn new(name: &str) -> Self {
+        return Cmd {
+            args: vec![name.as_bytes().to_vec()],
+        };
+    }
+
+    pub fn arg<A: ToArg + ?Sized>(mut self, arg: &A) -> Self {
+        self.args.push(arg.to_arg());
+        return self;
+    }
+
+    pub fn args<A: ToArg>(mut self, args: &[A]) -> Self {
+        self.args.extend(args.iter().map(|a| a.to_arg()));
+        return self;
+    }
+
+    /// Appends the request to `out` as a RESP array of bulk strings.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let request: RedisType = RedisType::Array(Box::new(
+            self.args
+                .iter()
+                .map(|a| RedisType::BulkBytes(a.clone()))
+                .collect(),
+        ));
+        request.encode(out);
+    }
+
+    fn is(&self, name: &str) -> bool {
+        return self.args[0].eq_ignore_ascii_case(name.as_bytes());
+    }
+}
+
+/// A socket with a buffer of bytes read but not yet decoded.
+pub(crate) struct Stream {
+    socket: TcpStream,
+    buffer: BytesMut,
+}
+
+impl Stream {
+    pub(crate) async fn connect(options: &ClientOptions) -> Result<Self, Error> {
+        let socket: TcpStream =
+            match tokio::time::timeout(options.connect_timeout, TcpStream::connect(&options.addr))
+                .await
+            {
+                Ok(socket) => socket?,
+                Err(_) => {
+                    return Err(Error {
+                        message: format!("Timed out connecting to {}", options.addr),
+                    })
+                }
+            };
+        socket.set_nodelay(true)?;
+        return Ok(Stream {
+            socket,
+            buffer: BytesMut::with_capacity(READ_CHUNK),
+        });
+    }
+
+    pub(crate) async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.socket.write_all(bytes).await?;
+        return Ok(());
+    }
+
+    /// Whether the server already closed the connection, checked without
+    /// waiting before a request is written to it. Anything the server sent
+    /// meanwhile is kept for the next read.
+    fn is_closed(&mut self) -> bool {
+        let mut buf: [u8; 512] = [0; 512];
+        return match self.socket.try_read(&mut buf) {
+            Ok(0) => true,
+            Ok(n) => {
+                self.buffer.extend_from_slice(&buf[..n]);
+                false
+            }
+            Err(e) => e.kind() != std::io::ErrorKind::WouldBlock,
+        };
+    }
+
+    /// Reads the next reply; an error once the server closed the connection.
+    pub(crate) async fn read_reply(&mut self) -> Result<RedisType<'static>, Error> {
+        return Ok(self.read_frame().await?.0);
+    }
+
+    /// Reads the next reply along with its size on the wire.
+    pub(crate) async fn read_frame(&mut self) -> Result<(RedisType<'static>, usize), Error> {
+        loop {
+            if let Some((reply, used)) = parse_reply(&self.buffer)? {
+                self.buffer.advance(used);
+                return Ok((reply, used));
+            }
+            if self.socket.read_buf(&mut self.buffer).await? == 0 {
+                return Err(Error::new("Connection closed by the server"));
+            }
+        }
+    }
+}
+
+/// One connection to the server.
+pub struct RedisClient {
+    options: ClientOptions,
+    stream: Option<Stream>,
+    /// Set between WATCH and the end of the transaction, when a reconnect would lose the watches.
+    watching: bool,
+}
+
+impl RedisClient {
+    pub async fn connect(addr: &str) -> Result<Self, Error> {
+        return RedisClient::with_options(ClientOptions::new(addr)).await;
+    }
+
+    pub async fn with_options(options: ClientOptions) -> Result<Self, Error> {
+        let mut client: RedisClient = RedisClient {
+            options,
+            stream: None,
+            watching: false,
+        };
+        client.reconnect().await?;
+        return Ok(client);
+    }
+
+    pub fn options(&self) -> &ClientOptions {
+        return &self.options;
+    }
+
+    /// Whether the connection is currently open; a closed one is reopened by the next request.
+    pub fn is_connected(&self) -> bool {
+        return self.stream.is_some();
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Error> {
+        self.stream = None;
+        let mut stream: Stream = Stream::connect(&self.options).await?;
+        if self.options.db != 0 {
+            let mut request: Vec<u8> = Vec::new();
+            Cmd::new("SELECT")
+                .arg(&self.options.db)
+                .encode(&mut request);
+            stream.write_all(&request).await?;
+            expect_ok(stream.read_reply().await?)?;
+        }
+        self.stream = Some(stream);
+        return Ok(());
+    }
+
+    /// Opens the connection again if it broke, trying up to `reconnect_attempts` times.
+    async fn ensure_connected(&mut self) -> Result<(), Error> {
+        if self
+            .stream
+            .as_mut()
+            .is_some_and(|stream| stream.is_closed())
+        {
+            self.stream = None;
+            if self.watching {
+                self.watching = false;
+                return Err(Error::new(
+                    "Connection closed by the server; the watched keys were released",
+                ));
+            }
+        }
+        if self.stream.is_some() {
+            return Ok(());
+        }
+
+        let mut error: Error = Error::new("Not connected");
+        for attempt in 0..self.options.reconnect_attempts {
+            if attempt > 0 {
+                tokio::time::sleep(self.options.reconnect_delay).await;
+            }
+            match self.reconnect().await {
+                Ok(()) => return Ok(()),
+                Err(e) => error = e,
+            }
+        }
+        return Err(error);
+    }
+
+    /// Writes `request` and reads `count` replies. Once written the request is
+    /// never sent again, as the server may have run it before the connection
+    /// broke; the error is returned and the next request reconnects.
+    async fn round_trip(
+        &mut self,
+        request: &[u8],
+        count: usize,
+    ) -> Result<Vec<RedisType<'static>>, Error> {
+        self.ensure_connected().await?;
+        let stream: &mut Stream = self.stream.as_mut().unwrap();
+        let e: Error = match exchange(stream, request, count).await {
+            Ok(replies) => return Ok(replies),
+            Err(e) => e,
+        };
+
+        self.stream = None;
+        if self.watching {
+            self.watching = false;
+            return Err(Error {
+                message: format!("{}; the watched keys were released", e.message),
+            });
+        }
+        return Err(e);
+    }
+
+    /// Sends `cmd` and returns the reply as it came, error replies included.
+    pub async fn query(&mut self, cmd: &Cmd) -> Result<RedisType<'static>, Error> {
+        let mut request: Vec<u8> = Vec::new();
+        cmd.encode(&mut request);
+        let reply: RedisType<'static> = self.round_trip(&request, 1).await?.remove(0);
+        if cmd.is("SELECT") && reply == RedisType::SimpleString("OK") {
+            // reconnects come back to the database picked last
+            self.options.db = String::from_utf8_lossy(&cmd.args[1])
+                .parse()
+                .unwrap_or(self.options.db);
+        }
+        return Ok(reply);
+    }
+
+    /// Like `query`, with an error reply turned into an `Err`.
+    pub async fn call(&mut self, cmd: &Cmd) -> Result<RedisType<'static>, Error> {
+        return check(self.query(cmd).await?);
+    }
+
+    /// Sends every command of `pipeline` in one write and returns their replies in order.
+    pub async fn execute(&mut self, pipeline: &Pipeline) -> Result<Vec<RedisType<'static>>, Error> {
+        if pipeline.is_empty() {
+            return Ok(Vec::new());
+        }
+        return self.round_trip(&pipeline.encode(), pipeline.len()).await;
+    }
+
+    /// Watches `keys` for the next `transaction`.
+    pub async fn watch<K: ToArg>(&mut self, keys: &[K]) -> Result<(), Error> {
+        expect_ok(self.call(&Cmd::new("WATCH").args(keys)).await?)?;
+        self.watching = true;
+        return Ok(());
+    }
+
+    pub async fn unwatch(&mut self) -> Result<(), Error> {
+        self.watching = false;
+        return expect_ok(self.call(&Cmd::new("UNWATCH")).await?);
+    }
+
+    /// Runs `pipeline` between MULTI and EXEC. Returns the replies of its
+    /// commands, or `None` when a watched key changed and nothing ran.
+    pub async fn transaction(
+        &mut self,
+        pipeline: &Pipeline,
+    ) -> Result<Option<Vec<RedisType<'static>>>, Error> {
+        let mut request: Vec<u8> = Vec::new();
+        Cmd::new("MULTI").encode(&mut request);
+        request.extend(pipeline.encode());
+        Cmd::new("EXEC").encode(&mut request);
+
+        let mut replies: Vec<RedisType<'static>> =
+            self.round_trip(&request, pipeline.len() + 2).await?;
+        self.watching = false;
+        let exec: RedisType<'static> = replies.pop().unwrap();
+        // the MULTI reply and one QUEUED per command come first; a command that
+        // could not be queued has its own error, EXEC only says it was aborted
+        for reply in replies {
+            check(reply)?;
+        }
+        return match check(exec)? {
+            RedisType::Array(replies) => Ok(Some(*replies)),
+            RedisType::NullArray | RedisType::Null => Ok(None),
+            other => Err(unexpected(&other)),
+        };
+    }
+
+    pub async fn ping(&mut self) -> Result<String, Error> {
+        return text(self.call(&Cmd::new("PING")).await?);
+    }
+
+    pub async fn select(&mut self, db: usize) -> Result<(), Error> {
+        return expect_ok(self.call(&Cmd::new("SELECT").arg(&db)).await?);
+    }
+
+    pub async fn get<K: ToArg + ?Sized>(&mut self, key: &K) -> Result<Option<Vec<u8>>, Error> {
+        return bulk(self.call(&Cmd::new("GET").arg(key)).await?);
+    }
+
+    pub async fn set<K: ToArg + ?Sized, V: ToArg + ?Sized>(
+        &mut self,
+        key: &K,
+        value: &V,
+    ) -> Result<(), Error> {
+        return expect_ok(self.call(&Cmd::new("SET").arg(key).arg(value)).await?);
+    }
+
+    /// SET with an expiry in seconds.
+    pub async fn set_ex<K: ToArg + ?Sized, V: ToArg + ?Sized>(
+        &mut self,
+        key: &K,
+        value: &V,
+        seconds: u64,
+    ) -> Result<(), Error> {
+        let cmd: Cmd = Cmd::new("SET").arg(key).arg(value).arg("EX").arg(&seconds);
+        return expect_ok(self.call(&cmd).await?);
+    }
+
+    pub async fn del<K: ToArg>(&mut self, keys: &[K]) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("DEL").args(keys)).await?);
+    }
+
+    /// Like `del`, but the server frees large values in the background.
+    pub async fn unlink<K: ToArg>(&mut self, keys: &[K]) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("UNLINK").args(keys)).await?);
+    }
+
+    pub async fn exists<K: ToArg>(&mut self, keys: &[K]) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("EXISTS").args(keys)).await?);
+    }
+
+    /// Returns whether the timeout was set.
+    pub async fn expire<K: ToArg + ?Sized>(
+        &mut self,
+        key: &K,
+        seconds: i64,
+    ) -> Result<bool, Error> {
+        let reply: RedisType = self
+            .call(&Cmd::new("EXPIRE").arg(key).arg(&seconds))
+            .await?;
+        return Ok(integer(reply)? == 1);
+    }
+
+    pub async fn ttl<K: ToArg + ?Sized>(&mut self, key: &K) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("TTL").arg(key)).await?);
+    }
+
+    pub async fn incr<K: ToArg + ?Sized>(&mut self, key: &K) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("INCR").arg(key)).await?);
+    }
+
+    pub async fn incr_by<K: ToArg + ?Sized>(&mut self, key: &K, by: i64) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("INCRBY").arg(key).arg(&by)).await?);
+    }
+
+    pub async fn decr<K: ToArg + ?Sized>(&mut self, key: &K) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("DECR").arg(key)).await?);
+    }
+
+    /// Sets one hash field; returns whether it is new.
+    pub async fn hset<K: ToArg + ?Sized, F: ToArg + ?Sized, V: ToArg + ?Sized>(
+        &mut self,
+        key: &K,
+        field: &F,
+        value: &V,
+    ) -> Result<bool, Error> {
+        let reply: RedisType = self
+            .call(&Cmd::new("HSET").arg(key).arg(field).arg(value))
+            .await?;
+        return Ok(integer(reply)? == 1);
+    }
+
+    pub async fn hget<K: ToArg + ?Sized, F: ToArg + ?Sized>(
+        &mut self,
+        key: &K,
+        field: &F,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        return bulk(self.call(&Cmd::new("HGET").arg(key).arg(field)).await?);
+    }
+
+    pub async fn hdel<K: ToArg + ?Sized, F: ToArg>(
+        &mut self,
+        key: &K,
+        fields: &[F],
+    ) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("HDEL").arg(key).args(fields)).await?);
+    }
+
+    pub async fn hgetall<K: ToArg + ?Sized>(
+        &mut self,
+        key: &K,
+    ) -> Result<HashMap<Vec<u8>, Vec<u8>>, Error> {
+        let values: Vec<Vec<u8>> = bulks(self.call(&Cmd::new("HGETALL").arg(key)).await?)?;
+        let mut hash: HashMap<Vec<u8>, Vec<u8>> = HashMap::with_capacity(values.len() / 2);
+        let mut iter = values.into_iter();
+        while let (Some(field), Some(value)) = (iter.next(), iter.next()) {
+            hash.insert(field, value);
+        }
+        return Ok(hash);
+    }
+
+    /// Returns the length of the list after the push.
+    pub async fn lpush<K: ToArg + ?Sized, V: ToArg>(
+        &mut self,
+        key: &K,
+        values: &[V],
+    ) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("LPUSH").arg(key).args(values)).await?);
+    }
+
+    pub async fn rpush<K: ToArg + ?Sized, V: ToArg>(
+        &mut self,
+        key: &K,
+        values: &[V],
+    ) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("RPUSH").arg(key).args(values)).await?);
+    }
+
+    pub async fn lpop<K: ToArg + ?Sized>(&mut self, key: &K) -> Result<Option<Vec<u8>>, Error> {
+        return bulk(self.call(&Cmd::new("LPOP").arg(key)).await?);
+    }
+
+    pub async fn rpop<K: ToArg + ?Sized>(&mut self, key: &K) -> Result<Option<Vec<u8>>, Error> {
+        return bulk(self.call(&Cmd::new("RPOP").arg(key)).await?);
+    }
+
+    pub async fn lrange<K: ToArg + ?Sized>(
+        &mut self,
+        key: &K,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let cmd: Cmd = Cmd::new("LRANGE").arg(key).arg(&start).arg(&stop);
+        return bulks(self.call(&cmd).await?);
+    }
+
+    pub async fn llen<K: ToArg + ?Sized>(&mut self, key: &K) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("LLEN").arg(key)).await?);
+    }
+
+    pub async fn sadd<K: ToArg + ?Sized, M: ToArg>(
+        &mut self,
+        key: &K,
+        members: &[M],
+    ) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("SADD").arg(key).args(members)).await?);
+    }
+
+    pub async fn smembers<K: ToArg + ?Sized>(&mut self, key: &K) -> Result<Vec<Vec<u8>>, Error> {
+        return bulks(self.call(&Cmd::new("SMEMBERS").arg(key)).await?);
+    }
+
+    /// Returns the number of subscribers that received the message.
+    pub async fn publish<C: ToArg + ?Sized, M: ToArg + ?Sized>(
+        &mut self,
+        channel: &C,
+        message: &M,
+    ) -> Result<i64, Error> {
+        return integer(
+            self.call(&Cmd::new("PUBLISH").arg(channel).arg(message))
+                .await?,
+        );
+    }
+
+    pub async fn dbsize(&mut self) -> Result<i64, Error> {
+        return integer(self.call(&Cmd::new("DBSIZE")).await?);
+    }
+
+    pub async fn flushdb(&mut self) -> Result<(), Error> {
+        return expect_ok(self.call(&Cmd::new("FLUSHDB")).await?);
+    }
+}
+
+async fn exchange(
+    stream: &mut Stream,
+    request: &[u8],
+    count: usize,
+) -> Result<Vec<RedisType<'static>>, Error> {
+    stream.write_all(request).await?;
+    let mut replies: Vec<RedisType<'static>> = Vec::with_capacity(count);
+    for _ in 0..count {
+        replies.push(stream.read_reply().await?);
+    }
+    return Ok(replies);
+}
+
+pub(crate) fn unexpected(reply: &RedisType) -> Error {
+    return Error {
+        message: format!("Unexpected reply: {}", reply.to_string().trim_end()),
+    };
+}
+
+/// Turns an error reply into an `Err`.
+pub fn check(reply: RedisType<'static>) -> Result<RedisType<'static>, Error> {
+    return match reply {
+        RedisType::Error(message) => Err(Error { message }),
+        reply => Ok(reply),
+    };
+}
+
+fn expect_ok(reply: RedisType) -> Result<(), Error> {
+    return match reply {
+        RedisType::Status(ref s) if s == "OK" => Ok(()),
+        RedisType::SimpleString("OK") => Ok(()),
+        other => Err(unexpected(&other)),
+    };
+}
+
+fn text(reply: RedisType) -> Result<String, Error> {
+    return match reply {
+        RedisType::Status(s) | RedisType::BulkString(s) => Ok(s),
+        RedisType::SimpleString(s) => Ok(s.to_string()),
+        RedisType::BulkBytes(b) => Ok(String::from_utf8_lossy(&b).to_string()),
+        other => Err(unexpected(&other)),
+    };
+}
+
+/// An integer reply as a number.
+pub fn integer(reply: RedisType) -> Result<i64, Error> {
+    return match reply {
+        RedisType::Integer(ref n) => n.parse::<i64>().map_err(|_| unexpected(&reply)),
+        other => Err(unexpected(&other)),
+    };
+}
+
+/// A bulk string reply as bytes, `None` for a nil reply.
+pub fn bulk(reply: RedisType) -> Result<Option<Vec<u8>>, Error> {
+    return match reply {
+        RedisType::BulkBytes(b) => Ok(Some(b)),
+        RedisType::BulkString(s) => Ok(Some(s.into_bytes())),
+        RedisType::NullBulk | RedisType::Null => Ok(None),
+        other => Err(unexpected(&other)),
+    };
+}
+
+/// An array of bulk strings; nil elements are skipped.
+pub fn bulks(reply: RedisType) -> Result<Vec<Vec<u8>>, Error> {
+    let elements: Vec<RedisType> = match reply {
+        RedisType::Array(elements) => *elements,
+        RedisType::Map(pairs) => pairs.into_iter().flat_map(|(k, v)| [k, v]).collect(),
+        RedisType::NullArray | RedisType::Null => Vec::new(),
+        other => return Err(unexpected(&other)),
+    };
+    let mut values: Vec<Vec<u8>> = Vec::with_capacity(elements.len());
+    for element in elements {
+        if let Some(value) = bulk(element)? {
+            values.push(value);
+        }
+    }
+    return Ok(values);
+}
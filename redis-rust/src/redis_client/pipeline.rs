@@ -0,0 +1,39 @@
+use crate::redis_client::Cmd;
+
+/// Commands sent together in one write, with `RedisClient::execute` or
+/// `RedisClient::transaction`.
+#[derive(Clone, Debug, Default)]
+pub struct Pipeline {
+    commands: Vec<Cmd>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        return Pipeline::default();
+    }
+
+    pub fn add(&mut self, cmd: Cmd) -> &mut Self {
+        self.commands.push(cmd);
+        return self;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.commands.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.commands.is_empty();
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        for cmd in self.commands.iter() {
+            cmd.encode(&mut out);
+        }
+        return out;
+    }
+}
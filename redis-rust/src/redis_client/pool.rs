@@ -0,0 +1,89 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::redis_client::{ClientOptions, RedisClient};
+use crate::Error;
+
+/// At most `size` connections shared between tasks. Connections are opened
+/// on demand and kept for reuse once returned.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    options: ClientOptions,
+    idle: Mutex<Vec<RedisClient>>,
+    /// One permit per connection that may be handed out.
+    permits: Arc<Semaphore>,
+}
+
+impl Pool {
+    pub fn new(options: ClientOptions, size: usize) -> Self {
+        return Pool {
+            inner: Arc::new(PoolInner {
+                options,
+                idle: Mutex::new(Vec::with_capacity(size)),
+                permits: Arc::new(Semaphore::new(size)),
+            }),
+        };
+    }
+
+    /// A connection for the caller alone, waiting while all of them are in use.
+    pub async fn get(&self) -> Result<PooledClient, Error> {
+        let permit: OwnedSemaphorePermit =
+            match Arc::clone(&self.inner.permits).acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => return Err(Error::new("The pool is closed")),
+            };
+
+        let idle: Option<RedisClient> = self.inner.idle.lock().unwrap().pop();
+        let client: RedisClient = match idle {
+            Some(client) => client,
+            None => RedisClient::with_options(self.inner.options.clone()).await?,
+        };
+        return Ok(PooledClient {
+            client: Some(client),
+            pool: Arc::clone(&self.inner),
+            _permit: permit,
+        });
+    }
+
+    /// Number of open connections waiting to be reused.
+    pub fn idle(&self) -> usize {
+        return self.inner.idle.lock().unwrap().len();
+    }
+}
+
+/// A connection borrowed from a `Pool`, given back when dropped.
+pub struct PooledClient {
+    client: Option<RedisClient>,
+    pool: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledClient {
+    type Target = RedisClient;
+
+    fn deref(&self) -> &RedisClient {
+        return self.client.as_ref().unwrap();
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut RedisClient {
+        return self.client.as_mut().unwrap();
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        let client: RedisClient = self.client.take().unwrap();
+        // a connection left with watched keys or another database would surprise the next user
+        if client.is_connected() && !client.watching && client.options.db == self.pool.options.db {
+            self.pool.idle.lock().unwrap().push(client);
+        }
+    }
+}
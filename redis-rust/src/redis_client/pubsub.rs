@@ -0,0 +1,215 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::redis_client::{unexpected, ClientOptions, Cmd, Stream, ToArg};
+use crate::redis_parser::RedisType;
+use crate::Error;
+
+/// A message delivered to a `Subscriber`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub channel: Vec<u8>,
+    /// The pattern that matched, for messages received through PSUBSCRIBE.
+    pub pattern: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+}
+
+/// A connection in Pub/Sub mode, reading the messages of its channels and
+/// patterns one at a time with `next_message`.
+///
+/// If the connection breaks it is opened again and every subscription is
+/// renewed; messages published in between are lost, as with any Redis client.
+pub struct Subscriber {
+    options: ClientOptions,
+    stream: Option<Stream>,
+    channels: BTreeSet<Vec<u8>>,
+    patterns: BTreeSet<Vec<u8>>,
+    /// Messages that arrived while a subscription change was waiting for its confirmation.
+    pending: VecDeque<Message>,
+}
+
+impl Subscriber {
+    pub async fn connect(options: ClientOptions) -> Result<Self, Error> {
+        let stream: Stream = Stream::connect(&options).await?;
+        return Ok(Subscriber {
+            options,
+            stream: Some(stream),
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            pending: VecDeque::new(),
+        });
+    }
+
+    pub async fn subscribe<C: ToArg>(&mut self, channels: &[C]) -> Result<(), Error> {
+        let names: Vec<Vec<u8>> = channels.iter().map(|c| c.to_arg()).collect();
+        self.change("SUBSCRIBE", &names).await?;
+        self.channels.extend(names);
+        return Ok(());
+    }
+
+    pub async fn psubscribe<P: ToArg>(&mut self, patterns: &[P]) -> Result<(), Error> {
+        let names: Vec<Vec<u8>> = patterns.iter().map(|p| p.to_arg()).collect();
+        self.change("PSUBSCRIBE", &names).await?;
+        self.patterns.extend(names);
+        return Ok(());
+    }
+
+    pub async fn unsubscribe<C: ToArg>(&mut self, channels: &[C]) -> Result<(), Error> {
+        let names: Vec<Vec<u8>> = channels.iter().map(|c| c.to_arg()).collect();
+        self.change("UNSUBSCRIBE", &names).await?;
+        for name in names.iter() {
+            self.channels.remove(name);
+        }
+        return Ok(());
+    }
+
+    pub async fn punsubscribe<P: ToArg>(&mut self, patterns: &[P]) -> Result<(), Error> {
+        let names: Vec<Vec<u8>> = patterns.iter().map(|p| p.to_arg()).collect();
+        self.change("PUNSUBSCRIBE", &names).await?;
+        for name in names.iter() {
+            self.patterns.remove(name);
+        }
+        return Ok(());
+    }
+
+    /// The channels subscribed to with `subscribe`.
+    pub fn channels(&self) -> impl Iterator<Item = &Vec<u8>> {
+        return self.channels.iter();
+    }
+
+    /// Sends `command` for `names` and waits until the server confirmed every one of them.
+    async fn change(&mut self, command: &str, names: &[Vec<u8>]) -> Result<(), Error> {
+        if names.is_empty() {
+            return Err(Error {
+                message: format!("{} needs at least one name", command),
+            });
+        }
+        let stream: &mut Stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Err(Error::new("Not connected")),
+        };
+        let result: Result<(), Error> = confirm(stream, &mut self.pending, command, names).await;
+        if result.is_err() {
+            self.stream = None;
+        }
+        return result;
+    }
+
+    /// Waits for the next message, reconnecting if the connection breaks.
+    pub async fn next_message(&mut self) -> Result<Message, Error> {
+        let mut attempts: usize = 0;
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return Ok(message);
+            }
+
+            let e: Error = match self.stream.as_mut() {
+                Some(stream) => match stream.read_reply().await {
+                    Ok(reply) => {
+                        if let Some(message) = parse_message(reply)? {
+                            return Ok(message);
+                        }
+                        continue;
+                    }
+                    Err(e) => e,
+                },
+                None => Error::new("Not connected"),
+            };
+
+            self.stream = None;
+            if attempts >= self.options.reconnect_attempts {
+                return Err(e);
+            }
+            attempts += 1;
+            tokio::time::sleep(self.options.reconnect_delay).await;
+            // a failed attempt is retried until the attempts run out
+            let _ = self.resubscribe().await;
+        }
+    }
+
+    async fn resubscribe(&mut self) -> Result<(), Error> {
+        let mut stream: Stream = Stream::connect(&self.options).await?;
+        let channels: Vec<Vec<u8>> = self.channels.iter().cloned().collect();
+        let patterns: Vec<Vec<u8>> = self.patterns.iter().cloned().collect();
+        if !channels.is_empty() {
+            confirm(&mut stream, &mut self.pending, "SUBSCRIBE", &channels).await?;
+        }
+        if !patterns.is_empty() {
+            confirm(&mut stream, &mut self.pending, "PSUBSCRIBE", &patterns).await?;
+        }
+        self.stream = Some(stream);
+        return Ok(());
+    }
+}
+
+async fn confirm(
+    stream: &mut Stream,
+    pending: &mut VecDeque<Message>,
+    command: &str,
+    names: &[Vec<u8>],
+) -> Result<(), Error> {
+    let mut request: Vec<u8> = Vec::new();
+    Cmd::new(command).args(names).encode(&mut request);
+    stream.write_all(&request).await?;
+
+    // one confirmation per name, named after the command in lowercase
+    let kind: Vec<u8> = command.to_lowercase().into_bytes();
+    let mut confirmed: usize = 0;
+    while confirmed < names.len() {
+        let reply: RedisType<'static> = stream.read_reply().await?;
+        if let RedisType::Error(message) = reply {
+            return Err(Error { message });
+        }
+        let parts: Vec<Vec<u8>> = push_parts(&reply)?;
+        if parts.first() == Some(&kind) {
+            confirmed += 1;
+        } else if let Some(message) = parse_message(reply)? {
+            pending.push_back(message);
+        }
+    }
+    return Ok(());
+}
+
+/// The elements of a Pub/Sub reply, with integers as their text.
+fn push_parts(reply: &RedisType) -> Result<Vec<Vec<u8>>, Error> {
+    let elements: &Vec<RedisType> = match reply {
+        RedisType::Array(elements) | RedisType::Push(elements) => elements,
+        other => return Err(unexpected(other)),
+    };
+    return Ok(elements
+        .iter()
+        .map(|element| match element {
+            RedisType::BulkBytes(b) => b.clone(),
+            RedisType::BulkString(s) | RedisType::Integer(s) | RedisType::Status(s) => {
+                s.as_bytes().to_vec()
+            }
+            _ => Vec::new(),
+        })
+        .collect());
+}
+
+/// The message in `reply`, or `None` for other Pub/Sub replies such as confirmations.
+fn parse_message(reply: RedisType) -> Result<Option<Message>, Error> {
+    let mut parts: Vec<Vec<u8>> = push_parts(&reply)?;
+    return Ok(match (parts.first().map(|k| k.as_slice()), parts.len()) {
+        (Some(b"message"), 3) => {
+            let payload: Vec<u8> = parts.pop().unwrap();
+            let channel: Vec<u8> = parts.pop().unwrap();
+            Some(Message {
+                channel,
+                pattern: None,
+                payload,
+            })
+        }
+        (Some(b"pmessage"), 4) => {
+            let payload: Vec<u8> = parts.pop().unwrap();
+            let channel: Vec<u8> = parts.pop().unwrap();
+            let pattern: Vec<u8> = parts.pop().unwrap();
+            Some(Message {
+                channel,
+                pattern: Some(pattern),
+                payload,
+            })
+        }
+        _ => None,
+    });
+}
@@ -1,378 +1,546 @@
-#[allow(unused_imports)]
 use std::fmt::Display;
 use std::sync::Arc;
-use tokio::fs::{self, File};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::Mutex;
 
-use crate::{Database, Error};
+use crate::commands::execute;
+use crate::{Error, ServerState, Session};
+
+/// Longest line accepted for inline commands and multibulk/bulk headers.
+pub const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
+
+/// The arguments of a parsed command and the number of bytes it occupied.
+pub type ParsedFrame = Option<(Vec<Vec<u8>>, usize)>;
+
+/// The command completed by a `FrameParser` call, if any, and the number of
+/// bytes it consumed either way.
+pub type FrameProgress = (Option<Vec<Vec<u8>>>, usize);
+
 #[derive(Debug)]
 pub enum RedisType<'a> {
     SimpleString(&'a str),
+    /// A simple string built at runtime, such as a MONITOR line.
+    Status(String),
     Error(String),
     Integer(String),
     BulkString(String),
+    BulkBytes(Vec<u8>),
     Array(Box<Vec<RedisType<'a>>>),
     Null,
     Boolean(bool),
     NullBulk,
-    Delay(Message),
+    /// A missing aggregate, such as an unknown member in GEOPOS.
+    NullArray,
+    /// Several replies written back to back, e.g. one per channel for SUBSCRIBE.
+    Sequence(Vec<RedisType<'a>>),
+    /// A RESP3 map; RESP2 clients get its keys and values as a flat array.
+    Map(Box<Vec<(RedisType<'a>, RedisType<'a>)>>),
+    /// An out-of-band RESP3 push (Pub/Sub messages, invalidations); an array in RESP2.
+    Push(Box<Vec<RedisType<'a>>>),
 }
 
-#[derive(Debug)]
-pub struct Message {
-    pub key: String,
-    pub time: u64,
-}
-#[allow(unreachable_code)]
-pub async fn get_redis_response<'a>(
-    req: &'a String,
-    data: Arc<Mutex<Database>>,
-) -> Result<RedisType<'a>, Error> {
-    // Transform request into a vector
-    let mut request: Vec<&str> = req.split("\r\n").collect();
-    request.pop();
-
-    // clone the arc
-    let _data = Arc::clone(&data);
-
-    if req.to_uppercase().contains("ECHO") {
-        // Safe to unwrap since we know it contains ECHO
-        let index: usize = request
-            .iter()
-            .position(|&s| s.to_uppercase() == "ECHO")
-            .unwrap()
-            + 1;
-        return Ok(RedisType::BulkString(
-            extract_msg(&request, index, None)[0].to_string(),
-        ));
-    } else if req.to_uppercase().contains("SET") {
-        // safe to unwrap since we know it contains SET
-        let index: usize = request
-            .iter()
-            .position(|&s| s.to_uppercase() == "SET")
-            .unwrap()
-            + 1;
+/// The protocol versions HELLO can select.
+pub const RESP2: u8 = 2;
+pub const RESP3: u8 = 3;
 
-        // extract the rest of the request message
-        let request_params: Vec<&str> = extract_msg(&request, index, None);
+/// Parses the first frame in `req` and executes it for a fresh client session.
+pub async fn get_redis_response(
+    req: &str,
+    data: &Arc<ServerState>,
+) -> Result<RedisType<'static>, Error> {
+    let max_bulk_len: usize = data.config.read().unwrap().proto_max_bulk_len;
 
-        _data.lock().await.add(request_params[0], request_params[1]);
+    let args: Vec<Vec<u8>> = match parse_frame(req.as_bytes(), max_bulk_len)? {
+        Some((args, _)) => args,
+        None => return Err(Error::new("ERR Protocol error: incomplete request")),
+    };
 
-        if request_params.len() > 2 && "px" == request_params[2].to_lowercase() {
-            // safe to unwrap here
-            return Ok(RedisType::Delay(Message::new(
-                request_params[3].parse::<u64>().unwrap(),
-                request_params[0].to_string(),
-            )));
-        }
+    let mut session: Session = Session::new();
+    return execute(&args, data, &mut session).await;
+}
 
-        return Ok(RedisType::SimpleString("OK"));
-    } else if req.to_uppercase().contains("CONFIG") {
-        let mut index: usize = match request.iter().position(|&s| s.to_uppercase() == "GET") {
-            Some(i) => i,
-            None => {
-                return Err(Error {
-                    message: "Error attaing index for CONFIG command".to_string(),
-                })
-            }
-        };
+/// Attempts to parse a single command from the start of `buf`.
+///
+/// Returns `Ok(None)` when the buffer does not yet hold a complete frame, or the
+/// arguments together with the number of bytes consumed. Both multibulk
+/// (`*2\r\n$4\r\nECHO\r\n...`) and inline (`ECHO hey\r\n`) requests are accepted.
+/// An empty argument list means the frame carried no command and should be skipped.
+pub fn parse_frame(buf: &[u8], max_bulk_len: usize) -> Result<ParsedFrame, Error> {
+    let mut parser: FrameParser = FrameParser::default();
+    return match parser.parse(buf, max_bulk_len)? {
+        (Some(args), used) => Ok(Some((args, used))),
+        (None, _) => Ok(None),
+    };
+}
 
-        index += 1;
+/// Parses commands out of a connection's reads, keeping a partly received
+/// multibulk between reads, like the `multibulklen` and `bulklen` fields of a
+/// Redis client. Arguments already complete are moved out of the read buffer,
+/// so a large pipelined payload is scanned once, not once per read.
+#[derive(Debug, Default)]
+pub struct FrameParser {
+    /// Arguments of the multibulk being received still missing, 0 between commands.
+    missing: usize,
+    /// Length of the bulk whose header was read but not yet its payload.
+    bulk_len: Option<usize>,
+    args: Vec<Vec<u8>>,
+    /// Bytes held in `args`, counted against the query buffer limit.
+    pending: usize,
+}
 
-        let request_params: Vec<&str> = extract_msg(&request, index, Some(1));
+impl FrameParser {
+    /// Bytes of a partly received command held by the parser.
+    pub fn pending_bytes(&self) -> usize {
+        return self.pending;
+    }
 
-        match data.lock().await.get(&request_params[0].to_lowercase()) {
-            Some(d) => {
-                let res: Vec<RedisType> = vec![
-                    RedisType::BulkString(request_params[0].to_string()),
-                    RedisType::BulkString(d),
-                ];
-                return Ok(RedisType::Array(Box::new(res)));
+    /// Parses from the start of `buf`, which continues the bytes consumed by
+    /// the earlier calls.
+    pub fn parse(&mut self, buf: &[u8], max_bulk_len: usize) -> Result<FrameProgress, Error> {
+        if self.missing == 0 {
+            if buf.is_empty() {
+                return Ok((None, 0));
             }
-            None => return Ok(RedisType::NullBulk),
-        };
-    } else if req.to_uppercase().contains("GET") {
-        let index: usize = request
-            .iter()
-            .position(|&s| s.to_uppercase() == "GET")
-            .unwrap()
-            + 1;
-
-        let req_msg: Vec<&str> = extract_msg(&request, index, Some(1));
-
-        match data.lock().await.get(req_msg[0]) {
-            Some(d) => {
-                return Ok(RedisType::BulkString(d));
+            if buf[0] != b'*' {
+                return Ok(match parse_inline(buf)? {
+                    Some((args, used)) => (Some(args), used),
+                    None => (None, 0),
+                });
             }
-            None => return Ok(RedisType::NullBulk),
-        };
-    } else if req.to_uppercase().contains("KEYS") {
-        let index: usize = request
-            .iter()
-            .position(|&s| s.to_uppercase() == "KEYS")
-            .unwrap()
-            + 1;
-
-        let mut path: String = data.lock().await.get("dir").unwrap().to_string();
-
-        path.push_str("/");
-        path.push_str(&data.lock().await.get("dir").unwrap());
+        }
+        return self.parse_multibulk(buf, max_bulk_len);
+    }
 
-        let _regex_expression: &str = extract_regex(&request, index)[1];
+    fn parse_multibulk(&mut self, buf: &[u8], max_bulk_len: usize) -> Result<FrameProgress, Error> {
+        let mut pos: usize = 0;
+        if self.missing == 0 {
+            let line_end: usize = match find_crlf(buf, 1) {
+                Some(i) => i,
+                None => {
+                    if buf.len() > PROTO_INLINE_MAX_SIZE {
+                        return Err(Error::new("ERR Protocol error: too big mbulk count string"));
+                    }
+                    return Ok((None, 0));
+                }
+            };
+
+            let count: i64 = match parse_integer(&buf[1..line_end]) {
+                Some(n) if n <= i32::MAX as i64 => n,
+                _ => return Err(Error::new("ERR Protocol error: invalid multibulk length")),
+            };
+            pos = line_end + 2;
+            if count <= 0 {
+                return Ok((Some(Vec::new()), pos));
+            }
+            self.missing = count as usize;
+            self.args = Vec::with_capacity(self.missing.min(1024));
+        }
 
-        let mut keys: Vec<RedisType> = Vec::new();
+        while self.missing > 0 {
+            let len: usize = match self.bulk_len {
+                Some(len) => len,
+                None => {
+                    if pos >= buf.len() {
+                        return Ok((None, pos));
+                    }
+                    if buf[pos] != b'$' {
+                        return Err(Error {
+                            message: format!(
+                                "ERR Protocol error: expected '$', got '{}'",
+                                buf[pos] as char
+                            ),
+                        });
+                    }
 
-        let contents: String = match fs::read_to_string(path).await {
-            Ok(file) => file,
-            Err(_) => return Ok(RedisType::Error("Error finding file".to_string())),
-        };
-        let _my_file: File = match File::create("input.txt").await {
-            Ok(file) => file,
-            Err(_) => return Ok(RedisType::Error("Error creating file".to_string())),
-        };
+                    let header_end: usize = match find_crlf(buf, pos + 1) {
+                        Some(i) => i,
+                        None => {
+                            if buf.len() - pos > PROTO_INLINE_MAX_SIZE {
+                                return Err(Error::new(
+                                    "ERR Protocol error: too big bulk count string",
+                                ));
+                            }
+                            return Ok((None, pos));
+                        }
+                    };
+                    let len: usize = match parse_integer(&buf[pos + 1..header_end]) {
+                        Some(n) if n >= 0 && n as u64 <= max_bulk_len as u64 => n as usize,
+                        _ => return Err(Error::new("ERR Protocol error: invalid bulk length")),
+                    };
+                    pos = header_end + 2;
+                    self.bulk_len = Some(len);
+                    len
+                }
+            };
 
-        if contents.contains("FC") {
-            println!("Found fc");
+            if buf.len() - pos < len + 2 {
+                return Ok((None, pos));
+            }
+            if &buf[pos + len..pos + len + 2] != b"\r\n" {
+                return Err(Error::new(
+                    "ERR Protocol error: expected CRLF after bulk data",
+                ));
+            }
+            self.args.push(buf[pos..pos + len].to_vec());
+            self.pending += len;
+            self.bulk_len = None;
+            self.missing -= 1;
+            pos += len + 2;
         }
 
-        println!("File contents: {}", contents);
-
-        let _index: usize = request
-            .iter()
-            .position(|&s| s.to_uppercase() == "KEYS")
-            .unwrap()
-            + 1;
+        self.pending = 0;
+        return Ok((Some(std::mem::take(&mut self.args)), pos));
+    }
+}
 
-        for key in data.lock().await.get_keys() {
-            keys.push(RedisType::BulkString(key));
+fn parse_inline(buf: &[u8]) -> Result<ParsedFrame, Error> {
+    let end: usize = match buf.iter().position(|&b| b == b'\n') {
+        Some(i) => i,
+        None => {
+            if buf.len() > PROTO_INLINE_MAX_SIZE {
+                return Err(Error::new("ERR Protocol error: too big inline request"));
+            }
+            return Ok(None);
         }
+    };
 
-        return Ok(RedisType::Array(Box::new(keys)));
-
-        // get the path to the db file
-        return Ok(RedisType::NullBulk);
-    } else {
-        //PING
-        return Ok(RedisType::SimpleString("PONG"));
+    let mut line: &[u8] = &buf[..end];
+    if line.last() == Some(&b'\r') {
+        line = &line[..line.len() - 1];
     }
+
+    let args: Vec<Vec<u8>> = line
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_vec())
+        .collect();
+
+    return Ok(Some((args, end + 1)));
 }
 
-fn extract_msg<'a>(req: &Vec<&'a str>, start: usize, count: Option<usize>) -> Vec<&'a str> {
-    let symbols: Vec<char> = vec!['*', ':', '+', '-', '$', '_', '#'];
-    let mut req_msg: Vec<&str> = Vec::new();
-    match count {
-        Some(num) => {
-            let mut count = 0;
-            for s in start..req.len() {
-                if !req[s].contains(&symbols[..]) {
-                    req_msg.push(req[s]);
-                    count += 1;
-                    if count >= num {
-                        break;
-                    }
-                }
+/// Attempts to parse a single reply from the start of `buf`, as a client reads them.
+///
+/// Returns `Ok(None)` when the buffer does not yet hold a complete reply, or the
+/// reply together with the number of bytes consumed. RESP3 types without a
+/// variant of their own are folded into the closest one: sets into arrays,
+/// doubles into bulk strings and big numbers into integers.
+pub fn parse_reply(buf: &[u8]) -> Result<Option<(RedisType<'static>, usize)>, Error> {
+    let line_end: usize = match find_crlf(buf, 1) {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    let line: &[u8] = &buf[1..line_end];
+    let text: String = String::from_utf8_lossy(line).to_string();
+    let next: usize = line_end + 2;
+
+    let reply: RedisType<'static> = match buf[0] {
+        b'+' => RedisType::Status(text),
+        b'-' => RedisType::Error(text),
+        b':' | b'(' => RedisType::Integer(text),
+        b',' => RedisType::BulkString(text),
+        b'#' => RedisType::Boolean(line == b"t"),
+        b'_' => RedisType::Null,
+        b'$' | b'=' | b'!' => {
+            let len: i64 = match parse_integer(line) {
+                Some(n) if n >= -1 => n,
+                _ => return Err(Error::new("ERR Protocol error: invalid bulk length")),
+            };
+            if len == -1 {
+                return Ok(Some((RedisType::NullBulk, next)));
             }
+            let end: usize = next + len as usize;
+            if buf.len() < end + 2 {
+                return Ok(None);
+            }
+            let bytes: &[u8] = &buf[next..end];
+            let reply: RedisType<'static> = match buf[0] {
+                b'!' => RedisType::Error(String::from_utf8_lossy(bytes).to_string()),
+                // verbatim strings start with their format, e.g. "txt:"
+                b'=' if bytes.len() >= 4 => RedisType::BulkBytes(bytes[4..].to_vec()),
+                _ => RedisType::BulkBytes(bytes.to_vec()),
+            };
+            return Ok(Some((reply, end + 2)));
         }
-        None => {
-            for s in start..req.len() {
-                if !req[s].contains(&symbols[..]) {
-                    req_msg.push(req[s]);
+        b'*' | b'~' | b'>' | b'%' => {
+            let count: i64 = match parse_integer(line) {
+                Some(n) if n >= -1 => n,
+                _ => return Err(Error::new("ERR Protocol error: invalid multibulk length")),
+            };
+            if count == -1 {
+                return Ok(Some((RedisType::NullArray, next)));
+            }
+            let items: usize = match buf[0] {
+                b'%' => count as usize * 2,
+                _ => count as usize,
+            };
+
+            let mut elements: Vec<RedisType<'static>> = Vec::with_capacity(items.min(1024));
+            let mut pos: usize = next;
+            for _ in 0..items {
+                match parse_reply(&buf[pos..])? {
+                    Some((element, used)) => {
+                        elements.push(element);
+                        pos += used;
+                    }
+                    None => return Ok(None),
                 }
             }
+
+            let reply: RedisType<'static> = match buf[0] {
+                b'>' => RedisType::Push(Box::new(elements)),
+                b'%' => {
+                    let mut pairs: Vec<(RedisType<'static>, RedisType<'static>)> =
+                        Vec::with_capacity(elements.len() / 2);
+                    let mut iter = elements.into_iter();
+                    while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
+                        pairs.push((key, value));
+                    }
+                    RedisType::Map(Box::new(pairs))
+                }
+                _ => RedisType::Array(Box::new(elements)),
+            };
+            return Ok(Some((reply, pos)));
         }
-    }
-    return req_msg;
-}
+        other => {
+            return Err(Error {
+                message: format!(
+                    "ERR Protocol error: unexpected reply type '{}'",
+                    other as char
+                ),
+            })
+        }
+    };
 
-fn extract_regex<'a>(req: &'a Vec<&str>, start: usize) -> Vec<&'a str> {
-    let mut req_msg: Vec<&str> = Vec::new();
-    for s in start..req.len() {
-        req_msg.push(req[s]);
-    }
-    return req_msg;
+    return Ok(Some((reply, next)));
 }
 
-impl Message {
-    pub fn new(time: u64, key: String) -> Self {
-        return Message { key, time };
+fn find_crlf(buf: &[u8], start: usize) -> Option<usize> {
+    if buf.len() < 2 || start > buf.len() - 2 {
+        return None;
     }
+    return (start..buf.len() - 1).find(|&i| buf[i] == b'\r' && buf[i + 1] == b'\n');
 }
 
-impl Clone for Message {
-    fn clone(&self) -> Self {
-        return Message {
-            key: self.key.clone(),
-            time: self.time,
-        };
-    }
-}
-
-impl PartialEq for Message {
-    fn eq(&self, other: &Self) -> bool {
-        return self.key == other.key && self.time == other.time;
-    }
+fn parse_integer(bytes: &[u8]) -> Option<i64> {
+    return std::str::from_utf8(bytes).ok()?.parse::<i64>().ok();
 }
 
 impl<'a> RedisType<'a> {
-    pub fn is_delay(&self) -> bool {
-        match self {
-            RedisType::Delay(_) => true,
-            _ => false,
-        }
+    /// Appends the RESP2 encoding of this value to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_with(out, RESP2);
     }
-}
 
-impl<'a> Display for RedisType<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// Appends the encoding of this value for a client speaking `protocol`.
+    pub fn encode_with(&self, out: &mut Vec<u8>, protocol: u8) {
         match &self {
             RedisType::SimpleString(msg) => {
-                return write!(f, "+{}\r\n", msg);
+                out.push(b'+');
+                out.extend_from_slice(msg.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            RedisType::Status(msg) => {
+                out.push(b'+');
+                out.extend_from_slice(msg.as_bytes());
+                out.extend_from_slice(b"\r\n");
             }
             RedisType::Error(msg) => {
-                return write!(f, "-{}\r\n", msg);
+                out.push(b'-');
+                out.extend_from_slice(msg.as_bytes());
+                out.extend_from_slice(b"\r\n");
             }
             RedisType::Integer(msg) => {
-                return write!(f, ":{}\r\n", msg);
-            }
-            RedisType::BulkString(msg) => {
-                return write!(f, "${}\r\n{}\r\n", &msg.len().to_string(), msg);
+                out.push(b':');
+                out.extend_from_slice(msg.as_bytes());
+                out.extend_from_slice(b"\r\n");
             }
+            RedisType::BulkString(msg) => encode_bulk(msg.as_bytes(), out),
+            RedisType::BulkBytes(msg) => encode_bulk(msg, out),
             RedisType::Array(elements) => {
-                let mut res: String = String::new();
-                for i in 0..elements.len() {
-                    res.push_str(&elements[i].to_string());
+                out.extend_from_slice(format!("*{}\r\n", elements.len()).as_bytes());
+                for element in elements.iter() {
+                    element.encode_with(out, protocol);
                 }
-                return write!(f, "*{}\r\n{}", &elements.len().to_string(), res);
             }
-            RedisType::Null => {
-                return write!(f, "_\r\n");
+            RedisType::Map(pairs) => {
+                match protocol {
+                    RESP3 => out.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes()),
+                    _ => out.extend_from_slice(format!("*{}\r\n", pairs.len() * 2).as_bytes()),
+                }
+                for (key, value) in pairs.iter() {
+                    key.encode_with(out, protocol);
+                    value.encode_with(out, protocol);
+                }
             }
+            RedisType::Push(elements) => {
+                let kind: u8 = if protocol == RESP3 { b'>' } else { b'*' };
+                out.extend_from_slice(format!("{}{}\r\n", kind as char, elements.len()).as_bytes());
+                for element in elements.iter() {
+                    element.encode_with(out, protocol);
+                }
+            }
+            RedisType::Null => out.extend_from_slice(b"_\r\n"),
             RedisType::Boolean(msg) => match msg {
-                true => return write!(f, "#t\r\n"),
-                false => return write!(f, "#f\r\n"),
+                true => out.extend_from_slice(b"#t\r\n"),
+                false => out.extend_from_slice(b"#f\r\n"),
             },
-            RedisType::NullBulk => {
-                return write!(f, "$-1\r\n");
-            }
-            RedisType::Delay(_) => {
-                return write!(f, "$+OK\r\n");
+            RedisType::NullBulk if protocol == RESP3 => out.extend_from_slice(b"_\r\n"),
+            RedisType::NullBulk => out.extend_from_slice(b"$-1\r\n"),
+            RedisType::NullArray if protocol == RESP3 => out.extend_from_slice(b"_\r\n"),
+            RedisType::NullArray => out.extend_from_slice(b"*-1\r\n"),
+            RedisType::Sequence(replies) => {
+                for reply in replies {
+                    reply.encode_with(out, protocol);
+                }
             }
         }
     }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        self.encode(&mut out);
+        return out;
+    }
+}
+
+fn encode_bulk(msg: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(format!("${}\r\n", msg.len()).as_bytes());
+    out.extend_from_slice(msg);
+    out.extend_from_slice(b"\r\n");
+}
+
+impl<'a> Display for RedisType<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", String::from_utf8_lossy(&self.to_bytes()));
+    }
 }
 
 impl<'a> PartialEq for RedisType<'a> {
     fn eq(&self, other: &Self) -> bool {
-        match &self {
-            RedisType::SimpleString(msg) => match other {
-                RedisType::SimpleString(msg2) => {
-                    if msg == msg2 {
-                        true
-                    } else {
-                        false
-                    }
-                }
-                _ => false,
-            },
-            RedisType::Error(msg) => match other {
-                RedisType::Error(msg2) => {
-                    if msg == msg2 {
-                        true
-                    } else {
-                        false
-                    }
-                }
-                _ => false,
-            },
-            RedisType::Integer(msg) => match other {
-                RedisType::Integer(msg2) => {
-                    if msg == msg2 {
-                        true
-                    } else {
-                        false
-                    }
+        return match (self, other) {
+            (RedisType::SimpleString(a), RedisType::SimpleString(b)) => a == b,
+            (RedisType::Status(a), RedisType::Status(b)) => a == b,
+            (RedisType::SimpleString(a), RedisType::Status(b)) => a == b,
+            (RedisType::Status(a), RedisType::SimpleString(b)) => a == b,
+            (RedisType::Error(a), RedisType::Error(b)) => a == b,
+            (RedisType::Integer(a), RedisType::Integer(b)) => a == b,
+            // bulk strings compare equal regardless of whether they hold text or raw bytes
+            (RedisType::BulkString(a), RedisType::BulkString(b)) => a == b,
+            (RedisType::BulkBytes(a), RedisType::BulkBytes(b)) => a == b,
+            (RedisType::BulkString(a), RedisType::BulkBytes(b)) => a.as_bytes() == b.as_slice(),
+            (RedisType::BulkBytes(a), RedisType::BulkString(b)) => a.as_slice() == b.as_bytes(),
+            (RedisType::Array(a), RedisType::Array(b)) => a == b,
+            (RedisType::Null, RedisType::Null) => true,
+            (RedisType::Boolean(a), RedisType::Boolean(b)) => a == b,
+            (RedisType::NullBulk, RedisType::NullBulk) => true,
+            (RedisType::NullArray, RedisType::NullArray) => true,
+            (RedisType::Sequence(a), RedisType::Sequence(b)) => a == b,
+            (RedisType::Map(a), RedisType::Map(b)) => a == b,
+            (RedisType::Push(a), RedisType::Push(b)) => a == b,
+            _ => false,
+        };
+    }
+}
+
+/// Splits a command line typed at a prompt into arguments, the way redis-cli does.
+///
+/// Arguments are separated by whitespace. In double quotes `\n`, `\r`, `\t`,
+/// `\b`, `\a` and `\xHH` are unescaped and any other escaped character is
+/// taken as is; in single quotes only `\'` is. A closing quote must be
+/// followed by whitespace or the end of the line.
+pub fn split_args(line: &str) -> Result<Vec<Vec<u8>>, Error> {
+    let bytes: &[u8] = line.as_bytes();
+    let mut args: Vec<Vec<u8>> = Vec::new();
+    let mut i: usize = 0;
+
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == bytes.len() {
+            return Ok(args);
+        }
+
+        let mut arg: Vec<u8> = Vec::new();
+        let mut quote: Option<u8> = None;
+        loop {
+            let c: Option<u8> = bytes.get(i).copied();
+            match (quote, c) {
+                (Some(_), None) => {
+                    return Err(Error::new("Invalid argument(s): unbalanced quotes"))
                 }
-                _ => false,
-            },
-            RedisType::BulkString(msg) => match other {
-                RedisType::BulkString(msg2) => {
-                    if msg == msg2 {
-                        true
-                    } else {
-                        false
+                (None, None) => break,
+                (None, Some(c)) if c.is_ascii_whitespace() => break,
+                (None, Some(c)) if c == b'"' || c == b'\'' => quote = Some(c),
+                (None, Some(c)) => arg.push(c),
+                (Some(q), Some(c)) if c == q => {
+                    if bytes.get(i + 1).is_some_and(|n| !n.is_ascii_whitespace()) {
+                        return Err(Error::new(
+                            "Invalid argument(s): closing quote must be followed by a space",
+                        ));
                     }
+                    i += 1;
+                    break;
                 }
-                _ => false,
-            },
-            RedisType::Array(elements) => match other {
-                RedisType::Array(elements2) => {
-                    if elements == elements2 {
-                        true
-                    } else {
-                        false
+                (Some(b'"'), Some(b'\\')) if i + 1 < bytes.len() => {
+                    i += 1;
+                    let hex: Option<u8> = match bytes.get(i + 1..i + 3) {
+                        Some(digits) if bytes[i] == b'x' => std::str::from_utf8(digits)
+                            .ok()
+                            .and_then(|d| u8::from_str_radix(d, 16).ok()),
+                        _ => None,
+                    };
+                    match (hex, bytes[i]) {
+                        (Some(value), _) => {
+                            arg.push(value);
+                            i += 2;
+                        }
+                        (None, b'n') => arg.push(b'\n'),
+                        (None, b'r') => arg.push(b'\r'),
+                        (None, b't') => arg.push(b'\t'),
+                        (None, b'b') => arg.push(0x08),
+                        (None, b'a') => arg.push(0x07),
+                        (None, other) => arg.push(other),
                     }
                 }
-                _ => false,
-            },
-            RedisType::Null => match other {
-                RedisType::Null => true,
-                _ => false,
-            },
-            RedisType::Boolean(msg) => match other {
-                RedisType::Boolean(msg2) => {
-                    if msg == msg2 {
-                        true
-                    } else {
-                        false
-                    }
+                (Some(b'\''), Some(b'\\')) if bytes.get(i + 1) == Some(&b'\'') => {
+                    i += 1;
+                    arg.push(b'\'');
                 }
-                _ => false,
-            },
-            RedisType::NullBulk => match other {
-                RedisType::NullBulk => true,
-                _ => false,
-            },
-
-            RedisType::Delay(_) => match other {
-                RedisType::Delay(_) => true,
-                _ => false,
-            },
-        }
-    }
-}
-
-pub fn split_command(command: &str) -> Option<Vec<&str>> {
-    let iter = command.char_indices();
-
-    let mut start: isize = -1;
-
-    for (pos, c) in iter {
-        if c.is_whitespace() {
-            if start == -1 {
-                start = pos as isize;
-                break;
+                (Some(_), Some(c)) => arg.push(c),
             }
+            i += 1;
         }
+        args.push(arg);
     }
-    if start == -1 {
-        return None;
-    }
-
-    let start: usize = start as usize;
-    return Some(vec![&command[..start], &command[start + 1..]]);
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::Config;
 
     #[test]
     fn split_test() {
-        let sen1: String = String::from("ECHO Hello world");
-        let res1 = split_command(&sen1).unwrap();
-        assert_eq!(res1, vec!["ECHO", "Hello world"]);
+        let res1: Vec<Vec<u8>> = split_args("  ECHO   Hello world ").unwrap();
+        assert_eq!(
+            res1,
+            vec![b"ECHO".to_vec(), b"Hello".to_vec(), b"world".to_vec()]
+        );
+
+        let res2: Vec<Vec<u8>> = split_args(r#"SET "a key" 'it\'s' "\x41\tb\"" """#).unwrap();
+        assert_eq!(
+            res2,
+            vec![
+                b"SET".to_vec(),
+                b"a key".to_vec(),
+                b"it's".to_vec(),
+                b"A\tb\"".to_vec(),
+                Vec::new()
+            ]
+        );
+        assert_eq!(split_args("").unwrap(), Vec::<Vec<u8>>::new());
+        assert!(split_args(r#"GET "key"#).is_err());
+        assert!(split_args(r#"GET "a"b"#).is_err());
     }
 
     #[test]
@@ -383,23 +551,158 @@ mod tests {
         assert_eq!(msg, vec!["*2", "$4", "ECHO", "$3", "hey"]);
     }
 
+    #[test]
+    fn parse_frame_test() {
+        let msg: &[u8] = b"*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n";
+        let (args, used) = parse_frame(msg, 512).unwrap().unwrap();
+        assert_eq!(args, vec![b"ECHO".to_vec(), b"hey".to_vec()]);
+        assert_eq!(used, msg.len());
+    }
+
+    #[test]
+    fn parse_partial_frame_test() {
+        let msg: &[u8] = b"*2\r\n$4\r\nECHO\r\n$3\r\nhe";
+        assert!(parse_frame(msg, 512).unwrap().is_none());
+        assert!(parse_frame(b"*2\r", 512).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_pipelined_frames_test() {
+        let msg: &[u8] = b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n";
+        let (first, used) = parse_frame(msg, 512).unwrap().unwrap();
+        assert_eq!(first, vec![b"PING".to_vec()]);
+
+        let (second, rest) = parse_frame(&msg[used..], 512).unwrap().unwrap();
+        assert_eq!(second, vec![b"GET".to_vec(), b"foo".to_vec()]);
+        assert_eq!(used + rest, msg.len());
+    }
+
+    #[test]
+    fn parse_bulk_terminator_test() {
+        let msg: &[u8] = b"*2\r\n$4\r\nECHO\r\n$3\r\nheyXX*1\r\n$4\r\nPING\r\n";
+        let err = parse_frame(msg, 512).unwrap_err();
+        assert_eq!(
+            err.message,
+            "ERR Protocol error: expected CRLF after bulk data"
+        );
+    }
+
+    #[test]
+    fn frame_parser_resume_test() {
+        let msg: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n";
+        let mut parser: FrameParser = FrameParser::default();
+        let mut buf: Vec<u8> = Vec::new();
+        let mut parsed: Option<Vec<Vec<u8>>> = None;
+        // fed one byte at a time, the complete arguments leave the buffer
+        for byte in msg {
+            buf.push(*byte);
+            let (args, used) = parser.parse(&buf, 512).unwrap();
+            buf.drain(..used);
+            assert!(buf.len() <= 10);
+            if args.is_some() {
+                parsed = args;
+            }
+        }
+        assert_eq!(
+            parsed.unwrap(),
+            vec![b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]
+        );
+        assert!(buf.is_empty());
+        assert_eq!(parser.pending_bytes(), 0);
+    }
+
+    #[test]
+    fn parse_binary_bulk_test() {
+        let msg: &[u8] = b"*2\r\n$4\r\nECHO\r\n$4\r\n\r\n\x00\xff\r\n";
+        let (args, _) = parse_frame(msg, 512).unwrap().unwrap();
+        assert_eq!(args[1], b"\r\n\x00\xff".to_vec());
+    }
+
+    #[test]
+    fn parse_inline_test() {
+        let (args, used) = parse_frame(b"SET  foo bar\r\n", 512).unwrap().unwrap();
+        assert_eq!(
+            args,
+            vec![b"SET".to_vec(), b"foo".to_vec(), b"bar".to_vec()]
+        );
+        assert_eq!(used, 14);
+    }
+
+    #[test]
+    fn parse_bulk_limit_test() {
+        let msg: &[u8] = b"*2\r\n$4\r\nECHO\r\n$1000\r\n";
+        let err = parse_frame(msg, 512).unwrap_err();
+        assert_eq!(err.message, "ERR Protocol error: invalid bulk length");
+    }
+
+    #[test]
+    fn parse_reply_test() {
+        let reply: RedisType = RedisType::Array(Box::new(vec![
+            RedisType::SimpleString("OK"),
+            RedisType::Error(String::from("ERR nope")),
+            RedisType::Integer(String::from("-3")),
+            RedisType::BulkBytes(b"a\r\nb".to_vec()),
+            RedisType::NullBulk,
+            RedisType::NullArray,
+        ]));
+        let bytes: Vec<u8> = reply.to_bytes();
+        assert_eq!(parse_reply(&bytes).unwrap(), Some((reply, bytes.len())));
+
+        // RESP3 aggregates keep their shape
+        let mut bytes: Vec<u8> = Vec::new();
+        let map: RedisType = RedisType::Map(Box::new(vec![(
+            RedisType::BulkString(String::from("proto")),
+            RedisType::Integer(String::from("3")),
+        )]));
+        map.encode_with(&mut bytes, RESP3);
+        RedisType::Push(Box::new(vec![RedisType::Boolean(true), RedisType::Null]))
+            .encode_with(&mut bytes, RESP3);
+        let (first, used) = parse_reply(&bytes).unwrap().unwrap();
+        assert_eq!(first, map);
+        assert_eq!(
+            parse_reply(&bytes[used..]).unwrap().unwrap().0,
+            RedisType::Push(Box::new(vec![RedisType::Boolean(true), RedisType::Null]))
+        );
+
+        // every prefix of a reply is incomplete
+        let bytes: Vec<u8> = b"*2\r\n$3\r\nfoo\r\n:1\r\n".to_vec();
+        for len in 0..bytes.len() {
+            assert_eq!(parse_reply(&bytes[..len]).unwrap(), None);
+        }
+        assert!(parse_reply(b"?\r\n").is_err());
+    }
+
+    #[test]
+    fn parse_unexpected_type_test() {
+        let err = parse_frame(b"*1\r\n:4\r\n", 512).unwrap_err();
+        assert_eq!(err.message, "ERR Protocol error: expected '$', got ':'");
+    }
+
     #[tokio::test]
     async fn echo_command_test() {
-        let data = Arc::new(Mutex::new(Database::new()));
+        let data = Arc::new(ServerState::new(Config::default()));
         let msg: String = String::from("*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n");
-        let ans = get_redis_response(&msg, data).await.unwrap();
+        let ans = get_redis_response(&msg, &data).await.unwrap();
         assert_eq!(ans, RedisType::BulkString("hey".to_string()));
     }
 
     #[tokio::test]
     async fn get_conf_command_test() {
-        let data = Arc::new(Mutex::new(Database::new()));
-        data.lock().await.add("dir", "/tmp/redis-files");
+        let data = Arc::new(ServerState::new(Config::default()));
+        data.config
+            .write()
+            .unwrap()
+            .set("dir", "/tmp/redis-files")
+            .unwrap();
 
-        data.lock().await.add("dbfilename", "dump.rdb");
+        data.config
+            .write()
+            .unwrap()
+            .set("dbfilename", "dump.rdb")
+            .unwrap();
 
         let msg: String = String::from("*3\r\n$6\r\nCONFIG\r\n$3\r\nGET\r\n$3\r\ndir\r\n");
-        let ans = get_redis_response(&msg, Arc::clone(&data)).await.unwrap();
+        let ans = get_redis_response(&msg, &data).await.unwrap();
         assert_eq!(
             ans,
             RedisType::Array(Box::new(vec![
@@ -408,8 +711,8 @@ mod tests {
             ]))
         );
 
-        let msg: String = String::from("*3\r\n$6\r\nCONFIG\r\n$3\r\nGET\r\n$3\r\ndbfilename\r\n");
-        let ans = get_redis_response(&msg, data).await.unwrap();
+        let msg: String = String::from("*3\r\n$6\r\nCONFIG\r\n$3\r\nGET\r\n$10\r\ndbfilename\r\n");
+        let ans = get_redis_response(&msg, &data).await.unwrap();
         assert_eq!(
             ans,
             RedisType::Array(Box::new(vec![
@@ -421,33 +724,106 @@ mod tests {
 
     #[tokio::test]
     async fn get_command_test() {
-        let data = Arc::new(Mutex::new(Database::new()));
-        data.lock().await.add("foo", "bar");
+        let data = Arc::new(ServerState::new(Config::default()));
+        data.keyspace
+            .lock_all()
+            .await
+            .db(0, b"foo")
+            .add(b"foo", b"bar");
 
         let msg: String = String::from("*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n");
-        let ans = get_redis_response(&msg, data).await.unwrap();
+        let ans = get_redis_response(&msg, &data).await.unwrap();
         assert_eq!(ans, RedisType::BulkString("bar".to_string()));
     }
 
     #[tokio::test]
     async fn set_command_test() {
-        let data = Arc::new(Mutex::new(Database::new()));
+        let data = Arc::new(ServerState::new(Config::default()));
 
         let msg: String = String::from("*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n");
-        let ans = get_redis_response(&msg, Arc::clone(&data)).await.unwrap();
+        let ans = get_redis_response(&msg, &data).await.unwrap();
         assert_eq!(ans, RedisType::SimpleString("OK"));
 
-        assert_eq!(*(data.lock().await.get("foo").unwrap()), "bar".to_string());
+        assert_eq!(
+            data.keyspace.lock_all().await.db(0, b"foo").get(b"foo"),
+            Some(b"bar".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_test() {
+        let data = Arc::new(ServerState::new(Config::default()));
+        let mut session: Session = Session::new();
+        let mut other: Session = Session::new();
+        let run = |args: &[&str]| -> Vec<Vec<u8>> {
+            return args.iter().map(|a| a.as_bytes().to_vec()).collect();
+        };
+
+        // the queued commands run together, whatever shards their keys live in
+        execute(&run(&["MULTI"]), &data, &mut session)
+            .await
+            .unwrap();
+        let queued = execute(&run(&["SET", "a", "1"]), &data, &mut session).await;
+        assert_eq!(queued.unwrap(), RedisType::SimpleString("QUEUED"));
+        execute(&run(&["SET", "b", "2"]), &data, &mut session)
+            .await
+            .unwrap();
+        let replies = execute(&run(&["EXEC"]), &data, &mut session).await.unwrap();
+        assert_eq!(
+            replies,
+            RedisType::Array(Box::new(vec![
+                RedisType::SimpleString("OK"),
+                RedisType::SimpleString("OK")
+            ]))
+        );
+
+        // a watched key changed by another client aborts the transaction
+        execute(&run(&["WATCH", "a"]), &data, &mut session)
+            .await
+            .unwrap();
+        execute(&run(&["SET", "a", "3"]), &data, &mut other)
+            .await
+            .unwrap();
+        execute(&run(&["MULTI"]), &data, &mut session)
+            .await
+            .unwrap();
+        execute(&run(&["GET", "b"]), &data, &mut session)
+            .await
+            .unwrap();
+        let aborted = execute(&run(&["EXEC"]), &data, &mut session).await.unwrap();
+        assert_eq!(aborted, RedisType::NullArray);
+
+        // a command refused while queuing discards the whole transaction
+        execute(&run(&["MULTI"]), &data, &mut session)
+            .await
+            .unwrap();
+        assert!(execute(&run(&["NOPE"]), &data, &mut session).await.is_err());
+        let failed = execute(&run(&["EXEC"]), &data, &mut session)
+            .await
+            .unwrap_err();
+        assert!(failed.message.starts_with("EXECABORT"));
+    }
+
+    #[tokio::test]
+    async fn unknown_command_test() {
+        let data = Arc::new(ServerState::new(Config::default()));
+
+        let msg: String = String::from("*2\r\n$5\r\nHOWDY\r\n$5\r\nthere\r\n");
+        let ans = get_redis_response(&msg, &data).await.unwrap_err();
+        assert_eq!(
+            ans.message,
+            "ERR unknown command 'HOWDY', with args beginning with: 'there' "
+        );
     }
 
     #[test]
     fn command_convert_test() {
-        let sen1: String = String::from("ECHO Hello world");
-        let res1: Vec<&str> = split_command(&sen1).unwrap();
+        let sen1: String = String::from("ECHO \"Hello world\"");
+        let res1: Vec<Vec<u8>> = split_args(&sen1).unwrap();
 
         assert_eq!(
-            RedisType::BulkString(res1[1].to_string()),
-            RedisType::BulkString("Hello world".to_string())
+            RedisType::BulkBytes(res1[1].clone()),
+            RedisType::BulkBytes(b"Hello world".to_vec())
         );
     }
 
@@ -475,6 +851,36 @@ mod tests {
         assert_eq!(my_bulk.to_string(), "$11\r\nHello world\r\n");
     }
 
+    #[test]
+    fn bulk_bytes_test() {
+        let my_bulk: RedisType = RedisType::BulkBytes(vec![0, 159, 146, 150]);
+        assert_eq!(my_bulk.to_bytes(), b"$4\r\n\x00\x9f\x92\x96\r\n".to_vec());
+    }
+
+    #[test]
+    fn resp3_test() {
+        let map: RedisType = RedisType::Map(Box::new(vec![(
+            RedisType::BulkString(String::from("proto")),
+            RedisType::Integer(String::from("3")),
+        )]));
+        let mut out: Vec<u8> = Vec::new();
+        map.encode_with(&mut out, RESP3);
+        assert_eq!(out, b"%1\r\n$5\r\nproto\r\n:3\r\n".to_vec());
+        assert_eq!(map.to_bytes(), b"*2\r\n$5\r\nproto\r\n:3\r\n".to_vec());
+
+        let push: RedisType = RedisType::Push(Box::new(vec![
+            RedisType::BulkString(String::from("invalidate")),
+            RedisType::NullBulk,
+        ]));
+        let mut out: Vec<u8> = Vec::new();
+        push.encode_with(&mut out, RESP3);
+        assert_eq!(out, b">2\r\n$10\r\ninvalidate\r\n_\r\n".to_vec());
+        assert_eq!(
+            push.to_bytes(),
+            b"*2\r\n$10\r\ninvalidate\r\n$-1\r\n".to_vec()
+        );
+    }
+
     #[test]
     fn array_test_string() {
         let array: Box<Vec<RedisType>> = Box::new(vec![
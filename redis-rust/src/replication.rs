@@ -0,0 +1,481 @@
+//! Master-replica replication.
+//!
+//! REPLICAOF turns the server into a replica: a task connects to the master,
+//! announces its listening port with REPLCONF and sends PSYNC. Only full
+//! resynchronizations exist: the master answers +FULLRESYNC with a snapshot of
+//! its dataset in RDB format, then streams every write command it runs, which
+//! the replica applies as if a client had sent them. Every reconnection
+//! transfers the whole dataset again.
+//!
+//! The replication offset counts the bytes of that stream, so the replicas of
+//! one master can be compared by how much of it they applied; Sentinel
+//! promotes the most up to date one. Expired keys are deleted by every server
+//! on its own rather than propagated as DEL.
+//!
+//! A replica refuses writes from its clients. It can have replicas of its own,
+//! which receive the commands it applies.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+
+use crate::client::Client;
+use crate::commands::execute;
+use crate::keyspace::Shards;
+use crate::random::random_hex;
+use crate::rdb;
+use crate::redis_client::{ClientOptions, Cmd, Stream};
+use crate::redis_parser::RedisType;
+use crate::{Database, Error, ServerState, Session};
+
+/// Pause before connecting to the master again after the link broke.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The master a replica follows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MasterAddr {
+    pub host: String,
+    pub port: u16,
+}
+
+impl MasterAddr {
+    pub fn new(host: &str, port: u16) -> Self {
+        return MasterAddr {
+            host: host.to_string(),
+            port,
+        };
+    }
+
+    pub fn addr(&self) -> String {
+        return format!("{}:{}", self.host, self.port);
+    }
+}
+
+/// Progress of a replica's link to its master, with the names ROLE uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkState {
+    Connect,
+    Connecting,
+    Sync,
+    Connected,
+}
+
+impl LinkState {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        };
+    }
+}
+
+/// A replica attached to this server.
+struct Replica {
+    client: Arc<Client>,
+    ip: String,
+    port: u16,
+}
+
+struct Inner {
+    master: Option<MasterAddr>,
+    link_state: LinkState,
+    link: Option<JoinHandle<()>>,
+    /// When the link to the master last went down, `None` while it is up.
+    link_down_since: Option<Instant>,
+    /// Last time anything arrived from the master.
+    last_io: Instant,
+    replicas: Vec<Replica>,
+    replid: String,
+    offset: u64,
+    /// Database the stream sent to the replicas selected last; `None` makes
+    /// the next command start with a SELECT.
+    selected_db: Option<usize>,
+}
+
+/// A snapshot of the replication state, for INFO and ROLE.
+#[derive(Clone, Debug)]
+pub struct ReplicationStatus {
+    pub master: Option<MasterAddr>,
+    pub link_state: LinkState,
+    pub link_down_secs: Option<u64>,
+    pub last_io_secs: u64,
+    /// Address and listening port of every attached replica.
+    pub replicas: Vec<(String, u16)>,
+    pub replid: String,
+    pub offset: u64,
+}
+
+pub struct Replication {
+    inner: Mutex<Inner>,
+    /// Whether the server follows a master, checked by every write.
+    replica: AtomicBool,
+    /// Number of attached replicas, so propagation costs nothing without any.
+    replica_count: AtomicUsize,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        return Replication::new();
+    }
+}
+
+impl Replication {
+    pub fn new() -> Self {
+        return Replication {
+            inner: Mutex::new(Inner {
+                master: None,
+                link_state: LinkState::Connect,
+                link: None,
+                link_down_since: None,
+                last_io: Instant::now(),
+                replicas: Vec::new(),
+                replid: random_hex(40),
+                offset: 0,
+                selected_db: None,
+            }),
+            replica: AtomicBool::new(false),
+            replica_count: AtomicUsize::new(0),
+        };
+    }
+
+    pub fn is_replica(&self) -> bool {
+        return self.replica.load(Ordering::Relaxed);
+    }
+
+    pub fn master(&self) -> Option<MasterAddr> {
+        return self.inner.lock().unwrap().master.clone();
+    }
+
+    pub fn link_state(&self) -> LinkState {
+        return self.inner.lock().unwrap().link_state;
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        let inner = self.inner.lock().unwrap();
+        return ReplicationStatus {
+            master: inner.master.clone(),
+            link_state: inner.link_state,
+            link_down_secs: inner.link_down_since.map(|since| since.elapsed().as_secs()),
+            last_io_secs: inner.last_io.elapsed().as_secs(),
+            replicas: inner
+                .replicas
+                .iter()
+                .map(|r| (r.ip.clone(), r.port))
+                .collect(),
+            replid: inner.replid.clone(),
+            offset: inner.offset,
+        };
+    }
+
+    /// Attaches `client` as a replica listening on `port`, returning the
+    /// replication id and offset its copy of the dataset starts at.
+    pub fn add_replica(&self, client: &Arc<Client>, port: u16) -> (String, u64) {
+        client.update(|meta| meta.replica = true);
+        let ip: String = match client.addr.rsplit_once(':') {
+            Some((ip, _)) => ip.to_string(),
+            None => client.addr.clone(),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.replicas.retain(|r| r.client.id != client.id);
+        inner.replicas.push(Replica {
+            client: Arc::clone(client),
+            ip,
+            port,
+        });
+        // the new replica has not seen the previous SELECT
+        inner.selected_db = None;
+        self.replica_count
+            .store(inner.replicas.len(), Ordering::Relaxed);
+        return (inner.replid.clone(), inner.offset);
+    }
+
+    pub fn remove_replica(&self, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.replicas.retain(|r| r.client.id != id);
+        self.replica_count
+            .store(inner.replicas.len(), Ordering::Relaxed);
+    }
+
+    /// Whether every attached replica had everything propagated so far written
+    /// to its link. Without REPLCONF ACK that is as far as the master can tell.
+    pub fn replicas_in_sync(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        return inner
+            .replicas
+            .iter()
+            .all(|r| r.client.pending_messages() == 0);
+    }
+
+    /// Sends a write command that ran on database `db` to every replica.
+    pub fn propagate(&self, db: usize, args: &[Vec<u8>]) {
+        if self.replica_count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let mut commands: Vec<Vec<Vec<u8>>> = Vec::new();
+        if inner.selected_db != Some(db) {
+            commands.push(vec![b"SELECT".to_vec(), db.to_string().into_bytes()]);
+            inner.selected_db = Some(db);
+        }
+        commands.push(args.to_vec());
+
+        // a replica's offset follows its master's stream, not what it forwards
+        let master: bool = inner.master.is_none();
+        for command in commands {
+            if master {
+                let mut bytes: Vec<u8> = Vec::new();
+                command_frame(&command).encode(&mut bytes);
+                inner.offset += bytes.len() as u64;
+            }
+            for replica in inner.replicas.iter() {
+                replica.client.send(command_frame(&command));
+            }
+        }
+    }
+
+    fn set_link_state(&self, link_state: LinkState) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.link_state = link_state;
+        if link_state != LinkState::Connected && inner.link_down_since.is_none() {
+            inner.link_down_since = Some(Instant::now());
+        }
+    }
+
+    /// Records a completed full resynchronization.
+    fn synced(&self, replid: &str, offset: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.replid = replid.to_string();
+        inner.offset = offset;
+        inner.link_state = LinkState::Connected;
+        inner.link_down_since = None;
+        inner.last_io = Instant::now();
+    }
+
+    /// Accounts for `bytes` of the master's stream.
+    fn received(&self, bytes: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.offset += bytes as u64;
+        inner.last_io = Instant::now();
+    }
+}
+
+fn command_frame(args: &[Vec<u8>]) -> RedisType<'static> {
+    let items: Vec<RedisType<'static>> = args
+        .iter()
+        .map(|arg| RedisType::BulkBytes(arg.clone()))
+        .collect();
+    return RedisType::Array(Box::new(items));
+}
+
+/// Follows `master`, or stops replicating when it is `None`. Returns false
+/// when the server already followed that master.
+pub fn replicaof(state: &Arc<ServerState>, master: Option<MasterAddr>) -> bool {
+    let replication: &Replication = &state.replication;
+    let mut inner = replication.inner.lock().unwrap();
+    if inner.master == master {
+        return false;
+    }
+
+    if let Some(link) = inner.link.take() {
+        link.abort();
+    }
+    match &master {
+        Some(addr) => {
+            // the replicas of this server get the new dataset with a full resync
+            for replica in inner.replicas.drain(..) {
+                replica.client.kill();
+            }
+            replication.replica_count.store(0, Ordering::Relaxed);
+            inner.link_state = LinkState::Connect;
+            inner.link_down_since = Some(Instant::now());
+            inner.link = Some(tokio::spawn(follow(Arc::clone(state), addr.clone())));
+            println!("Connecting to MASTER {}", addr.addr());
+        }
+        None => {
+            // a new history starts here; the attached replicas keep a consistent copy
+            inner.replid = random_hex(40);
+            inner.link_state = LinkState::Connect;
+            inner.link_down_since = None;
+            println!("MASTER MODE enabled");
+        }
+    }
+    inner.master = master;
+    replication
+        .replica
+        .store(inner.master.is_some(), Ordering::Relaxed);
+    return true;
+}
+
+/// Keeps the link to `master` up until REPLICAOF changes it or the server shuts down.
+async fn follow(state: Arc<ServerState>, master: MasterAddr) {
+    loop {
+        state.replication.set_link_state(LinkState::Connecting);
+        let result: Result<(), Error> = tokio::select! {
+            r = sync_with_master(&state, &master) => r,
+            _ = state.shutdown.wait() => return,
+        };
+        if let Err(e) = result {
+            eprintln!("Lost the link with MASTER {}: {}", master.addr(), e.message);
+        }
+        state.replication.set_link_state(LinkState::Connect);
+
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+            _ = state.shutdown.wait() => return,
+        }
+    }
+}
+
+async fn request(stream: &mut Stream, cmd: Cmd) -> Result<RedisType<'static>, Error> {
+    let mut bytes: Vec<u8> = Vec::new();
+    cmd.encode(&mut bytes);
+    stream.write_all(&bytes).await?;
+    return match stream.read_reply().await? {
+        RedisType::Error(e) => Err(Error { message: e }),
+        reply => Ok(reply),
+    };
+}
+
+/// Parses the `FULLRESYNC <replid> <offset>` answer to PSYNC.
+fn parse_fullresync(reply: &RedisType) -> Option<(String, u64)> {
+    let line: &str = match reply {
+        RedisType::Status(line) => line,
+        _ => return None,
+    };
+    let mut words = line.split(' ');
+    if words.next() != Some("FULLRESYNC") {
+        return None;
+    }
+    let replid: &str = words.next()?;
+    let offset: u64 = words.next()?.parse().ok()?;
+    return Some((replid.to_string(), offset));
+}
+
+/// Performs the handshake and the full resynchronization, then applies the
+/// command stream until the connection breaks.
+async fn sync_with_master(state: &Arc<ServerState>, master: &MasterAddr) -> Result<(), Error> {
+    let mut stream: Stream = Stream::connect(&ClientOptions::new(&master.addr())).await?;
+    let port: u16 = state.config.read().unwrap().port;
+    request(&mut stream, Cmd::new("PING")).await?;
+    request(
+        &mut stream,
+        Cmd::new("REPLCONF")
+            .arg("listening-port")
+            .arg(&port.to_string()),
+    )
+    .await?;
+
+    state.replication.set_link_state(LinkState::Sync);
+    let reply: RedisType = request(&mut stream, Cmd::new("PSYNC").arg("?").arg("-1")).await?;
+    let (replid, offset) = match parse_fullresync(&reply) {
+        Some(r) => r,
+        None => return Err(Error::new("Unexpected reply to PSYNC")),
+    };
+    let payload: Vec<u8> = match stream.read_reply().await? {
+        RedisType::BulkBytes(payload) => payload,
+        _ => return Err(Error::new("Unexpected RDB payload from the master")),
+    };
+
+    {
+        let mut shards: Shards = state.keyspace.lock_all().await;
+        shards.set_recording(state.records_changes());
+        let old: Vec<Database> = shards
+            .parts()
+            .into_iter()
+            .flat_map(|dbs| dbs.iter_mut())
+            .map(|db| db.take())
+            .collect();
+        rdb::decode(&payload, &mut shards.parts(), &state.modules)?;
+        state.signal_changes(&mut shards, 0);
+        state.lazyfree.free_databases(old);
+    }
+    state.replication.synced(&replid, offset);
+    println!("MASTER <-> REPLICA sync: Finished with success");
+
+    let mut session: Session = Session::new();
+    session.master_link = true;
+    loop {
+        let (frame, size) = stream.read_frame().await?;
+        state.replication.received(size);
+        let args: Vec<Vec<u8>> = match frame {
+            RedisType::Array(items) => items
+                .into_iter()
+                .filter_map(|item| match item {
+                    RedisType::BulkBytes(bytes) => Some(bytes),
+                    _ => None,
+                })
+                .collect(),
+            _ => continue,
+        };
+        if args.is_empty() {
+            continue;
+        }
+        // the master already ran the command, so an error only means this copy drifted
+        if let Err(e) = execute(&args, state, &mut session).await {
+            eprintln!("Error applying a command from the master: {}", e.message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn propagate_test() {
+        let replication: Replication = Replication::new();
+        // nothing is accounted while there is nobody to send to
+        replication.propagate(0, &[b"SET".to_vec(), b"k".to_vec(), b"v".to_vec()]);
+        assert_eq!(replication.status().offset, 0);
+
+        let client: Arc<Client> = Arc::new(Client::new(7, "10.0.0.2:51000", "", -1));
+        let mut inbox = client.take_inbox().unwrap();
+        let (_, offset) = replication.add_replica(&client, 6380);
+        assert_eq!(offset, 0);
+        assert!(client.meta().replica);
+        assert_eq!(
+            replication.status().replicas,
+            vec![(String::from("10.0.0.2"), 6380)]
+        );
+
+        replication.propagate(0, &[b"SET".to_vec(), b"k".to_vec(), b"v".to_vec()]);
+        replication.propagate(0, &[b"DEL".to_vec(), b"k".to_vec()]);
+        replication.propagate(3, &[b"DEL".to_vec(), b"k".to_vec()]);
+        let mut stream: Vec<u8> = Vec::new();
+        while let Ok(frame) = inbox.try_recv() {
+            frame.encode(&mut stream);
+        }
+        assert_eq!(
+            String::from_utf8(stream.clone()).unwrap(),
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n\
+             *3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n\
+             *2\r\n$3\r\nDEL\r\n$1\r\nk\r\n\
+             *2\r\n$6\r\nSELECT\r\n$1\r\n3\r\n\
+             *2\r\n$3\r\nDEL\r\n$1\r\nk\r\n"
+        );
+        assert_eq!(replication.status().offset, stream.len() as u64);
+
+        replication.remove_replica(7);
+        assert!(replication.status().replicas.is_empty());
+    }
+
+    #[test]
+    fn parse_fullresync_test() {
+        let reply: RedisType = RedisType::Status(String::from("FULLRESYNC abc 42"));
+        assert_eq!(parse_fullresync(&reply), Some((String::from("abc"), 42)));
+        assert_eq!(
+            parse_fullresync(&RedisType::Status(String::from("CONTINUE"))),
+            None
+        );
+        assert_eq!(
+            parse_fullresync(&RedisType::SimpleString("FULLRESYNC abc 1")),
+            None
+        );
+    }
+}
@@ -0,0 +1,621 @@
+//! What a sentinel knows about the servers it monitors and about the other
+//! sentinels, along with the decisions taken from that knowledge alone:
+//! parsing INFO and hello messages, voting for a leader and picking the
+//! replica to promote.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::random::random_below;
+use crate::replication::MasterAddr;
+use crate::sentinel::{event, MasterConfig};
+
+/// How far apart sentinels try to start failovers for the same master, so they
+/// do not all split the vote by asking for it at the same time.
+pub const MAX_DESYNC: Duration = Duration::from_millis(1000);
+
+/// What an instance reported in its last INFO.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Info {
+    pub run_id: String,
+    pub master: bool,
+    /// The master a replica follows.
+    pub master_addr: Option<MasterAddr>,
+    pub link_up: bool,
+    pub priority: u64,
+    pub offset: u64,
+    /// The replicas a master reports.
+    pub replicas: Vec<MasterAddr>,
+}
+
+impl Info {
+    /// Reads the fields of interest from an INFO reply.
+    pub fn parse(text: &str) -> Info {
+        let fields: HashMap<&str, &str> = text
+            .lines()
+            .filter_map(|line| line.trim_end().split_once(':'))
+            .collect();
+        let number = |name: &str| -> u64 {
+            return fields.get(name).and_then(|v| v.parse().ok()).unwrap_or(0);
+        };
+
+        let mut info: Info = Info {
+            run_id: fields.get("run_id").unwrap_or(&"").to_string(),
+            master: fields.get("role") == Some(&"master"),
+            master_addr: None,
+            link_up: fields.get("master_link_status") == Some(&"up"),
+            priority: number("slave_priority"),
+            offset: number("slave_repl_offset"),
+            replicas: Vec::new(),
+        };
+        if let (Some(host), Some(port)) = (fields.get("master_host"), fields.get("master_port")) {
+            if let Ok(port) = port.parse::<u16>() {
+                info.master_addr = Some(MasterAddr::new(host, port));
+            }
+        }
+
+        // slave0:ip=127.0.0.1,port=6380,state=online,offset=0,lag=0
+        let mut index: usize = 0;
+        while let Some(line) = fields.get(format!("slave{}", index).as_str()) {
+            let attributes: HashMap<&str, &str> = line
+                .split(',')
+                .filter_map(|attribute| attribute.split_once('='))
+                .collect();
+            if let (Some(ip), Some(port)) = (attributes.get("ip"), attributes.get("port")) {
+                if let Ok(port) = port.parse::<u16>() {
+                    info.replicas.push(MasterAddr::new(ip, port));
+                }
+            }
+            index += 1;
+        }
+        return info;
+    }
+}
+
+/// A master or replica under watch.
+#[derive(Debug)]
+pub struct Instance {
+    pub addr: MasterAddr,
+    /// Sending time of the oldest PING still unanswered; the instance is
+    /// subjectively down once it is older than `down-after-milliseconds`.
+    pub ping_pending_since: Option<Instant>,
+    pub last_ping: Option<Instant>,
+    pub ping_in_flight: bool,
+    pub last_ok: Option<Instant>,
+    pub info: Option<Info>,
+    pub info_at: Option<Instant>,
+    pub info_in_flight: bool,
+    pub last_hello: Option<Instant>,
+    pub sdown: bool,
+    /// Since when the instance reports a role that disagrees with this sentinel's view.
+    pub misconfigured_since: Option<Instant>,
+    pub last_reconf: Option<Instant>,
+}
+
+impl Instance {
+    pub fn new(addr: MasterAddr) -> Self {
+        return Instance {
+            addr,
+            ping_pending_since: None,
+            last_ping: None,
+            ping_in_flight: false,
+            last_ok: None,
+            info: None,
+            info_at: None,
+            info_in_flight: false,
+            last_hello: None,
+            sdown: false,
+            misconfigured_since: None,
+            last_reconf: None,
+        };
+    }
+
+    pub fn run_id(&self) -> &str {
+        return match &self.info {
+            Some(info) => &info.run_id,
+            None => "",
+        };
+    }
+
+    /// `ip:port`, the name Sentinel gives replicas.
+    pub fn name(&self) -> String {
+        return self.addr.addr();
+    }
+
+    /// Whether INFO arrived after `since`.
+    pub fn info_since(&self, since: Instant) -> Option<&Info> {
+        return match self.info_at {
+            Some(at) if at >= since => self.info.as_ref(),
+            _ => None,
+        };
+    }
+}
+
+/// Another sentinel monitoring the same master, known from its hello messages.
+#[derive(Debug)]
+pub struct Peer {
+    pub addr: MasterAddr,
+    pub run_id: String,
+    pub last_hello: Instant,
+    /// Its last answer to IS-MASTER-DOWN-BY-ADDR.
+    pub master_down: bool,
+    pub reply_at: Option<Instant>,
+    pub last_ask: Option<Instant>,
+    pub ask_in_flight: bool,
+    /// The leader it voted for and in which epoch.
+    pub leader: Option<String>,
+    pub leader_epoch: u64,
+}
+
+impl Peer {
+    pub fn new(addr: MasterAddr, run_id: &str) -> Self {
+        return Peer {
+            addr,
+            run_id: run_id.to_string(),
+            last_hello: Instant::now(),
+            master_down: false,
+            reply_at: None,
+            last_ask: None,
+            ask_in_flight: false,
+            leader: None,
+            leader_epoch: 0,
+        };
+    }
+}
+
+/// The steps of a failover, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailoverState {
+    /// Collecting the votes that make this sentinel the leader.
+    WaitStart,
+    SelectReplica,
+    /// REPLICAOF NO ONE must be sent to the chosen replica.
+    SendReplicaofNoOne,
+    /// Waiting for the chosen replica to report the master role.
+    WaitPromotion,
+    /// Pointing the other replicas to the new master.
+    ReconfReplicas,
+}
+
+impl FailoverState {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            FailoverState::WaitStart => "wait_start",
+            FailoverState::SelectReplica => "select_slave",
+            FailoverState::SendReplicaofNoOne => "send_slaveof_noone",
+            FailoverState::WaitPromotion => "wait_promotion",
+            FailoverState::ReconfReplicas => "reconf_slaves",
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct Failover {
+    pub epoch: u64,
+    pub state: FailoverState,
+    pub started: Instant,
+    pub state_changed: Instant,
+    pub promoted: Option<MasterAddr>,
+    /// Replicas already told to follow the promoted one.
+    pub reconf_sent: BTreeSet<String>,
+    /// Started with SENTINEL FAILOVER, without waiting for the master to fail or for votes.
+    pub forced: bool,
+}
+
+impl Failover {
+    pub fn new(epoch: u64, state: FailoverState, forced: bool) -> Self {
+        let now: Instant = Instant::now();
+        return Failover {
+            epoch,
+            state,
+            started: now,
+            state_changed: now,
+            promoted: None,
+            reconf_sent: BTreeSet::new(),
+            forced,
+        };
+    }
+
+    pub fn set_state(&mut self, state: FailoverState) {
+        self.state = state;
+        self.state_changed = Instant::now();
+    }
+}
+
+/// A master given with `--monitor`, with its replicas and the other sentinels watching it.
+#[derive(Debug)]
+pub struct Monitored {
+    pub name: String,
+    pub quorum: usize,
+    pub down_after: Duration,
+    pub failover_timeout: Duration,
+    /// Epoch of the failover that produced the current master address; a
+    /// sentinel announcing a greater one knows of a newer configuration.
+    pub config_epoch: u64,
+    pub master: Instance,
+    /// Keyed by `ip:port`.
+    pub replicas: BTreeMap<String, Instance>,
+    /// Keyed by run id.
+    pub peers: BTreeMap<String, Peer>,
+    pub odown: bool,
+    /// The sentinel this one voted for, and in which epoch.
+    pub leader: Option<String>,
+    pub leader_epoch: u64,
+    pub failover: Option<Failover>,
+    /// No failover is attempted before this time, so failed attempts are
+    /// retried every two failover timeouts and sentinels do not compete.
+    pub next_attempt: Instant,
+}
+
+impl Monitored {
+    pub fn new(config: &MasterConfig) -> Self {
+        return Monitored {
+            name: config.name.clone(),
+            quorum: config.quorum,
+            down_after: config.down_after,
+            failover_timeout: config.failover_timeout,
+            config_epoch: 0,
+            master: Instance::new(config.addr.clone()),
+            replicas: BTreeMap::new(),
+            peers: BTreeMap::new(),
+            odown: false,
+            leader: None,
+            leader_epoch: 0,
+            failover: None,
+            next_attempt: Instant::now(),
+        };
+    }
+
+    /// `master <name> <ip> <port>`, as events describe the master.
+    pub fn describe(&self) -> String {
+        return format!(
+            "master {} {} {}",
+            self.name, self.master.addr.host, self.master.addr.port
+        );
+    }
+
+    /// `slave <ip:port> <ip> <port> @ <name> <master ip> <master port>`.
+    pub fn describe_replica(&self, addr: &MasterAddr) -> String {
+        return format!(
+            "slave {} {} {} @ {} {} {}",
+            addr.addr(),
+            addr.host,
+            addr.port,
+            self.name,
+            self.master.addr.host,
+            self.master.addr.port
+        );
+    }
+
+    pub fn instance_mut(&mut self, addr: &MasterAddr) -> Option<&mut Instance> {
+        if self.master.addr == *addr {
+            return Some(&mut self.master);
+        }
+        return self.replicas.get_mut(&addr.addr());
+    }
+
+    pub fn add_replica(&mut self, addr: &MasterAddr) {
+        if *addr == self.master.addr || self.replicas.contains_key(&addr.addr()) {
+            return;
+        }
+        event("+slave", &self.describe_replica(addr));
+        self.replicas
+            .insert(addr.addr(), Instance::new(addr.clone()));
+    }
+
+    /// Handles a vote request of `run_id` for `epoch`. Every sentinel votes
+    /// for the first sentinel asking in a given epoch, itself included.
+    pub fn vote(&mut self, current_epoch: &mut u64, epoch: u64, run_id: &str, myid: &str) {
+        if epoch > *current_epoch {
+            *current_epoch = epoch;
+            event("+new-epoch", &epoch.to_string());
+        }
+        if self.leader_epoch < epoch && *current_epoch <= epoch {
+            self.leader = Some(run_id.to_string());
+            self.leader_epoch = *current_epoch;
+            event("+vote-for-leader", &format!("{} {}", run_id, epoch));
+            // leave the failover to the sentinel voted for
+            if run_id != myid {
+                let deadline: Instant = Instant::now()
+                    + 2 * self.failover_timeout
+                    + MAX_DESYNC.mul_f64(random_below(1000) as f64 / 1000.0);
+                self.next_attempt = self.next_attempt.max(deadline);
+            }
+        }
+    }
+
+    /// The sentinel elected for `epoch`, once one has the votes of a majority
+    /// of the known sentinels and at least `quorum` of them.
+    pub fn leader(&self, epoch: u64) -> Option<String> {
+        let mut votes: BTreeMap<&str, usize> = BTreeMap::new();
+        if self.leader_epoch == epoch {
+            if let Some(leader) = &self.leader {
+                *votes.entry(leader).or_insert(0) += 1;
+            }
+        }
+        for peer in self.peers.values() {
+            if peer.leader_epoch == epoch {
+                if let Some(leader) = &peer.leader {
+                    *votes.entry(leader).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let voters: usize = self.peers.len() + 1;
+        let needed: usize = self.quorum.max(voters / 2 + 1);
+        let (winner, count) = votes.into_iter().max_by_key(|(_, count)| *count)?;
+        if count >= needed {
+            return Some(winner.to_string());
+        }
+        return None;
+    }
+
+    /// The replica to promote: reachable, recently heard from and not
+    /// excluded with priority 0, preferring the lowest priority, then the
+    /// largest replication offset, then the smallest run id.
+    pub fn select_replica(&self, info_validity: Duration) -> Option<MasterAddr> {
+        let mut candidates: Vec<(&Instance, &Info)> = self
+            .replicas
+            .values()
+            .filter(|replica| !replica.sdown)
+            .filter_map(|replica| match (&replica.info, replica.info_at) {
+                (Some(info), Some(at)) if at.elapsed() <= info_validity => Some((replica, info)),
+                _ => None,
+            })
+            .filter(|(_, info)| info.priority != 0)
+            .collect();
+        candidates.sort_by(|(_, a), (_, b)| {
+            return a
+                .priority
+                .cmp(&b.priority)
+                .then(b.offset.cmp(&a.offset))
+                .then(a.run_id.cmp(&b.run_id));
+        });
+        return candidates.first().map(|(replica, _)| replica.addr.clone());
+    }
+
+    /// Makes `addr` the master after a failover, every other instance becoming one of its replicas.
+    pub fn switch_master(&mut self, addr: &MasterAddr) {
+        event(
+            "+switch-master",
+            &format!(
+                "{} {} {} {} {}",
+                self.name, self.master.addr.host, self.master.addr.port, addr.host, addr.port
+            ),
+        );
+        let mut replicas: Vec<MasterAddr> = self
+            .replicas
+            .values()
+            .map(|replica| replica.addr.clone())
+            .filter(|replica| replica != addr)
+            .collect();
+        if self.master.addr != *addr {
+            replicas.push(self.master.addr.clone());
+        }
+
+        self.master = Instance::new(addr.clone());
+        self.replicas = replicas
+            .into_iter()
+            .map(|replica| (replica.addr(), Instance::new(replica)))
+            .collect();
+        self.odown = false;
+        self.failover = None;
+    }
+}
+
+/// The message sentinels publish to `__sentinel__:hello` on every monitored
+/// instance, to discover each other and spread the latest configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub addr: MasterAddr,
+    pub run_id: String,
+    pub current_epoch: u64,
+    pub master_name: String,
+    pub master_addr: MasterAddr,
+    pub master_config_epoch: u64,
+}
+
+impl Hello {
+    /// `ip,port,runid,current_epoch,master_name,master_ip,master_port,master_config_epoch`
+    pub fn parse(text: &str) -> Option<Hello> {
+        let parts: Vec<&str> = text.split(',').collect();
+        if parts.len() != 8 {
+            return None;
+        }
+        return Some(Hello {
+            addr: MasterAddr::new(parts[0], parts[1].parse().ok()?),
+            run_id: parts[2].to_string(),
+            current_epoch: parts[3].parse().ok()?,
+            master_name: parts[4].to_string(),
+            master_addr: MasterAddr::new(parts[5], parts[6].parse().ok()?),
+            master_config_epoch: parts[7].parse().ok()?,
+        });
+    }
+
+    pub fn encode(&self) -> String {
+        return format!(
+            "{},{},{},{},{},{},{},{}",
+            self.addr.host,
+            self.addr.port,
+            self.run_id,
+            self.current_epoch,
+            self.master_name,
+            self.master_addr.host,
+            self.master_addr.port,
+            self.master_config_epoch
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitored(quorum: usize) -> Monitored {
+        return Monitored::new(&MasterConfig {
+            name: String::from("mymaster"),
+            addr: MasterAddr::new("127.0.0.1", 6379),
+            quorum,
+            down_after: Duration::from_millis(30000),
+            failover_timeout: Duration::from_millis(180000),
+        });
+    }
+
+    fn replica(port: u16, priority: u64, offset: u64, run_id: &str) -> Instance {
+        let mut replica: Instance = Instance::new(MasterAddr::new("127.0.0.1", port));
+        replica.info = Some(Info {
+            run_id: run_id.to_string(),
+            priority,
+            offset,
+            ..Info::default()
+        });
+        replica.info_at = Some(Instant::now());
+        return replica;
+    }
+
+    #[test]
+    fn hello_test() {
+        let text: &str = "127.0.0.1,26379,abc,3,mymaster,127.0.0.1,6380,2";
+        let hello: Hello = Hello::parse(text).unwrap();
+        assert_eq!(hello.addr, MasterAddr::new("127.0.0.1", 26379));
+        assert_eq!(hello.run_id, "abc");
+        assert_eq!(hello.current_epoch, 3);
+        assert_eq!(hello.master_name, "mymaster");
+        assert_eq!(hello.master_addr, MasterAddr::new("127.0.0.1", 6380));
+        assert_eq!(hello.master_config_epoch, 2);
+        assert_eq!(hello.encode(), text);
+
+        assert_eq!(Hello::parse("127.0.0.1,26379,abc"), None);
+        assert_eq!(
+            Hello::parse("127.0.0.1,port,abc,3,mymaster,127.0.0.1,6380,2"),
+            None
+        );
+    }
+
+    #[test]
+    fn info_test() {
+        let master: Info = Info::parse(
+            "# Server\r\nrun_id:r1\r\n\r\n# Replication\r\nrole:master\r\nconnected_slaves:2\r\n\
+             slave0:ip=127.0.0.1,port=6380,state=online,offset=10,lag=0\r\n\
+             slave1:ip=127.0.0.1,port=6381,state=online,offset=10,lag=0\r\n",
+        );
+        assert!(master.master);
+        assert_eq!(master.run_id, "r1");
+        assert_eq!(
+            master.replicas,
+            vec![
+                MasterAddr::new("127.0.0.1", 6380),
+                MasterAddr::new("127.0.0.1", 6381)
+            ]
+        );
+
+        let replica: Info = Info::parse(
+            "role:slave\r\nmaster_host:127.0.0.1\r\nmaster_port:6379\r\n\
+             master_link_status:up\r\nslave_repl_offset:42\r\nslave_priority:100\r\n",
+        );
+        assert!(!replica.master);
+        assert_eq!(
+            replica.master_addr,
+            Some(MasterAddr::new("127.0.0.1", 6379))
+        );
+        assert!(replica.link_up);
+        assert_eq!(replica.offset, 42);
+        assert_eq!(replica.priority, 100);
+    }
+
+    #[test]
+    fn select_replica_test() {
+        let mut master: Monitored = monitored(2);
+        let validity: Duration = Duration::from_secs(5);
+        assert_eq!(master.select_replica(validity), None);
+
+        for replica in [
+            replica(6380, 100, 10, "b"),
+            replica(6381, 100, 20, "c"),
+            replica(6382, 100, 20, "a"),
+            replica(6383, 0, 90, "d"),
+        ] {
+            master.replicas.insert(replica.name(), replica);
+        }
+        // the largest offset wins, the run id breaks the tie
+        assert_eq!(
+            master.select_replica(validity),
+            Some(MasterAddr::new("127.0.0.1", 6382))
+        );
+
+        // a lower priority comes first, a down replica or priority 0 never
+        master
+            .replicas
+            .get_mut("127.0.0.1:6380")
+            .unwrap()
+            .info
+            .as_mut()
+            .unwrap()
+            .priority = 10;
+        assert_eq!(
+            master.select_replica(validity),
+            Some(MasterAddr::new("127.0.0.1", 6380))
+        );
+        master.replicas.get_mut("127.0.0.1:6380").unwrap().sdown = true;
+        master.replicas.get_mut("127.0.0.1:6382").unwrap().info_at = None;
+        assert_eq!(
+            master.select_replica(validity),
+            Some(MasterAddr::new("127.0.0.1", 6381))
+        );
+    }
+
+    #[test]
+    fn vote_test() {
+        let mut master: Monitored = monitored(2);
+        for id in ["p1", "p2"] {
+            master.peers.insert(
+                id.to_string(),
+                Peer::new(MasterAddr::new("127.0.0.1", 26380), id),
+            );
+        }
+        let mut current_epoch: u64 = 0;
+
+        // the first request of an epoch gets the vote, later ones see it
+        master.vote(&mut current_epoch, 1, "p1", "me");
+        master.vote(&mut current_epoch, 1, "me", "me");
+        assert_eq!(current_epoch, 1);
+        assert_eq!(master.leader.as_deref(), Some("p1"));
+        assert!(master.next_attempt > Instant::now() + master.failover_timeout);
+
+        // one vote of three is not a majority
+        assert_eq!(master.leader(1), None);
+        master.peers.get_mut("p1").unwrap().leader = Some(String::from("p1"));
+        master.peers.get_mut("p1").unwrap().leader_epoch = 1;
+        assert_eq!(master.leader(1).as_deref(), Some("p1"));
+        // votes of older epochs do not count
+        assert_eq!(master.leader(2), None);
+
+        // a new epoch gets a new vote
+        master.vote(&mut current_epoch, 2, "me", "me");
+        assert_eq!(master.leader.as_deref(), Some("me"));
+        assert_eq!(master.leader_epoch, 2);
+        master.peers.get_mut("p2").unwrap().leader = Some(String::from("me"));
+        master.peers.get_mut("p2").unwrap().leader_epoch = 2;
+        assert_eq!(master.leader(2).as_deref(), Some("me"));
+
+        // with a quorum of 3 a majority is not enough
+        master.quorum = 3;
+        assert_eq!(master.leader(2), None);
+    }
+
+    #[test]
+    fn switch_master_test() {
+        let mut master: Monitored = monitored(2);
+        master.add_replica(&MasterAddr::new("127.0.0.1", 6380));
+        master.add_replica(&MasterAddr::new("127.0.0.1", 6381));
+        master.add_replica(&MasterAddr::new("127.0.0.1", 6379));
+        assert_eq!(master.replicas.len(), 2);
+
+        master.switch_master(&MasterAddr::new("127.0.0.1", 6380));
+        assert_eq!(master.master.addr, MasterAddr::new("127.0.0.1", 6380));
+        assert_eq!(
+            master.replicas.keys().collect::<Vec<&String>>(),
+            vec!["127.0.0.1:6379", "127.0.0.1:6381"]
+        );
+    }
+}
@@ -0,0 +1,767 @@
+//! Sentinel mode: high availability for a master and its replicas.
+//!
+//! `redis-rust --sentinel --monitor "mymaster 127.0.0.1 6379 2"` runs no
+//! dataset of its own. It pings the master and its replicas (found in the
+//! master's INFO) and marks an instance subjectively down (SDOWN) when it
+//! did not answer for `down-after-milliseconds`. The sentinels watching the
+//! same master find each other through hello messages published on the
+//! monitored instances, and ask each other with SENTINEL
+//! IS-MASTER-DOWN-BY-ADDR whether they see it down too; with `quorum` of
+//! them agreeing the master is objectively down (ODOWN).
+//!
+//! A failover then needs a leader: the sentinel starting it bumps the
+//! epoch and asks the others for their vote, and each votes for the first
+//! sentinel asking in an epoch. With the votes of a majority (and at least
+//! `quorum`) the leader promotes the best replica with REPLICAOF NO ONE,
+//! points the other replicas to it and announces the new configuration,
+//! tagged with the epoch, in its hello messages. The old master is turned
+//! into a replica when it comes back.
+//!
+//! Clients find the current master with SENTINEL GET-MASTER-ADDR-BY-NAME.
+//! Unlike Redis, the state is not written to a configuration file: a
+//! restarted sentinel learns the current configuration from the hellos of
+//! the others.
+
+mod instance;
+mod monitor;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::connection::{Batch, Connection};
+use crate::random::random_hex;
+use crate::redis_parser::RedisType;
+use crate::replication::MasterAddr;
+use crate::server::bind_tcp;
+use crate::Error;
+
+use instance::{Failover, Instance, Peer};
+pub use instance::{FailoverState, Hello, Info, Monitored};
+
+/// The channel hello messages are published on.
+pub const HELLO_CHANNEL: &str = "__sentinel__:hello";
+
+/// Limits on what a client of the sentinel may send.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+const MAX_QUERY_BUFFER: usize = 1024 * 1024 * 1024;
+
+/// A master to monitor, given with `--monitor`.
+#[derive(Clone, Debug)]
+pub struct MasterConfig {
+    pub name: String,
+    pub addr: MasterAddr,
+    pub quorum: usize,
+    pub down_after: Duration,
+    pub failover_timeout: Duration,
+}
+
+/// Sentinel configuration, from the `--name value` pairs after `--sentinel`.
+#[derive(Clone, Debug)]
+pub struct SentinelConfig {
+    pub port: u16,
+    /// Address listened on, and announced to the other sentinels.
+    pub bind: String,
+    pub masters: Vec<MasterConfig>,
+}
+
+impl Default for SentinelConfig {
+    fn default() -> Self {
+        return SentinelConfig {
+            port: 26379,
+            bind: String::from("127.0.0.1"),
+            masters: Vec::new(),
+        };
+    }
+}
+
+impl SentinelConfig {
+    /// Accepts `--port`, `--bind`, `--monitor "<name> <ip> <port> <quorum>"`
+    /// and the per-master `--down-after-milliseconds "<name> <ms>"` and
+    /// `--failover-timeout "<name> <ms>"`, which follow the `--monitor` of
+    /// their master.
+    pub fn from_args(args: &[String]) -> Result<Self, Error> {
+        let mut config: SentinelConfig = SentinelConfig::default();
+
+        for pair in args.chunks(2) {
+            let name: &str = match pair[0].strip_prefix("--") {
+                Some(n) => n,
+                None => {
+                    return Err(Error {
+                        message: format!("Unexpected argument '{}'", pair[0]),
+                    })
+                }
+            };
+            let value: &str = match pair.get(1) {
+                Some(v) => v,
+                None => {
+                    return Err(Error {
+                        message: format!("Missing value for '--{}'", name),
+                    })
+                }
+            };
+            let words: Vec<&str> = value.split_whitespace().collect();
+
+            match name.to_lowercase().as_str() {
+                "port" => config.port = parse_number(name, value)?,
+                "bind" => config.bind = value.to_string(),
+                "monitor" => {
+                    if words.len() != 4 {
+                        return Err(Error::new("--monitor takes '<name> <ip> <port> <quorum>'"));
+                    }
+                    let quorum: usize = parse_number(name, words[3])?;
+                    if quorum == 0 {
+                        return Err(Error::new("Quorum must be 1 or greater."));
+                    }
+                    config.masters.push(MasterConfig {
+                        name: words[0].to_string(),
+                        addr: MasterAddr::new(words[1], parse_number(name, words[2])?),
+                        quorum,
+                        down_after: Duration::from_millis(30000),
+                        failover_timeout: Duration::from_millis(180000),
+                    });
+                }
+                "down-after-milliseconds" | "failover-timeout" => {
+                    if words.len() != 2 {
+                        return Err(Error {
+                            message: format!("--{} takes '<name> <milliseconds>'", name),
+                        });
+                    }
+                    let ms: u64 = parse_number(name, words[1])?;
+                    let master: &mut MasterConfig =
+                        match config.masters.iter_mut().find(|m| m.name == words[0]) {
+                            Some(m) => m,
+                            None => return Err(no_such_master()),
+                        };
+                    match name {
+                        "down-after-milliseconds" => master.down_after = Duration::from_millis(ms),
+                        _ => master.failover_timeout = Duration::from_millis(ms),
+                    }
+                }
+                _ => {
+                    return Err(Error {
+                        message: format!("Unknown sentinel option '--{}'", name),
+                    })
+                }
+            }
+        }
+
+        if config.masters.is_empty() {
+            return Err(Error::new("Sentinel needs at least one --monitor"));
+        }
+        return Ok(config);
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    return value.parse::<T>().map_err(|_| Error {
+        message: format!("Invalid value '{}' for '--{}'", value, name),
+    });
+}
+
+fn no_such_master() -> Error {
+    return Error::new("ERR No such master with that name");
+}
+
+/// Logs a Sentinel event, as `<type> <description>` like Redis does.
+pub(crate) fn event(kind: &str, description: &str) {
+    println!("{} {}", kind, description);
+}
+
+/// The epoch and the masters, behind one lock.
+pub struct SentinelState {
+    /// The greatest epoch seen, bumped by every failover attempt.
+    pub current_epoch: u64,
+    pub masters: BTreeMap<String, Monitored>,
+}
+
+/// A running sentinel.
+pub struct Sentinel {
+    pub myid: String,
+    /// The address announced to the other sentinels.
+    pub addr: MasterAddr,
+    pub state: Mutex<SentinelState>,
+    /// The task reading hello messages from each monitored instance, keyed by `ip:port`.
+    hello_links: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl Sentinel {
+    pub fn new(config: &SentinelConfig) -> Self {
+        let masters: BTreeMap<String, Monitored> = config
+            .masters
+            .iter()
+            .map(|m| (m.name.clone(), Monitored::new(m)))
+            .collect();
+        return Sentinel {
+            myid: random_hex(40),
+            addr: MasterAddr::new(&config.bind, config.port),
+            state: Mutex::new(SentinelState {
+                current_epoch: 0,
+                masters,
+            }),
+            hello_links: Mutex::new(HashMap::new()),
+        };
+    }
+
+    /// Takes in a hello message read from a monitored instance.
+    pub fn process_hello(&self, text: &str) {
+        let hello: Hello = match Hello::parse(text) {
+            Some(h) => h,
+            None => return,
+        };
+        if hello.run_id == self.myid {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if hello.current_epoch > state.current_epoch {
+            state.current_epoch = hello.current_epoch;
+            event("+new-epoch", &hello.current_epoch.to_string());
+        }
+        let master: &mut Monitored = match state.masters.get_mut(&hello.master_name) {
+            Some(m) => m,
+            None => return,
+        };
+
+        // a sentinel restarted at the same address comes back with a new run id
+        master
+            .peers
+            .retain(|id, peer| *id == hello.run_id || peer.addr != hello.addr);
+        if !master.peers.contains_key(&hello.run_id) {
+            event(
+                "+sentinel",
+                &format!(
+                    "sentinel {} {} {} @ {} {} {}",
+                    hello.run_id,
+                    hello.addr.host,
+                    hello.addr.port,
+                    master.name,
+                    master.master.addr.host,
+                    master.master.addr.port
+                ),
+            );
+            master.peers.insert(
+                hello.run_id.clone(),
+                Peer::new(hello.addr.clone(), &hello.run_id),
+            );
+        }
+        let peer: &mut Peer = master.peers.get_mut(&hello.run_id).unwrap();
+        peer.addr = hello.addr.clone();
+        peer.last_hello = Instant::now();
+
+        // another sentinel completed a failover this one does not know about yet
+        if hello.master_config_epoch > master.config_epoch {
+            master.config_epoch = hello.master_config_epoch;
+            if hello.master_addr != master.master.addr {
+                master.switch_master(&hello.master_addr);
+            }
+        }
+    }
+
+    /// SENTINEL IS-MASTER-DOWN-BY-ADDR: whether this sentinel sees the master
+    /// at `addr` down and, when `run_id` is not `*`, its vote for that epoch.
+    pub fn is_master_down_by_addr(
+        &self,
+        addr: &MasterAddr,
+        epoch: u64,
+        run_id: &str,
+    ) -> (bool, String, u64) {
+        let mut guard = self.state.lock().unwrap();
+        let state: &mut SentinelState = &mut guard;
+        let master: &mut Monitored =
+            match state.masters.values_mut().find(|m| m.master.addr == *addr) {
+                Some(m) => m,
+                None => return (false, String::from("*"), 0),
+            };
+
+        let down: bool = master.master.sdown;
+        if run_id == "*" {
+            return (down, String::from("*"), 0);
+        }
+        master.vote(&mut state.current_epoch, epoch, run_id, &self.myid);
+        let leader: String = master.leader.clone().unwrap_or_else(|| String::from("*"));
+        return (down, leader, master.leader_epoch);
+    }
+
+    /// Answers one command of a client.
+    pub fn command(&self, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+        let name: String = String::from_utf8_lossy(&args[0]).to_lowercase();
+        return match name.as_str() {
+            "ping" => Ok(RedisType::SimpleString("PONG")),
+            "info" => Ok(RedisType::BulkString(self.info())),
+            "role" => {
+                let names: Vec<RedisType> = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .masters
+                    .keys()
+                    .map(|name| RedisType::BulkString(name.clone()))
+                    .collect();
+                Ok(RedisType::Array(Box::new(vec![
+                    RedisType::BulkString(String::from("sentinel")),
+                    RedisType::Array(Box::new(names)),
+                ])))
+            }
+            "sentinel" if args.len() >= 2 => self.sentinel_command(args),
+            _ => Err(Error {
+                message: format!(
+                    "ERR unknown command '{}', with args beginning with: {}",
+                    name,
+                    args[1..]
+                        .iter()
+                        .map(|arg| format!("'{}' ", String::from_utf8_lossy(arg)))
+                        .collect::<String>()
+                ),
+            }),
+        };
+    }
+
+    fn sentinel_command(&self, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+        let subcommand: String = String::from_utf8_lossy(&args[1]).to_uppercase();
+        let arity = |n: usize| -> Result<(), Error> {
+            if args.len() != n {
+                return Err(Error {
+                    message: format!(
+                        "ERR wrong number of arguments for 'sentinel|{}' command",
+                        subcommand.to_lowercase()
+                    ),
+                });
+            }
+            return Ok(());
+        };
+        let master_name = || -> String { return String::from_utf8_lossy(&args[2]).to_string() };
+
+        match subcommand.as_str() {
+            "MYID" => {
+                arity(2)?;
+                return Ok(RedisType::BulkString(self.myid.clone()));
+            }
+            "MASTERS" => {
+                arity(2)?;
+                let state = self.state.lock().unwrap();
+                let masters: Vec<RedisType> = state
+                    .masters
+                    .values()
+                    .map(|m| fields_reply(master_fields(m)))
+                    .collect();
+                return Ok(RedisType::Array(Box::new(masters)));
+            }
+            "MASTER" => {
+                arity(3)?;
+                let state = self.state.lock().unwrap();
+                return match state.masters.get(&master_name()) {
+                    Some(m) => Ok(fields_reply(master_fields(m))),
+                    None => Err(no_such_master()),
+                };
+            }
+            "REPLICAS" | "SLAVES" => {
+                arity(3)?;
+                let state = self.state.lock().unwrap();
+                let master: &Monitored = match state.masters.get(&master_name()) {
+                    Some(m) => m,
+                    None => return Err(no_such_master()),
+                };
+                let replicas: Vec<RedisType> = master
+                    .replicas
+                    .values()
+                    .map(|r| fields_reply(replica_fields(r)))
+                    .collect();
+                return Ok(RedisType::Array(Box::new(replicas)));
+            }
+            "SENTINELS" => {
+                arity(3)?;
+                let state = self.state.lock().unwrap();
+                let master: &Monitored = match state.masters.get(&master_name()) {
+                    Some(m) => m,
+                    None => return Err(no_such_master()),
+                };
+                let peers: Vec<RedisType> = master
+                    .peers
+                    .values()
+                    .map(|p| fields_reply(peer_fields(p)))
+                    .collect();
+                return Ok(RedisType::Array(Box::new(peers)));
+            }
+            "GET-MASTER-ADDR-BY-NAME" => {
+                arity(3)?;
+                let state = self.state.lock().unwrap();
+                return Ok(match state.masters.get(&master_name()) {
+                    Some(m) => RedisType::Array(Box::new(vec![
+                        RedisType::BulkString(m.master.addr.host.clone()),
+                        RedisType::BulkString(m.master.addr.port.to_string()),
+                    ])),
+                    None => RedisType::NullArray,
+                });
+            }
+            "IS-MASTER-DOWN-BY-ADDR" => {
+                arity(6)?;
+                let text = |i: usize| -> String { String::from_utf8_lossy(&args[i]).to_string() };
+                let (port, epoch) = match (text(3).parse::<u16>(), text(4).parse::<u64>()) {
+                    (Ok(port), Ok(epoch)) => (port, epoch),
+                    _ => return Err(Error::new("ERR value is not an integer or out of range")),
+                };
+                let (down, leader, leader_epoch) =
+                    self.is_master_down_by_addr(&MasterAddr::new(&text(2), port), epoch, &text(5));
+                return Ok(RedisType::Array(Box::new(vec![
+                    RedisType::Integer((down as u8).to_string()),
+                    RedisType::BulkString(leader),
+                    RedisType::Integer(leader_epoch.to_string()),
+                ])));
+            }
+            "FAILOVER" => {
+                arity(3)?;
+                self.force_failover(&master_name())?;
+                return Ok(RedisType::SimpleString("OK"));
+            }
+            "CKQUORUM" => {
+                arity(3)?;
+                let state = self.state.lock().unwrap();
+                let master: &Monitored = match state.masters.get(&master_name()) {
+                    Some(m) => m,
+                    None => return Err(no_such_master()),
+                };
+                return ckquorum(master);
+            }
+            _ => {
+                return Err(Error {
+                    message: format!(
+                        "ERR unknown subcommand '{}'. Try SENTINEL HELP.",
+                        String::from_utf8_lossy(&args[1])
+                    ),
+                })
+            }
+        }
+    }
+
+    /// SENTINEL FAILOVER: promotes a replica right away, without the master
+    /// being down and without asking the other sentinels.
+    fn force_failover(&self, name: &str) -> Result<(), Error> {
+        let mut guard = self.state.lock().unwrap();
+        let state: &mut SentinelState = &mut guard;
+        let master: &mut Monitored = match state.masters.get_mut(name) {
+            Some(m) => m,
+            None => return Err(no_such_master()),
+        };
+        if master.failover.is_some() {
+            return Err(Error::new("INPROG Failover already in progress"));
+        }
+        if master
+            .select_replica(monitor::info_validity(master))
+            .is_none()
+        {
+            return Err(Error::new("NOGOODSLAVE No suitable replica to promote"));
+        }
+
+        state.current_epoch += 1;
+        let epoch: u64 = state.current_epoch;
+        event("+new-epoch", &epoch.to_string());
+        event("+try-failover", &master.describe());
+        master.vote(&mut state.current_epoch, epoch, &self.myid, &self.myid);
+        master.failover = Some(Failover::new(epoch, FailoverState::SelectReplica, true));
+        return Ok(());
+    }
+
+    fn info(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out: String = format!(
+            "# Server\r\nredis_mode:sentinel\r\nrun_id:{}\r\ntcp_port:{}\r\n\r\n# Sentinel\r\nsentinel_masters:{}\r\nsentinel_current_epoch:{}\r\n",
+            self.myid,
+            self.addr.port,
+            state.masters.len(),
+            state.current_epoch
+        );
+        for (i, master) in state.masters.values().enumerate() {
+            let status: &str = match master.odown {
+                true => "odown",
+                false if master.master.sdown => "sdown",
+                false => "ok",
+            };
+            out.push_str(&format!(
+                "master{}:name={},status={},address={},slaves={},sentinels={}\r\n",
+                i,
+                master.name,
+                status,
+                master.master.addr.addr(),
+                master.replicas.len(),
+                master.peers.len() + 1
+            ));
+        }
+        return out;
+    }
+}
+
+fn fields_reply(fields: Vec<(&str, String)>) -> RedisType<'static> {
+    let items: Vec<RedisType> = fields
+        .into_iter()
+        .flat_map(|(name, value)| {
+            [
+                RedisType::BulkString(name.to_string()),
+                RedisType::BulkString(value),
+            ]
+        })
+        .collect();
+    return RedisType::Array(Box::new(items));
+}
+
+fn master_fields(master: &Monitored) -> Vec<(&'static str, String)> {
+    let mut flags: Vec<&str> = vec!["master"];
+    if master.master.sdown {
+        flags.push("s_down");
+    }
+    if master.odown {
+        flags.push("o_down");
+    }
+    if master.failover.is_some() {
+        flags.push("failover_in_progress");
+    }
+    let failover_state: &str = match &master.failover {
+        Some(failover) => failover.state.name(),
+        None => "none",
+    };
+    return vec![
+        ("name", master.name.clone()),
+        ("ip", master.master.addr.host.clone()),
+        ("port", master.master.addr.port.to_string()),
+        ("runid", master.master.run_id().to_string()),
+        ("flags", flags.join(",")),
+        ("num-slaves", master.replicas.len().to_string()),
+        ("num-other-sentinels", master.peers.len().to_string()),
+        ("quorum", master.quorum.to_string()),
+        ("config-epoch", master.config_epoch.to_string()),
+        ("failover-state", failover_state.to_string()),
+        (
+            "down-after-milliseconds",
+            master.down_after.as_millis().to_string(),
+        ),
+        (
+            "failover-timeout",
+            master.failover_timeout.as_millis().to_string(),
+        ),
+    ];
+}
+
+fn replica_fields(replica: &Instance) -> Vec<(&'static str, String)> {
+    let mut flags: Vec<&str> = vec!["slave"];
+    if replica.sdown {
+        flags.push("s_down");
+    }
+    let info: Info = replica.info.clone().unwrap_or_default();
+    let (master_host, master_port) = match &info.master_addr {
+        Some(addr) => (addr.host.clone(), addr.port.to_string()),
+        None => (String::from("?"), String::from("0")),
+    };
+    return vec![
+        ("name", replica.name()),
+        ("ip", replica.addr.host.clone()),
+        ("port", replica.addr.port.to_string()),
+        ("runid", info.run_id.clone()),
+        ("flags", flags.join(",")),
+        ("master-host", master_host),
+        ("master-port", master_port),
+        (
+            "master-link-status",
+            String::from(if info.link_up { "ok" } else { "err" }),
+        ),
+        ("slave-priority", info.priority.to_string()),
+        ("slave-repl-offset", info.offset.to_string()),
+    ];
+}
+
+fn peer_fields(peer: &Peer) -> Vec<(&'static str, String)> {
+    return vec![
+        ("name", peer.run_id.clone()),
+        ("ip", peer.addr.host.clone()),
+        ("port", peer.addr.port.to_string()),
+        ("runid", peer.run_id.clone()),
+        ("flags", String::from("sentinel")),
+        (
+            "last-hello-message",
+            peer.last_hello.elapsed().as_millis().to_string(),
+        ),
+        (
+            "voted-leader",
+            peer.leader.clone().unwrap_or_else(|| String::from("?")),
+        ),
+        ("voted-leader-epoch", peer.leader_epoch.to_string()),
+    ];
+}
+
+/// SENTINEL CKQUORUM: whether enough sentinels are reachable to agree that
+/// the master is down and to elect a leader.
+fn ckquorum(master: &Monitored) -> Result<RedisType<'static>, Error> {
+    let voters: usize = master.peers.len() + 1;
+    let usable: usize = 1 + master
+        .peers
+        .values()
+        .filter(|peer| peer.last_hello.elapsed() <= monitor::PEER_VALIDITY)
+        .count();
+    if usable < master.quorum {
+        return Err(Error {
+            message: format!(
+                "NOQUORUM {} usable Sentinels. Not enough available Sentinels to reach the specified quorum for this master",
+                usable
+            ),
+        });
+    }
+    if usable < voters / 2 + 1 {
+        return Err(Error {
+            message: format!(
+                "NOQUORUM {} usable Sentinels. Not enough available Sentinels to reach the majority and authorize a failover",
+                usable
+            ),
+        });
+    }
+    return Ok(RedisType::Status(format!(
+        "OK {} usable Sentinels. Quorum and failover authorization can be reached",
+        usable
+    )));
+}
+
+/// Runs a sentinel until the process is stopped.
+pub async fn run(config: SentinelConfig) -> Result<(), Error> {
+    let listener: TcpListener = bind_tcp(&config.bind, config.port).await?;
+    println!("Sentinel listening on {}", listener.local_addr()?);
+    let sentinel: Arc<Sentinel> = Arc::new(Sentinel::new(&config));
+    println!("Sentinel ID is {}", sentinel.myid);
+    for master in sentinel.state.lock().unwrap().masters.values() {
+        event(
+            "+monitor",
+            &format!("{} quorum {}", master.describe(), master.quorum),
+        );
+    }
+
+    tokio::spawn(monitor::run_timer(Arc::clone(&sentinel)));
+    loop {
+        let (socket, _) = listener.accept().await?;
+        socket.set_nodelay(true)?;
+        tokio::spawn(serve_client(Arc::clone(&sentinel), socket));
+    }
+}
+
+async fn serve_client<S: AsyncRead + AsyncWrite + Unpin>(sentinel: Arc<Sentinel>, socket: S) {
+    let mut connection: Connection<S> = Connection::new(socket);
+    loop {
+        let batch: Batch = match connection.read_batch(MAX_BULK_LEN, MAX_QUERY_BUFFER).await {
+            Ok(Some(batch)) => batch,
+            _ => return,
+        };
+
+        let mut replies: Vec<u8> = Vec::new();
+        for args in batch.commands.iter() {
+            let reply: RedisType = match sentinel.command(args) {
+                Ok(reply) => reply,
+                Err(e) => RedisType::Error(e.message),
+            };
+            reply.encode(&mut replies);
+        }
+        if let Some(e) = &batch.error {
+            RedisType::Error(e.message.clone()).encode(&mut replies);
+        }
+        if connection.write_all(&replies).await.is_err() || batch.error.is_some() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        return text.split('|').map(String::from).collect();
+    }
+
+    #[test]
+    fn config_test() {
+        let config: SentinelConfig = SentinelConfig::from_args(&args(
+            "--port|26380|--monitor|mymaster 127.0.0.1 6379 2|--down-after-milliseconds|mymaster 500|--failover-timeout|mymaster 3000",
+        ))
+        .unwrap();
+        assert_eq!(config.port, 26380);
+        assert_eq!(config.masters.len(), 1);
+        let master: &MasterConfig = &config.masters[0];
+        assert_eq!(master.name, "mymaster");
+        assert_eq!(master.addr, MasterAddr::new("127.0.0.1", 6379));
+        assert_eq!(master.quorum, 2);
+        assert_eq!(master.down_after, Duration::from_millis(500));
+        assert_eq!(master.failover_timeout, Duration::from_millis(3000));
+
+        assert!(SentinelConfig::from_args(&args("--port|26379")).is_err());
+        assert!(SentinelConfig::from_args(&args("--monitor|mymaster 127.0.0.1 6379")).is_err());
+        assert!(SentinelConfig::from_args(&args(
+            "--monitor|mymaster 127.0.0.1 6379 2|--down-after-milliseconds|other 500"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn command_test() {
+        let config: SentinelConfig =
+            SentinelConfig::from_args(&args("--monitor|mymaster 127.0.0.1 6379 2")).unwrap();
+        let sentinel: Sentinel = Sentinel::new(&config);
+        let call = |text: &str| -> String {
+            let args: Vec<Vec<u8>> = text.split(' ').map(|a| a.as_bytes().to_vec()).collect();
+            let mut out: Vec<u8> = Vec::new();
+            match sentinel.command(&args) {
+                Ok(reply) => reply.encode(&mut out),
+                Err(e) => RedisType::Error(e.message).encode(&mut out),
+            }
+            return String::from_utf8(out).unwrap();
+        };
+
+        assert_eq!(
+            call("SENTINEL GET-MASTER-ADDR-BY-NAME mymaster"),
+            "*2\r\n$9\r\n127.0.0.1\r\n$4\r\n6379\r\n"
+        );
+        assert_eq!(call("SENTINEL GET-MASTER-ADDR-BY-NAME other"), "*-1\r\n");
+        assert_eq!(
+            call("SENTINEL MASTER other"),
+            "-ERR No such master with that name\r\n"
+        );
+        assert!(
+            call("SENTINEL MASTERS").starts_with("*1\r\n*24\r\n$4\r\nname\r\n$8\r\nmymaster\r\n")
+        );
+        assert_eq!(call("SENTINEL REPLICAS mymaster"), "*0\r\n");
+        assert_eq!(
+            call("ROLE"),
+            "*2\r\n$8\r\nsentinel\r\n*1\r\n$8\r\nmymaster\r\n"
+        );
+        assert!(call("SENTINEL CKQUORUM mymaster").starts_with("-NOQUORUM 1 usable Sentinels."));
+        assert_eq!(
+            call("SENTINEL FAILOVER mymaster"),
+            "-NOGOODSLAVE No suitable replica to promote\r\n"
+        );
+
+        // a vote request for the master is answered with the vote
+        let myid: String = sentinel.myid.clone();
+        assert_eq!(
+            call("SENTINEL IS-MASTER-DOWN-BY-ADDR 127.0.0.1 6379 1 abc"),
+            "*3\r\n:0\r\n$3\r\nabc\r\n:1\r\n"
+        );
+        assert_eq!(
+            call(&format!(
+                "SENTINEL IS-MASTER-DOWN-BY-ADDR 127.0.0.1 6379 1 {}",
+                myid
+            )),
+            "*3\r\n:0\r\n$3\r\nabc\r\n:1\r\n"
+        );
+        assert_eq!(
+            call("SENTINEL IS-MASTER-DOWN-BY-ADDR 127.0.0.1 6390 1 *"),
+            "*3\r\n:0\r\n$1\r\n*\r\n:0\r\n"
+        );
+
+        sentinel.process_hello("127.0.0.1,26380,peer,5,mymaster,127.0.0.1,6380,3");
+        assert_eq!(sentinel.state.lock().unwrap().current_epoch, 5);
+        assert_eq!(
+            call("SENTINEL GET-MASTER-ADDR-BY-NAME mymaster"),
+            "*2\r\n$9\r\n127.0.0.1\r\n$4\r\n6380\r\n"
+        );
+        assert!(call("SENTINEL SENTINELS mymaster").contains("$4\r\npeer\r\n"));
+        assert!(call("SENTINEL CKQUORUM mymaster").starts_with("+OK 2 usable Sentinels."));
+    }
+}
@@ -0,0 +1,635 @@
+//! The sentinel timer: it pings the monitored instances, refreshes their INFO,
+//! publishes hellos, asks the other sentinels about the master and drives
+//! failovers. Network requests run as separate tasks that report back
+//! under the state lock, so a slow instance never holds up the timer.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::random::random_below;
+use crate::redis_client::{bulk, integer, ClientOptions, Cmd, RedisClient, Subscriber};
+use crate::redis_parser::RedisType;
+use crate::replication::MasterAddr;
+use crate::sentinel::instance::{Failover, FailoverState, Hello, Info, Instance, MAX_DESYNC};
+use crate::sentinel::{event, Monitored, Sentinel, SentinelState, HELLO_CHANNEL};
+use crate::Error;
+
+const TIMER_PERIOD: Duration = Duration::from_millis(100);
+const PING_PERIOD: Duration = Duration::from_millis(1000);
+const INFO_PERIOD: Duration = Duration::from_secs(10);
+/// INFO period while the master is down or failing over, to follow the replicas closely.
+const FAST_INFO_PERIOD: Duration = Duration::from_secs(1);
+const HELLO_PERIOD: Duration = Duration::from_secs(2);
+const ASK_PERIOD: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// A peer's view of the master counts for this long after it answered.
+const REPLY_VALIDITY: Duration = Duration::from_secs(5);
+/// A peer silent for longer is not counted by CKQUORUM.
+pub const PEER_VALIDITY: Duration = Duration::from_secs(10);
+/// The longest wait for votes, shortened to the failover timeout.
+const ELECTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// A replica reporting the wrong master is fixed after this long, leaving the
+/// other sentinels time to announce a configuration this one does not know.
+const RECONF_WAIT: Duration = Duration::from_secs(8);
+const RECONF_PERIOD: Duration = Duration::from_secs(10);
+
+/// A network request for a task to carry out.
+enum Job {
+    Ping {
+        master: String,
+        addr: MasterAddr,
+    },
+    Info {
+        master: String,
+        addr: MasterAddr,
+    },
+    Hello {
+        addr: MasterAddr,
+        payload: String,
+    },
+    /// SENTINEL IS-MASTER-DOWN-BY-ADDR to a peer, asking for its vote when
+    /// `run_id` is not `*`.
+    Ask {
+        master: String,
+        peer: String,
+        peer_addr: MasterAddr,
+        master_addr: MasterAddr,
+        epoch: u64,
+        run_id: String,
+    },
+    Replicaof {
+        addr: MasterAddr,
+        target: Option<MasterAddr>,
+    },
+}
+
+/// How old a replica's INFO may be for it to be promoted.
+pub fn info_validity(master: &Monitored) -> Duration {
+    return match master.master.sdown || master.failover.is_some() {
+        true => 5 * FAST_INFO_PERIOD,
+        false => 3 * INFO_PERIOD,
+    };
+}
+
+pub async fn run_timer(sentinel: Arc<Sentinel>) {
+    let mut interval = tokio::time::interval(TIMER_PERIOD);
+    loop {
+        interval.tick().await;
+        for job in sentinel.tick() {
+            tokio::spawn(run_job(Arc::clone(&sentinel), job));
+        }
+        update_hello_links(&sentinel);
+    }
+}
+
+impl Sentinel {
+    fn tick(&self) -> Vec<Job> {
+        let now: Instant = Instant::now();
+        let mut jobs: Vec<Job> = Vec::new();
+        let mut guard = self.state.lock().unwrap();
+        let SentinelState {
+            current_epoch,
+            masters,
+        } = &mut *guard;
+        for master in masters.values_mut() {
+            self.tick_master(current_epoch, master, now, &mut jobs);
+        }
+        return jobs;
+    }
+
+    fn tick_master(
+        &self,
+        current_epoch: &mut u64,
+        master: &mut Monitored,
+        now: Instant,
+        jobs: &mut Vec<Job>,
+    ) {
+        let hello: String = Hello {
+            addr: self.addr.clone(),
+            run_id: self.myid.clone(),
+            current_epoch: *current_epoch,
+            master_name: master.name.clone(),
+            master_addr: master.master.addr.clone(),
+            master_config_epoch: master.config_epoch,
+        }
+        .encode();
+        let ping_period: Duration = PING_PERIOD.min(master.down_after);
+        let info_period: Duration = match master.master.sdown || master.failover.is_some() {
+            true => FAST_INFO_PERIOD,
+            false => INFO_PERIOD,
+        };
+
+        let mut changes: Vec<(MasterAddr, bool)> = Vec::new();
+        let instances = std::iter::once(&mut master.master).chain(master.replicas.values_mut());
+        for instance in instances {
+            schedule(
+                instance,
+                &master.name,
+                ping_period,
+                info_period,
+                &hello,
+                now,
+                jobs,
+            );
+            let down: bool = instance
+                .ping_pending_since
+                .is_some_and(|since| now.duration_since(since) > master.down_after);
+            if down != instance.sdown {
+                instance.sdown = down;
+                changes.push((instance.addr.clone(), down));
+            }
+        }
+        for (addr, down) in changes {
+            let kind: &str = if down { "+sdown" } else { "-sdown" };
+            match addr == master.master.addr {
+                true => event(kind, &master.describe()),
+                false => event(kind, &master.describe_replica(&addr)),
+            }
+        }
+
+        self.check_odown(master, now);
+        self.ask_peers(current_epoch, master, now, jobs);
+
+        if master.odown && master.failover.is_none() && now >= master.next_attempt {
+            *current_epoch += 1;
+            let epoch: u64 = *current_epoch;
+            event("+new-epoch", &epoch.to_string());
+            event("+try-failover", &master.describe());
+            master.vote(current_epoch, epoch, &self.myid, &self.myid);
+            master.failover = Some(Failover::new(epoch, FailoverState::WaitStart, false));
+            master.next_attempt = now + 2 * master.failover_timeout;
+            // ask for the votes right away
+            for peer in master.peers.values_mut() {
+                peer.last_ask = None;
+            }
+            self.ask_peers(current_epoch, master, now, jobs);
+        }
+
+        self.step_failover(master, now, jobs);
+        fix_replicas(master, now, jobs);
+    }
+
+    fn check_odown(&self, master: &mut Monitored, now: Instant) {
+        let mut agreeing: usize = 0;
+        if master.master.sdown {
+            agreeing = 1 + master
+                .peers
+                .values()
+                .filter(|peer| {
+                    peer.master_down
+                        && peer
+                            .reply_at
+                            .is_some_and(|at| now.duration_since(at) <= REPLY_VALIDITY)
+                })
+                .count();
+        }
+
+        let odown: bool = agreeing >= master.quorum;
+        if odown == master.odown {
+            return;
+        }
+        master.odown = odown;
+        if odown {
+            event(
+                "+odown",
+                &format!(
+                    "{} #quorum {}/{}",
+                    master.describe(),
+                    agreeing,
+                    master.quorum
+                ),
+            );
+            // sentinels seeing the failure together do not all ask for votes at once
+            let desync: Duration = MAX_DESYNC.mul_f64(random_below(1000) as f64 / 1000.0);
+            master.next_attempt = master.next_attempt.max(now + desync);
+        } else {
+            event("-odown", &master.describe());
+        }
+    }
+
+    fn ask_peers(
+        &self,
+        current_epoch: &u64,
+        master: &mut Monitored,
+        now: Instant,
+        jobs: &mut Vec<Job>,
+    ) {
+        if !master.master.sdown {
+            return;
+        }
+        let (epoch, run_id) = match &master.failover {
+            Some(failover) if failover.state == FailoverState::WaitStart => {
+                (failover.epoch, self.myid.clone())
+            }
+            _ => (*current_epoch, String::from("*")),
+        };
+        for peer in master.peers.values_mut() {
+            let due: bool = peer
+                .last_ask
+                .is_none_or(|at| now.duration_since(at) >= ASK_PERIOD);
+            if peer.ask_in_flight || !due {
+                continue;
+            }
+            peer.ask_in_flight = true;
+            peer.last_ask = Some(now);
+            jobs.push(Job::Ask {
+                master: master.name.clone(),
+                peer: peer.run_id.clone(),
+                peer_addr: peer.addr.clone(),
+                master_addr: master.master.addr.clone(),
+                epoch,
+                run_id: run_id.clone(),
+            });
+        }
+    }
+
+    fn step_failover(&self, master: &mut Monitored, now: Instant, jobs: &mut Vec<Job>) {
+        let (state, epoch, started, state_changed, promoted) = match &master.failover {
+            Some(f) => (
+                f.state,
+                f.epoch,
+                f.started,
+                f.state_changed,
+                f.promoted.clone(),
+            ),
+            None => return,
+        };
+        let elapsed: Duration = now.duration_since(started);
+
+        match state {
+            FailoverState::WaitStart => {
+                if master.leader(epoch).as_deref() == Some(self.myid.as_str()) {
+                    event("+elected-leader", &master.describe());
+                    event("+failover-state-select-slave", &master.describe());
+                    set_state(master, FailoverState::SelectReplica);
+                } else if elapsed > ELECTION_TIMEOUT.min(master.failover_timeout) {
+                    abort_failover(master, "-failover-abort-not-elected");
+                }
+            }
+            FailoverState::SelectReplica => match master.select_replica(info_validity(master)) {
+                Some(addr) => {
+                    event("+selected-slave", &master.describe_replica(&addr));
+                    event(
+                        "+failover-state-send-slaveof-noone",
+                        &master.describe_replica(&addr),
+                    );
+                    if let Some(failover) = master.failover.as_mut() {
+                        failover.promoted = Some(addr);
+                    }
+                    set_state(master, FailoverState::SendReplicaofNoOne);
+                }
+                None => abort_failover(master, "-failover-abort-no-good-slave"),
+            },
+            FailoverState::SendReplicaofNoOne => {
+                let addr: MasterAddr = promoted.unwrap();
+                event(
+                    "+failover-state-wait-promotion",
+                    &master.describe_replica(&addr),
+                );
+                jobs.push(Job::Replicaof { addr, target: None });
+                set_state(master, FailoverState::WaitPromotion);
+            }
+            FailoverState::WaitPromotion => {
+                let addr: MasterAddr = promoted.unwrap();
+                let is_master: bool = master
+                    .replicas
+                    .get(&addr.addr())
+                    .and_then(|replica| replica.info_since(state_changed))
+                    .is_some_and(|info| info.master);
+                if is_master {
+                    // from now on the hellos carry the new configuration
+                    master.config_epoch = epoch;
+                    event("+promoted-slave", &master.describe_replica(&addr));
+                    event("+failover-state-reconf-slaves", &master.describe());
+                    set_state(master, FailoverState::ReconfReplicas);
+                } else if elapsed > master.failover_timeout {
+                    abort_failover(master, "-failover-abort-slave-timeout");
+                }
+            }
+            FailoverState::ReconfReplicas => {
+                let addr: MasterAddr = promoted.unwrap();
+                let sent = &master.failover.as_ref().unwrap().reconf_sent;
+                let mut to_send: Vec<MasterAddr> = Vec::new();
+                let mut done: bool = true;
+                for replica in master.replicas.values().filter(|r| r.addr != addr) {
+                    if replica.sdown {
+                        continue;
+                    }
+                    if !sent.contains(&replica.name()) {
+                        to_send.push(replica.addr.clone());
+                        done = false;
+                        continue;
+                    }
+                    let following: bool = replica
+                        .info_since(state_changed)
+                        .is_some_and(|info| info.master_addr.as_ref() == Some(&addr));
+                    done &= following;
+                }
+
+                for replica in to_send {
+                    event("+slave-reconf-sent", &master.describe_replica(&replica));
+                    if let Some(failover) = master.failover.as_mut() {
+                        failover.reconf_sent.insert(replica.addr());
+                    }
+                    jobs.push(Job::Replicaof {
+                        addr: replica,
+                        target: Some(addr.clone()),
+                    });
+                }
+                // replicas still lagging behind are fixed up later like any misconfigured one
+                if done || elapsed > master.failover_timeout {
+                    event("+failover-end", &master.describe());
+                    master.switch_master(&addr);
+                }
+            }
+        }
+    }
+
+    fn ping_done(&self, master: &str, addr: &MasterAddr, ok: bool) {
+        let mut state = self.state.lock().unwrap();
+        let instance: &mut Instance = match state
+            .masters
+            .get_mut(master)
+            .and_then(|m| m.instance_mut(addr))
+        {
+            Some(i) => i,
+            None => return,
+        };
+        instance.ping_in_flight = false;
+        if ok {
+            instance.ping_pending_since = None;
+            instance.last_ok = Some(Instant::now());
+        }
+    }
+
+    fn info_done(&self, master: &str, addr: &MasterAddr, info: Option<Info>) {
+        let mut state = self.state.lock().unwrap();
+        let monitored: &mut Monitored = match state.masters.get_mut(master) {
+            Some(m) => m,
+            None => return,
+        };
+        let is_master: bool = monitored.master.addr == *addr;
+        let instance: &mut Instance = match monitored.instance_mut(addr) {
+            Some(i) => i,
+            None => return,
+        };
+        instance.info_in_flight = false;
+        let info: Info = match info {
+            Some(info) => info,
+            None => return,
+        };
+        instance.info_at = Some(Instant::now());
+        instance.info = Some(info.clone());
+
+        if is_master && info.master {
+            for replica in info.replicas.iter() {
+                monitored.add_replica(replica);
+            }
+        }
+    }
+
+    fn ask_done(&self, master: &str, peer: &str, reply: Option<(bool, String, u64)>) {
+        let mut state = self.state.lock().unwrap();
+        let peer = match state
+            .masters
+            .get_mut(master)
+            .and_then(|m| m.peers.get_mut(peer))
+        {
+            Some(p) => p,
+            None => return,
+        };
+        peer.ask_in_flight = false;
+        if let Some((down, leader, leader_epoch)) = reply {
+            peer.master_down = down;
+            peer.reply_at = Some(Instant::now());
+            if leader != "*" {
+                peer.leader = Some(leader);
+                peer.leader_epoch = leader_epoch;
+            }
+        }
+    }
+}
+
+/// Queues the PING, INFO and hello an instance is due for.
+fn schedule(
+    instance: &mut Instance,
+    master: &str,
+    ping_period: Duration,
+    info_period: Duration,
+    hello: &str,
+    now: Instant,
+    jobs: &mut Vec<Job>,
+) {
+    let due = |last: Option<Instant>, period: Duration| -> bool {
+        return last.is_none_or(|at| now.duration_since(at) >= period);
+    };
+
+    if !instance.ping_in_flight && due(instance.last_ping, ping_period) {
+        instance.ping_in_flight = true;
+        instance.last_ping = Some(now);
+        // an instance is as late as its oldest unanswered PING
+        instance.ping_pending_since.get_or_insert(now);
+        jobs.push(Job::Ping {
+            master: master.to_string(),
+            addr: instance.addr.clone(),
+        });
+    }
+    if !instance.info_in_flight && due(instance.info_at, info_period) {
+        instance.info_in_flight = true;
+        jobs.push(Job::Info {
+            master: master.to_string(),
+            addr: instance.addr.clone(),
+        });
+    }
+    if !instance.sdown && due(instance.last_hello, HELLO_PERIOD) {
+        instance.last_hello = Some(now);
+        jobs.push(Job::Hello {
+            addr: instance.addr.clone(),
+            payload: hello.to_string(),
+        });
+    }
+}
+
+fn set_state(master: &mut Monitored, state: FailoverState) {
+    if let Some(failover) = master.failover.as_mut() {
+        failover.set_state(state);
+    }
+}
+
+fn abort_failover(master: &mut Monitored, kind: &str) {
+    event(kind, &master.describe());
+    master.failover = None;
+}
+
+/// Points replicas reporting another master, or the master role, to the
+/// current master: an old master that came back, or a replica a failover did
+/// not reach.
+fn fix_replicas(master: &mut Monitored, now: Instant, jobs: &mut Vec<Job>) {
+    if master.failover.is_some() || master.master.sdown {
+        return;
+    }
+
+    let current: MasterAddr = master.master.addr.clone();
+    let mut fixed: Vec<MasterAddr> = Vec::new();
+    for replica in master.replicas.values_mut() {
+        let wrong: bool = match &replica.info {
+            Some(info) => info.master || info.master_addr.as_ref() != Some(&current),
+            None => false,
+        };
+        if !wrong || replica.sdown {
+            replica.misconfigured_since = None;
+            continue;
+        }
+        let since: Instant = *replica.misconfigured_since.get_or_insert(now);
+        let throttled: bool = replica
+            .last_reconf
+            .is_some_and(|at| now.duration_since(at) < RECONF_PERIOD);
+        if now.duration_since(since) >= RECONF_WAIT && !throttled {
+            replica.last_reconf = Some(now);
+            fixed.push(replica.addr.clone());
+        }
+    }
+
+    for addr in fixed {
+        event("+fix-slave-config", &master.describe_replica(&addr));
+        jobs.push(Job::Replicaof {
+            addr,
+            target: Some(current.clone()),
+        });
+    }
+}
+
+/// Keeps one task reading hellos from every monitored instance.
+fn update_hello_links(sentinel: &Arc<Sentinel>) {
+    let wanted: Vec<MasterAddr> = {
+        let state = sentinel.state.lock().unwrap();
+        state
+            .masters
+            .values()
+            .flat_map(|m| std::iter::once(&m.master).chain(m.replicas.values()))
+            .map(|instance| instance.addr.clone())
+            .collect()
+    };
+
+    let mut links = sentinel.hello_links.lock().unwrap();
+    links.retain(|name, link| {
+        let keep: bool = wanted.iter().any(|addr| addr.addr() == *name);
+        if !keep {
+            link.abort();
+        }
+        return keep;
+    });
+    for addr in wanted {
+        links
+            .entry(addr.addr())
+            .or_insert_with(|| tokio::spawn(read_hellos(Arc::clone(sentinel), addr.clone())));
+    }
+}
+
+async fn read_hellos(sentinel: Arc<Sentinel>, addr: MasterAddr) {
+    loop {
+        if let Ok(mut subscriber) = Subscriber::connect(ClientOptions::new(&addr.addr())).await {
+            if subscriber.subscribe(&[HELLO_CHANNEL]).await.is_ok() {
+                while let Ok(message) = subscriber.next_message().await {
+                    sentinel.process_hello(&String::from_utf8_lossy(&message.payload));
+                }
+            }
+        }
+        tokio::time::sleep(PING_PERIOD).await;
+    }
+}
+
+/// Sends one command on a fresh connection.
+async fn request(addr: &MasterAddr, cmd: Cmd) -> Result<RedisType<'static>, Error> {
+    let mut options: ClientOptions = ClientOptions::new(&addr.addr());
+    options.reconnect_attempts = 0;
+    options.connect_timeout = REQUEST_TIMEOUT;
+    let exchange = async {
+        let mut client: RedisClient = RedisClient::with_options(options).await?;
+        return client.query(&cmd).await;
+    };
+    return match tokio::time::timeout(REQUEST_TIMEOUT, exchange).await {
+        Ok(result) => result,
+        Err(_) => Err(Error {
+            message: format!("Timed out talking to {}", addr.addr()),
+        }),
+    };
+}
+
+/// Reads the `[down, leader, leader_epoch]` answer to IS-MASTER-DOWN-BY-ADDR.
+fn parse_vote(reply: RedisType<'static>) -> Option<(bool, String, u64)> {
+    let items: Vec<RedisType> = match reply {
+        RedisType::Array(items) if items.len() == 3 => *items,
+        _ => return None,
+    };
+    let mut items = items.into_iter();
+    let down: i64 = integer(items.next()?).ok()?;
+    let leader: Vec<u8> = bulk(items.next()?).ok()??;
+    let leader_epoch: i64 = integer(items.next()?).ok()?;
+    return Some((
+        down == 1,
+        String::from_utf8_lossy(&leader).to_string(),
+        leader_epoch as u64,
+    ));
+}
+
+async fn run_job(sentinel: Arc<Sentinel>, job: Job) {
+    match job {
+        Job::Ping { master, addr } => {
+            // a server loading its dataset or cut from its master is alive
+            let ok: bool = match request(&addr, Cmd::new("PING")).await {
+                Ok(RedisType::Error(e)) => e.starts_with("LOADING") || e.starts_with("MASTERDOWN"),
+                Ok(_) => true,
+                Err(_) => false,
+            };
+            sentinel.ping_done(&master, &addr, ok);
+        }
+        Job::Info { master, addr } => {
+            let info: Option<Info> = match request(&addr, Cmd::new("INFO")).await {
+                Ok(RedisType::Error(_)) | Err(_) => None,
+                Ok(reply) => bulk(reply)
+                    .ok()
+                    .flatten()
+                    .map(|text| Info::parse(&String::from_utf8_lossy(&text))),
+            };
+            sentinel.info_done(&master, &addr, info);
+        }
+        Job::Hello { addr, payload } => {
+            let publish: Cmd = Cmd::new("PUBLISH").arg(HELLO_CHANNEL).arg(&payload);
+            let _ = request(&addr, publish).await;
+        }
+        Job::Ask {
+            master,
+            peer,
+            peer_addr,
+            master_addr,
+            epoch,
+            run_id,
+        } => {
+            let ask: Cmd = Cmd::new("SENTINEL")
+                .arg("IS-MASTER-DOWN-BY-ADDR")
+                .arg(&master_addr.host)
+                .arg(&master_addr.port.to_string())
+                .arg(&epoch.to_string())
+                .arg(&run_id);
+            let reply: Option<(bool, String, u64)> = match request(&peer_addr, ask).await {
+                Ok(reply) => parse_vote(reply),
+                Err(_) => None,
+            };
+            sentinel.ask_done(&master, &peer, reply);
+        }
+        Job::Replicaof { addr, target } => {
+            let cmd: Cmd = match &target {
+                Some(master) => Cmd::new("REPLICAOF")
+                    .arg(&master.host)
+                    .arg(&master.port.to_string()),
+                None => Cmd::new("REPLICAOF").arg("NO").arg("ONE"),
+            };
+            match request(&addr, cmd).await {
+                Ok(RedisType::Error(e)) => eprintln!("REPLICAOF failed on {}: {}", addr.addr(), e),
+                Err(e) => eprintln!("REPLICAOF failed on {}: {}", addr.addr(), e.message),
+                Ok(_) => {}
+            }
+        }
+    }
+}
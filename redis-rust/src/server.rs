@@ -0,0 +1,640 @@
+//! Accepting clients and running their commands.
+//!
+//! `run` restores the snapshot, opens every configured listener, runs the
+//! periodic maintenance task and serves clients until a shutdown is started
+//! with SHUTDOWN or `shutdown::prepare`. The server binary only adds the
+//! command line and signal handling.
+//!
+//! `Server` runs the same server inside the calling process and returns a
+//! `ServerHandle` once it listens. It can listen on a port picked by the OS
+//! instead of `port`, or on no socket at all with clients connected through
+//! in-memory pipes, so any number of isolated servers can share one process.
+
+use std::fs::Permissions;
+use std::net::SocketAddr;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+
+use crate::client::Client;
+use crate::commands::{execute, lookup};
+use crate::connection::{Batch, Connection};
+use crate::db::now_ms;
+use crate::keyspace::Shards;
+use crate::latency::add_sample_if_needed;
+use crate::lazyfree::free_detached;
+use crate::metrics;
+use crate::notify::publish_events;
+use crate::redis_parser::RedisType;
+use crate::replication::{self, MasterAddr};
+use crate::shutdown::ShutdownOptions;
+use crate::{rdb, shutdown, tls, Config, Error, ServerState, Session};
+
+/// How often the active expiry cycle runs, and how many keys it may delete per database.
+const EXPIRE_CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+const EXPIRE_CYCLE_KEYS: usize = 200;
+
+/// How long each maintenance run may spend rehashing, as in Redis.
+const ACTIVE_REHASH_BUDGET: Duration = Duration::from_millis(1);
+
+/// How long a TLS client may take to complete its handshake.
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a shutdown waits for the open connections to write their last replies.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Capacity of each direction of an in-memory connection.
+const IN_MEMORY_BUFFER: usize = 64 * 1024;
+
+/// Builds a server running inside the calling process.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), redis_starter_rust::Error> {
+/// use redis_starter_rust::server::{Server, ServerHandle};
+/// use redis_starter_rust::Config;
+///
+/// let server: ServerHandle = Server::new(Config::default()).ephemeral_port().start().await?;
+/// println!("listening on {}", server.local_addr().unwrap());
+/// server.shutdown().await?;
+/// # return Ok(());
+/// # }
+/// ```
+pub struct Server {
+    config: Config,
+    transport: Transport,
+}
+
+/// Where a server started with `Server` listens, besides what its config asks for.
+#[derive(Clone, Copy, Debug, Default)]
+struct Transport {
+    ephemeral_port: bool,
+    in_memory: bool,
+}
+
+impl Server {
+    pub fn new(config: Config) -> Self {
+        return Server {
+            config,
+            transport: Transport::default(),
+        };
+    }
+
+    /// Listens on a port picked by the OS instead of `port`, so servers
+    /// started side by side do not compete for one.
+    pub fn ephemeral_port(mut self) -> Self {
+        self.transport.ephemeral_port = true;
+        return self;
+    }
+
+    /// Opens no socket at all; clients connect with `ServerHandle::connect`.
+    pub fn in_memory(mut self) -> Self {
+        self.config.port = 0;
+        self.config.tls_port = 0;
+        self.config.unixsocket = String::new();
+        self.config.metrics_port = 0;
+        self.transport = Transport {
+            ephemeral_port: false,
+            in_memory: true,
+        };
+        return self;
+    }
+
+    /// Loads the snapshot and opens the listeners, then serves clients in the
+    /// background until `ServerHandle::shutdown`.
+    pub async fn start(self) -> Result<ServerHandle, Error> {
+        let state: Arc<ServerState> = Arc::new(ServerState::new(self.config));
+        let listening: Listening = listen(&state, self.transport).await?;
+        let local_addr: Option<SocketAddr> = listening.local_addrs.first().copied();
+        let task: JoinHandle<()> = tokio::spawn(serve(Arc::clone(&state), listening));
+        return Ok(ServerHandle {
+            state,
+            local_addr,
+            task,
+        });
+    }
+}
+
+/// A server started with `Server`. Dropping the handle leaves the server
+/// running until the runtime stops.
+pub struct ServerHandle {
+    state: Arc<ServerState>,
+    local_addr: Option<SocketAddr>,
+    task: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// The address of the first TCP listener, `None` for an in-memory server.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        return self.local_addr;
+    }
+
+    pub fn state(&self) -> &Arc<ServerState> {
+        return &self.state;
+    }
+
+    /// A new client connected through an in-memory pipe; the returned end
+    /// is used like a socket.
+    pub fn connect(&self) -> Result<DuplexStream, Error> {
+        if self.state.shutdown.is_started() {
+            return Err(Error::new("ERR Server is shutting down"));
+        }
+        let (client, server) = tokio::io::duplex(IN_MEMORY_BUFFER);
+        // there is no address or file descriptor behind an in-memory client
+        handle_connection(
+            server,
+            "in-memory:0",
+            "in-memory:0",
+            -1,
+            Arc::clone(&self.state),
+        );
+        return Ok(client);
+    }
+
+    /// Shuts down like SHUTDOWN without modifiers and waits until every client is gone.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        return self.shutdown_with(ShutdownOptions::default()).await;
+    }
+
+    /// Shuts down like SHUTDOWN with `options`. If the final save fails the
+    /// server keeps running and the error is returned.
+    pub async fn shutdown_with(self, options: ShutdownOptions) -> Result<(), Error> {
+        if options.saves() {
+            shutdown::wait_for_bgsave(&self.state).await;
+        }
+        {
+            let shards: Shards = self.state.keyspace.lock_all().await;
+            shutdown::wait_for_replicas(&self.state, options).await;
+            shutdown::prepare(&self.state, &shards.views(), options)?;
+        }
+        if let Err(e) = self.task.await {
+            return Err(Error {
+                message: format!("The server task failed: {}", e),
+            });
+        }
+        return Ok(());
+    }
+}
+
+/// A server whose listeners are open, waiting for `serve` to run it.
+struct Listening {
+    acceptors: Vec<JoinHandle<()>>,
+    local_addrs: Vec<SocketAddr>,
+}
+
+/// Serves clients with `state` until the shutdown completes.
+pub async fn run(state: Arc<ServerState>) -> Result<(), Error> {
+    let listening: Listening = listen(&state, Transport::default()).await?;
+    serve(state, listening).await;
+    return Ok(());
+}
+
+/// Loads the configured modules, restores the snapshot, opens the listeners
+/// and creates the pid file.
+async fn listen(state: &Arc<ServerState>, transport: Transport) -> Result<Listening, Error> {
+    // the snapshot may hold values of the types the modules add
+    let modules: Vec<String> = state.config.read().unwrap().loadmodule.clone();
+    state.modules.load_configured(&modules)?;
+
+    // restore the snapshot from dir/dbfilename if there is one
+    let path = state.rdb_path();
+    if rdb::load(
+        &path,
+        &mut state.keyspace.lock_all().await.parts(),
+        &state.modules,
+    )? {
+        println!("DB loaded from disk: {}", path.display());
+    }
+
+    let config: Config = state.config.read().unwrap().clone();
+    let tcp: bool = config.port != 0 || transport.ephemeral_port;
+    if !tcp && config.tls_port == 0 && config.unixsocket.is_empty() && !transport.in_memory {
+        return Err(Error::new("Configured to not listen anywhere, exiting."));
+    }
+
+    let mut acceptors: Vec<JoinHandle<()>> = Vec::new();
+    let mut local_addrs: Vec<SocketAddr> = Vec::new();
+    // port 0 disables TCP
+    if tcp {
+        let mut port: u16 = match transport.ephemeral_port {
+            true => 0,
+            false => config.port,
+        };
+        for address in config.bind.iter() {
+            let listener: TcpListener = bind_tcp(address, port).await?;
+            let local_addr: SocketAddr = listener.local_addr()?;
+            println!("Listening on {}", local_addr);
+            // the other bind addresses share the port picked for the first one
+            port = local_addr.port();
+            local_addrs.push(local_addr);
+            acceptors.push(tokio::spawn(accept_tcp(listener, Arc::clone(state))));
+        }
+        // CONFIG GET port and INFO show the port actually listened on
+        state.config.write().unwrap().port = port;
+    }
+    if config.tls_port != 0 {
+        #[cfg(feature = "tls")]
+        {
+            let acceptor: TlsAcceptor = tls::acceptor(&config)?;
+            for address in config.bind.iter() {
+                let listener: TcpListener = bind_tcp(address, config.tls_port).await?;
+                println!("Listening on {} (TLS)", listener.local_addr()?);
+                acceptors.push(tokio::spawn(accept_tls(
+                    listener,
+                    acceptor.clone(),
+                    Arc::clone(state),
+                )));
+            }
+        }
+        #[cfg(not(feature = "tls"))]
+        return Err(tls::unsupported());
+    }
+    if config.metrics_port != 0 {
+        for address in config.bind.iter() {
+            let listener: TcpListener = bind_tcp(address, config.metrics_port).await?;
+            println!(
+                "Serving metrics on http://{}/metrics",
+                listener.local_addr()?
+            );
+            acceptors.push(tokio::spawn(metrics::accept(listener, Arc::clone(state))));
+        }
+    }
+    let unixsocket: String = config.unixsocket.clone();
+    if !unixsocket.is_empty() {
+        // a socket file left behind by an earlier run would make the bind fail
+        let _ = std::fs::remove_file(&unixsocket);
+        let listener: UnixListener = match UnixListener::bind(&unixsocket) {
+            Ok(listener) => listener,
+            Err(e) => {
+                return Err(Error {
+                    message: format!("Could not create server Unix socket {unixsocket}: {e}"),
+                })
+            }
+        };
+        if config.unixsocketperm != 0 {
+            std::fs::set_permissions(&unixsocket, Permissions::from_mode(config.unixsocketperm))?;
+        }
+        println!("Listening on unix socket {unixsocket}");
+        acceptors.push(tokio::spawn(accept_unix(
+            listener,
+            unixsocket.clone(),
+            Arc::clone(state),
+        )));
+    }
+
+    if !config.pidfile.is_empty() {
+        shutdown::create_pid_file(&config.pidfile)?;
+    }
+    return Ok(Listening {
+        acceptors,
+        local_addrs,
+    });
+}
+
+/// Runs the maintenance task and waits for the shutdown, then for the clients to leave.
+async fn serve(state: Arc<ServerState>, listening: Listening) {
+    let cron: JoinHandle<()> = tokio::spawn(cron(Arc::clone(&state)));
+    let master: Option<MasterAddr> = state.config.read().unwrap().master_addr();
+    if master.is_some() {
+        replication::replicaof(&state, master);
+    }
+    // SHUTDOWN or a signal saved the dataset and told every task to stop
+    state.shutdown.wait().await;
+
+    // no new clients; the connected ones return as soon as their current replies are written
+    for acceptor in listening.acceptors {
+        let _ = acceptor.await;
+    }
+    let drained = tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, async {
+        while state.stats.connected_clients.load(Ordering::Relaxed) > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    });
+    if drained.await.is_err() {
+        eprintln!("Timed out waiting for the clients to disconnect");
+    }
+    let _ = cron.await;
+
+    let config: Config = state.config.read().unwrap().clone();
+    if !config.unixsocket.is_empty() {
+        let _ = std::fs::remove_file(&config.unixsocket);
+    }
+    if !config.pidfile.is_empty() {
+        shutdown::remove_pid_file(&config.pidfile);
+    }
+    println!("Redis is now ready to exit, bye bye...");
+}
+
+/// Expires keys, rehashes, samples the metrics and closes idle clients, until
+/// the shutdown starts.
+async fn cron(state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(EXPIRE_CYCLE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.shutdown.wait() => return,
+        }
+        let now: u64 = now_ms();
+        // keys must not disappear while writes are paused
+        if !state.clients.is_paused() {
+            let started: Instant = Instant::now();
+            // one shard at a time, so clients of the other shards are not held up
+            for shard in 0..state.keyspace.len() {
+                let mut shards: Shards = state.keyspace.lock(Some(vec![shard])).await;
+                shards.set_recording(state.records_changes());
+                for dbs in shards.parts() {
+                    for db in dbs.iter_mut() {
+                        db.active_expire(now, EXPIRE_CYCLE_KEYS);
+                    }
+                }
+                state.signal_changes(&mut shards, 0);
+                publish_events(&state, &mut shards.parts());
+                free_detached(&state, &mut shards.parts());
+            }
+            add_sample_if_needed(&state, "expire-cycle", started.elapsed());
+        }
+        if state.config.read().unwrap().activerehashing {
+            active_rehash(&state).await;
+        }
+        state
+            .stats
+            .used_memory_peak
+            .fetch_max(state.keyspace.used_memory() as u64, Ordering::Relaxed);
+        state.stats.track_instantaneous_metrics();
+
+        let timeout: u64 = state.config.read().unwrap().timeout;
+        if timeout > 0 {
+            state.clients.close_idle(Duration::from_secs(timeout));
+        }
+    }
+}
+
+/// Moves the keys of the databases that grow or shrink to their new tables
+/// for up to `ACTIVE_REHASH_BUDGET`, so idle databases finish rehashing
+/// without waiting for writes to do it.
+async fn active_rehash(state: &ServerState) {
+    let started: Instant = Instant::now();
+    for shard in 0..state.keyspace.len() {
+        let mut shards: Shards = state.keyspace.lock(Some(vec![shard])).await;
+        for db in shards.parts().into_iter().flat_map(|dbs| dbs.iter_mut()) {
+            let left: Duration = ACTIVE_REHASH_BUDGET.saturating_sub(started.elapsed());
+            if left.is_zero() {
+                return;
+            }
+            db.rehash_for(left);
+        }
+    }
+}
+
+/// Binds one of the `bind` addresses, where `*` and `::*` stand for every IPv4 and IPv6 address.
+pub(crate) async fn bind_tcp(address: &str, port: u16) -> Result<TcpListener, Error> {
+    let host: &str = match address {
+        "*" => "0.0.0.0",
+        "::*" => "::",
+        host => host,
+    };
+    return match TcpListener::bind((host, port)).await {
+        Ok(listener) => Ok(listener),
+        Err(e) => Err(Error {
+            message: format!("Could not create server TCP listening socket {host}:{port}: {e}"),
+        }),
+    };
+}
+
+/// Hands every client connecting to `listener` to its own task until the shutdown starts.
+async fn accept_tcp(listener: TcpListener, state: Arc<ServerState>) {
+    loop {
+        let client: TcpStream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((client, _addr)) => client,
+                Err(e) => {
+                    eprintln!("Accepting client connection: {}", e);
+                    continue;
+                }
+            },
+            _ = state.shutdown.wait() => return,
+        };
+
+        let addr: String = client
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        let laddr: String = client
+            .local_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        let fd: i32 = client.as_raw_fd();
+        handle_connection(client, &addr, &laddr, fd, Arc::clone(&state));
+    }
+}
+
+/// Like `accept_tcp`, with the TLS handshake done in the client's task before
+/// its commands are read.
+#[cfg(feature = "tls")]
+async fn accept_tls(listener: TcpListener, acceptor: TlsAcceptor, state: Arc<ServerState>) {
+    loop {
+        let client: TcpStream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((client, _addr)) => client,
+                Err(e) => {
+                    eprintln!("Accepting client connection: {}", e);
+                    continue;
+                }
+            },
+            _ = state.shutdown.wait() => return,
+        };
+
+        let addr: String = client
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        let laddr: String = client
+            .local_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        let fd: i32 = client.as_raw_fd();
+        let acceptor: TlsAcceptor = acceptor.clone();
+        let state: Arc<ServerState> = Arc::clone(&state);
+        tokio::spawn(async move {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(client)).await {
+                Ok(Ok(stream)) => handle_connection(stream, &addr, &laddr, fd, state),
+                Ok(Err(e)) => {
+                    eprintln!("Error accepting a client connection: {} (addr={})", e, addr)
+                }
+                Err(_) => eprintln!("TLS handshake timed out (addr={})", addr),
+            }
+        });
+    }
+}
+
+/// Like `accept_tcp` for the Unix socket at `path`.
+async fn accept_unix(listener: UnixListener, path: String, state: Arc<ServerState>) {
+    // Unix socket clients have no address, Redis shows the socket path instead
+    let addr: String = format!("{}:0", path);
+    loop {
+        let client: UnixStream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((client, _addr)) => client,
+                Err(e) => {
+                    eprintln!("Accepting client connection: {}", e);
+                    continue;
+                }
+            },
+            _ = state.shutdown.wait() => return,
+        };
+
+        let fd: i32 = client.as_raw_fd();
+        handle_connection(client, &addr, &addr, fd, Arc::clone(&state));
+    }
+}
+
+/// Keeps the client registry, the Pub/Sub subscriptions and the connected_clients
+/// gauge accurate however the connection task ends.
+struct ClientGuard(Arc<ServerState>, Arc<Client>);
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.pubsub.remove_client(&self.1);
+        self.0.tracking.disable(&self.1);
+        self.0.watches.unwatch_all(self.1.id);
+        self.0.replication.remove_replica(self.1.id);
+        self.0.clients.unregister(self.1.id);
+        self.0
+            .stats
+            .connected_clients
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn handle_connection<S>(client: S, addr: &str, laddr: &str, fd: i32, state: Arc<ServerState>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    state
+        .stats
+        .total_connections_received
+        .fetch_add(1, Ordering::Relaxed);
+    state
+        .stats
+        .connected_clients
+        .fetch_add(1, Ordering::Relaxed);
+
+    let registered: Arc<Client> = state.clients.register(addr, laddr, fd);
+
+    tokio::spawn(async move {
+        let _guard: ClientGuard = ClientGuard(Arc::clone(&state), Arc::clone(&registered));
+        let mut connection: Connection<S> = Connection::new(client);
+        let mut session: Session = Session::for_client(Arc::clone(&registered));
+        let mut inbox = match registered.take_inbox() {
+            Some(i) => i,
+            None => return,
+        };
+
+        loop {
+            let (max_bulk_len, max_query_buffer) = {
+                let config = state.config.read().unwrap();
+                (config.proto_max_bulk_len, config.client_query_buffer_limit)
+            };
+
+            // wait for every complete command the client has sent so far
+            let read = tokio::select! {
+                r = connection.read_batch(max_bulk_len, max_query_buffer) => r,
+                // Pub/Sub messages are written as soon as they arrive
+                Some(message) = inbox.recv() => {
+                    let mut bytes: Vec<u8> = Vec::new();
+                    message.encode_with(&mut bytes, registered.protocol());
+                    if let Err(e) = connection.write_all(&bytes).await {
+                        eprintln!("Failed to write to client: {}", e.message);
+                        return;
+                    }
+                    registered.written();
+                    state
+                        .stats
+                        .total_net_output_bytes
+                        .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                    continue;
+                }
+                // CLIENT KILL or the idle timeout
+                _ = registered.killed() => return,
+                _ = state.shutdown.wait() => return,
+            };
+            let batch: Batch = match read {
+                Ok(Some(b)) => b,
+                // the conntection is closed
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("{}", e.message);
+                    return;
+                }
+            };
+
+            state
+                .stats
+                .total_net_input_bytes
+                .fetch_add(batch.bytes_read as u64, Ordering::Relaxed);
+
+            // run the commands in order and queue their replies in the same order
+            let mut replies: Vec<u8> = Vec::new();
+            for args in batch.commands.iter() {
+                // HELLO may switch the protocol, so it is read again for every reply
+                state
+                    .stats
+                    .total_commands_processed
+                    .fetch_add(1, Ordering::Relaxed);
+
+                let started: Instant = Instant::now();
+                let result: Result<RedisType, Error> = execute(args, &state, &mut session).await;
+                // unknown commands are not counted, like in Redis' commandstats
+                if let Some(command) = lookup(&state.modules, &args[0]) {
+                    state
+                        .stats
+                        .record_command(command.name, started.elapsed(), result.is_err());
+                }
+
+                let response: RedisType = match result {
+                    // like Redis, a successful SHUTDOWN gets no reply, only the earlier ones
+                    Ok(_)
+                        if args[0].eq_ignore_ascii_case(b"shutdown")
+                            && state.shutdown.is_started() =>
+                    {
+                        let _ = connection.write_all(&replies).await;
+                        return;
+                    }
+                    Ok(r) => r,
+                    Err(e) => {
+                        eprintln!("{}", e.message);
+                        RedisType::Error(e.message.to_string())
+                    }
+                };
+
+                response.encode_with(&mut replies, registered.protocol());
+            }
+
+            // a protocol error is reported after the valid commands, then the client is dropped
+            if let Some(e) = &batch.error {
+                RedisType::Error(e.message.clone()).encode(&mut replies);
+            }
+
+            if let Err(e) = connection.write_all(&replies).await {
+                eprintln!("Failed to write to client: {}", e.message);
+                return;
+            }
+            state
+                .stats
+                .total_net_output_bytes
+                .fetch_add(replies.len() as u64, Ordering::Relaxed);
+
+            if batch.error.is_some() {
+                return;
+            }
+        }
+    });
+}
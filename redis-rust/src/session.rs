@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use crate::client::Client;
+
+/// Per-connection state that commands can read and change.
+pub struct Session {
+    /// Index of the logical database selected with SELECT.
+    pub db: usize,
+    /// This connection's entry in the client registry.
+    pub client: Arc<Client>,
+    /// Commands queued since MULTI, `None` outside a transaction.
+    pub transaction: Option<Vec<Vec<Vec<u8>>>>,
+    /// Set when a command was refused while queuing, so that EXEC fails.
+    pub transaction_failed: bool,
+    /// Set for the stream of commands a replica receives from its master,
+    /// which may write although the replica is read-only.
+    pub master_link: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        return Session::new();
+    }
+}
+
+impl Session {
+    /// A session that is not backed by a registered connection.
+    pub fn new() -> Self {
+        return Session::for_client(Arc::new(Client::detached()));
+    }
+
+    pub fn for_client(client: Arc<Client>) -> Self {
+        return Session {
+            db: 0,
+            client,
+            transaction: None,
+            transaction_failed: false,
+            master_link: false,
+        };
+    }
+}
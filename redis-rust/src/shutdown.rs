@@ -0,0 +1,243 @@
+//! Orderly shutdown, started by SHUTDOWN or by SIGTERM/SIGINT.
+//!
+//! Unless NOW or FORCE is given, every shard is locked first and the replicas
+//! get up to ten seconds to be sent the writes still queued for them, so none
+//! of those is lost; new writes wait for the locks meanwhile.
+//!
+//! The final snapshot is taken while every shard is locked, and the shutdown
+//! is marked as started before the locks are released. Commands that already
+//! ran are therefore in the snapshot, and commands still waiting for a shard
+//! are refused instead of changing data that would not be saved. The main
+//! loop then stops accepting clients, lets the open connections write their
+//! pending replies and removes the pid file.
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+use crate::latency::add_sample_if_needed;
+use crate::{rdb, Database, Error, ServerState};
+
+/// How often a shutdown checks whether the background save or the replicas
+/// it waits for are done.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a shutdown waits for the replicas, Redis' default `shutdown-timeout`.
+const REPLICA_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How to shut down, from the SHUTDOWN modifiers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShutdownOptions {
+    /// `Some(false)` for NOSAVE, `Some(true)` for SAVE. Without either the
+    /// dataset is saved, like Redis does with its default save points.
+    pub save: Option<bool>,
+    /// Shut down even if the final save fails, without waiting for the replicas.
+    pub force: bool,
+    /// Shut down without waiting for the replicas.
+    pub now: bool,
+}
+
+impl ShutdownOptions {
+    pub fn saves(&self) -> bool {
+        return self.save.unwrap_or(true);
+    }
+}
+
+/// Whether the server is shutting down, watched by the main loop, the cron
+/// task and every connection.
+pub struct Shutdown {
+    started: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        return Shutdown::new();
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (started, _) = watch::channel(false);
+        return Shutdown { started };
+    }
+
+    pub fn is_started(&self) -> bool {
+        return *self.started.borrow();
+    }
+
+    /// Resolves once the shutdown starts, right away if it already has.
+    pub async fn wait(&self) {
+        let mut started: watch::Receiver<bool> = self.started.subscribe();
+        // the sender lives as long as `self`, so this cannot fail
+        let _ = started.wait_for(|started| *started).await;
+    }
+
+    fn start(&self) {
+        self.started.send_replace(true);
+    }
+}
+
+/// Waits until no background save is running, as a saving shutdown must
+/// before it locks the keyspace: a background save still writing would race
+/// the final one for the temporary file.
+pub async fn wait_for_bgsave(state: &ServerState) {
+    while state.bgsave_in_progress.load(Ordering::SeqCst) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Waits until every replica was sent the writes queued for it, or for
+/// `REPLICA_WAIT_TIMEOUT`, unless `options` has NOW or FORCE. The caller holds
+/// every shard, so nothing new is propagated meanwhile.
+pub async fn wait_for_replicas(state: &ServerState, options: ShutdownOptions) {
+    if options.now || options.force || state.replication.replicas_in_sync() {
+        return;
+    }
+
+    println!("Waiting for replicas before shutting down.");
+    let synced = timeout(REPLICA_WAIT_TIMEOUT, async {
+        while !state.replication.replicas_in_sync() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+    match synced.await {
+        Ok(()) => println!("All replicas are in sync."),
+        Err(_) => eprintln!("Lagging replicas, shutting down anyway"),
+    }
+}
+
+/// Takes the final snapshot of `shards`, which must be the whole keyspace
+/// locked by the caller after `wait_for_bgsave` and before
+/// `wait_for_replicas`, then starts the shutdown. On error the server keeps
+/// running.
+pub fn prepare(
+    state: &ServerState,
+    shards: &[&Vec<Database>],
+    options: ShutdownOptions,
+) -> Result<(), Error> {
+    if options.saves() {
+        // a BGSAVE may still have started between the wait and the locks
+        if state.bgsave_in_progress.load(Ordering::SeqCst) {
+            eprintln!("A background save is in progress, can't exit");
+            return Err(Error::new("ERR Errors trying to SHUTDOWN. Check logs."));
+        }
+
+        println!("Saving the final RDB snapshot before exiting.");
+        match rdb::save(shards, &state.modules, &state.rdb_path()) {
+            Ok(fsync) => {
+                add_sample_if_needed(state, "rdb-fsync", fsync);
+                println!("DB saved on disk");
+            }
+            Err(e) if options.force => {
+                eprintln!("Error trying to save the DB, exiting anyway: {}", e.message);
+            }
+            Err(e) => {
+                eprintln!("Error trying to save the DB, can't exit: {}", e.message);
+                return Err(Error::new("ERR Errors trying to SHUTDOWN. Check logs."));
+            }
+        }
+    }
+
+    state.shutdown.start();
+    return Ok(());
+}
+
+/// Writes the process id to `path`, as the `pidfile` config asks.
+pub fn create_pid_file(path: &str) -> Result<(), Error> {
+    std::fs::write(path, format!("{}\n", std::process::id()))?;
+    return Ok(());
+}
+
+pub fn remove_pid_file(path: &str) {
+    if let Err(e) = std::fs::remove_file(path) {
+        eprintln!("Unable to remove the PID file {}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::Config;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn prepare_test() {
+        let config: Config = Config {
+            dir: String::from("/nonexistent/redis-rust"),
+            ..Config::default()
+        };
+        let state: ServerState = ServerState::new(config);
+        let dbs: Vec<Database> = vec![Database::new()];
+
+        // a failed save keeps the server up unless FORCE is given
+        let options: ShutdownOptions = ShutdownOptions::default();
+        assert!(prepare(&state, &[&dbs], options).is_err());
+        assert!(!state.shutdown.is_started());
+
+        let forced: ShutdownOptions = ShutdownOptions {
+            force: true,
+            ..options
+        };
+        assert!(prepare(&state, &[&dbs], forced).is_ok());
+        assert!(state.shutdown.is_started());
+        state.shutdown.wait().await;
+    }
+
+    #[tokio::test]
+    async fn wait_for_bgsave_test() {
+        let state: Arc<ServerState> = Arc::new(ServerState::new(Config::default()));
+        let dbs: Vec<Database> = vec![Database::new()];
+        state.bgsave_in_progress.store(true, Ordering::SeqCst);
+
+        // the wait leaves the runtime free for the clients meanwhile
+        let waiting = tokio::spawn({
+            let state: Arc<ServerState> = Arc::clone(&state);
+            async move { wait_for_bgsave(&state).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        assert!(prepare(&state, &[&dbs], ShutdownOptions::default()).is_err());
+
+        state.bgsave_in_progress.store(false, Ordering::SeqCst);
+        waiting.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wait_for_replicas_test() {
+        let state: Arc<ServerState> = Arc::new(ServerState::new(Config::default()));
+        let replica: Arc<Client> = Arc::new(Client::new(1, "127.0.0.1:50000", "", -1));
+        let mut inbox = replica.take_inbox().unwrap();
+        state.replication.add_replica(&replica, 6380);
+        let set: Vec<Vec<u8>> = vec![b"SET".to_vec(), b"foo".to_vec(), b"bar".to_vec()];
+        state.replication.propagate(0, &set);
+
+        // NOW and FORCE leave the queued writes behind
+        for options in [
+            ShutdownOptions {
+                now: true,
+                ..ShutdownOptions::default()
+            },
+            ShutdownOptions {
+                force: true,
+                ..ShutdownOptions::default()
+            },
+        ] {
+            wait_for_replicas(&state, options).await;
+        }
+
+        let waiting = tokio::spawn({
+            let state: Arc<ServerState> = Arc::clone(&state);
+            async move { wait_for_replicas(&state, ShutdownOptions::default()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        // the SELECT and the SET reach the replica's link
+        while inbox.try_recv().is_ok() {
+            replica.written();
+        }
+        waiting.await.unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use crate::redis_parser::RedisType;
+
+/// Arguments kept per entry; the rest are summarized in a final argument.
+const SLOWLOG_ENTRY_MAX_ARGC: usize = 32;
+/// Bytes kept per argument; longer arguments are truncated.
+const SLOWLOG_ENTRY_MAX_STRING: usize = 128;
+
+/// A command that ran for longer than `slowlog-log-slower-than`.
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// Unix time in seconds when the command was executed.
+    pub time: u64,
+    pub duration_us: u64,
+    pub args: Vec<Vec<u8>>,
+    pub client_addr: String,
+    pub client_name: String,
+}
+
+/// Bounded log of slow commands, newest first, as shown by SLOWLOG GET.
+#[derive(Default)]
+pub struct SlowLog {
+    entries: VecDeque<SlowLogEntry>,
+    next_id: u64,
+}
+
+impl SlowLog {
+    pub fn new() -> Self {
+        return SlowLog::default();
+    }
+
+    /// Records a command, dropping the oldest entries beyond `max_len`.
+    pub fn push(
+        &mut self,
+        args: &[Vec<u8>],
+        time: u64,
+        duration_us: u64,
+        client_addr: &str,
+        client_name: &str,
+        max_len: usize,
+    ) {
+        self.entries.push_front(SlowLogEntry {
+            id: self.next_id,
+            time,
+            duration_us,
+            args: truncate_args(args),
+            client_addr: client_addr.to_string(),
+            client_name: client_name.to_string(),
+        });
+        self.next_id += 1;
+        self.entries.truncate(max_len);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    /// Empties the log; entry ids keep increasing.
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The `count` most recent entries in SLOWLOG GET format.
+    pub fn get(&self, count: usize) -> RedisType<'static> {
+        let entries: Vec<RedisType> = self
+            .entries
+            .iter()
+            .take(count)
+            .map(|e| {
+                let args: Vec<RedisType> =
+                    e.args.iter().cloned().map(RedisType::BulkBytes).collect();
+                return RedisType::Array(Box::new(vec![
+                    RedisType::Integer(e.id.to_string()),
+                    RedisType::Integer(e.time.to_string()),
+                    RedisType::Integer(e.duration_us.to_string()),
+                    RedisType::Array(Box::new(args)),
+                    RedisType::BulkString(e.client_addr.clone()),
+                    RedisType::BulkString(e.client_name.clone()),
+                ]));
+            })
+            .collect();
+        return RedisType::Array(Box::new(entries));
+    }
+}
+
+/// Copies the arguments, shortening very long commands and values like Redis does.
+fn truncate_args(args: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut kept: Vec<Vec<u8>> = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i == SLOWLOG_ENTRY_MAX_ARGC - 1 && args.len() > SLOWLOG_ENTRY_MAX_ARGC {
+            kept.push(format!("... ({} more arguments)", args.len() - i).into_bytes());
+            break;
+        }
+
+        if arg.len() > SLOWLOG_ENTRY_MAX_STRING {
+            let mut short: Vec<u8> = arg[..SLOWLOG_ENTRY_MAX_STRING].to_vec();
+            short.extend_from_slice(
+                format!("... ({} more bytes)", arg.len() - SLOWLOG_ENTRY_MAX_STRING).as_bytes(),
+            );
+            kept.push(short);
+        } else {
+            kept.push(arg.clone());
+        }
+    }
+    return kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_trim_test() {
+        let mut log: SlowLog = SlowLog::new();
+        for i in 0..5u64 {
+            log.push(
+                &[b"SET".to_vec(), i.to_string().into_bytes()],
+                100,
+                i,
+                "1.2.3.4:5",
+                "",
+                3,
+            );
+        }
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.entries[0].id, 4);
+        assert_eq!(log.entries[2].id, 2);
+
+        log.reset();
+        log.push(&[b"PING".to_vec()], 100, 1, "", "", 3);
+        assert_eq!(log.entries[0].id, 5);
+    }
+
+    #[test]
+    fn truncate_args_test() {
+        let args: Vec<Vec<u8>> = (0..40).map(|i| i.to_string().into_bytes()).collect();
+        let kept: Vec<Vec<u8>> = truncate_args(&args);
+        assert_eq!(kept.len(), 32);
+        assert_eq!(kept[31], b"... (9 more arguments)".to_vec());
+
+        let kept: Vec<Vec<u8>> = truncate_args(&[vec![b'x'; 130]]);
+        assert_eq!(kept[0].len(), 128 + "... (2 more bytes)".len());
+    }
+}
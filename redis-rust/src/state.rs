@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64};
+use std::sync::{Mutex, RwLock};
+
+use crate::client::ClientRegistry;
+use crate::db::now_ms;
+use crate::eviction::EvictionState;
+use crate::keyspace::{Keyspace, Shards};
+use crate::latency::LatencyMonitor;
+use crate::lazyfree::LazyFree;
+use crate::module::Modules;
+use crate::multi::Watches;
+use crate::pubsub::PubSub;
+use crate::random::random_hex;
+use crate::replication::Replication;
+use crate::shutdown::Shutdown;
+use crate::slowlog::SlowLog;
+use crate::tracking::Tracking;
+use crate::{Config, Stats};
+
+/// State shared by every connection of a running server.
+pub struct ServerState {
+    pub keyspace: Keyspace,
+    pub config: RwLock<Config>,
+    /// Unix time in seconds of the last successful RDB save.
+    pub lastsave: AtomicU64,
+    pub bgsave_in_progress: AtomicBool,
+    pub last_bgsave_ok: AtomicBool,
+    /// Duration of the last background save in seconds, -1 if there was none.
+    pub last_bgsave_time_sec: AtomicI64,
+    /// Total of the per-database dirty counters when the last save started.
+    pub dirty_at_last_save: AtomicU64,
+    pub stats: Stats,
+    pub eviction: Mutex<EvictionState>,
+    pub lazyfree: LazyFree,
+    /// The modules this server loaded.
+    pub modules: Modules,
+    pub clients: ClientRegistry,
+    pub pubsub: PubSub,
+    pub slowlog: Mutex<SlowLog>,
+    pub latency: Mutex<LatencyMonitor>,
+    pub tracking: Tracking,
+    pub replication: Replication,
+    pub watches: Watches,
+    pub shutdown: Shutdown,
+    /// Random identifier of this server instance, as reported by INFO.
+    pub run_id: String,
+}
+
+impl ServerState {
+    pub fn new(config: Config) -> Self {
+        return ServerState {
+            keyspace: Keyspace::new(&config),
+            config: RwLock::new(config),
+            lastsave: AtomicU64::new(now_ms() / 1000),
+            bgsave_in_progress: AtomicBool::new(false),
+            last_bgsave_ok: AtomicBool::new(true),
+            last_bgsave_time_sec: AtomicI64::new(-1),
+            dirty_at_last_save: AtomicU64::new(0),
+            stats: Stats::new(),
+            eviction: Mutex::new(EvictionState::new()),
+            lazyfree: LazyFree::new(),
+            modules: Modules::new(),
+            clients: ClientRegistry::new(),
+            pubsub: PubSub::new(),
+            slowlog: Mutex::new(SlowLog::new()),
+            latency: Mutex::new(LatencyMonitor::new()),
+            tracking: Tracking::new(),
+            replication: Replication::new(),
+            watches: Watches::new(),
+            shutdown: Shutdown::new(),
+            run_id: random_hex(40),
+        };
+    }
+
+    /// Whether databases must record the keys that change, for CLIENT TRACKING and WATCH.
+    pub fn records_changes(&self) -> bool {
+        return self.tracking.is_active() || self.watches.is_active();
+    }
+
+    /// Reports the keys changed while `shards` were held to the clients caching
+    /// or watching them. `origin` is the client that made the changes, 0 for the server.
+    pub fn signal_changes(&self, shards: &mut Shards, origin: u64) {
+        let (changed, flushed) = shards.take_changes();
+        if changed.is_empty() && flushed.is_empty() {
+            return;
+        }
+        self.watches
+            .touch(&changed, &flushed, |db, key| shards.contains(db, key));
+        let keys: Vec<Vec<u8>> = changed.into_iter().map(|(_, key)| key).collect();
+        self.tracking
+            .invalidate(self, keys, !flushed.is_empty(), origin);
+    }
+
+    pub fn rdb_path(&self) -> std::path::PathBuf {
+        let config = self.config.read().unwrap();
+        return std::path::Path::new(&config.dir).join(&config.dbfilename);
+    }
+}
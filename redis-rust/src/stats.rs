@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of samples averaged for the instantaneous metrics, as in Redis.
+const METRIC_SAMPLES: usize = 16;
+
+/// Upper bounds in microseconds of the command latency histogram buckets,
+/// besides the last one, which takes every slower call.
+pub const LATENCY_BUCKETS_US: &[u64] = &[
+    10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000,
+    500_000, 1_000_000,
+];
+
+/// Server-wide counters maintained by the connection handler and reported by INFO.
+pub struct Stats {
+    pub started: Instant,
+    pub total_connections_received: AtomicU64,
+    pub connected_clients: AtomicU64,
+    pub total_commands_processed: AtomicU64,
+    pub total_net_input_bytes: AtomicU64,
+    pub total_net_output_bytes: AtomicU64,
+    pub evicted_keys: AtomicU64,
+    /// Calls and latencies of every command run so far, by command name.
+    pub commands: Mutex<HashMap<&'static str, CommandStats>>,
+    /// Highest dataset size seen, sampled by the cron and by INFO.
+    pub used_memory_peak: AtomicU64,
+    ops_per_sec: Mutex<InstantaneousMetric>,
+    input_per_sec: Mutex<InstantaneousMetric>,
+    output_per_sec: Mutex<InstantaneousMetric>,
+}
+
+/// Calls of one command, counted by the connection handler.
+#[derive(Clone, Debug)]
+pub struct CommandStats {
+    pub calls: u64,
+    /// Calls answered with an error.
+    pub failed_calls: u64,
+    /// Total time spent in the command, queueing for its shards included.
+    pub usec: u64,
+    /// Calls per bucket of `LATENCY_BUCKETS_US`, not cumulative, plus the
+    /// calls slower than the last bound.
+    pub latency_buckets: [u64; LATENCY_BUCKETS_US.len() + 1],
+}
+
+impl Default for CommandStats {
+    fn default() -> Self {
+        return CommandStats {
+            calls: 0,
+            failed_calls: 0,
+            usec: 0,
+            latency_buckets: [0; LATENCY_BUCKETS_US.len() + 1],
+        };
+    }
+}
+
+impl CommandStats {
+    pub fn record(&mut self, elapsed: Duration, failed: bool) {
+        let usec: u64 = elapsed.as_micros() as u64;
+        self.calls += 1;
+        self.failed_calls += failed as u64;
+        self.usec += usec;
+        self.latency_buckets[LATENCY_BUCKETS_US.partition_point(|bound| *bound < usec)] += 1;
+    }
+}
+
+/// A rate derived from a monotonically increasing counter, averaged over recent samples.
+#[derive(Default)]
+struct InstantaneousMetric {
+    last_sample: Option<(Instant, u64)>,
+    samples: [u64; METRIC_SAMPLES],
+    index: usize,
+}
+
+impl InstantaneousMetric {
+    fn track(&mut self, now: Instant, value: u64) {
+        if let Some((at, last)) = self.last_sample {
+            let elapsed_ms: u128 = now.duration_since(at).as_millis();
+            if let Some(rate) = (value.saturating_sub(last) as u128 * 1000).checked_div(elapsed_ms)
+            {
+                self.samples[self.index] = rate as u64;
+                self.index = (self.index + 1) % METRIC_SAMPLES;
+            }
+        }
+        self.last_sample = Some((now, value));
+    }
+
+    fn get(&self) -> u64 {
+        return self.samples.iter().sum::<u64>() / METRIC_SAMPLES as u64;
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        return Stats::new();
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        return Stats {
+            started: Instant::now(),
+            total_connections_received: AtomicU64::new(0),
+            connected_clients: AtomicU64::new(0),
+            total_commands_processed: AtomicU64::new(0),
+            total_net_input_bytes: AtomicU64::new(0),
+            total_net_output_bytes: AtomicU64::new(0),
+            evicted_keys: AtomicU64::new(0),
+            commands: Mutex::new(HashMap::new()),
+            used_memory_peak: AtomicU64::new(0),
+            ops_per_sec: Mutex::new(InstantaneousMetric::default()),
+            input_per_sec: Mutex::new(InstantaneousMetric::default()),
+            output_per_sec: Mutex::new(InstantaneousMetric::default()),
+        };
+    }
+
+    /// Records a sample of every instantaneous metric; called from the server cron.
+    pub fn track_instantaneous_metrics(&self) {
+        let now: Instant = Instant::now();
+        self.ops_per_sec
+            .lock()
+            .unwrap()
+            .track(now, self.total_commands_processed.load(Ordering::Relaxed));
+        self.input_per_sec
+            .lock()
+            .unwrap()
+            .track(now, self.total_net_input_bytes.load(Ordering::Relaxed));
+        self.output_per_sec
+            .lock()
+            .unwrap()
+            .track(now, self.total_net_output_bytes.load(Ordering::Relaxed));
+    }
+
+    /// Counts a call of the command `name` that took `elapsed`.
+    pub fn record_command(&self, name: &'static str, elapsed: Duration, failed: bool) {
+        self.commands
+            .lock()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .record(elapsed, failed);
+    }
+
+    /// The statistics of every command called so far, sorted by name.
+    pub fn command_stats(&self) -> Vec<(&'static str, CommandStats)> {
+        let mut stats: Vec<(&'static str, CommandStats)> = self
+            .commands
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| (*name, stats.clone()))
+            .collect();
+        stats.sort_unstable_by_key(|(name, _)| *name);
+        return stats;
+    }
+
+    pub fn instantaneous_ops_per_sec(&self) -> u64 {
+        return self.ops_per_sec.lock().unwrap().get();
+    }
+
+    pub fn instantaneous_input_kbps(&self) -> f64 {
+        return self.input_per_sec.lock().unwrap().get() as f64 / 1024.0;
+    }
+
+    pub fn instantaneous_output_kbps(&self) -> f64 {
+        return self.output_per_sec.lock().unwrap().get() as f64 / 1024.0;
+    }
+
+    /// Clears the counters, as done by CONFIG RESETSTAT.
+    pub fn reset(&self) {
+        self.total_connections_received.store(0, Ordering::Relaxed);
+        self.total_commands_processed.store(0, Ordering::Relaxed);
+        self.total_net_input_bytes.store(0, Ordering::Relaxed);
+        self.total_net_output_bytes.store(0, Ordering::Relaxed);
+        self.evicted_keys.store(0, Ordering::Relaxed);
+        self.commands.lock().unwrap().clear();
+        self.used_memory_peak.store(0, Ordering::Relaxed);
+        *self.ops_per_sec.lock().unwrap() = InstantaneousMetric::default();
+        *self.input_per_sec.lock().unwrap() = InstantaneousMetric::default();
+        *self.output_per_sec.lock().unwrap() = InstantaneousMetric::default();
+    }
+}
@@ -0,0 +1,130 @@
+//! TLS for the `tls-port` listener, on top of rustls.
+//!
+//! The handshake happens before a connection is handed to the client handler,
+//! which then reads and writes the decrypted stream exactly like a plaintext
+//! one. Built only with the `tls` feature; without it the options are still
+//! recognised but setting `tls-port` stops the server at startup.
+
+#[cfg(feature = "tls")]
+use std::fs::File;
+#[cfg(feature = "tls")]
+use std::io::BufReader;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+#[cfg(feature = "tls")]
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+#[cfg(feature = "tls")]
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+#[cfg(feature = "tls")]
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+#[cfg(feature = "tls")]
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+
+#[cfg(feature = "tls")]
+use crate::Config;
+use crate::Error;
+
+/// Whether TLS clients must present a certificate signed by `tls-ca-cert-file`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TlsAuthClients {
+    Yes,
+    No,
+    /// A certificate is checked if the client sends one.
+    Optional,
+}
+
+impl TlsAuthClients {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            TlsAuthClients::Yes => "yes",
+            TlsAuthClients::No => "no",
+            TlsAuthClients::Optional => "optional",
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        return match name.to_lowercase().as_str() {
+            "yes" => Some(TlsAuthClients::Yes),
+            "no" => Some(TlsAuthClients::No),
+            "optional" => Some(TlsAuthClients::Optional),
+            _ => None,
+        };
+    }
+}
+
+fn config_error(e: impl std::fmt::Display) -> Error {
+    return Error {
+        message: format!("Failed to configure TLS: {}", e),
+    };
+}
+
+#[cfg(feature = "tls")]
+fn open(name: &str, path: &str) -> Result<BufReader<File>, Error> {
+    if path.is_empty() {
+        return Err(config_error(format!("{} is not set", name)));
+    }
+    return match File::open(path) {
+        Ok(file) => Ok(BufReader::new(file)),
+        Err(e) => Err(config_error(format!("{} {}: {}", name, path, e))),
+    };
+}
+
+#[cfg(feature = "tls")]
+fn load_certs(name: &str, path: &str) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut open(name, path)?)
+        .collect::<Result<_, _>>()
+        .map_err(config_error)?;
+    if certs.is_empty() {
+        return Err(config_error(format!("no certificate in {} {}", name, path)));
+    }
+    return Ok(certs);
+}
+
+/// The acceptor for the TLS listener, built from the `tls-*` options.
+#[cfg(feature = "tls")]
+pub fn acceptor(config: &Config) -> Result<TlsAcceptor, Error> {
+    let certs: Vec<CertificateDer<'static>> = load_certs("tls-cert-file", &config.tls_cert_file)?;
+    let key: PrivateKeyDer<'static> =
+        match rustls_pemfile::private_key(&mut open("tls-key-file", &config.tls_key_file)?) {
+            Ok(Some(key)) => key,
+            Ok(None) => {
+                return Err(config_error(format!(
+                    "no private key in tls-key-file {}",
+                    config.tls_key_file
+                )))
+            }
+            Err(e) => return Err(config_error(e)),
+        };
+
+    let provider: Arc<CryptoProvider> = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(config_error)?;
+    let builder = match config.tls_auth_clients {
+        TlsAuthClients::No => builder.with_no_client_auth(),
+        auth => {
+            let mut roots: RootCertStore = RootCertStore::empty();
+            for cert in load_certs("tls-ca-cert-file", &config.tls_ca_cert_file)? {
+                roots.add(cert).map_err(config_error)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match auth {
+                TlsAuthClients::Optional => verifier.allow_unauthenticated(),
+                _ => verifier,
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(config_error)?)
+        }
+    };
+
+    let server: ServerConfig = builder.with_single_cert(certs, key).map_err(config_error)?;
+    return Ok(TlsAcceptor::from(Arc::new(server)));
+}
+
+/// Without the `tls` feature there is no TLS listener to build.
+#[cfg(not(feature = "tls"))]
+pub fn unsupported() -> Error {
+    return config_error("this build has no TLS support, rebuild with the 'tls' feature");
+}
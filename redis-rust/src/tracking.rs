@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::client::Client;
+use crate::redis_parser::{RedisType, RESP3};
+use crate::ServerState;
+
+/// Channel invalidations are published on for RESP2 clients, through REDIRECT.
+pub const INVALIDATE_CHANNEL: &[u8] = b"__redis__:invalidate";
+
+/// The CLIENT TRACKING settings of one client.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackingOptions {
+    pub enabled: bool,
+    /// Id of the client receiving the invalidations, 0 for the client itself.
+    pub redirect: u64,
+    /// Set once the redirect target disconnected.
+    pub broken_redirect: bool,
+    /// Broadcast mode: every change under `prefixes` is reported, reads are not recorded.
+    pub bcast: bool,
+    pub prefixes: Vec<Vec<u8>>,
+    pub optin: bool,
+    pub optout: bool,
+    /// Changes made by this very client are not reported back to it.
+    pub noloop: bool,
+    /// CLIENT CACHING was called for the next command (YES with OPTIN, NO with OPTOUT).
+    pub caching: bool,
+}
+
+impl TrackingOptions {
+    /// Whether keys read by the current command are to be remembered.
+    pub fn tracks_reads(&self) -> bool {
+        // OPTIN tracks only after CLIENT CACHING YES, OPTOUT all but after CLIENT CACHING NO
+        let wanted: bool = match (self.optin, self.optout) {
+            (true, _) => self.caching,
+            (_, true) => !self.caching,
+            _ => true,
+        };
+        return self.enabled && !self.bcast && wanted;
+    }
+}
+
+/// Which clients cached which keys, shared by the whole server.
+///
+/// Like in Redis, keys are tracked by name regardless of the database, and a
+/// key is forgotten as soon as its invalidation has been sent.
+#[derive(Default)]
+pub struct Tracking {
+    table: Mutex<TrackingTable>,
+    /// Number of clients with tracking on, checked before taking the lock.
+    clients: AtomicUsize,
+}
+
+#[derive(Default)]
+struct TrackingTable {
+    /// Keys read by clients in the default mode.
+    keys: HashMap<Vec<u8>, BTreeSet<u64>>,
+    /// BCAST prefixes and the clients registered for each.
+    prefixes: BTreeMap<Vec<u8>, BTreeSet<u64>>,
+}
+
+impl Tracking {
+    pub fn new() -> Self {
+        return Tracking::default();
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.clients.load(Ordering::Relaxed) > 0;
+    }
+
+    /// Turns tracking on for `client` with `options`, which must have `enabled` set.
+    pub fn enable(&self, client: &Client, options: TrackingOptions) {
+        let mut table = self.table.lock().unwrap();
+        let mut was_enabled: bool = false;
+        client.update(|meta| {
+            was_enabled = meta.tracking.enabled;
+            meta.tracking = options.clone();
+        });
+        if !was_enabled {
+            self.clients.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if options.bcast {
+            // BCAST without a prefix covers every key
+            let prefixes: Vec<Vec<u8>> = match options.prefixes.is_empty() {
+                true => vec![Vec::new()],
+                false => options.prefixes,
+            };
+            for prefix in prefixes {
+                table.prefixes.entry(prefix).or_default().insert(client.id);
+            }
+        }
+    }
+
+    /// Turns tracking off for `client`; keys it read are dropped lazily.
+    pub fn disable(&self, client: &Client) {
+        let mut table = self.table.lock().unwrap();
+        let mut was_enabled: bool = false;
+        client.update(|meta| {
+            was_enabled = meta.tracking.enabled;
+            meta.tracking = TrackingOptions::default();
+        });
+        if !was_enabled {
+            return;
+        }
+        self.clients.fetch_sub(1, Ordering::Relaxed);
+
+        table.prefixes.retain(|_, ids| {
+            ids.remove(&client.id);
+            return !ids.is_empty();
+        });
+    }
+
+    /// Records that `client` may now cache `keys`.
+    pub fn remember_keys(&self, client_id: u64, keys: &[&[u8]]) {
+        let mut table = self.table.lock().unwrap();
+        for key in keys {
+            table
+                .keys
+                .entry(key.to_vec())
+                .or_default()
+                .insert(client_id);
+        }
+    }
+
+    /// A BCAST prefix of `client_id` overlapping `prefix`; Redis refuses such pairs.
+    pub fn overlapping_prefix(&self, client_id: u64, prefix: &[u8]) -> Option<Vec<u8>> {
+        let table = self.table.lock().unwrap();
+        return table
+            .prefixes
+            .iter()
+            .filter(|(_, ids)| ids.contains(&client_id))
+            .map(|(p, _)| p)
+            .find(|p| !p.is_empty() && (p.starts_with(prefix) || prefix.starts_with(p)))
+            .cloned();
+    }
+
+    /// Sends the invalidations for `modified` keys, or for everything after a
+    /// flush. `origin` is the client that made the changes, 0 for the server.
+    pub fn invalidate(
+        &self,
+        state: &ServerState,
+        modified: Vec<Vec<u8>>,
+        flushed: bool,
+        origin: u64,
+    ) {
+        if !self.is_active() {
+            return;
+        }
+
+        // a flush invalidates everything at once, with a null key list
+        if flushed {
+            let ids: Vec<u64> = {
+                let mut table = self.table.lock().unwrap();
+                table.keys.clear();
+                state
+                    .clients
+                    .list()
+                    .iter()
+                    .filter(|c| c.meta().tracking.enabled)
+                    .map(|c| c.id)
+                    .collect()
+            };
+            for id in ids {
+                send_invalidation(state, id, None, origin);
+            }
+            return;
+        }
+
+        let mut pending: BTreeMap<u64, Vec<Vec<u8>>> = BTreeMap::new();
+        {
+            let mut table = self.table.lock().unwrap();
+            for key in modified {
+                if let Some(ids) = table.keys.remove(&key) {
+                    for id in ids {
+                        pending.entry(id).or_default().push(key.clone());
+                    }
+                }
+                for (prefix, ids) in table.prefixes.iter() {
+                    if key.starts_with(prefix) {
+                        for id in ids {
+                            pending.entry(*id).or_default().push(key.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        for (id, mut keys) in pending {
+            keys.sort();
+            keys.dedup();
+            send_invalidation(state, id, Some(keys), origin);
+        }
+    }
+}
+
+/// Delivers an invalidation to the client `id`, or to its redirect target.
+fn send_invalidation(state: &ServerState, id: u64, keys: Option<Vec<Vec<u8>>>, origin: u64) {
+    let client: Arc<Client> = match state.clients.get(id) {
+        Some(c) => c,
+        None => return,
+    };
+    let options: TrackingOptions = client.meta().tracking;
+    if !options.enabled || (options.noloop && id == origin) {
+        return;
+    }
+
+    let target: Arc<Client> = match options.redirect {
+        0 => Arc::clone(&client),
+        redirect => match state.clients.get(redirect) {
+            Some(t) => t,
+            None => {
+                if !options.broken_redirect {
+                    client.update(|meta| meta.tracking.broken_redirect = true);
+                    if client.protocol() == RESP3 {
+                        client.send(RedisType::Push(Box::new(vec![
+                            RedisType::BulkString(String::from("tracking-redir-broken")),
+                            RedisType::Integer(redirect.to_string()),
+                        ])));
+                    }
+                }
+                return;
+            }
+        },
+    };
+
+    let keys: RedisType = match keys {
+        Some(keys) => RedisType::Array(Box::new(
+            keys.into_iter().map(RedisType::BulkBytes).collect(),
+        )),
+        None => RedisType::NullBulk,
+    };
+
+    if target.protocol() == RESP3 {
+        target.send(RedisType::Push(Box::new(vec![
+            RedisType::BulkString(String::from("invalidate")),
+            keys,
+        ])));
+    } else if target.meta().channels.contains(INVALIDATE_CHANNEL) {
+        // a RESP2 connection can only take it as a message on the invalidation channel
+        target.send(RedisType::Push(Box::new(vec![
+            RedisType::BulkString(String::from("message")),
+            RedisType::BulkBytes(INVALIDATE_CHANNEL.to_vec()),
+            keys,
+        ])));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn invalidation(keys: Option<&[&[u8]]>) -> RedisType<'static> {
+        let keys: RedisType = match keys {
+            Some(keys) => RedisType::Array(Box::new(
+                keys.iter()
+                    .map(|k| RedisType::BulkBytes(k.to_vec()))
+                    .collect(),
+            )),
+            None => RedisType::NullBulk,
+        };
+        return RedisType::Push(Box::new(vec![
+            RedisType::BulkString(String::from("invalidate")),
+            keys,
+        ]));
+    }
+
+    #[test]
+    fn invalidate_test() {
+        let state: ServerState = ServerState::new(Config::default());
+        let reader: Arc<Client> = state.clients.register("127.0.0.1:5000", "", -1);
+        let mut inbox = reader.take_inbox().unwrap();
+        reader.set_protocol(RESP3);
+        let options: TrackingOptions = TrackingOptions {
+            enabled: true,
+            ..TrackingOptions::default()
+        };
+        state.tracking.enable(&reader, options);
+
+        state.tracking.remember_keys(reader.id, &[b"foo"]);
+        state
+            .tracking
+            .invalidate(&state, vec![b"foo".to_vec(), b"bar".to_vec()], false, 0);
+        assert_eq!(inbox.try_recv().unwrap(), invalidation(Some(&[b"foo"])));
+
+        // a key is reported once, until it is read again
+        state
+            .tracking
+            .invalidate(&state, vec![b"foo".to_vec()], false, 0);
+        assert!(inbox.try_recv().is_err());
+
+        state.tracking.invalidate(&state, Vec::new(), true, 0);
+        assert_eq!(inbox.try_recv().unwrap(), invalidation(None));
+
+        state.tracking.disable(&reader);
+        assert!(!state.tracking.is_active());
+    }
+
+    #[test]
+    fn bcast_test() {
+        let state: ServerState = ServerState::new(Config::default());
+        let client: Arc<Client> = state.clients.register("127.0.0.1:5000", "", -1);
+        let mut inbox = client.take_inbox().unwrap();
+        client.set_protocol(RESP3);
+        let options: TrackingOptions = TrackingOptions {
+            enabled: true,
+            bcast: true,
+            prefixes: vec![b"user:".to_vec()],
+            noloop: true,
+            ..TrackingOptions::default()
+        };
+        state.tracking.enable(&client, options);
+        assert_eq!(
+            state.tracking.overlapping_prefix(client.id, b"user:1"),
+            Some(b"user:".to_vec())
+        );
+
+        let changed: Vec<Vec<u8>> = vec![b"user:1".to_vec(), b"order:1".to_vec()];
+        state.tracking.invalidate(&state, changed, false, 0);
+        assert_eq!(inbox.try_recv().unwrap(), invalidation(Some(&[b"user:1"])));
+
+        // NOLOOP hides the client's own changes
+        let changed: Vec<Vec<u8>> = vec![b"user:2".to_vec()];
+        state.tracking.invalidate(&state, changed, false, client.id);
+        assert!(inbox.try_recv().is_err());
+    }
+}
@@ -0,0 +1,821 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::intset::{parse_member, Intset};
+use crate::listpack::Listpack;
+use crate::module::ModuleObject;
+use crate::Error;
+
+/// Per-element bookkeeping cost added to the payload when estimating the memory
+/// of an aggregate value in a full encoding.
+pub const ELEMENT_OVERHEAD: usize = 16;
+
+/// Bookkeeping cost of a quicklist node besides its listpack.
+const QUICKLIST_NODE_OVERHEAD: usize = 32;
+
+/// Size limits under which Redis keeps sorted sets in a listpack (the
+/// `zset-max-listpack-*` defaults). Sorted sets always use the skiplist
+/// structure here; only the encoding OBJECT ENCODING reports follows them.
+const ZSET_MAX_LISTPACK_ENTRIES: usize = 128;
+const ZSET_MAX_LISTPACK_VALUE: usize = 64;
+
+/// Longest string stored with the embedded string encoding.
+const EMBSTR_MAX_LEN: usize = 44;
+
+/// The value held by a key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(Vec<u8>),
+    List(List),
+    Set(Set),
+    Hash(Hash),
+    ZSet(SortedSet),
+    /// A value of a type added by a module, see `module`.
+    Module(ModuleObject),
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(value: Vec<u8>) -> Self {
+        return Value::String(value);
+    }
+}
+
+pub fn wrong_type_error() -> Error {
+    return Error::new("WRONGTYPE Operation against a key holding the wrong kind of value");
+}
+
+/// The `*-max-listpack-*` and `set-max-intset-entries` parameters, copied into
+/// every database. Collections within them use the compact encodings and are
+/// converted to the full ones for good once they outgrow them.
+#[derive(Clone, Copy, Debug)]
+pub struct EncodingLimits {
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+    /// A positive value caps the entries of a list node; -1 to -5 cap its size
+    /// at 4, 8, 16, 32 or 64 kb.
+    pub list_max_listpack_size: i64,
+}
+
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        return EncodingLimits {
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+        };
+    }
+}
+
+impl EncodingLimits {
+    /// Whether `item` can be added to the list node `node`.
+    fn list_node_fits(&self, node: &Listpack, item: &[u8]) -> bool {
+        if self.list_max_listpack_size >= 0 {
+            return node.len() < (self.list_max_listpack_size as usize).max(1);
+        }
+        let level: u32 = self.list_max_listpack_size.unsigned_abs().min(5) as u32;
+        // the new entry also needs its length header
+        return node.bytes() + item.len() + 2 <= 4096 << (level - 1);
+    }
+}
+
+impl Value {
+    /// The name TYPE reports.
+    pub fn type_name(&self) -> &'static str {
+        return match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::Hash(_) => "hash",
+            Value::ZSet(_) => "zset",
+            Value::Module(m) => m.value().type_name(),
+        };
+    }
+
+    /// The encoding OBJECT ENCODING reports.
+    pub fn encoding(&self) -> &'static str {
+        return match self {
+            Value::String(s) if parse_member(s).is_some() => "int",
+            Value::String(s) if s.len() <= EMBSTR_MAX_LEN => "embstr",
+            Value::String(_) => "raw",
+            Value::List(l) => l.encoding(),
+            Value::Set(s) => s.encoding(),
+            Value::Hash(h) => h.encoding(),
+            Value::ZSet(z) => {
+                let longest: usize = z.iter().map(|(m, _)| m.len()).max().unwrap_or(0);
+                match z.len() <= ZSET_MAX_LISTPACK_ENTRIES && longest <= ZSET_MAX_LISTPACK_VALUE {
+                    true => "listpack",
+                    false => "skiplist",
+                }
+            }
+            Value::Module(_) => "raw",
+        };
+    }
+
+    /// Estimated bytes used by the value. Aggregates are walked, so this is
+    /// linear in their size.
+    pub fn memory(&self) -> usize {
+        return match self {
+            Value::String(s) => s.len(),
+            Value::List(l) => l.memory(),
+            Value::Set(s) => s.memory(),
+            Value::Hash(h) => h.memory(),
+            Value::ZSet(z) => z.iter().map(|(m, _)| m.len() + 8 + ELEMENT_OVERHEAD).sum(),
+            Value::Module(m) => m.value().memory(),
+        };
+    }
+
+    /// Roughly how many allocations dropping the value releases: 1 for the
+    /// compact encodings, which are a single buffer.
+    pub fn free_effort(&self) -> usize {
+        return match self {
+            Value::List(List::Quicklist(ql)) => ql.nodes.len(),
+            Value::Set(Set::Hashtable(s)) => s.len(),
+            Value::Hash(Hash::Hashtable(h)) => h.len(),
+            Value::ZSet(z) => z.len(),
+            _ => 1,
+        };
+    }
+
+    /// Number of elements of an aggregate; 1 for a string or a module value.
+    pub fn len(&self) -> usize {
+        return match self {
+            Value::String(_) | Value::Module(_) => 1,
+            Value::List(l) => l.len(),
+            Value::Set(s) => s.len(),
+            Value::Hash(h) => h.len(),
+            Value::ZSet(z) => z.len(),
+        };
+    }
+
+    /// Whether an aggregate lost its last element; such keys are deleted.
+    pub fn is_empty(&self) -> bool {
+        return match self {
+            Value::String(_) | Value::Module(_) => false,
+            _ => self.len() == 0,
+        };
+    }
+}
+
+/// A list: one listpack while small, then a quicklist of listpack nodes.
+#[derive(Clone, Debug)]
+pub enum List {
+    Listpack(Listpack),
+    Quicklist(Quicklist),
+}
+
+/// Listpack nodes each within `list-max-listpack-size`, like the Redis quicklist.
+#[derive(Clone, Debug, Default)]
+pub struct Quicklist {
+    nodes: VecDeque<Listpack>,
+    len: usize,
+}
+
+impl Quicklist {
+    fn push(&mut self, item: &[u8], front: bool, limits: &EncodingLimits) {
+        let node: Option<&mut Listpack> = match front {
+            true => self.nodes.front_mut(),
+            false => self.nodes.back_mut(),
+        };
+        match node {
+            Some(node) if limits.list_node_fits(node, item) => match front {
+                true => node.push_front(item),
+                false => node.push_back(item),
+            },
+            _ => {
+                let mut node: Listpack = Listpack::new();
+                node.push_back(item);
+                match front {
+                    true => self.nodes.push_front(node),
+                    false => self.nodes.push_back(node),
+                }
+            }
+        }
+        self.len += 1;
+    }
+
+    fn pop(&mut self, front: bool) -> Option<Vec<u8>> {
+        let node: &mut Listpack = match front {
+            true => self.nodes.front_mut()?,
+            false => self.nodes.back_mut()?,
+        };
+        let item: Option<Vec<u8>> = match front {
+            true => node.remove(0),
+            false => node.remove(node.len() - 1),
+        };
+        if node.is_empty() {
+            match front {
+                true => self.nodes.pop_front(),
+                false => self.nodes.pop_back(),
+            };
+        }
+        self.len -= 1;
+        return item;
+    }
+}
+
+impl Default for List {
+    fn default() -> Self {
+        return List::Listpack(Listpack::new());
+    }
+}
+
+impl List {
+    pub fn new() -> Self {
+        return List::default();
+    }
+
+    pub fn from_items<T: AsRef<[u8]>, I: IntoIterator<Item = T>>(
+        items: I,
+        limits: &EncodingLimits,
+    ) -> Self {
+        let mut list: List = List::new();
+        for item in items {
+            list.push(item.as_ref(), false, limits);
+        }
+        return list;
+    }
+
+    pub fn len(&self) -> usize {
+        return match self {
+            List::Listpack(lp) => lp.len(),
+            List::Quicklist(ql) => ql.len,
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Adds `item` at the head or the tail, switching to a quicklist once a
+    /// single listpack would exceed `list-max-listpack-size`.
+    pub fn push(&mut self, item: &[u8], front: bool, limits: &EncodingLimits) {
+        if let List::Listpack(lp) = self {
+            if !limits.list_node_fits(lp, item) {
+                let node: Listpack = std::mem::take(lp);
+                let mut ql: Quicklist = Quicklist::default();
+                if !node.is_empty() {
+                    ql.len = node.len();
+                    ql.nodes.push_back(node);
+                }
+                *self = List::Quicklist(ql);
+            }
+        }
+        match self {
+            List::Listpack(lp) if front => lp.push_front(item),
+            List::Listpack(lp) => lp.push_back(item),
+            List::Quicklist(ql) => ql.push(item, front, limits),
+        }
+    }
+
+    /// Removes and returns the item at the head or the tail.
+    pub fn pop(&mut self, front: bool) -> Option<Vec<u8>> {
+        return match self {
+            List::Listpack(lp) if lp.is_empty() => None,
+            List::Listpack(lp) if front => lp.remove(0),
+            List::Listpack(lp) => lp.remove(lp.len() - 1),
+            List::Quicklist(ql) => ql.pop(front),
+        };
+    }
+
+    /// The items from head to tail.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &[u8]> + '_> {
+        return match self {
+            List::Listpack(lp) => Box::new(lp.iter()),
+            List::Quicklist(ql) => Box::new(ql.nodes.iter().flat_map(|node| node.iter())),
+        };
+    }
+
+    fn encoding(&self) -> &'static str {
+        return match self {
+            List::Listpack(_) => "listpack",
+            List::Quicklist(_) => "quicklist",
+        };
+    }
+
+    fn memory(&self) -> usize {
+        return match self {
+            List::Listpack(lp) => lp.bytes(),
+            List::Quicklist(ql) => ql
+                .nodes
+                .iter()
+                .map(|node| node.bytes() + QUICKLIST_NODE_OVERHEAD)
+                .sum(),
+        };
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        return self.len() == other.len() && self.iter().eq(other.iter());
+    }
+}
+
+/// A set: an intset while it only holds integers, a listpack while small, and
+/// a hash table past the limits.
+#[derive(Clone, Debug)]
+pub enum Set {
+    Intset(Intset),
+    Listpack(Listpack),
+    Hashtable(HashSet<Vec<u8>>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        return Set::Intset(Intset::new());
+    }
+}
+
+impl Set {
+    pub fn new() -> Self {
+        return Set::default();
+    }
+
+    pub fn from_members<T: AsRef<[u8]>, I: IntoIterator<Item = T>>(
+        members: I,
+        limits: &EncodingLimits,
+    ) -> Self {
+        let mut set: Set = Set::new();
+        for member in members {
+            set.insert(member.as_ref(), limits);
+        }
+        return set;
+    }
+
+    pub fn len(&self) -> usize {
+        return match self {
+            Set::Intset(is) => is.len(),
+            Set::Listpack(lp) => lp.len(),
+            Set::Hashtable(ht) => ht.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        return match self {
+            Set::Intset(is) => parse_member(member).is_some_and(|n| is.contains(n)),
+            Set::Listpack(lp) => lp.position(member, 1).is_some(),
+            Set::Hashtable(ht) => ht.contains(member),
+        };
+    }
+
+    /// Adds `member`, returning false when it was already there. The set moves
+    /// to a larger encoding when the new member does not fit the current one.
+    pub fn insert(&mut self, member: &[u8], limits: &EncodingLimits) -> bool {
+        if self.contains(member) {
+            return false;
+        }
+
+        let len: usize = self.len() + 1;
+        let fits_listpack = |longest: usize| -> bool {
+            return len <= limits.set_max_listpack_entries
+                && longest <= limits.set_max_listpack_value;
+        };
+        match self {
+            Set::Intset(is) => {
+                if let Some(n) = parse_member(member) {
+                    if len <= limits.set_max_intset_entries {
+                        is.insert(n);
+                        return true;
+                    }
+                }
+                let members: Vec<Vec<u8>> = is.iter().map(|n| n.to_string().into_bytes()).collect();
+                let longest: usize = members.iter().map(|m| m.len()).max().unwrap_or(0);
+                *self = match fits_listpack(longest.max(member.len())) {
+                    true => {
+                        let mut lp: Listpack = Listpack::new();
+                        members.iter().for_each(|m| lp.push_back(m));
+                        Set::Listpack(lp)
+                    }
+                    false => Set::Hashtable(members.into_iter().collect()),
+                };
+            }
+            Set::Listpack(lp) if !fits_listpack(member.len()) => {
+                *self = Set::Hashtable(lp.iter().map(|m| m.to_vec()).collect());
+            }
+            _ => (),
+        }
+
+        match self {
+            Set::Intset(_) => unreachable!("integers are added above"),
+            Set::Listpack(lp) => lp.push_back(member),
+            Set::Hashtable(ht) => {
+                ht.insert(member.to_vec());
+            }
+        }
+        return true;
+    }
+
+    /// The members, in no particular order.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Cow<'_, [u8]>> + '_> {
+        return match self {
+            Set::Intset(is) => Box::new(is.iter().map(|n| Cow::Owned(n.to_string().into_bytes()))),
+            Set::Listpack(lp) => Box::new(lp.iter().map(Cow::Borrowed)),
+            Set::Hashtable(ht) => Box::new(ht.iter().map(|m| Cow::Borrowed(m.as_slice()))),
+        };
+    }
+
+    fn encoding(&self) -> &'static str {
+        return match self {
+            Set::Intset(_) => "intset",
+            Set::Listpack(_) => "listpack",
+            Set::Hashtable(_) => "hashtable",
+        };
+    }
+
+    fn memory(&self) -> usize {
+        return match self {
+            Set::Intset(is) => is.bytes(),
+            Set::Listpack(lp) => lp.bytes(),
+            Set::Hashtable(ht) => ht.iter().map(|m| m.len() + ELEMENT_OVERHEAD).sum(),
+        };
+    }
+}
+
+impl PartialEq for Set {
+    fn eq(&self, other: &Self) -> bool {
+        return self.len() == other.len() && self.iter().all(|m| other.contains(&m));
+    }
+}
+
+/// A hash: fields and values alternating in a listpack while small, a hash
+/// table past the limits.
+#[derive(Clone, Debug)]
+pub enum Hash {
+    Listpack(Listpack),
+    Hashtable(HashMap<Vec<u8>, Vec<u8>>),
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        return Hash::Listpack(Listpack::new());
+    }
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        return Hash::default();
+    }
+
+    pub fn from_pairs<T: AsRef<[u8]>, I: IntoIterator<Item = (T, T)>>(
+        pairs: I,
+        limits: &EncodingLimits,
+    ) -> Self {
+        let mut hash: Hash = Hash::new();
+        for (field, value) in pairs {
+            hash.insert(field.as_ref(), value.as_ref(), limits);
+        }
+        return hash;
+    }
+
+    pub fn len(&self) -> usize {
+        return match self {
+            Hash::Listpack(lp) => lp.len() / 2,
+            Hash::Hashtable(ht) => ht.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&[u8]> {
+        return match self {
+            Hash::Listpack(lp) => lp.position(field, 2).and_then(|i| lp.get(i + 1)),
+            Hash::Hashtable(ht) => ht.get(field).map(|v| v.as_slice()),
+        };
+    }
+
+    /// Sets `field`, returning true when it is new. The hash moves to a hash
+    /// table when the pair does not fit the listpack limits.
+    pub fn insert(&mut self, field: &[u8], value: &[u8], limits: &EncodingLimits) -> bool {
+        if let Hash::Listpack(lp) = self {
+            let index: Option<usize> = lp.position(field, 2);
+            let len: usize = lp.len() / 2 + index.is_none() as usize;
+            if len <= limits.hash_max_listpack_entries
+                && field.len() <= limits.hash_max_listpack_value
+                && value.len() <= limits.hash_max_listpack_value
+            {
+                return match index {
+                    Some(i) => {
+                        lp.replace(i + 1, value);
+                        false
+                    }
+                    None => {
+                        lp.push_back(field);
+                        lp.push_back(value);
+                        true
+                    }
+                };
+            }
+            *self = Hash::Hashtable(self.iter().map(|(f, v)| (f.to_vec(), v.to_vec())).collect());
+        }
+
+        return match self {
+            Hash::Hashtable(ht) => ht.insert(field.to_vec(), value.to_vec()).is_none(),
+            Hash::Listpack(_) => unreachable!("converted above"),
+        };
+    }
+
+    /// Removes `field`, returning whether it was there.
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        return match self {
+            Hash::Listpack(lp) => match lp.position(field, 2) {
+                Some(i) => {
+                    lp.remove(i);
+                    lp.remove(i);
+                    true
+                }
+                None => false,
+            },
+            Hash::Hashtable(ht) => ht.remove(field).is_some(),
+        };
+    }
+
+    /// The fields with their values, in no particular order.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+        return match self {
+            Hash::Listpack(lp) => {
+                let mut items = lp.iter();
+                Box::new(std::iter::from_fn(move || {
+                    Some((items.next()?, items.next()?))
+                }))
+            }
+            Hash::Hashtable(ht) => Box::new(ht.iter().map(|(f, v)| (f.as_slice(), v.as_slice()))),
+        };
+    }
+
+    fn encoding(&self) -> &'static str {
+        return match self {
+            Hash::Listpack(_) => "listpack",
+            Hash::Hashtable(_) => "hashtable",
+        };
+    }
+
+    fn memory(&self) -> usize {
+        return match self {
+            Hash::Listpack(lp) => lp.bytes(),
+            Hash::Hashtable(ht) => ht
+                .iter()
+                .map(|(f, v)| f.len() + v.len() + ELEMENT_OVERHEAD)
+                .sum(),
+        };
+    }
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        return self.len() == other.len() && self.iter().all(|(f, v)| other.get(f) == Some(v));
+    }
+}
+
+/// A score ordered with `f64::total_cmp`, so it can key a `BTreeSet`.
+#[derive(Clone, Copy, Debug)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self.0.total_cmp(&other.0);
+    }
+}
+
+/// Members with a score, ordered by score then member like a Redis sorted set.
+///
+/// The map answers ZSCORE in constant time; the ordered set plays the part of
+/// the skiplist for range queries.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    ordered: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        return SortedSet::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.scores.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.scores.is_empty();
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        return self.scores.get(member).copied();
+    }
+
+    /// Adds `member` or updates its score, returning true when it was new.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        let previous: Option<f64> = self.scores.insert(member.to_vec(), score);
+        if let Some(old) = previous {
+            self.ordered.remove(&(Score(old), member.to_vec()));
+        }
+        self.ordered.insert((Score(score), member.to_vec()));
+        return previous.is_none();
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        return match self.scores.remove(member) {
+            Some(score) => {
+                self.ordered.remove(&(Score(score), member.to_vec()));
+                true
+            }
+            None => false,
+        };
+    }
+
+    /// Members with their scores, lowest score first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Vec<u8>, f64)> {
+        return self.ordered.iter().map(|(score, member)| (member, score.0));
+    }
+
+    /// Members scored within `min..max` (the maximum excluded), lowest score first.
+    pub fn range(&self, min: f64, max: f64) -> impl Iterator<Item = (&Vec<u8>, f64)> {
+        return self
+            .ordered
+            .range((Score(min), Vec::new())..(Score(max), Vec::new()))
+            .map(|(score, member)| (member, score.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_test() {
+        assert_eq!(Value::from(b"12345".to_vec()).encoding(), "int");
+        assert_eq!(Value::from(b"012".to_vec()).encoding(), "embstr");
+        assert_eq!(Value::from(vec![b'x'; 45]).encoding(), "raw");
+
+        let limits: EncodingLimits = EncodingLimits::default();
+        let numbers: Vec<Vec<u8>> = (0..200).map(|i| i.to_string().into_bytes()).collect();
+        let list: List = List::from_items(&numbers, &limits);
+        assert_eq!(Value::List(list).encoding(), "listpack");
+        let big: List = List::from_items([vec![b'x'; 10_000]], &limits);
+        assert_eq!(Value::List(big).encoding(), "quicklist");
+    }
+
+    #[test]
+    fn set_conversion_test() {
+        let limits: EncodingLimits = EncodingLimits {
+            set_max_intset_entries: 4,
+            set_max_listpack_entries: 6,
+            ..EncodingLimits::default()
+        };
+        let mut set: Set = Set::from_members([b"3", b"1", b"2"], &limits);
+        assert_eq!(set.encoding(), "intset");
+        assert!(!set.insert(b"2", &limits));
+        assert!(set.contains(b"1"));
+        assert!(!set.contains(b"01"));
+
+        // a string member, then too many members, move it up a step at a time
+        assert!(set.insert(b"a", &limits));
+        assert_eq!(set.encoding(), "listpack");
+        assert!(set.contains(b"3") && set.contains(b"a"));
+        for member in [b"b", b"c", b"d"] {
+            set.insert(member, &limits);
+        }
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set.len(), 7);
+        assert_eq!(
+            set,
+            Set::from_members([b"1", b"2", b"3", b"a", b"b", b"c", b"d"], &limits)
+        );
+
+        let mut ints: Set = Set::from_members([b"1", b"2", b"3", b"4"], &limits);
+        ints.insert(b"5", &limits);
+        assert_eq!(ints.encoding(), "listpack");
+        let long: Set = Set::from_members([vec![b'x'; 65]], &limits);
+        assert_eq!(long.encoding(), "hashtable");
+    }
+
+    #[test]
+    fn hash_conversion_test() {
+        let limits: EncodingLimits = EncodingLimits {
+            hash_max_listpack_entries: 2,
+            ..EncodingLimits::default()
+        };
+        let mut hash: Hash = Hash::new();
+        assert!(hash.insert(b"f1", b"v1", &limits));
+        assert!(!hash.insert(b"f1", b"new", &limits));
+        assert!(hash.insert(b"v1", b"f1", &limits));
+        assert_eq!(hash.encoding(), "listpack");
+        assert_eq!(hash.get(b"f1"), Some(b"new".as_slice()));
+        assert_eq!(hash.get(b"v1"), Some(b"f1".as_slice()));
+        assert_eq!(hash.get(b"new"), None);
+
+        assert!(hash.insert(b"f3", b"v3", &limits));
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.len(), 3);
+        assert_eq!(hash.get(b"f1"), Some(b"new".as_slice()));
+        assert!(hash.remove(b"f1"));
+        assert!(!hash.remove(b"f1"));
+        assert_eq!(hash.len(), 2);
+
+        // a field is removed along with its value
+        let mut small: Hash = Hash::from_pairs([(b"f1", b"v1"), (b"f2", b"v2")], &limits);
+        assert!(small.remove(b"f1"));
+        assert!(!small.remove(b"v2"));
+        assert_eq!(small.get(b"f2"), Some(b"v2".as_slice()));
+        assert_eq!(small.len(), 1);
+
+        let mut long: Hash = Hash::new();
+        long.insert(b"f", &[b'v'; 65], &limits);
+        assert_eq!(long.encoding(), "hashtable");
+    }
+
+    #[test]
+    fn list_nodes_test() {
+        let limits: EncodingLimits = EncodingLimits {
+            list_max_listpack_size: 3,
+            ..EncodingLimits::default()
+        };
+        let mut list: List = List::new();
+        for i in 0..3 {
+            list.push(i.to_string().as_bytes(), false, &limits);
+        }
+        assert_eq!(list.encoding(), "listpack");
+        list.push(b"-1", true, &limits);
+        list.push(b"3", false, &limits);
+        assert_eq!(list.encoding(), "quicklist");
+        if let List::Quicklist(ql) = &list {
+            assert_eq!(ql.nodes.len(), 3);
+        }
+
+        let items: Vec<&[u8]> = list.iter().collect();
+        assert_eq!(items, vec![b"-1".as_slice(), b"0", b"1", b"2", b"3"]);
+        assert_eq!(list.len(), 5);
+
+        // popping empties the end nodes, which are then dropped
+        assert_eq!(list.pop(true), Some(b"-1".to_vec()));
+        assert_eq!(list.pop(false), Some(b"3".to_vec()));
+        if let List::Quicklist(ql) = &list {
+            assert_eq!(ql.nodes.len(), 1);
+        }
+        for item in [b"0", b"1", b"2"] {
+            assert_eq!(list.pop(true), Some(item.to_vec()));
+        }
+        assert_eq!(list.pop(false), None);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn compact_memory_test() {
+        let limits: EncodingLimits = EncodingLimits::default();
+        let members: Vec<Vec<u8>> = (0..100).map(|i| i.to_string().into_bytes()).collect();
+        let compact: Value = Value::Set(Set::from_members(&members, &limits));
+        let full: Value = Value::Set(Set::Hashtable(members.iter().cloned().collect()));
+        assert_eq!(compact, full);
+        assert!(compact.memory() * 4 < full.memory());
+    }
+
+    #[test]
+    fn sorted_set_test() {
+        let mut zset: SortedSet = SortedSet::new();
+        assert!(zset.insert(b"b", 2.0));
+        assert!(zset.insert(b"a", 2.0));
+        assert!(zset.insert(b"c", -1.5));
+        assert!(!zset.insert(b"c", 3.0));
+
+        let order: Vec<(&[u8], f64)> = zset.iter().map(|(m, s)| (m.as_slice(), s)).collect();
+        assert_eq!(
+            order,
+            vec![
+                (b"a".as_slice(), 2.0),
+                (b"b".as_slice(), 2.0),
+                (b"c".as_slice(), 3.0)
+            ]
+        );
+
+        assert!(zset.remove(b"a"));
+        assert!(!zset.remove(b"a"));
+        assert_eq!(zset.score(b"b"), Some(2.0));
+        assert_eq!(zset.len(), 2);
+    }
+}
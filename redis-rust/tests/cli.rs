@@ -0,0 +1,144 @@
+//! Runs the redis-cli binary against the server binary, or one in the test process.
+
+#![cfg(feature = "cli")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+use redis_starter_rust::redis_client::RedisClient;
+
+use common::{call, connect, temp_dir, InProcess, Server};
+
+/// Runs redis-cli with `args`, feeding it `input`.
+fn cli(port: u16, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_redis-cli"))
+        .arg("-p")
+        .arg(port.to_string())
+        .args(args)
+        .env("REDISCLI_HISTFILE", "")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    return child.wait_with_output().unwrap();
+}
+
+fn stdout(output: &Output) -> String {
+    return String::from_utf8(output.stdout.clone()).unwrap();
+}
+
+#[test]
+fn cli_test() {
+    let dir: PathBuf = temp_dir("cli");
+    let (server, port) = Server::start_in(&dir);
+    let mut stream: TcpStream = connect("127.0.0.1", port);
+
+    let set: Output = cli(port, &["RPUSH", "list", "a b", "c"], b"");
+    assert!(set.status.success());
+    assert_eq!(stdout(&set), "2\n");
+
+    let range: &[&str] = &["LRANGE", "list", "0", "-1"];
+    assert_eq!(stdout(&cli(port, range, b"")), "a b\nc\n");
+    assert_eq!(
+        stdout(&cli(port, &[&["--no-raw"], range].concat(), b"")),
+        "1) \"a b\"\n2) \"c\"\n"
+    );
+    assert_eq!(
+        stdout(&cli(port, &[&["--csv"], range].concat(), b"")),
+        "\"a b\",\"c\"\n"
+    );
+
+    // an error reply is printed and fails the command
+    let error: Output = cli(port, &["--no-raw", "GET", "list"], b"");
+    assert!(!error.status.success());
+    assert!(stdout(&error).starts_with("(error) WRONGTYPE"));
+
+    // -n selects the database before the command
+    assert!(cli(port, &["-n", "2", "SET", "k", "in 2"], b"")
+        .status
+        .success());
+    assert_eq!(call(&mut stream, &["GET", "k"]), b"$-1\r\n");
+    assert_eq!(stdout(&cli(port, &["-n", "2", "GET", "k"], b"")), "in 2\n");
+
+    // without a command, lines are read as commands
+    let session: Output = cli(
+        port,
+        &["--no-raw"],
+        b"SET greeting \"hello\\tworld\"\nGET greeting\nSELECT 2\nGET k\nquit\nGET never\n",
+    );
+    assert_eq!(stdout(&session), "OK\n\"hello\\tworld\"\nOK\n\"in 2\"\n");
+
+    call(&mut stream, &["SHUTDOWN", "NOSAVE"]);
+    assert!(server.wait().success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn pipe_test() {
+    let dir: PathBuf = temp_dir("cli-pipe");
+    let (server, port) = Server::start_in(&dir);
+    let mut stream: TcpStream = connect("127.0.0.1", port);
+
+    let mut input: Vec<u8> = Vec::new();
+    for i in 0..1000 {
+        let value: String = i.to_string();
+        input.extend(
+            format!(
+                "*3\r\n$5\r\nRPUSH\r\n$4\r\nmass\r\n${}\r\n{}\r\n",
+                value.len(),
+                value
+            )
+            .into_bytes(),
+        );
+    }
+    input.extend_from_slice(b"*2\r\n$3\r\nGET\r\n$4\r\nmass\r\n");
+    let output: Output = cli(port, &["--pipe"], &input);
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "errors: 1, replies: 1001\n");
+    let stderr: String = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("WRONGTYPE"));
+    assert!(stderr.contains("Last reply received from server."));
+    assert_eq!(call(&mut stream, &["LLEN", "mass"]), b":1000\r\n");
+
+    call(&mut stream, &["SHUTDOWN", "NOSAVE"]);
+    assert!(server.wait().success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn scan_test() {
+    let server: InProcess = InProcess::start("cli-scan", &[]).await;
+    let port: u16 = server.server.local_addr().unwrap().port();
+    let mut client: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+    for i in 0..200 {
+        client.set(&format!("user:{}", i), "x").await.unwrap();
+        client.set(&format!("order:{}", i), "x").await.unwrap();
+    }
+
+    // the cli blocks, so it runs off the runtime serving it
+    let output: Output = tokio::task::spawn_blocking(move || {
+        return cli(
+            port,
+            &["--scan", "--pattern", "user:*", "--count", "7"],
+            b"",
+        );
+    })
+    .await
+    .unwrap();
+    assert!(output.status.success());
+    let mut keys: Vec<String> = stdout(&output).lines().map(String::from).collect();
+    keys.sort();
+    let mut expected: Vec<String> = (0..200).map(|i| format!("user:{}", i)).collect();
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    drop(client);
+    server.stop().await;
+}
@@ -0,0 +1,310 @@
+//! Drives a server running inside the test process with the bundled client.
+
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use redis_starter_rust::redis_client::{
+    integer, ClientOptions, Cmd, Message, Pipeline, Pool, PooledClient, RedisClient, Subscriber,
+};
+use redis_starter_rust::server::{Server, ServerHandle};
+use redis_starter_rust::{rdb, Config, Error, RedisType};
+
+use common::{temp_dir, InProcess};
+
+#[tokio::test]
+async fn commands_test() {
+    let server: InProcess = InProcess::start("client-commands", &[]).await;
+    let mut client: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+
+    assert_eq!(client.ping().await.unwrap(), "PONG");
+    client.set("name", "redis").await.unwrap();
+    assert_eq!(client.get("name").await.unwrap(), Some(b"redis".to_vec()));
+    assert_eq!(client.get("missing").await.unwrap(), None);
+    client.set_ex("session", &[0u8, 255], 100).await.unwrap();
+    assert_eq!(client.get("session").await.unwrap(), Some(vec![0u8, 255]));
+    let e = client.lpush("name", &["x"]).await.unwrap_err();
+    assert!(e.message.starts_with("WRONGTYPE"));
+
+    assert!(client.hset("user", "name", "ada").await.unwrap());
+    assert!(!client.hset("user", "name", "grace").await.unwrap());
+    client.hset("user", "age", &36).await.unwrap();
+    assert_eq!(
+        client.hget("user", "age").await.unwrap(),
+        Some(b"36".to_vec())
+    );
+    let user: HashMap<Vec<u8>, Vec<u8>> = client.hgetall("user").await.unwrap();
+    assert_eq!(user.len(), 2);
+    assert_eq!(user.get(b"name".as_slice()), Some(&b"grace".to_vec()));
+
+    assert_eq!(client.rpush("list", &["b", "c"]).await.unwrap(), 2);
+    assert_eq!(client.lpush("list", &["a"]).await.unwrap(), 3);
+    assert_eq!(
+        client.lrange("list", 0, -1).await.unwrap(),
+        vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+    );
+    assert_eq!(client.llen("list").await.unwrap(), 3);
+
+    assert_eq!(client.sadd("set", &["x", "y", "x"]).await.unwrap(), 2);
+    assert_eq!(client.smembers("set").await.unwrap().len(), 2);
+    assert_eq!(client.dbsize().await.unwrap(), 5);
+    assert_eq!(client.del(&["set", "list", "missing"]).await.unwrap(), 2);
+
+    assert_eq!(client.incr("hits").await.unwrap(), 1);
+    assert_eq!(client.incr_by("hits", 41).await.unwrap(), 42);
+    assert_eq!(client.decr("hits").await.unwrap(), 41);
+    assert_eq!(
+        client.exists(&["hits", "name", "missing"]).await.unwrap(),
+        2
+    );
+    assert_eq!(client.ttl("hits").await.unwrap(), -1);
+    assert!(client.expire("hits", 100).await.unwrap());
+    assert!(!client.expire("missing", 100).await.unwrap());
+    assert_eq!(client.ttl("hits").await.unwrap(), 100);
+    assert_eq!(client.ttl("missing").await.unwrap(), -2);
+    assert_eq!(client.hdel("user", &["age", "missing"]).await.unwrap(), 1);
+    client.rpush("queue", &["a", "b", "c"]).await.unwrap();
+    assert_eq!(client.lpop("queue").await.unwrap(), Some(b"a".to_vec()));
+    assert_eq!(client.rpop("queue").await.unwrap(), Some(b"c".to_vec()));
+    assert_eq!(client.lpop("queue").await.unwrap(), Some(b"b".to_vec()));
+    assert_eq!(client.rpop("queue").await.unwrap(), None);
+
+    // anything else goes through Cmd
+    let reply: RedisType = client.query(&Cmd::new("SCARD").arg("set")).await.unwrap();
+    assert_eq!(integer(reply).unwrap(), 0);
+    let reply: RedisType = client.query(&Cmd::new("NOSUCHCOMMAND")).await.unwrap();
+    assert!(matches!(reply, RedisType::Error(_)));
+    client.flushdb().await.unwrap();
+    assert_eq!(client.dbsize().await.unwrap(), 0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn pipeline_test() {
+    let server: InProcess = InProcess::start("client-pipeline", &[]).await;
+    let mut client: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+
+    let mut pipeline: Pipeline = Pipeline::new();
+    for i in 0..100 {
+        pipeline.add(Cmd::new("RPUSH").arg("hits").arg(&i));
+    }
+    pipeline.add(Cmd::new("LLEN").arg("hits"));
+    let replies: Vec<RedisType> = client.execute(&pipeline).await.unwrap();
+    assert_eq!(replies.len(), 101);
+    assert_eq!(replies[99], RedisType::Integer(String::from("100")));
+    assert_eq!(replies[100], RedisType::Integer(String::from("100")));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn transaction_test() {
+    let server: InProcess = InProcess::start("client-transaction", &[]).await;
+    let mut client: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+    let mut other: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+
+    let mut pipeline: Pipeline = Pipeline::new();
+    pipeline
+        .add(Cmd::new("SET").arg("balance").arg(&90))
+        .add(Cmd::new("RPUSH").arg("withdrawals").arg(&10));
+    client.set("balance", &100).await.unwrap();
+    client.watch(&["balance"]).await.unwrap();
+    assert_eq!(
+        client.transaction(&pipeline).await.unwrap(),
+        Some(vec![
+            RedisType::SimpleString("OK"),
+            RedisType::Integer(String::from("1"))
+        ])
+    );
+
+    // a watched key changed by someone else aborts the transaction
+    client.watch(&["balance"]).await.unwrap();
+    other.set("balance", &50).await.unwrap();
+    assert_eq!(client.transaction(&pipeline).await.unwrap(), None);
+    assert_eq!(client.get("balance").await.unwrap(), Some(b"50".to_vec()));
+
+    // a command that can not be queued fails the whole transaction
+    let mut broken: Pipeline = Pipeline::new();
+    broken.add(Cmd::new("SET").arg("balance"));
+    let e = client.transaction(&broken).await.unwrap_err();
+    assert!(e.message.contains("wrong number of arguments"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn pubsub_test() {
+    let server: InProcess = InProcess::start("client-pubsub", &[]).await;
+    let mut subscriber: Subscriber = Subscriber::connect(ClientOptions::new(&server.addr))
+        .await
+        .unwrap();
+    subscriber.subscribe(&["news"]).await.unwrap();
+    subscriber.psubscribe(&["alerts.*"]).await.unwrap();
+
+    let mut client: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+    assert_eq!(client.publish("news", "hello").await.unwrap(), 1);
+    assert_eq!(client.publish("alerts.disk", "full").await.unwrap(), 1);
+    assert_eq!(
+        subscriber.next_message().await.unwrap(),
+        Message {
+            channel: b"news".to_vec(),
+            pattern: None,
+            payload: b"hello".to_vec(),
+        }
+    );
+    assert_eq!(
+        subscriber.next_message().await.unwrap(),
+        Message {
+            channel: b"alerts.disk".to_vec(),
+            pattern: Some(b"alerts.*".to_vec()),
+            payload: b"full".to_vec(),
+        }
+    );
+
+    subscriber.unsubscribe(&["news"]).await.unwrap();
+    assert_eq!(client.publish("news", "again").await.unwrap(), 0);
+
+    drop(subscriber);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn pool_test() {
+    let server: InProcess = InProcess::start("client-pool", &[]).await;
+    let pool: Pool = Pool::new(ClientOptions::new(&server.addr), 2);
+
+    let mut tasks = Vec::new();
+    for _ in 0..10 {
+        let pool: Pool = pool.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut client: PooledClient = pool.get().await.unwrap();
+            client.rpush("visits", &["home"]).await.unwrap();
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    // ten tasks shared at most two connections
+    assert!(pool.idle() <= 2);
+    let mut client: PooledClient = pool.get().await.unwrap();
+    assert_eq!(client.llen("visits").await.unwrap(), 10);
+    drop(client);
+    drop(pool);
+
+    server.stop().await;
+}
+
+/// Waits until the server closed the connection of client `id`.
+async fn wait_until_gone(admin: &mut RedisClient, id: i64) {
+    let entry: String = format!("id={} ", id);
+    loop {
+        let list: RedisType = admin.call(&Cmd::new("CLIENT").arg("LIST")).await.unwrap();
+        if !list.to_string().contains(&entry) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn reconnect_test() {
+    let server: InProcess = InProcess::start("client-reconnect", &[]).await;
+    let mut client: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+    client.select(3).await.unwrap();
+    client.set("key", "in db 3").await.unwrap();
+
+    let id: i64 = integer(client.call(&Cmd::new("CLIENT").arg("ID")).await.unwrap()).unwrap();
+    let mut admin: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+    let kill: Cmd = Cmd::new("CLIENT").arg("KILL").arg("ID").arg(&id);
+    assert_eq!(integer(admin.call(&kill).await.unwrap()).unwrap(), 1);
+    wait_until_gone(&mut admin, id).await;
+
+    // the next request notices the closed connection before writing to it and
+    // reopens it, in the database selected before
+    assert_eq!(client.get("key").await.unwrap(), Some(b"in db 3".to_vec()));
+    let new_id: i64 = integer(client.call(&Cmd::new("CLIENT").arg("ID")).await.unwrap()).unwrap();
+    assert_ne!(new_id, id);
+
+    // watched keys do not survive a reconnect, so the transaction is not attempted
+    client.watch(&["key"]).await.unwrap();
+    let kill: Cmd = Cmd::new("CLIENT").arg("KILL").arg("ID").arg(&new_id);
+    admin.call(&kill).await.unwrap();
+    wait_until_gone(&mut admin, new_id).await;
+    let mut pipeline: Pipeline = Pipeline::new();
+    pipeline.add(Cmd::new("SET").arg("key").arg("changed"));
+    assert!(client.transaction(&pipeline).await.is_err());
+    assert_eq!(client.get("key").await.unwrap(), Some(b"in db 3".to_vec()));
+
+    drop(client);
+    drop(admin);
+    server.stop().await;
+}
+
+/// A DUMP payload with a valid footer around `body`.
+fn signed_payload(body: &[u8]) -> Vec<u8> {
+    let mut payload: Vec<u8> = body.to_vec();
+    payload.extend_from_slice(&(rdb::RDB_VERSION as u16).to_le_bytes());
+    let checksum: u64 = rdb::crc64(0, &payload);
+    payload.extend_from_slice(&checksum.to_le_bytes());
+    return payload;
+}
+
+#[tokio::test]
+async fn restore_oversized_lengths_test() {
+    let server: InProcess = InProcess::start("client-restore", &[]).await;
+    let mut client: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+
+    // a string claiming u64::MAX bytes
+    let mut huge_string: Vec<u8> = vec![rdb::RDB_TYPE_STRING, 0x81];
+    huge_string.extend_from_slice(&u64::MAX.to_be_bytes());
+    // an LZF string of one compressed byte claiming 2^62 decompressed bytes
+    let mut huge_lzf: Vec<u8> = vec![rdb::RDB_TYPE_STRING, 0xC3, 0x01, 0x81];
+    huge_lzf.extend_from_slice(&(1u64 << 62).to_be_bytes());
+    huge_lzf.push(0x00);
+
+    for body in [huge_string, huge_lzf] {
+        let payload: Vec<u8> = signed_payload(&body);
+        let restore: Cmd = Cmd::new("RESTORE").arg("key").arg("0").arg(&payload);
+        let error: Error = client.call(&restore).await.unwrap_err();
+        assert_eq!(error.message, "ERR Bad data format");
+        // the connection is still there
+        assert_eq!(client.ping().await.unwrap(), "PONG");
+    }
+
+    drop(client);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn no_resend_test() {
+    let dir: PathBuf = temp_dir("client-no-resend");
+    let args: Vec<String> = vec![String::from("--dir"), dir.to_string_lossy().to_string()];
+    let server: ServerHandle = Server::new(Config::from_args(&args).unwrap())
+        .ephemeral_port()
+        .start()
+        .await
+        .unwrap();
+    let addr: String = server.local_addr().unwrap().to_string();
+    let mut client: RedisClient = RedisClient::connect(&addr).await.unwrap();
+
+    // the server ran the request and went away, so it is not sent again
+    let error: Error = client
+        .call(&Cmd::new("SHUTDOWN").arg("NOSAVE"))
+        .await
+        .unwrap_err();
+    assert_eq!(error.message, "Connection closed by the server");
+    assert!(!client.is_connected());
+    assert!(server.state().shutdown.is_started());
+
+    // the next request tries to reconnect, and fails as nobody listens anymore
+    assert!(client.ping().await.is_err());
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,178 @@
+//! Starts the server, as a child process or inside the test, and talks RESP
+//! to it, for the integration tests.
+
+#![allow(clippy::needless_return, dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use redis_starter_rust::server::{self, ServerHandle};
+use redis_starter_rust::shutdown::ShutdownOptions;
+use redis_starter_rust::Config;
+
+/// How long a server may take to come up or to exit.
+const PATIENCE: Duration = Duration::from_secs(10);
+
+pub struct Server {
+    pub child: Child,
+}
+
+impl Server {
+    /// Runs the server with `--name value` pairs `args`.
+    pub fn start(args: &[&str]) -> Server {
+        let child: Child = Command::new(env!("CARGO_BIN_EXE_redis-starter-rust"))
+            .args(args)
+            .spawn()
+            .unwrap();
+        return Server { child };
+    }
+
+    /// Runs the server on a free port with `dir` as its working directory and `redis.pid` inside it.
+    pub fn start_in(dir: &Path) -> (Server, u16) {
+        let port: u16 = free_port();
+        let server: Server = Server::start(&[
+            "--dir",
+            dir.to_str().unwrap(),
+            "--port",
+            &port.to_string(),
+            "--pidfile",
+            dir.join("redis.pid").to_str().unwrap(),
+        ]);
+        return (server, port);
+    }
+
+    /// Waits for the server to exit on its own.
+    pub fn wait(mut self) -> ExitStatus {
+        let started: Instant = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status;
+            }
+            if started.elapsed() > 2 * PATIENCE {
+                panic!("server did not exit");
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+/// A test that fails half way must not leave its server running.
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A port nobody listens on right now.
+pub fn free_port() -> u16 {
+    return TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+}
+
+/// Calls `connect` until the server accepts the connection.
+fn retry<S, F: FnMut() -> std::io::Result<S>>(mut connect: F) -> S {
+    let started: Instant = Instant::now();
+    loop {
+        match connect() {
+            Ok(stream) => return stream,
+            Err(e) if started.elapsed() > PATIENCE => panic!("server did not come up: {}", e),
+            Err(_) => thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+pub fn connect(host: &str, port: u16) -> TcpStream {
+    let stream: TcpStream = retry(|| TcpStream::connect((host, port)));
+    stream.set_read_timeout(Some(PATIENCE)).unwrap();
+    return stream;
+}
+
+pub fn connect_unix(path: &Path) -> UnixStream {
+    let stream: UnixStream = retry(|| UnixStream::connect(path));
+    stream.set_read_timeout(Some(PATIENCE)).unwrap();
+    return stream;
+}
+
+/// Sends one command and reads back everything up to the end of its reply line,
+/// or an empty vector if the server closed the connection instead.
+pub fn call<S: Read + Write>(stream: &mut S, args: &[&str]) -> Vec<u8> {
+    let mut request: Vec<u8> = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+    }
+    stream.write_all(&request).unwrap();
+
+    let mut reply: Vec<u8> = Vec::new();
+    let mut buf: [u8; 512] = [0; 512];
+    loop {
+        let n: usize = stream.read(&mut buf).unwrap_or(0);
+        if n == 0 {
+            return reply;
+        }
+        reply.extend_from_slice(&buf[..n]);
+        // bulk strings carry a second line
+        let lines: usize = reply.windows(2).filter(|w| w == b"\r\n").count();
+        let expected: usize = if reply.starts_with(b"$") && !reply.starts_with(b"$-1") {
+            2
+        } else {
+            1
+        };
+        if lines >= expected {
+            return reply;
+        }
+    }
+}
+
+/// An empty directory for one test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("redis-rust-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+/// A server running on the test's own runtime.
+pub struct InProcess {
+    pub server: ServerHandle,
+    pub addr: String,
+    dir: PathBuf,
+}
+
+impl InProcess {
+    /// Serves on an ephemeral port until `stop`; `args` are extra `--name value` pairs.
+    pub async fn start(name: &str, args: &[&str]) -> InProcess {
+        let dir: PathBuf = temp_dir(name);
+        let mut all: Vec<String> = vec![String::from("--dir"), dir.to_string_lossy().to_string()];
+        all.extend(args.iter().map(|a| a.to_string()));
+
+        let config: Config = Config::from_args(&all).unwrap();
+        let server: ServerHandle = server::Server::new(config)
+            .ephemeral_port()
+            .start()
+            .await
+            .unwrap();
+        let addr: String = server.local_addr().unwrap().to_string();
+        return InProcess { server, addr, dir };
+    }
+
+    /// Shuts down without saving and waits for the server to finish.
+    pub async fn stop(self) {
+        let options: ShutdownOptions = ShutdownOptions {
+            save: Some(false),
+            force: false,
+            now: false,
+        };
+        self.server.shutdown_with(options).await.unwrap();
+        std::fs::remove_dir_all(&self.dir).unwrap();
+    }
+}
@@ -0,0 +1,1107 @@
+//! Table driven conformance scenarios: every step sends one command over raw
+//! RESP and compares the reply frames byte for byte with what Redis sends.
+//!
+//! Each group runs on a server of its own, one connection, steps in order, so
+//! a step sees what the earlier ones did. An expected reply ending in `...`
+//! only has to match up to there, for replies that change from run to run
+//! such as INFO or CLIENT ID. A command replying with several frames, like
+//! SUBSCRIBE with several channels, lists them one after the other. All the
+//! mismatches of a group are reported together.
+
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use redis_starter_rust::redis_parser::parse_reply;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use common::InProcess;
+
+/// A command and the reply frames expected for it.
+type Step = (&'static [&'static str], &'static str);
+
+const WRONGTYPE: &str = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+const NOT_INTEGER: &str = "-ERR value is not an integer or out of range\r\n";
+const SYNTAX: &str = "-ERR syntax error\r\n";
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    async fn send(&mut self, request: &[u8]) {
+        self.stream.write_all(request).await.unwrap();
+    }
+
+    /// The bytes of the next reply frame, empty if the server closed the connection.
+    async fn read_frame(&mut self) -> Vec<u8> {
+        loop {
+            if let Some((_, used)) = parse_reply(&self.buffer).unwrap() {
+                return self.buffer.drain(..used).collect();
+            }
+            if self.stream.read_buf(&mut self.buffer).await.unwrap() == 0 {
+                return std::mem::take(&mut self.buffer);
+            }
+        }
+    }
+}
+
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut request: Vec<u8> = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+    }
+    return request;
+}
+
+/// How many frames `expected` holds; a prefix stands for a single frame.
+fn frame_count(expected: &str) -> usize {
+    if expected.ends_with("...") {
+        return 1;
+    }
+    let mut rest: &[u8] = expected.as_bytes();
+    let mut count: usize = 0;
+    while !rest.is_empty() {
+        let (_, used) = parse_reply(rest)
+            .unwrap()
+            .unwrap_or_else(|| panic!("incomplete expected reply {:?}", expected));
+        rest = &rest[used..];
+        count += 1;
+    }
+    return count;
+}
+
+fn matches(expected: &str, actual: &[u8]) -> bool {
+    return match expected.strip_suffix("...") {
+        Some(prefix) => actual.starts_with(prefix.as_bytes()),
+        None => actual == expected.as_bytes(),
+    };
+}
+
+/// Runs `steps` in order on a fresh server and fails with every mismatch.
+async fn run(group: &str, steps: &[Step]) {
+    let server: InProcess = InProcess::start(&format!("conformance-{}", group), &[]).await;
+    let mut connection: Connection = Connection {
+        stream: TcpStream::connect(&server.addr).await.unwrap(),
+        buffer: Vec::new(),
+    };
+
+    let mut failures: Vec<String> = Vec::new();
+    for (i, (args, expected)) in steps.iter().enumerate() {
+        connection.send(&encode(args)).await;
+        let mut actual: Vec<u8> = Vec::new();
+        for _ in 0..frame_count(expected) {
+            actual.extend(connection.read_frame().await);
+        }
+        if !matches(expected, &actual) {
+            failures.push(format!(
+                "{} step {}: {}\n  expected: {:?}\n  actual:   {:?}",
+                group,
+                i + 1,
+                args.join(" "),
+                expected,
+                String::from_utf8_lossy(&actual)
+            ));
+        }
+    }
+
+    drop(connection);
+    server.stop().await;
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}
+
+#[tokio::test]
+async fn strings_test() {
+    run(
+        "strings",
+        &[
+            (&["GET", "foo"], "$-1\r\n"),
+            (&["SET", "foo", "bar"], "+OK\r\n"),
+            (&["GET", "foo"], "$3\r\nbar\r\n"),
+            (&["SET", "foo", "baz"], "+OK\r\n"),
+            (&["GET", "foo"], "$3\r\nbaz\r\n"),
+            // empty values and empty key names are ordinary strings
+            (&["SET", "foo", ""], "+OK\r\n"),
+            (&["GET", "foo"], "$0\r\n\r\n"),
+            (&["SET", "", "empty key"], "+OK\r\n"),
+            (&["GET", ""], "$9\r\nempty key\r\n"),
+            (&["SET", "ttl", "v", "EX", "100"], "+OK\r\n"),
+            (&["SET", "ttl", "v", "px", "100000"], "+OK\r\n"),
+            (&["GET", "ttl"], "$1\r\nv\r\n"),
+            (
+                &["SET", "ttl", "v", "EX", "0"],
+                "-ERR invalid expire time in 'set' command\r\n",
+            ),
+            (
+                &["SET", "ttl", "v", "PX", "-5"],
+                "-ERR invalid expire time in 'set' command\r\n",
+            ),
+            (&["SET", "ttl", "v", "EX", "soon"], NOT_INTEGER),
+            (&["SET", "ttl", "v", "EX"], SYNTAX),
+            (&["SET", "ttl", "v", "EX", "10", "PX", "100"], SYNTAX),
+            (&["SET", "ttl", "v", "BOGUS"], SYNTAX),
+            (
+                &["SET", "foo"],
+                "-ERR wrong number of arguments for 'set' command\r\n",
+            ),
+            (
+                &["GET"],
+                "-ERR wrong number of arguments for 'get' command\r\n",
+            ),
+            (
+                &["GET", "a", "b"],
+                "-ERR wrong number of arguments for 'get' command\r\n",
+            ),
+            // GET only reads strings, SET replaces a value of any type
+            (&["RPUSH", "list", "a"], ":1\r\n"),
+            (&["GET", "list"], WRONGTYPE),
+            (&["SET", "list", "now a string"], "+OK\r\n"),
+            (&["TYPE", "list"], "+string\r\n"),
+            (&["GET", "list"], "$12\r\nnow a string\r\n"),
+            // INCR, INCRBY and DECR work on integer strings and keep the TTL
+            (&["INCR", "counter"], ":1\r\n"),
+            (&["INCRBY", "counter", "10"], ":11\r\n"),
+            (&["DECR", "counter"], ":10\r\n"),
+            (&["INCRBY", "counter", "-20"], ":-10\r\n"),
+            (&["GET", "counter"], "$3\r\n-10\r\n"),
+            (&["SET", "counter", "5", "EX", "100"], "+OK\r\n"),
+            (&["INCR", "counter"], ":6\r\n"),
+            (&["TTL", "counter"], ":100\r\n"),
+            (&["INCR", "foo"], NOT_INTEGER),
+            (&["INCRBY", "counter", "ten"], NOT_INTEGER),
+            (&["SET", "big", "9223372036854775807"], "+OK\r\n"),
+            (
+                &["INCR", "big"],
+                "-ERR increment or decrement would overflow\r\n",
+            ),
+            (&["RPUSH", "numbers", "1"], ":1\r\n"),
+            (&["INCR", "numbers"], WRONGTYPE),
+            (
+                &["INCR"],
+                "-ERR wrong number of arguments for 'incr' command\r\n",
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn keys_test() {
+    run(
+        "keys",
+        &[
+            (&["SET", "a", "1"], "+OK\r\n"),
+            (&["SET", "b", "2"], "+OK\r\n"),
+            (&["DBSIZE"], ":2\r\n"),
+            (&["DEL", "a", "b", "missing"], ":2\r\n"),
+            (&["DEL", "a"], ":0\r\n"),
+            (&["DBSIZE"], ":0\r\n"),
+            (&["SET", "a", "1"], "+OK\r\n"),
+            (&["UNLINK", "a", "missing"], ":1\r\n"),
+            (&["GET", "a"], "$-1\r\n"),
+            (
+                &["UNLINK"],
+                "-ERR wrong number of arguments for 'unlink' command\r\n",
+            ),
+            (&["RANDOMKEY"], "$-1\r\n"),
+            (&["SET", "hello", "1"], "+OK\r\n"),
+            (&["RANDOMKEY"], "$5\r\nhello\r\n"),
+            (&["KEYS", "h?llo"], "*1\r\n$5\r\nhello\r\n"),
+            (&["KEYS", "h[ae]l*"], "*1\r\n$5\r\nhello\r\n"),
+            (&["KEYS", "nothing*"], "*0\r\n"),
+            (&["SCAN", "0"], "*2\r\n$1\r\n0\r\n*1\r\n$5\r\nhello\r\n"),
+            (
+                &["SCAN", "0", "MATCH", "nothing*"],
+                "*2\r\n$1\r\n0\r\n*0\r\n",
+            ),
+            (
+                &["SCAN", "0", "TYPE", "STRING", "COUNT", "100"],
+                "*2\r\n$1\r\n0\r\n*1\r\n$5\r\nhello\r\n",
+            ),
+            (&["SCAN", "0", "TYPE", "list"], "*2\r\n$1\r\n0\r\n*0\r\n"),
+            (&["SCAN", "x"], "-ERR invalid cursor\r\n"),
+            (&["SCAN", "0", "COUNT", "0"], SYNTAX),
+            (
+                &["SCAN", "0", "COUNT", "9223372036854775807"],
+                "*2\r\n$1\r\n0\r\n*1\r\n$5\r\nhello\r\n",
+            ),
+            (&["SCAN", "0", "MATCH"], SYNTAX),
+            (&["TOUCH", "hello", "missing"], ":1\r\n"),
+            // EXISTS counts repeated keys every time
+            (&["EXISTS", "hello", "hello", "missing"], ":2\r\n"),
+            // EXPIRE and TTL
+            (&["TTL", "missing"], ":-2\r\n"),
+            (&["TTL", "hello"], ":-1\r\n"),
+            (&["EXPIRE", "missing", "100"], ":0\r\n"),
+            (&["EXPIRE", "hello", "100"], ":1\r\n"),
+            (&["TTL", "hello"], ":100\r\n"),
+            (&["EXPIRE", "hello", "soon"], NOT_INTEGER),
+            (
+                &["EXPIRE", "hello", "9223372036854775807"],
+                "-ERR invalid expire time in 'expire' command\r\n",
+            ),
+            (&["SET", "gone", "1"], "+OK\r\n"),
+            (&["EXPIRE", "gone", "-1"], ":1\r\n"),
+            (&["EXISTS", "gone"], ":0\r\n"),
+            // TYPE names every kind of value
+            (&["TYPE", "hello"], "+string\r\n"),
+            (&["TYPE", "missing"], "+none\r\n"),
+            (&["RPUSH", "l", "x"], ":1\r\n"),
+            (&["TYPE", "l"], "+list\r\n"),
+            (&["HSET", "h", "f", "v"], ":1\r\n"),
+            (&["TYPE", "h"], "+hash\r\n"),
+            (&["SADD", "s", "m"], ":1\r\n"),
+            (&["TYPE", "s"], "+set\r\n"),
+            (&["ZADD", "z", "1", "m"], ":1\r\n"),
+            (&["TYPE", "z"], "+zset\r\n"),
+            // RENAME and RENAMENX
+            (&["RENAME", "missing", "x"], "-ERR no such key\r\n"),
+            (&["RENAME", "hello", "hello"], "+OK\r\n"),
+            (&["RENAME", "hello", "greeting"], "+OK\r\n"),
+            (&["GET", "hello"], "$-1\r\n"),
+            (&["GET", "greeting"], "$1\r\n1\r\n"),
+            (&["RENAME", "l", "greeting"], "+OK\r\n"),
+            (&["TYPE", "greeting"], "+list\r\n"),
+            (&["RENAMENX", "h", "greeting"], ":0\r\n"),
+            (&["RENAMENX", "h", "h2"], ":1\r\n"),
+            (&["RENAMENX", "missing", "h3"], "-ERR no such key\r\n"),
+            // COPY, also across databases
+            (&["SET", "src", "v"], "+OK\r\n"),
+            (&["COPY", "src", "dst"], ":1\r\n"),
+            (&["COPY", "src", "dst"], ":0\r\n"),
+            (&["COPY", "src", "dst", "REPLACE"], ":1\r\n"),
+            (&["COPY", "missing", "dst2"], ":0\r\n"),
+            (
+                &["COPY", "src", "src"],
+                "-ERR source and destination objects are the same\r\n",
+            ),
+            (&["COPY", "src", "src", "DB", "1"], ":1\r\n"),
+            (
+                &["COPY", "src", "x", "DB", "99"],
+                "-ERR DB index is out of range\r\n",
+            ),
+            (&["COPY", "src", "x", "BOGUS"], SYNTAX),
+            // MOVE
+            (&["MOVE", "src", "1"], ":0\r\n"),
+            (&["MOVE", "dst", "1"], ":1\r\n"),
+            (&["GET", "dst"], "$-1\r\n"),
+            (&["MOVE", "missing", "1"], ":0\r\n"),
+            (
+                &["MOVE", "src", "0"],
+                "-ERR source and destination objects are the same\r\n",
+            ),
+            (&["MOVE", "src", "one"], NOT_INTEGER),
+            (&["MOVE", "src", "16"], "-ERR DB index is out of range\r\n"),
+            (&["SELECT", "1"], "+OK\r\n"),
+            (&["GET", "dst"], "$1\r\nv\r\n"),
+            (&["GET", "src"], "$1\r\nv\r\n"),
+            (&["SELECT", "0"], "+OK\r\n"),
+            // OBJECT
+            (&["SET", "n", "12345"], "+OK\r\n"),
+            (&["OBJECT", "ENCODING", "n"], "$3\r\nint\r\n"),
+            (&["OBJECT", "ENCODING", "src"], "$6\r\nembstr\r\n"),
+            (&["OBJECT", "ENCODING", "z"], "$8\r\nlistpack\r\n"),
+            (&["OBJECT", "ENCODING", "missing"], "$-1\r\n"),
+            (&["OBJECT", "REFCOUNT", "n"], ":1\r\n"),
+            (&["OBJECT", "IDLETIME", "n"], ":0\r\n"),
+            (
+                &["OBJECT", "FREQ", "n"],
+                "-ERR An LFU maxmemory policy is not selected, access frequency not tracked. ...",
+            ),
+            (
+                &["OBJECT", "BOGUS", "n"],
+                "-ERR unknown subcommand 'BOGUS'. Try OBJECT HELP.\r\n",
+            ),
+            (
+                &["OBJECT", "ENCODING"],
+                "-ERR wrong number of arguments for 'object|encoding' command\r\n",
+            ),
+            // DUMP and RESTORE
+            (&["DUMP", "missing"], "$-1\r\n"),
+            (&["DUMP", "n"], "$..."),
+            (
+                &["RESTORE", "r", "0", "not a payload"],
+                "-ERR DUMP payload version or checksum are wrong\r\n",
+            ),
+            (
+                &["RESTORE", "r", "-1", "x"],
+                "-ERR Invalid TTL value, must be >= 0\r\n",
+            ),
+            (&["RESTORE", "r", "0", "x", "BOGUS"], SYNTAX),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn lists_test() {
+    run(
+        "lists",
+        &[
+            (&["LLEN", "l"], ":0\r\n"),
+            (&["LRANGE", "l", "0", "-1"], "*0\r\n"),
+            (&["RPUSH", "l", "c", "d"], ":2\r\n"),
+            (&["LPUSH", "l", "b", "a"], ":4\r\n"),
+            (&["RPUSH", "l", "e"], ":5\r\n"),
+            (&["LLEN", "l"], ":5\r\n"),
+            (
+                &["LRANGE", "l", "0", "-1"],
+                "*5\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\nd\r\n$1\r\ne\r\n",
+            ),
+            // negative indices count from the end, out of range ones are clamped
+            (&["LRANGE", "l", "-2", "-1"], "*2\r\n$1\r\nd\r\n$1\r\ne\r\n"),
+            (
+                &["LRANGE", "l", "-100", "1"],
+                "*2\r\n$1\r\na\r\n$1\r\nb\r\n",
+            ),
+            (&["LRANGE", "l", "3", "100"], "*2\r\n$1\r\nd\r\n$1\r\ne\r\n"),
+            (&["LRANGE", "l", "3", "1"], "*0\r\n"),
+            (&["LRANGE", "l", "5", "10"], "*0\r\n"),
+            (&["LRANGE", "l", "-1", "-2"], "*0\r\n"),
+            (&["LRANGE", "l", "0", "0"], "*1\r\n$1\r\na\r\n"),
+            (&["LRANGE", "l", "first", "-1"], NOT_INTEGER),
+            (
+                &["LRANGE", "l", "0"],
+                "-ERR wrong number of arguments for 'lrange' command\r\n",
+            ),
+            (
+                &["LPUSH", "l"],
+                "-ERR wrong number of arguments for 'lpush' command\r\n",
+            ),
+            (&["RPUSH", "l", ""], ":6\r\n"),
+            (&["LRANGE", "l", "-1", "-1"], "*1\r\n$0\r\n\r\n"),
+            // LPOP and RPOP, with and without a count
+            (&["LPOP", "l"], "$1\r\na\r\n"),
+            (&["RPOP", "l"], "$0\r\n\r\n"),
+            (&["LPOP", "l", "2"], "*2\r\n$1\r\nb\r\n$1\r\nc\r\n"),
+            (&["RPOP", "l", "0"], "*0\r\n"),
+            (
+                &["LPOP", "l", "-1"],
+                "-ERR value is out of range, must be positive\r\n",
+            ),
+            (&["RPOP", "l", "5"], "*2\r\n$1\r\ne\r\n$1\r\nd\r\n"),
+            (&["EXISTS", "l"], ":0\r\n"),
+            (&["LPOP", "l"], "$-1\r\n"),
+            (&["LPOP", "l", "1"], "*-1\r\n"),
+            (
+                &["LPOP", "l", "1", "2"],
+                "-ERR wrong number of arguments for 'lpop' command\r\n",
+            ),
+            // list commands refuse other types and leave them alone
+            (&["SET", "s", "x"], "+OK\r\n"),
+            (&["LPUSH", "s", "y"], WRONGTYPE),
+            (&["RPUSH", "s", "y"], WRONGTYPE),
+            (&["LLEN", "s"], WRONGTYPE),
+            (&["LRANGE", "s", "0", "-1"], WRONGTYPE),
+            (&["GET", "s"], "$1\r\nx\r\n"),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn hashes_test() {
+    run(
+        "hashes",
+        &[
+            (&["HGET", "h", "f"], "$-1\r\n"),
+            (&["HGETALL", "h"], "*0\r\n"),
+            (&["HSET", "h", "f", "v"], ":1\r\n"),
+            (&["HSET", "h", "f", "v2"], ":0\r\n"),
+            (&["HSET", "h", "f", "v3", "g", "w"], ":1\r\n"),
+            (&["HGET", "h", "f"], "$2\r\nv3\r\n"),
+            (&["HGET", "h", "missing"], "$-1\r\n"),
+            (&["HSET", "one", "", ""], ":1\r\n"),
+            (&["OBJECT", "ENCODING", "h"], "$8\r\nlistpack\r\n"),
+            (
+                &["CONFIG", "SET", "hash-max-listpack-value", "4"],
+                "+OK\r\n",
+            ),
+            (&["HSET", "h", "long", "value"], ":1\r\n"),
+            (&["OBJECT", "ENCODING", "h"], "$9\r\nhashtable\r\n"),
+            (&["HGET", "h", "g"], "$1\r\nw\r\n"),
+            (
+                &["CONFIG", "GET", "hash-max-listpack-value"],
+                "*2\r\n$23\r\nhash-max-listpack-value\r\n$1\r\n4\r\n",
+            ),
+            (&["HGETALL", "one"], "*2\r\n$0\r\n\r\n$0\r\n\r\n"),
+            (
+                &["HSET", "h", "f"],
+                "-ERR wrong number of arguments for 'hset' command\r\n",
+            ),
+            (
+                &["HSET", "h", "f", "v", "g"],
+                "-ERR wrong number of arguments for 'hset' command\r\n",
+            ),
+            (
+                &["HGET", "h"],
+                "-ERR wrong number of arguments for 'hget' command\r\n",
+            ),
+            (&["RPUSH", "l", "x"], ":1\r\n"),
+            (&["HSET", "l", "f", "v"], WRONGTYPE),
+            (&["HGET", "l", "f"], WRONGTYPE),
+            (&["HGETALL", "l"], WRONGTYPE),
+            // HDEL removes the key with its last field
+            (&["HDEL", "h", "f", "missing", "f"], ":1\r\n"),
+            (&["HGET", "h", "f"], "$-1\r\n"),
+            (&["HDEL", "missing", "f"], ":0\r\n"),
+            (&["HDEL", "one", ""], ":1\r\n"),
+            (&["EXISTS", "one"], ":0\r\n"),
+            (&["HDEL", "l", "f"], WRONGTYPE),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn sets_test() {
+    run(
+        "sets",
+        &[
+            (&["SCARD", "s"], ":0\r\n"),
+            (&["SMEMBERS", "s"], "*0\r\n"),
+            (&["SADD", "s", "a", "b", "a"], ":2\r\n"),
+            (&["SADD", "s", "a"], ":0\r\n"),
+            (&["SADD", "s", "c"], ":1\r\n"),
+            (&["SCARD", "s"], ":3\r\n"),
+            // members come back in no particular order, SORT puts them in one
+            (
+                &["SORT", "s", "ALPHA"],
+                "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n",
+            ),
+            (&["SADD", "one", "only"], ":1\r\n"),
+            (&["SMEMBERS", "one"], "*1\r\n$4\r\nonly\r\n"),
+            // small sets of integers stay an intset until a string or too many members arrive
+            (&["SADD", "ints", "1", "2", "3"], ":3\r\n"),
+            (&["OBJECT", "ENCODING", "ints"], "$6\r\nintset\r\n"),
+            (&["SADD", "ints", "x"], ":1\r\n"),
+            (&["OBJECT", "ENCODING", "ints"], "$8\r\nlistpack\r\n"),
+            (
+                &["CONFIG", "SET", "set-max-listpack-entries", "4"],
+                "+OK\r\n",
+            ),
+            (&["SADD", "ints", "y"], ":1\r\n"),
+            (&["OBJECT", "ENCODING", "ints"], "$9\r\nhashtable\r\n"),
+            (&["SCARD", "ints"], ":5\r\n"),
+            (
+                &["SADD", "s"],
+                "-ERR wrong number of arguments for 'sadd' command\r\n",
+            ),
+            (&["SET", "str", "x"], "+OK\r\n"),
+            (&["SADD", "str", "a"], WRONGTYPE),
+            (&["SCARD", "str"], WRONGTYPE),
+            (&["SMEMBERS", "str"], WRONGTYPE),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn sorted_sets_test() {
+    run(
+        "zsets",
+        &[
+            (&["ZCARD", "z"], ":0\r\n"),
+            (&["ZRANGE", "z", "0", "-1"], "*0\r\n"),
+            (&["ZADD", "z", "1", "a", "2", "b", "3", "c"], ":3\r\n"),
+            // updating a score adds nothing
+            (&["ZADD", "z", "5", "a"], ":0\r\n"),
+            (&["ZCARD", "z"], ":3\r\n"),
+            (&["ZSCORE", "z", "a"], "$1\r\n5\r\n"),
+            (&["ZSCORE", "z", "missing"], "$-1\r\n"),
+            (&["ZSCORE", "missing", "a"], "$-1\r\n"),
+            (
+                &["ZRANGE", "z", "0", "-1"],
+                "*3\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\na\r\n",
+            ),
+            (
+                &["ZRANGE", "z", "0", "0", "WITHSCORES"],
+                "*2\r\n$1\r\nb\r\n$1\r\n2\r\n",
+            ),
+            (&["ZRANGE", "z", "-2", "-1"], "*2\r\n$1\r\nc\r\n$1\r\na\r\n"),
+            (&["ZRANGE", "z", "2", "1"], "*0\r\n"),
+            (&["ZRANGE", "z", "0", "-1", "BOGUS"], SYNTAX),
+            (&["ZRANGE", "z", "zero", "-1"], NOT_INTEGER),
+            // equal scores are ordered by member
+            (&["ZADD", "ties", "1", "b", "1", "a"], ":2\r\n"),
+            (
+                &["ZRANGE", "ties", "0", "-1"],
+                "*2\r\n$1\r\na\r\n$1\r\nb\r\n",
+            ),
+            (&["ZADD", "z", "1.5", "d"], ":1\r\n"),
+            (&["ZSCORE", "z", "d"], "$3\r\n1.5\r\n"),
+            (&["ZADD", "z", "inf", "top", "-inf", "bottom"], ":2\r\n"),
+            (&["ZSCORE", "z", "top"], "$3\r\ninf\r\n"),
+            (&["ZRANGE", "z", "0", "0"], "*1\r\n$6\r\nbottom\r\n"),
+            (
+                &["ZADD", "z", "nan", "x"],
+                "-ERR value is not a valid float\r\n",
+            ),
+            (
+                &["ZADD", "z", "high", "x"],
+                "-ERR value is not a valid float\r\n",
+            ),
+            (&["ZADD", "z", "1", "x", "2"], SYNTAX),
+            (
+                &["ZADD", "z", "1"],
+                "-ERR wrong number of arguments for 'zadd' command\r\n",
+            ),
+            (&["ZCARD", "z"], ":6\r\n"),
+            (&["SET", "str", "x"], "+OK\r\n"),
+            (&["ZADD", "str", "1", "a"], WRONGTYPE),
+            (&["ZSCORE", "str", "a"], WRONGTYPE),
+            (&["ZRANGE", "str", "0", "-1"], WRONGTYPE),
+            (&["ZCARD", "str"], WRONGTYPE),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn sort_test() {
+    run(
+        "sort",
+        &[
+            (&["SORT", "missing"], "*0\r\n"),
+            (&["RPUSH", "n", "3", "1", "2", "10"], ":4\r\n"),
+            (
+                &["SORT", "n"],
+                "*4\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$2\r\n10\r\n",
+            ),
+            (
+                &["SORT", "n", "DESC"],
+                "*4\r\n$2\r\n10\r\n$1\r\n3\r\n$1\r\n2\r\n$1\r\n1\r\n",
+            ),
+            (
+                &["SORT", "n", "ALPHA"],
+                "*4\r\n$1\r\n1\r\n$2\r\n10\r\n$1\r\n2\r\n$1\r\n3\r\n",
+            ),
+            (
+                &["SORT", "n", "LIMIT", "1", "2"],
+                "*2\r\n$1\r\n2\r\n$1\r\n3\r\n",
+            ),
+            (&["SORT", "n", "LIMIT", "10", "2"], "*0\r\n"),
+            (&["SORT", "n", "LIMIT", "-1", "1"], "*1\r\n$1\r\n1\r\n"),
+            // BY and GET read other keys, `#` is the element itself
+            (&["SET", "w_1", "30"], "+OK\r\n"),
+            (&["SET", "w_2", "20"], "+OK\r\n"),
+            (&["SET", "w_3", "10"], "+OK\r\n"),
+            (&["SET", "w_10", "40"], "+OK\r\n"),
+            (
+                &["SORT", "n", "BY", "w_*"],
+                "*4\r\n$1\r\n3\r\n$1\r\n2\r\n$1\r\n1\r\n$2\r\n10\r\n",
+            ),
+            (&["HSET", "obj_1", "name", "one"], ":1\r\n"),
+            (
+                &[
+                    "SORT",
+                    "n",
+                    "LIMIT",
+                    "0",
+                    "2",
+                    "GET",
+                    "#",
+                    "GET",
+                    "obj_*->name",
+                ],
+                "*4\r\n$1\r\n1\r\n$3\r\none\r\n$1\r\n2\r\n$-1\r\n",
+            ),
+            (
+                &["SORT", "n", "BY", "nosort"],
+                "*4\r\n$1\r\n3\r\n$1\r\n1\r\n$1\r\n2\r\n$2\r\n10\r\n",
+            ),
+            (&["SORT", "n", "STORE", "sorted"], ":4\r\n"),
+            (&["LRANGE", "sorted", "0", "0"], "*1\r\n$1\r\n1\r\n"),
+            (&["SORT", "missing", "STORE", "sorted"], ":0\r\n"),
+            (&["TYPE", "sorted"], "+none\r\n"),
+            (&["SORT_RO", "n", "STORE", "sorted"], SYNTAX),
+            (&["SORT_RO", "n", "LIMIT", "0", "1"], "*1\r\n$1\r\n1\r\n"),
+            (&["SORT", "n", "LIMIT", "0"], SYNTAX),
+            (&["SORT", "n", "BOGUS"], SYNTAX),
+            // elements that are not numbers need ALPHA
+            (&["RPUSH", "words", "b", "a"], ":2\r\n"),
+            (
+                &["SORT", "words"],
+                "-ERR One or more scores can't be converted into double\r\n",
+            ),
+            (&["SORT", "words", "ALPHA"], "*2\r\n$1\r\na\r\n$1\r\nb\r\n"),
+            (&["ZADD", "z", "2", "x", "1", "y"], ":2\r\n"),
+            (
+                &["SORT", "z", "BY", "nosort", "DESC"],
+                "*2\r\n$1\r\nx\r\n$1\r\ny\r\n",
+            ),
+            (&["SET", "str", "x"], "+OK\r\n"),
+            (&["SORT", "str"], WRONGTYPE),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn geo_test() {
+    run(
+        "geo",
+        &[
+            (
+                &[
+                    "GEOADD",
+                    "Sicily",
+                    "13.361389",
+                    "38.115556",
+                    "Palermo",
+                    "15.087269",
+                    "37.502669",
+                    "Catania",
+                ],
+                ":2\r\n",
+            ),
+            (
+                &["GEOADD", "Sicily", "13.361389", "38.115556", "Palermo"],
+                ":0\r\n",
+            ),
+            (
+                &["GEODIST", "Sicily", "Palermo", "Catania"],
+                "$11\r\n166274.1516\r\n",
+            ),
+            (
+                &["GEODIST", "Sicily", "Palermo", "Catania", "km"],
+                "$8\r\n166.2742\r\n",
+            ),
+            (&["GEODIST", "Sicily", "Palermo", "Rome"], "$-1\r\n"),
+            (
+                &["GEODIST", "Sicily", "Palermo", "Catania", "parsecs"],
+                "-ERR unsupported unit provided. please use M, KM, FT, MI\r\n",
+            ),
+            (
+                &["GEOHASH", "Sicily", "Palermo", "Catania", "Rome"],
+                "*3\r\n$11\r\nsqc8b49rny0\r\n$11\r\nsqdtr74hyu0\r\n$-1\r\n",
+            ),
+            (
+                &["GEOPOS", "Sicily", "Palermo", "Rome"],
+                "*2\r\n*2\r\n$20\r\n13.36138933897018433\r\n$20\r\n38.11555639549629859\r\n*-1\r\n",
+            ),
+            (
+                &[
+                    "GEOSEARCH",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "200",
+                    "km",
+                    "ASC",
+                ],
+                "*2\r\n$7\r\nCatania\r\n$7\r\nPalermo\r\n",
+            ),
+            (
+                &[
+                    "GEOSEARCH",
+                    "Sicily",
+                    "FROMMEMBER",
+                    "Palermo",
+                    "BYBOX",
+                    "400",
+                    "400",
+                    "km",
+                    "DESC",
+                    "COUNT",
+                    "1",
+                ],
+                "*1\r\n$7\r\nCatania\r\n",
+            ),
+            (
+                &[
+                    "GEOSEARCH",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "100",
+                    "km",
+                    "ASC",
+                    "WITHDIST",
+                ],
+                "*1\r\n*2\r\n$7\r\nCatania\r\n$7\r\n56.4413\r\n",
+            ),
+            (
+                &[
+                    "GEOSEARCHSTORE",
+                    "near",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "200",
+                    "km",
+                ],
+                ":2\r\n",
+            ),
+            (&["ZCARD", "near"], ":2\r\n"),
+            (
+                &["GEOADD", "Sicily", "200", "100", "Nowhere"],
+                "-ERR invalid longitude,latitude pair 200.000000,100.000000\r\n",
+            ),
+            (
+                &["GEOADD", "Sicily", "east", "37", "Nowhere"],
+                "-ERR value is not a valid float\r\n",
+            ),
+            (
+                &[
+                    "GEOSEARCH",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "-1",
+                    "km",
+                ],
+                "-ERR radius cannot be negative\r\n",
+            ),
+            (
+                &[
+                    "GEOSEARCH",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "10",
+                    "km",
+                    "COUNT",
+                    "0",
+                ],
+                "-ERR COUNT must be > 0\r\n",
+            ),
+            (
+                &[
+                    "GEOSEARCH",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    "10",
+                    "km",
+                    "ANY",
+                ],
+                "-ERR the ANY argument requires COUNT argument\r\n",
+            ),
+            (&["SET", "str", "x"], "+OK\r\n"),
+            (&["GEOADD", "str", "13", "38", "x"], WRONGTYPE),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn transactions_test() {
+    run(
+        "transactions",
+        &[
+            (&["EXEC"], "-ERR EXEC without MULTI\r\n"),
+            (&["DISCARD"], "-ERR DISCARD without MULTI\r\n"),
+            (&["MULTI"], "+OK\r\n"),
+            (&["MULTI"], "-ERR MULTI calls can not be nested\r\n"),
+            (&["SET", "a", "1"], "+QUEUED\r\n"),
+            (&["GET", "a"], "+QUEUED\r\n"),
+            (&["EXEC"], "*2\r\n+OK\r\n$1\r\n1\r\n"),
+            (&["MULTI"], "+OK\r\n"),
+            (&["EXEC"], "*0\r\n"),
+            (&["MULTI"], "+OK\r\n"),
+            (&["SET", "a", "2"], "+QUEUED\r\n"),
+            (&["DISCARD"], "+OK\r\n"),
+            (&["GET", "a"], "$1\r\n1\r\n"),
+            // a command that can not be queued aborts the transaction
+            (&["MULTI"], "+OK\r\n"),
+            (&["SET", "a", "3"], "+QUEUED\r\n"),
+            (
+                &["SET", "a"],
+                "-ERR wrong number of arguments for 'set' command\r\n",
+            ),
+            (
+                &["NOSUCHCOMMAND"],
+                "-ERR unknown command 'NOSUCHCOMMAND', with args beginning with: \r\n",
+            ),
+            (
+                &["EXEC"],
+                "-EXECABORT Transaction discarded because of previous errors.\r\n",
+            ),
+            (&["GET", "a"], "$1\r\n1\r\n"),
+            // errors while running do not stop the other commands
+            (&["MULTI"], "+OK\r\n"),
+            (&["LPUSH", "a", "x"], "+QUEUED\r\n"),
+            (&["SET", "a", "4"], "+QUEUED\r\n"),
+            (
+                &["EXEC"],
+                "*2\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n+OK\r\n",
+            ),
+            // a watched key changed before EXEC fails the transaction, even by the same client
+            (&["WATCH", "a"], "+OK\r\n"),
+            (&["SET", "a", "5"], "+OK\r\n"),
+            (&["MULTI"], "+OK\r\n"),
+            (&["WATCH", "a"], "-ERR WATCH inside MULTI is not allowed\r\n"),
+            (&["SET", "a", "6"], "+QUEUED\r\n"),
+            (&["EXEC"], "*-1\r\n"),
+            (&["GET", "a"], "$1\r\n5\r\n"),
+            // EXEC forgets the watched keys either way
+            (&["MULTI"], "+OK\r\n"),
+            (&["SET", "a", "7"], "+QUEUED\r\n"),
+            (&["EXEC"], "*1\r\n+OK\r\n"),
+            (&["WATCH", "a"], "+OK\r\n"),
+            (&["UNWATCH"], "+OK\r\n"),
+            (&["SET", "a", "8"], "+OK\r\n"),
+            (&["MULTI"], "+OK\r\n"),
+            (&["GET", "a"], "+QUEUED\r\n"),
+            (&["EXEC"], "*1\r\n$1\r\n8\r\n"),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn pubsub_test() {
+    run(
+        "pubsub",
+        &[
+            (&["PUBLISH", "news", "hello"], ":0\r\n"),
+            (&["PUBSUB", "CHANNELS"], "*0\r\n"),
+            (&["PUBSUB", "NUMPAT"], ":0\r\n"),
+            (
+                &["UNSUBSCRIBE"],
+                "*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n",
+            ),
+            (
+                &["SUBSCRIBE", "news", "sport"],
+                "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n*3\r\n$9\r\nsubscribe\r\n$5\r\nsport\r\n:2\r\n",
+            ),
+            (
+                &["PSUBSCRIBE", "alerts.*"],
+                "*3\r\n$10\r\npsubscribe\r\n$8\r\nalerts.*\r\n:3\r\n",
+            ),
+            // a RESP2 subscriber may only manage its subscriptions
+            (
+                &["GET", "news"],
+                "-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n",
+            ),
+            (&["PING"], "*2\r\n$4\r\npong\r\n$0\r\n\r\n"),
+            (&["PING", "hi"], "*2\r\n$4\r\npong\r\n$2\r\nhi\r\n"),
+            (
+                &["UNSUBSCRIBE", "news"],
+                "*3\r\n$11\r\nunsubscribe\r\n$4\r\nnews\r\n:2\r\n",
+            ),
+            (
+                &["PUNSUBSCRIBE"],
+                "*3\r\n$12\r\npunsubscribe\r\n$8\r\nalerts.*\r\n:1\r\n",
+            ),
+            (
+                &["UNSUBSCRIBE"],
+                "*3\r\n$11\r\nunsubscribe\r\n$5\r\nsport\r\n:0\r\n",
+            ),
+            (&["PING"], "+PONG\r\n"),
+            (&["PUBSUB", "NUMSUB", "news"], "*2\r\n$4\r\nnews\r\n:0\r\n"),
+            (
+                &["PUBSUB", "BOGUS"],
+                "-ERR unknown subcommand 'BOGUS'. Try PUBSUB HELP.\r\n",
+            ),
+            (
+                &["PUBLISH", "news"],
+                "-ERR wrong number of arguments for 'publish' command\r\n",
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn connection_test() {
+    run(
+        "connection",
+        &[
+            (&["PING"], "+PONG\r\n"),
+            (&["ping", "hello"], "$5\r\nhello\r\n"),
+            (
+                &["PING", "a", "b"],
+                "-ERR wrong number of arguments for 'ping' command\r\n",
+            ),
+            (&["ECHO", "hi there"], "$8\r\nhi there\r\n"),
+            (&["ECHO", ""], "$0\r\n\r\n"),
+            (
+                &["ECHO"],
+                "-ERR wrong number of arguments for 'echo' command\r\n",
+            ),
+            (
+                &["NOSUCHCOMMAND", "a", "b"],
+                "-ERR unknown command 'NOSUCHCOMMAND', with args beginning with: 'a' 'b' \r\n",
+            ),
+            // databases are separate namespaces
+            (&["SET", "k", "zero"], "+OK\r\n"),
+            (&["SELECT", "1"], "+OK\r\n"),
+            (&["GET", "k"], "$-1\r\n"),
+            (&["SELECT", "16"], "-ERR DB index is out of range\r\n"),
+            (&["SELECT", "-1"], "-ERR DB index is out of range\r\n"),
+            (&["SELECT", "one"], NOT_INTEGER),
+            (&["SELECT", "0"], "+OK\r\n"),
+            (&["GET", "k"], "$4\r\nzero\r\n"),
+            // CLIENT
+            (&["CLIENT", "GETNAME"], "$-1\r\n"),
+            (&["CLIENT", "SETNAME", "conformance"], "+OK\r\n"),
+            (&["CLIENT", "GETNAME"], "$11\r\nconformance\r\n"),
+            (
+                &["CLIENT", "SETNAME", "two words"],
+                "-ERR Client names cannot contain spaces, newlines or special characters.\r\n",
+            ),
+            (&["CLIENT", "ID"], ":..."),
+            (&["CLIENT", "INFO"], "$..."),
+            (&["CLIENT", "LIST"], "$..."),
+            (&["CLIENT", "KILL", "ID", "999999"], ":0\r\n"),
+            (
+                &["CLIENT", "BOGUS"],
+                "-ERR unknown subcommand 'BOGUS'. Try CLIENT HELP.\r\n",
+            ),
+            // HELLO switches the protocol of the replies
+            (&["HELLO", "4"], "-NOPROTO unsupported protocol version\r\n"),
+            (&["HELLO", "3"], "%..."),
+            (&["GET", "missing"], "_\r\n"),
+            (&["HSET", "h", "f", "v"], ":1\r\n"),
+            (&["HGETALL", "h"], "%1\r\n$1\r\nf\r\n$1\r\nv\r\n"),
+            (&["HELLO", "2"], "*..."),
+            (&["GET", "missing"], "$-1\r\n"),
+            (&["HGETALL", "h"], "*2\r\n$1\r\nf\r\n$1\r\nv\r\n"),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn server_test() {
+    run(
+        "server",
+        &[
+            (&["DBSIZE"], ":0\r\n"),
+            (&["SET", "a", "1"], "+OK\r\n"),
+            (&["SELECT", "1"], "+OK\r\n"),
+            (&["SET", "b", "2"], "+OK\r\n"),
+            (&["FLUSHDB"], "+OK\r\n"),
+            (&["DBSIZE"], ":0\r\n"),
+            (&["SELECT", "0"], "+OK\r\n"),
+            (&["DBSIZE"], ":1\r\n"),
+            (&["FLUSHDB", "BOGUS"], SYNTAX),
+            // SWAPDB exchanges the contents of two databases
+            (&["SWAPDB", "0", "1"], "+OK\r\n"),
+            (&["DBSIZE"], ":0\r\n"),
+            (&["SWAPDB", "0", "1"], "+OK\r\n"),
+            (&["GET", "a"], "$1\r\n1\r\n"),
+            (&["SWAPDB", "x", "1"], "-ERR invalid first DB index\r\n"),
+            (&["SWAPDB", "0", "x"], "-ERR invalid second DB index\r\n"),
+            (&["SWAPDB", "0", "99"], "-ERR DB index is out of range\r\n"),
+            (&["FLUSHALL", "ASYNC"], "+OK\r\n"),
+            (&["DBSIZE"], ":0\r\n"),
+            (&["CONFIG", "SET", "lazyfree-lazy-user-flush", "yes"], "+OK\r\n"),
+            (
+                &["CONFIG", "GET", "lazyfree-lazy-user-flush"],
+                "*2\r\n$24\r\nlazyfree-lazy-user-flush\r\n$3\r\nyes\r\n",
+            ),
+            (
+                &["CONFIG", "SET", "lazyfree-lazy-expire", "maybe"],
+                "-ERR CONFIG SET failed (possibly related to argument 'lazyfree-lazy-expire') - argument must be 'yes' or 'no'\r\n",
+            ),
+            // CONFIG
+            (
+                &["CONFIG", "GET", "databases"],
+                "*2\r\n$9\r\ndatabases\r\n$2\r\n16\r\n",
+            ),
+            (&["CONFIG", "GET", "no-such-option"], "*0\r\n"),
+            (&["CONFIG", "SET", "maxmemory", "0"], "+OK\r\n"),
+            (
+                &["CONFIG", "GET", "maxmemory"],
+                "*2\r\n$9\r\nmaxmemory\r\n$1\r\n0\r\n",
+            ),
+            (
+                &["CONFIG", "SET", "databases", "4"],
+                "-ERR CONFIG SET failed (possibly related to argument 'databases') - can't set immutable config\r\n",
+            ),
+            (
+                &["CONFIG", "GET"],
+                "-ERR wrong number of arguments for 'config|get' command\r\n",
+            ),
+            (
+                &["CONFIG", "SET", "maxmemory"],
+                "-ERR wrong number of arguments for 'config|set' command\r\n",
+            ),
+            (
+                &["CONFIG", "BOGUS"],
+                "-ERR unknown subcommand 'BOGUS'. Try CONFIG HELP.\r\n",
+            ),
+            // INFO, persistence and diagnostics
+            (&["INFO"], "$..."),
+            (&["INFO", "keyspace"], "$12\r\n# Keyspace\r\n\r\n"),
+            (&["SAVE"], "+OK\r\n"),
+            (&["LASTSAVE"], ":..."),
+            (&["SLOWLOG", "RESET"], "+OK\r\n"),
+            (&["SLOWLOG", "GET"], "*0\r\n"),
+            (&["SLOWLOG", "LEN"], ":0\r\n"),
+            (&["LATENCY", "RESET"], ":0\r\n"),
+            (&["LATENCY", "LATEST"], "*0\r\n"),
+            (&["SET", "m", "value"], "+OK\r\n"),
+            (&["MEMORY", "USAGE", "m"], ":..."),
+            (&["MEMORY", "USAGE", "missing"], "$-1\r\n"),
+            (&["MEMORY", "USAGE", "m", "SAMPLES", "-1"], SYNTAX),
+            (&["MEMORY", "STATS"], "*..."),
+            (&["MEMORY", "DOCTOR"], "$..."),
+            (
+                &["MEMORY", "BOGUS"],
+                "-ERR unknown subcommand 'BOGUS'. Try MEMORY HELP.\r\n",
+            ),
+            (&["SHUTDOWN", "ABORT"], "-ERR No shutdown in progress.\r\n"),
+            (&["MONITOR"], "+OK\r\n"),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn protocol_test() {
+    let server: InProcess = InProcess::start("conformance-protocol", &[]).await;
+    let mut connection: Connection = Connection {
+        stream: TcpStream::connect(&server.addr).await.unwrap(),
+        buffer: Vec::new(),
+    };
+
+    // inline commands and pipelined requests are answered in order
+    connection.send(b"PING\r\nECHO  inline\n").await;
+    assert_eq!(connection.read_frame().await, b"+PONG\r\n");
+    assert_eq!(connection.read_frame().await, b"$6\r\ninline\r\n");
+    connection
+        .send(b"*1\r\n$4\r\nPING\r\n\r\n*2\r\n$4\r\nECHO\r\n$0\r\n\r\n")
+        .await;
+    assert_eq!(connection.read_frame().await, b"+PONG\r\n");
+    assert_eq!(connection.read_frame().await, b"$0\r\n\r\n");
+
+    // a protocol error is reported, then the connection is closed
+    connection.send(b"*1\r\n$4\r\nPING\r\n*x\r\n").await;
+    assert_eq!(connection.read_frame().await, b"+PONG\r\n");
+    assert_eq!(
+        connection.read_frame().await,
+        b"-ERR Protocol error: invalid multibulk length\r\n"
+    );
+    assert_eq!(connection.read_frame().await, b"");
+
+    let mut connection: Connection = Connection {
+        stream: TcpStream::connect(&server.addr).await.unwrap(),
+        buffer: Vec::new(),
+    };
+    connection.send(b"*1\r\n+PING\r\n").await;
+    assert_eq!(
+        connection.read_frame().await,
+        b"-ERR Protocol error: expected '$', got '+'\r\n"
+    );
+    assert_eq!(connection.read_frame().await, b"");
+
+    server.stop().await;
+}
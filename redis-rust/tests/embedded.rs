@@ -0,0 +1,129 @@
+//! Runs several servers inside the test process with `Server`.
+
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::path::PathBuf;
+
+use redis_starter_rust::redis_client::{bulks, Cmd, RedisClient};
+use redis_starter_rust::redis_parser::parse_reply;
+use redis_starter_rust::server::{Server, ServerHandle};
+use redis_starter_rust::{Config, RedisType};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use common::{free_port, temp_dir, InProcess};
+
+/// Sends one command over an in-memory connection and reads its reply.
+async fn call(stream: &mut DuplexStream, cmd: Cmd) -> RedisType<'static> {
+    let mut request: Vec<u8> = Vec::new();
+    cmd.encode(&mut request);
+    stream.write_all(&request).await.unwrap();
+
+    let mut buffer: Vec<u8> = Vec::new();
+    loop {
+        if let Some((reply, _)) = parse_reply(&buffer).unwrap() {
+            return reply;
+        }
+        assert!(
+            stream.read_buf(&mut buffer).await.unwrap() > 0,
+            "connection closed"
+        );
+    }
+}
+
+#[tokio::test]
+async fn ephemeral_port_test() {
+    // both listen on ports of their own and keep their data apart
+    let (first, second) = tokio::join!(
+        InProcess::start("embedded-first", &[]),
+        InProcess::start("embedded-second", &[])
+    );
+    assert_ne!(first.addr, second.addr);
+
+    let mut a: RedisClient = RedisClient::connect(&first.addr).await.unwrap();
+    let mut b: RedisClient = RedisClient::connect(&second.addr).await.unwrap();
+    a.set("owner", "first").await.unwrap();
+    assert_eq!(b.get("owner").await.unwrap(), None);
+
+    // the port actually listened on replaces the configured one
+    let port: String = first.server.local_addr().unwrap().port().to_string();
+    let reply: RedisType = a
+        .call(&Cmd::new("CONFIG").arg("GET").arg("port"))
+        .await
+        .unwrap();
+    assert_eq!(
+        bulks(reply).unwrap(),
+        vec![b"port".to_vec(), port.into_bytes()]
+    );
+
+    drop(a);
+    drop(b);
+    first.stop().await;
+    second.stop().await;
+}
+
+#[tokio::test]
+async fn in_memory_test() {
+    let dir: PathBuf = temp_dir("embedded-in-memory");
+    let metrics_port: u16 = free_port();
+    let args: Vec<String> = vec![
+        String::from("--dir"),
+        dir.to_string_lossy().to_string(),
+        String::from("--metrics-port"),
+        metrics_port.to_string(),
+    ];
+
+    let server: ServerHandle = Server::new(Config::from_args(&args).unwrap())
+        .in_memory()
+        .start()
+        .await
+        .unwrap();
+    assert_eq!(server.local_addr(), None);
+    // not even the metrics endpoint listens
+    assert!(tokio::net::TcpStream::connect(("127.0.0.1", metrics_port))
+        .await
+        .is_err());
+
+    let mut writer: DuplexStream = server.connect().unwrap();
+    let mut reader: DuplexStream = server.connect().unwrap();
+    assert_eq!(
+        call(&mut writer, Cmd::new("SET").arg("k").arg("v")).await,
+        RedisType::Status(String::from("OK"))
+    );
+    assert_eq!(
+        call(&mut reader, Cmd::new("GET").arg("k")).await,
+        RedisType::BulkBytes(b"v".to_vec())
+    );
+
+    // a plain shutdown saves, and the connections are closed
+    server.shutdown().await.unwrap();
+    assert!(dir.join("dump.rdb").exists());
+    let mut rest: Vec<u8> = Vec::new();
+    assert_eq!(reader.read_to_end(&mut rest).await.unwrap(), 0);
+
+    // the next server starts from the snapshot
+    let server: ServerHandle = Server::new(Config::from_args(&args).unwrap())
+        .in_memory()
+        .start()
+        .await
+        .unwrap();
+    let mut client: DuplexStream = server.connect().unwrap();
+    assert_eq!(
+        call(&mut client, Cmd::new("GET").arg("k")).await,
+        RedisType::BulkBytes(b"v".to_vec())
+    );
+    drop(client);
+    server.shutdown().await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn no_listener_test() {
+    let config: Config = Config {
+        port: 0,
+        ..Config::default()
+    };
+    let e = Server::new(config).start().await.err().unwrap();
+    assert_eq!(e.message, "Configured to not listen anywhere, exiting.");
+}
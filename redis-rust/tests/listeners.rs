@@ -0,0 +1,78 @@
+//! Runs the server binary on several TCP addresses and on a Unix socket.
+
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::net::TcpStream;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use common::{call, connect, connect_unix, free_port, temp_dir, Server};
+
+#[test]
+fn bind_test() {
+    let dir: PathBuf = temp_dir("bind");
+    let port: u16 = free_port();
+    let server: Server = Server::start(&[
+        "--dir",
+        dir.to_str().unwrap(),
+        "--port",
+        &port.to_string(),
+        "--bind",
+        "127.0.0.1 ::1",
+    ]);
+
+    let mut v4: TcpStream = connect("127.0.0.1", port);
+    let mut v6: TcpStream = connect("::1", port);
+    assert_eq!(call(&mut v4, &["SET", "foo", "bar"]), b"+OK\r\n");
+    assert_eq!(call(&mut v6, &["GET", "foo"]), b"$3\r\nbar\r\n");
+    assert_eq!(
+        call(&mut v6, &["CONFIG", "SET", "bind", "0.0.0.0"]),
+        b"-ERR CONFIG SET failed (possibly related to argument 'bind') - can't set immutable config\r\n"
+    );
+
+    assert_eq!(call(&mut v4, &["SHUTDOWN", "NOSAVE"]), b"");
+    assert!(server.wait().success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unixsocket_test() {
+    let dir: PathBuf = temp_dir("unixsocket");
+    let socket: PathBuf = dir.join("redis.sock");
+    // port 0 leaves the Unix socket as the only way in
+    let server: Server = Server::start(&[
+        "--dir",
+        dir.to_str().unwrap(),
+        "--port",
+        "0",
+        "--unixsocket",
+        socket.to_str().unwrap(),
+        "--unixsocketperm",
+        "700",
+    ]);
+
+    let mut client: UnixStream = connect_unix(&socket);
+    assert_eq!(call(&mut client, &["PING"]), b"+PONG\r\n");
+    let mode: u32 = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o700);
+    assert_eq!(
+        call(&mut client, &["CONFIG", "GET", "unixsocketperm"]),
+        b"*2\r\n$14\r\nunixsocketperm\r\n$3\r\n700\r\n"
+    );
+    let list: String = String::from_utf8(call(&mut client, &["CLIENT", "LIST"])).unwrap();
+    assert!(list.contains(&format!("addr={}:0", socket.display())));
+
+    assert_eq!(call(&mut client, &["SHUTDOWN", "NOSAVE"]), b"");
+    assert!(server.wait().success());
+    assert!(!socket.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn no_listener_test() {
+    let server: Server = Server::start(&["--port", "0"]);
+    assert!(!server.wait().success());
+}
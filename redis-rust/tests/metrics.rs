@@ -0,0 +1,81 @@
+//! Scrapes the Prometheus endpoint of a server running inside the test process.
+
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use redis_starter_rust::redis_client::{Cmd, RedisClient};
+
+use common::{free_port, InProcess};
+
+/// Sends a bare HTTP/1.1 request and returns the whole response.
+async fn http(port: u16, request_line: &str) -> String {
+    let mut stream: TcpStream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let request: String = format!("{}\r\nHost: localhost\r\n\r\n", request_line);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response: String = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    return response;
+}
+
+#[tokio::test]
+async fn metrics_test() {
+    let port: u16 = free_port();
+    let server: InProcess =
+        InProcess::start("metrics", &["--metrics-port", &port.to_string()]).await;
+    let mut client: RedisClient = RedisClient::connect(&server.addr).await.unwrap();
+
+    client.set("a", "1").await.unwrap();
+    client.set("b", "2").await.unwrap();
+    client.get("a").await.unwrap();
+    client.lpush("a", &["x"]).await.unwrap_err();
+    client.call(&Cmd::new("SELECT").arg("3")).await.unwrap();
+    client.set_ex("c", "3", 100).await.unwrap();
+    client.call(&Cmd::new("NOSUCHCOMMAND")).await.unwrap_err();
+
+    let response: String = http(port, "GET /metrics HTTP/1.1").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+    let lines: Vec<&str> = response.lines().collect();
+    for expected in [
+        "redis_commands_total{cmd=\"set\"} 3",
+        "redis_commands_total{cmd=\"get\"} 1",
+        "redis_commands_failed_total{cmd=\"lpush\"} 1",
+        "redis_command_duration_seconds_count{cmd=\"set\"} 3",
+        "redis_command_duration_seconds_bucket{cmd=\"set\",le=\"+Inf\"} 3",
+        "redis_connected_clients 1",
+        "redis_db_keys{db=\"db0\"} 2",
+        "redis_db_keys{db=\"db3\"} 1",
+        "redis_db_keys_expiring{db=\"db3\"} 1",
+        "redis_expired_keys_total 0",
+        "redis_evicted_keys_total 0",
+        "redis_rdb_changes_since_last_save 3",
+        "redis_rdb_last_bgsave_status 1",
+    ] {
+        assert!(lines.contains(&expected), "missing {}", expected);
+    }
+    // unknown commands have no series of their own
+    assert!(!response.contains("nosuchcommand"));
+
+    // CONFIG RESETSTAT starts the command series over
+    client
+        .call(&Cmd::new("CONFIG").arg("RESETSTAT"))
+        .await
+        .unwrap();
+    let response: String = http(port, "GET /metrics HTTP/1.1").await;
+    assert!(response.contains("redis_commands_total{cmd=\"config\"} 1"));
+    assert!(!response.contains("cmd=\"set\""));
+
+    let response: String = http(port, "GET /other HTTP/1.1").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    let response: String = http(port, "POST /metrics HTTP/1.1").await;
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    let response: String = http(port, "HEAD /metrics HTTP/1.1").await;
+    assert!(response.ends_with("\r\n\r\n"));
+
+    drop(client);
+    server.stop().await;
+}
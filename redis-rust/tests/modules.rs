@@ -0,0 +1,339 @@
+//! A plugin adding a sorted list of integers type, loaded through the config
+//! like a Redis module.
+
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use redis_starter_rust::commands::{
+    create_if_missing, lookup_typed, parse_i64, Command, Context, CMD_DENYOOM, CMD_READONLY,
+    CMD_WRITE,
+};
+use redis_starter_rust::module::{
+    self, Module, ModuleLoader, ModuleObject, ModuleReader, ModuleType, ModuleValue, ModuleWriter,
+};
+use redis_starter_rust::notify::NOTIFY_MODULE;
+use redis_starter_rust::redis_client::{Cmd, RedisClient};
+use redis_starter_rust::server::{Server, ServerHandle};
+use redis_starter_rust::{Config, Database, Error, RedisType, Value};
+
+use common::temp_dir;
+
+#[derive(Clone, Debug, Default)]
+struct HelloValue {
+    values: Vec<i64>,
+}
+
+impl ModuleValue for HelloValue {
+    fn type_name(&self) -> &'static str {
+        return "hellotype";
+    }
+
+    fn clone_value(&self) -> Box<dyn ModuleValue> {
+        return Box::new(self.clone());
+    }
+
+    fn rdb_save(&self, io: &mut ModuleWriter) {
+        io.save_unsigned(self.values.len() as u64);
+        for value in &self.values {
+            io.save_signed(*value);
+        }
+    }
+
+    fn aof_rewrite(&self, key: &[u8]) -> Vec<Vec<Vec<u8>>> {
+        return self
+            .values
+            .iter()
+            .map(|v| {
+                vec![
+                    b"HELLOTYPE.INSERT".to_vec(),
+                    key.to_vec(),
+                    v.to_string().into_bytes(),
+                ]
+            })
+            .collect();
+    }
+}
+
+struct HelloType;
+
+impl ModuleType for HelloType {
+    fn name(&self) -> &'static str {
+        return "hellotype";
+    }
+
+    fn encoding_version(&self) -> u32 {
+        return 0;
+    }
+
+    fn rdb_load(&self, io: &mut ModuleReader, _encver: u32) -> Result<Box<dyn ModuleValue>, Error> {
+        let len: u64 = io.load_unsigned()?;
+        let mut values: Vec<i64> = Vec::new();
+        for _ in 0..len {
+            values.push(io.load_signed()?);
+        }
+        return Ok(Box::new(HelloValue { values }));
+    }
+}
+
+fn hello_insert(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let value: i64 = parse_i64(&args[2])?;
+    let db: &mut Database = ctx.db(&args[1]);
+    create_if_missing(
+        db,
+        &args[1],
+        Value::Module(ModuleObject::new(HelloValue::default())),
+    )?;
+    let len: Option<usize> = db.modify(&args[1], |v| match v {
+        Value::Module(m) => {
+            let hello: &mut HelloValue = m.downcast_mut::<HelloValue>().unwrap();
+            let at: usize = hello.values.partition_point(|v| *v < value);
+            hello.values.insert(at, value);
+            hello.values.len()
+        }
+        _ => 0,
+    });
+    db.notify(NOTIFY_MODULE, "hellotype.insert", &args[1]);
+    return Ok(RedisType::Integer(len.unwrap_or(0).to_string()));
+}
+
+fn hello_range(ctx: &mut Context, args: &[Vec<u8>]) -> Result<RedisType<'static>, Error> {
+    let first: usize = parse_i64(&args[2])?.max(0) as usize;
+    let count: usize = parse_i64(&args[3])?.max(0) as usize;
+    let values: Vec<RedisType> = match lookup_typed(ctx.db(&args[1]), &args[1], "hellotype")? {
+        Some(Value::Module(m)) => m
+            .downcast_ref::<HelloValue>()
+            .unwrap()
+            .values
+            .iter()
+            .skip(first)
+            .take(count)
+            .map(|v| RedisType::Integer(v.to_string()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    return Ok(RedisType::Array(Box::new(values)));
+}
+
+struct HelloModule;
+
+impl Module for HelloModule {
+    fn name(&self) -> &'static str {
+        return "hellotype";
+    }
+
+    fn version(&self) -> i64 {
+        return 2;
+    }
+
+    fn load(&self, loader: &mut ModuleLoader, _args: &[String]) -> Result<(), Error> {
+        loader.create_data_type(Arc::new(HelloType))?;
+        loader.create_command(Command {
+            name: "hellotype.insert",
+            arity: 3,
+            flags: CMD_WRITE | CMD_DENYOOM,
+            keys: (1, 1, 1),
+            handler: hello_insert,
+        })?;
+        loader.create_command(Command {
+            name: "hellotype.range",
+            arity: 4,
+            flags: CMD_READONLY,
+            keys: (1, 1, 1),
+            handler: hello_range,
+        })?;
+        return Ok(());
+    }
+}
+
+/// A module whose command clashes with a built-in one.
+struct ClashModule;
+
+impl Module for ClashModule {
+    fn name(&self) -> &'static str {
+        return "clash";
+    }
+
+    fn load(&self, loader: &mut ModuleLoader, _args: &[String]) -> Result<(), Error> {
+        return loader.create_command(Command {
+            name: "GET",
+            arity: 2,
+            flags: CMD_READONLY,
+            keys: (1, 1, 1),
+            handler: hello_range,
+        });
+    }
+}
+
+async fn start(dir: &Path, args: &[&str]) -> Result<ServerHandle, Error> {
+    let mut all: Vec<String> = vec![String::from("--dir"), dir.to_string_lossy().to_string()];
+    all.extend(args.iter().map(|a| a.to_string()));
+    return Server::new(Config::from_args(&all)?)
+        .ephemeral_port()
+        .start()
+        .await;
+}
+
+async fn connect(server: &ServerHandle) -> RedisClient {
+    let addr: String = server.local_addr().unwrap().to_string();
+    return RedisClient::connect(&addr).await.unwrap();
+}
+
+async fn range(client: &mut RedisClient, key: &str) -> Vec<Vec<u8>> {
+    let cmd: Cmd = Cmd::new("HELLOTYPE.RANGE").arg(key).arg("0").arg("100");
+    return match client.call(&cmd).await.unwrap() {
+        RedisType::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                RedisType::Integer(n) => n.into_bytes(),
+                other => panic!("unexpected element {:?}", other),
+            })
+            .collect(),
+        other => panic!("unexpected reply {:?}", other),
+    };
+}
+
+#[tokio::test]
+async fn module_type_test() {
+    module::register(Arc::new(HelloModule));
+    let dir: PathBuf = temp_dir("modules-type");
+    let server: ServerHandle = start(&dir, &["--loadmodule", "hellotype some args"])
+        .await
+        .unwrap();
+    let mut client: RedisClient = connect(&server).await;
+
+    let list: RedisType = client.call(&Cmd::new("MODULE").arg("LIST")).await.unwrap();
+    let modules: Vec<RedisType> = match list {
+        RedisType::Array(modules) => *modules,
+        other => panic!("unexpected reply {:?}", other),
+    };
+    let fields: Vec<RedisType> = match modules.into_iter().next() {
+        Some(RedisType::Array(fields)) => *fields,
+        other => panic!("unexpected module {:?}", other),
+    };
+    assert_eq!(fields[1], RedisType::BulkBytes(b"hellotype".to_vec()));
+    assert_eq!(fields[3], RedisType::Integer(String::from("2")));
+    let info: RedisType = client.call(&Cmd::new("INFO").arg("modules")).await.unwrap();
+    let info: String = match info {
+        RedisType::BulkBytes(b) => String::from_utf8(b).unwrap(),
+        other => panic!("unexpected reply {:?}", other),
+    };
+    assert!(info.contains("module:name=hellotype,ver=2,args=[some args]"));
+
+    // another server in the same process has not loaded the module
+    let other_dir: PathBuf = temp_dir("modules-other");
+    let other: ServerHandle = start(&other_dir, &[]).await.unwrap();
+    let mut other_client: RedisClient = connect(&other).await;
+    let list: RedisType = other_client
+        .call(&Cmd::new("MODULE").arg("LIST"))
+        .await
+        .unwrap();
+    assert_eq!(list, RedisType::Array(Box::new(Vec::new())));
+    let error: Error = other_client
+        .call(&Cmd::new("HELLOTYPE.INSERT").arg("numbers").arg("1"))
+        .await
+        .unwrap_err();
+    assert!(error
+        .message
+        .starts_with("ERR unknown command 'HELLOTYPE.INSERT'"));
+    drop(other_client);
+    other.shutdown().await.unwrap();
+    std::fs::remove_dir_all(&other_dir).unwrap();
+
+    // module commands go through the same arity and type checks
+    for value in ["5", "-1", "3"] {
+        let cmd: Cmd = Cmd::new("hellotype.insert").arg("numbers").arg(value);
+        client.call(&cmd).await.unwrap();
+    }
+    assert_eq!(
+        range(&mut client, "numbers").await,
+        vec![b"-1".to_vec(), b"3".to_vec(), b"5".to_vec()]
+    );
+    let error: Error = client
+        .call(&Cmd::new("HELLOTYPE.INSERT").arg("numbers"))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.message,
+        "ERR wrong number of arguments for 'hellotype.insert' command"
+    );
+    client.set("plain", "string").await.unwrap();
+    let error: Error = client
+        .call(&Cmd::new("HELLOTYPE.INSERT").arg("plain").arg("1"))
+        .await
+        .unwrap_err();
+    assert!(error.message.starts_with("WRONGTYPE"));
+    let reply: RedisType = client.call(&Cmd::new("TYPE").arg("numbers")).await.unwrap();
+    assert_eq!(reply, RedisType::Status(String::from("hellotype")));
+
+    // DUMP and RESTORE go through the callbacks of the type
+    let payload: RedisType = client.call(&Cmd::new("DUMP").arg("numbers")).await.unwrap();
+    let payload: Vec<u8> = match payload {
+        RedisType::BulkBytes(b) => b,
+        other => panic!("unexpected reply {:?}", other),
+    };
+    let restore: Cmd = Cmd::new("RESTORE").arg("copy").arg("0").arg(&payload);
+    client.call(&restore).await.unwrap();
+    assert_eq!(
+        range(&mut client, "copy").await,
+        range(&mut client, "numbers").await
+    );
+
+    // and so do saving and loading the snapshot
+    client.call(&Cmd::new("SAVE")).await.unwrap();
+    drop(client);
+    server.shutdown().await.unwrap();
+    let server: ServerHandle = start(&dir, &["--loadmodule", "hellotype"]).await.unwrap();
+    let mut client: RedisClient = connect(&server).await;
+    assert_eq!(
+        range(&mut client, "numbers").await,
+        vec![b"-1".to_vec(), b"3".to_vec(), b"5".to_vec()]
+    );
+
+    drop(client);
+    server.shutdown().await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn module_errors_test() {
+    module::register(Arc::new(ClashModule));
+    let dir: PathBuf = temp_dir("modules-errors");
+
+    let error: Error = start(&dir, &["--loadmodule", "missing"])
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(error.message, "Module missing is not registered");
+    let error: Error = start(&dir, &["--loadmodule", "clash"]).await.err().unwrap();
+    assert_eq!(
+        error.message,
+        "Module clash failed to load: command 'GET' already exists"
+    );
+
+    let server: ServerHandle = start(&dir, &[]).await.unwrap();
+    let mut client: RedisClient = connect(&server).await;
+    let error: Error = client
+        .call(&Cmd::new("MODULE").arg("LOAD").arg("clash"))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.message,
+        "ERR Error loading the extension. Please check the server logs."
+    );
+    let error: Error = client
+        .call(&Cmd::new("MODULE").arg("UNLOAD").arg("clash"))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.message,
+        "ERR Error unloading module: no such module with that name"
+    );
+
+    drop(client);
+    server.shutdown().await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
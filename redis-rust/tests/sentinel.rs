@@ -0,0 +1,213 @@
+//! Runs a master, two replicas and three sentinels as processes, then kills
+//! the master and checks that the sentinels promote a replica.
+
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use redis_starter_rust::redis_client::{bulks, ClientOptions, Cmd, RedisClient};
+use redis_starter_rust::RedisType;
+
+use common::{free_port, temp_dir, Server};
+
+/// Failover takes a few seconds; replicas are found through INFO every 10 seconds.
+const PATIENCE: Duration = Duration::from_secs(40);
+
+fn server(name: &str, port: u16, args: &[&str]) -> Server {
+    let dir: PathBuf = temp_dir(name);
+    let mut all: Vec<String> = vec![
+        String::from("--port"),
+        port.to_string(),
+        String::from("--dir"),
+        dir.to_string_lossy().to_string(),
+    ];
+    all.extend(args.iter().map(|a| a.to_string()));
+    return Server::start(&all.iter().map(String::as_str).collect::<Vec<&str>>());
+}
+
+/// Sends one command on a new connection, `None` if the server can not be reached.
+async fn query(port: u16, args: &[&str]) -> Option<RedisType<'static>> {
+    let mut options: ClientOptions = ClientOptions::new(&format!("127.0.0.1:{}", port));
+    options.reconnect_attempts = 0;
+    let mut client: RedisClient = RedisClient::with_options(options).await.ok()?;
+    let cmd: Cmd = Cmd::new(args[0]).args(&args[1..]);
+    return client.query(&cmd).await.ok();
+}
+
+/// The elements of an array reply as text, empty if there is no such reply.
+async fn strings(port: u16, args: &[&str]) -> Vec<String> {
+    return match query(port, args).await {
+        Some(RedisType::Array(items)) => items.into_iter().map(text).collect(),
+        _ => Vec::new(),
+    };
+}
+
+fn text(reply: RedisType) -> String {
+    return match reply {
+        RedisType::BulkBytes(b) => String::from_utf8(b).unwrap(),
+        RedisType::Integer(s) | RedisType::Status(s) => s,
+        other => format!("{:?}", other),
+    };
+}
+
+async fn wait_for<F: FnMut() -> Fut, Fut: Future<Output = bool>>(what: &str, mut check: F) {
+    let started: Instant = Instant::now();
+    while !check().await {
+        if started.elapsed() > PATIENCE {
+            panic!("timed out waiting for {}", what);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn failover_test() {
+    let master_port: u16 = free_port();
+    let replica_ports: [u16; 2] = [free_port(), free_port()];
+    let sentinel_ports: [u16; 3] = [free_port(), free_port(), free_port()];
+    let replicaof: String = format!("127.0.0.1 {}", master_port);
+
+    let master: Server = server("sentinel-master", master_port, &[]);
+    let _replicas: Vec<Server> = replica_ports
+        .iter()
+        .enumerate()
+        .map(|(i, port)| {
+            let name: String = format!("sentinel-replica{}", i);
+            server(&name, *port, &["--replicaof", &replicaof])
+        })
+        .collect();
+
+    wait_for("the master", || async {
+        query(master_port, &["SET", "before", "1"]).await.is_some()
+    })
+    .await;
+    for port in replica_ports {
+        wait_for("the replicas to sync", || async {
+            strings(port, &["ROLE"]).await.get(3).map(String::as_str) == Some("connected")
+        })
+        .await;
+        // writes are refused on a replica
+        let write: Option<RedisType> = query(port, &["SET", "k", "v"]).await;
+        assert!(matches!(write, Some(RedisType::Error(e)) if e.starts_with("READONLY")));
+    }
+    query(master_port, &["SET", "key", "from the old master"])
+        .await
+        .unwrap();
+    for port in replica_ports {
+        wait_for("the write to replicate", || async {
+            let value: Option<RedisType> = query(port, &["GET", "key"]).await;
+            value == Some(RedisType::BulkBytes(b"from the old master".to_vec()))
+        })
+        .await;
+    }
+
+    let monitor: String = format!("mymaster 127.0.0.1 {} 2", master_port);
+    let _sentinels: Vec<Server> = sentinel_ports
+        .iter()
+        .map(|port| {
+            Server::start(&[
+                "--sentinel",
+                "--port",
+                &port.to_string(),
+                "--monitor",
+                &monitor,
+                "--down-after-milliseconds",
+                "mymaster 500",
+                "--failover-timeout",
+                "mymaster 5000",
+            ])
+        })
+        .collect();
+    for port in sentinel_ports {
+        wait_for(
+            "the sentinels to find each other and the replicas",
+            || async {
+                let master: Vec<String> = strings(port, &["SENTINEL", "MASTER", "mymaster"]).await;
+                let field = |name: &str| -> Option<&str> {
+                    let i: usize = master.iter().position(|f| f == name)?;
+                    return master.get(i + 1).map(String::as_str);
+                };
+                field("num-slaves") == Some("2") && field("num-other-sentinels") == Some("2")
+            },
+        )
+        .await;
+    }
+    assert_eq!(
+        strings(
+            sentinel_ports[0],
+            &["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "mymaster"]
+        )
+        .await,
+        vec![String::from("127.0.0.1"), master_port.to_string()]
+    );
+    assert_eq!(
+        query(
+            sentinel_ports[0],
+            &["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "other"]
+        )
+        .await,
+        Some(RedisType::NullArray)
+    );
+
+    drop(master);
+
+    // every sentinel ends up with the same promoted replica
+    let started: Instant = Instant::now();
+    let promoted: u16 = loop {
+        let mut ports: Vec<u16> = Vec::new();
+        for port in sentinel_ports {
+            let addr: Vec<String> =
+                strings(port, &["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "mymaster"]).await;
+            ports.push(addr.get(1).and_then(|p| p.parse().ok()).unwrap_or(0));
+        }
+        if ports.iter().all(|p| *p == ports[0]) && replica_ports.contains(&ports[0]) {
+            break ports[0];
+        }
+        assert!(
+            started.elapsed() < PATIENCE,
+            "timed out waiting for the failover"
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    let other: u16 = *replica_ports.iter().find(|p| **p != promoted).unwrap();
+
+    assert_eq!(
+        strings(promoted, &["ROLE"]).await.first().unwrap(),
+        "master"
+    );
+    wait_for("the other replica to follow the new master", || async {
+        let role: Vec<String> = strings(other, &["ROLE"]).await;
+        role.get(2) == Some(&promoted.to_string())
+            && role.get(3).map(String::as_str) == Some("connected")
+    })
+    .await;
+    // the old master is kept as a replica to reconfigure when it comes back
+    let replicas: Vec<String> =
+        match query(sentinel_ports[0], &["SENTINEL", "REPLICAS", "mymaster"]).await {
+            Some(RedisType::Array(replicas)) => replicas
+                .into_iter()
+                .map(|r| String::from_utf8(bulks(r).unwrap().remove(1)).unwrap())
+                .collect(),
+            other => panic!("unexpected reply {:?}", other),
+        };
+    assert!(replicas.contains(&format!("127.0.0.1:{}", master_port)));
+    assert!(replicas.contains(&format!("127.0.0.1:{}", other)));
+
+    // the data survived and writes flow from the new master
+    assert_eq!(
+        query(promoted, &["GET", "key"]).await,
+        Some(RedisType::BulkBytes(b"from the old master".to_vec()))
+    );
+    query(promoted, &["SET", "key", "from the new master"])
+        .await
+        .unwrap();
+    wait_for("the write to reach the other replica", || async {
+        let value: Option<RedisType> = query(other, &["GET", "key"]).await;
+        value == Some(RedisType::BulkBytes(b"from the new master".to_vec()))
+    })
+    .await;
+}
@@ -0,0 +1,65 @@
+//! Runs the server binary and checks that the dataset survives a restart,
+//! whether it is stopped by SHUTDOWN or by SIGTERM.
+
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+
+use common::{call, connect, temp_dir, Server};
+
+#[test]
+fn shutdown_test() {
+    let dir: PathBuf = temp_dir("shutdown");
+    let pidfile: PathBuf = dir.join("redis.pid");
+
+    let (server, port) = Server::start_in(&dir);
+    let mut client: TcpStream = connect("127.0.0.1", port);
+    assert_eq!(call(&mut client, &["SET", "foo", "bar"]), b"+OK\r\n");
+    let pid: String = std::fs::read_to_string(&pidfile).unwrap();
+    assert_eq!(pid.trim(), server.child.id().to_string());
+
+    assert_eq!(
+        call(&mut client, &["SHUTDOWN", "ABORT"]),
+        b"-ERR No shutdown in progress.\r\n"
+    );
+    // SHUTDOWN is not answered, the connection is closed
+    assert_eq!(call(&mut client, &["SHUTDOWN"]), b"");
+    assert!(server.wait().success());
+    assert!(!pidfile.exists());
+    assert!(dir.join("dump.rdb").exists());
+
+    let (server, port) = Server::start_in(&dir);
+    let mut client: TcpStream = connect("127.0.0.1", port);
+    assert_eq!(call(&mut client, &["GET", "foo"]), b"$3\r\nbar\r\n");
+    assert_eq!(call(&mut client, &["SET", "foo", "baz"]), b"+OK\r\n");
+
+    // SIGTERM saves too
+    let status: ExitStatus = Command::new("kill")
+        .args(["-TERM", &server.child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(server.wait().success());
+    assert!(!pidfile.exists());
+
+    let (server, port) = Server::start_in(&dir);
+    let mut client: TcpStream = connect("127.0.0.1", port);
+    assert_eq!(call(&mut client, &["GET", "foo"]), b"$3\r\nbaz\r\n");
+
+    // NOSAVE drops what changed since the last save
+    assert_eq!(call(&mut client, &["SET", "foo", "lost"]), b"+OK\r\n");
+    assert_eq!(call(&mut client, &["SHUTDOWN", "NOSAVE"]), b"");
+    assert!(server.wait().success());
+
+    let (server, port) = Server::start_in(&dir);
+    let mut client: TcpStream = connect("127.0.0.1", port);
+    assert_eq!(call(&mut client, &["GET", "foo"]), b"$3\r\nbaz\r\n");
+    assert_eq!(call(&mut client, &["SHUTDOWN", "NOSAVE"]), b"");
+    assert!(server.wait().success());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
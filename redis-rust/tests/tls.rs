@@ -0,0 +1,154 @@
+//! Runs the server binary with a TLS listener next to the plaintext one,
+//! using certificates generated on the spot.
+
+#![cfg(feature = "tls")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use common::{call, connect, free_port, temp_dir, Server};
+
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// A CA and the server certificate it signed, written to `dir` as PEM files.
+struct Pki {
+    ca: Certificate,
+    ca_key: KeyPair,
+}
+
+impl Pki {
+    fn create(dir: &Path) -> Pki {
+        let ca_key: KeyPair = KeyPair::generate().unwrap();
+        let mut params: CertificateParams = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca: Certificate = params.self_signed(&ca_key).unwrap();
+
+        let server_key: KeyPair = KeyPair::generate().unwrap();
+        let server: Certificate = CertificateParams::new(vec![String::from("localhost")])
+            .unwrap()
+            .signed_by(&server_key, &ca, &ca_key)
+            .unwrap();
+
+        std::fs::write(dir.join("ca.crt"), ca.pem()).unwrap();
+        std::fs::write(dir.join("redis.crt"), server.pem()).unwrap();
+        std::fs::write(dir.join("redis.key"), server_key.serialize_pem()).unwrap();
+        return Pki { ca, ca_key };
+    }
+
+    /// A client certificate signed by the CA, with its key.
+    fn client_identity(&self) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+        let key: KeyPair = KeyPair::generate().unwrap();
+        let cert: Certificate = CertificateParams::new(Vec::<String>::new())
+            .unwrap()
+            .signed_by(&key, &self.ca, &self.ca_key)
+            .unwrap();
+        return (
+            vec![cert.der().clone()],
+            PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+        );
+    }
+
+    /// Opens a TLS connection trusting only the CA, presenting `identity` if given.
+    fn connect(
+        &self,
+        port: u16,
+        identity: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    ) -> TlsStream {
+        let mut roots: RootCertStore = RootCertStore::empty();
+        roots.add(self.ca.der().clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config: ClientConfig = match identity {
+            Some((chain, key)) => builder.with_client_auth_cert(chain, key).unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+
+        let name: ServerName<'static> = ServerName::try_from("localhost").unwrap();
+        let connection: ClientConnection = ClientConnection::new(Arc::new(config), name).unwrap();
+        return StreamOwned::new(connection, connect("127.0.0.1", port));
+    }
+}
+
+fn start(dir: &Path, port: u16, tls_port: u16, auth: &str) -> Server {
+    return Server::start(&[
+        "--dir",
+        dir.to_str().unwrap(),
+        "--port",
+        &port.to_string(),
+        "--tls-port",
+        &tls_port.to_string(),
+        "--tls-cert-file",
+        dir.join("redis.crt").to_str().unwrap(),
+        "--tls-key-file",
+        dir.join("redis.key").to_str().unwrap(),
+        "--tls-ca-cert-file",
+        dir.join("ca.crt").to_str().unwrap(),
+        "--tls-auth-clients",
+        auth,
+    ]);
+}
+
+#[test]
+fn tls_test() {
+    let dir: PathBuf = temp_dir("tls");
+    let pki: Pki = Pki::create(&dir);
+    let (port, tls_port) = (free_port(), free_port());
+    let server: Server = start(&dir, port, tls_port, "no");
+
+    // both listeners serve the same keyspace
+    let mut secure: TlsStream = pki.connect(tls_port, None);
+    let mut plain: TcpStream = connect("127.0.0.1", port);
+    assert_eq!(call(&mut secure, &["SET", "foo", "bar"]), b"+OK\r\n");
+    assert_eq!(call(&mut plain, &["GET", "foo"]), b"$3\r\nbar\r\n");
+    assert_eq!(
+        call(&mut secure, &["CONFIG", "GET", "tls-auth-clients"]),
+        b"*2\r\n$16\r\ntls-auth-clients\r\n$2\r\nno\r\n"
+    );
+
+    // plaintext on the TLS port gets a TLS alert record back
+    let mut confused: TcpStream = connect("127.0.0.1", tls_port);
+    assert_eq!(call(&mut confused, &["PING"])[0], 21);
+
+    assert_eq!(call(&mut secure, &["SHUTDOWN", "NOSAVE"]), b"");
+    assert!(server.wait().success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn client_auth_test() {
+    let dir: PathBuf = temp_dir("tls-auth");
+    let pki: Pki = Pki::create(&dir);
+    let tls_port: u16 = free_port();
+    let server: Server = start(&dir, 0, tls_port, "yes");
+
+    let mut anonymous: TlsStream = pki.connect(tls_port, None);
+    assert_eq!(call(&mut anonymous, &["PING"]), b"");
+    let mut trusted: TlsStream = pki.connect(tls_port, Some(pki.client_identity()));
+    assert_eq!(call(&mut trusted, &["PING"]), b"+PONG\r\n");
+
+    assert_eq!(call(&mut trusted, &["SHUTDOWN", "NOSAVE"]), b"");
+    assert!(server.wait().success());
+
+    // optional only checks the certificates that are sent
+    let tls_port: u16 = free_port();
+    let server: Server = start(&dir, 0, tls_port, "optional");
+    let mut anonymous: TlsStream = pki.connect(tls_port, None);
+    assert_eq!(call(&mut anonymous, &["PING"]), b"+PONG\r\n");
+    let mut trusted: TlsStream = pki.connect(tls_port, Some(pki.client_identity()));
+    assert_eq!(call(&mut trusted, &["PING"]), b"+PONG\r\n");
+
+    assert_eq!(call(&mut trusted, &["SHUTDOWN", "NOSAVE"]), b"");
+    assert!(server.wait().success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}